@@ -0,0 +1,390 @@
+//! Deterministic scenario replay.
+//!
+//! Loads a JSON scenario describing the vAMM, the engine risk parameters, the
+//! traders' starting balances and a list of events, then runs every event
+//! through the engine and vAMM contracts in-process and prints the resulting
+//! positions, reserves and funding state. Scenarios with an `oracle` get a
+//! price feed for the index, so they can quote prices and pay funding rounds.
+//! Useful for reproducing incidents locally.
+//!
+//! ```sh
+//! cargo run -p margined_engine --features replay --bin replay -- scenarios/example.json
+//! ```
+use cosmwasm_std::{from_slice, Addr, Empty, Uint128};
+use cw20::{Cw20Coin, Cw20ExecuteMsg};
+use cw_multi_test::{App, AppBuilder, Contract, ContractWrapper, Executor};
+use margined_engine::contract::{execute, instantiate, query, reply};
+use margined_perp::asset::AssetInfo;
+use margined_perp::margined_engine::{
+    ExecuteMsg, FundingResponse, InstantiateMsg, PositionResponse, QueryMsg, Side,
+};
+use margined_perp::margined_pricefeed::{
+    ExecuteMsg as PricefeedExecuteMsg, InstantiateMsg as PricefeedInstantiateMsg,
+};
+use margined_perp::margined_vamm::{
+    ExecuteMsg as VammExecuteMsg, InstantiateMsg as VammInstantiateMsg, QueryMsg as VammQueryMsg,
+    StateResponse,
+};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+
+#[derive(Deserialize, Debug)]
+struct Scenario {
+    vamm: VammInstantiateMsg,
+    engine: EngineParams,
+    traders: Vec<TraderBalance>,
+    #[serde(default)]
+    oracle: Option<OracleParams>,
+    events: Vec<Event>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OracleParams {
+    decimals: u8,
+}
+
+#[derive(Deserialize, Debug)]
+struct EngineParams {
+    decimals: u8,
+    initial_margin_ratio: Uint128,
+    maintenance_margin_ratio: Uint128,
+    liquidation_fee: Uint128,
+}
+
+#[derive(Deserialize, Debug)]
+struct TraderBalance {
+    address: String,
+    balance: Uint128,
+}
+
+// events mirror the engine execute messages, plus block advancement and
+// index prices
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Event {
+    OpenPosition {
+        trader: String,
+        side: Side,
+        quote_asset_amount: Uint128,
+        leverage: Uint128,
+        base_asset_limit: Option<Uint128>,
+        #[serde(default)]
+        reduce_only: bool,
+    },
+    ClosePosition {
+        trader: String,
+        size: Option<Uint128>,
+        quote_asset_limit: Option<Uint128>,
+        #[serde(default)]
+        partial: bool,
+    },
+    AdvanceBlock {
+        blocks: u64,
+        seconds: u64,
+    },
+    // quotes the index at the price, in the oracle decimals, as of the current block
+    SetOraclePrice {
+        price: Uint128,
+    },
+    PayFunding {},
+}
+
+struct Markets {
+    vamm: Addr,
+    engine: Addr,
+    pricefeed: Option<Addr>,
+}
+
+fn contract_cw20() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new_with_empty(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    );
+    Box::new(contract)
+}
+
+fn contract_vamm() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new_with_empty(
+        margined_vamm::contract::execute,
+        margined_vamm::contract::instantiate,
+        margined_vamm::contract::query,
+    );
+    Box::new(contract)
+}
+
+fn contract_pricefeed() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new_with_empty(
+        margined_pricefeed::contract::execute,
+        margined_pricefeed::contract::instantiate,
+        margined_pricefeed::contract::query,
+    );
+    Box::new(contract)
+}
+
+fn contract_engine() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new_with_empty(execute, instantiate, query).with_reply(reply);
+    Box::new(contract)
+}
+
+fn setup(router: &mut App, scenario: &Scenario) -> Result<Markets, String> {
+    let owner = Addr::unchecked("owner");
+
+    let usdc_id = router.store_code(contract_cw20());
+    let vamm_id = router.store_code(contract_vamm());
+    let engine_id = router.store_code(contract_engine());
+    let pricefeed_id = router.store_code(contract_pricefeed());
+
+    let usdc = router
+        .instantiate_contract(
+            usdc_id,
+            owner.clone(),
+            &cw20_base::msg::InstantiateMsg {
+                name: "USDC".to_string(),
+                symbol: "USDC".to_string(),
+                decimals: scenario.engine.decimals,
+                initial_balances: scenario
+                    .traders
+                    .iter()
+                    .map(|t| Cw20Coin {
+                        address: t.address.clone(),
+                        amount: t.balance,
+                    })
+                    .collect(),
+                mint: None,
+                marketing: None,
+            },
+            &[],
+            "cw20",
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+
+    let vamm = router
+        .instantiate_contract(vamm_id, owner.clone(), &scenario.vamm, &[], "vamm", None)
+        .map_err(|e| e.to_string())?;
+
+    let engine = router
+        .instantiate_contract(
+            engine_id,
+            owner.clone(),
+            &InstantiateMsg {
+                decimals: scenario.engine.decimals,
+                eligible_collateral: AssetInfo::Token {
+                    contract_addr: usdc.to_string(),
+                },
+                initial_margin_ratio: scenario.engine.initial_margin_ratio,
+                maintenance_margin_ratio: scenario.engine.maintenance_margin_ratio,
+                liquidation_fee: scenario.engine.liquidation_fee,
+                vamm: vec![vamm.to_string()],
+            },
+            &[],
+            "engine",
+            None,
+        )
+        .map_err(|e| e.to_string())?;
+
+    let pricefeed = match &scenario.oracle {
+        Some(oracle) => Some(
+            router
+                .instantiate_contract(
+                    pricefeed_id,
+                    owner.clone(),
+                    &PricefeedInstantiateMsg {
+                        decimals: oracle.decimals,
+                        oracle_hub_contract: "oracle_hub".to_string(),
+                    },
+                    &[],
+                    "pricefeed",
+                    None,
+                )
+                .map_err(|e| e.to_string())?,
+        ),
+        None => None,
+    };
+
+    // only the engine may swap on the vamm
+    router
+        .execute_contract(
+            owner,
+            vamm.clone(),
+            &VammExecuteMsg::UpdateConfig {
+                toll_ratio: None,
+                spread_ratio: None,
+                price_feed: pricefeed.as_ref().map(|addr| addr.to_string()),
+                fluctuation_limit_ratio: None,
+                engine: Some(engine.to_string()),
+            },
+            &[],
+        )
+        .map_err(|e| e.to_string())?;
+
+    // every trader allows the engine to pull their entire balance
+    for trader in scenario.traders.iter() {
+        router
+            .execute_contract(
+                Addr::unchecked(&trader.address),
+                usdc.clone(),
+                &Cw20ExecuteMsg::IncreaseAllowance {
+                    spender: engine.to_string(),
+                    amount: trader.balance,
+                    expires: None,
+                },
+                &[],
+            )
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(Markets {
+        vamm,
+        engine,
+        pricefeed,
+    })
+}
+
+fn main() -> Result<(), String> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or("usage: replay <scenario.json>")?;
+    let raw = std::fs::read(&path).map_err(|e| format!("{}: {}", path, e))?;
+    let scenario: Scenario = from_slice(&raw).map_err(|e| e.to_string())?;
+
+    let mut router = AppBuilder::new().build();
+    let markets = setup(&mut router, &scenario)?;
+
+    // positions can only be queried once they exist
+    let mut traders: BTreeSet<String> = BTreeSet::new();
+
+    for (index, event) in scenario.events.iter().enumerate() {
+        let result = match event {
+            Event::OpenPosition {
+                trader,
+                side,
+                quote_asset_amount,
+                leverage,
+                base_asset_limit,
+                reduce_only,
+            } => {
+                let msg = ExecuteMsg::OpenPosition {
+                    vamm: markets.vamm.to_string(),
+                    side: side.clone(),
+                    quote_asset_amount: *quote_asset_amount,
+                    leverage: *leverage,
+                    base_asset_limit: *base_asset_limit,
+                    reduce_only: *reduce_only,
+                    deadline: None,
+                    trader: None,
+                };
+                let res = router.execute_contract(
+                    Addr::unchecked(trader),
+                    markets.engine.clone(),
+                    &msg,
+                    &[],
+                );
+                if res.is_ok() {
+                    traders.insert(trader.clone());
+                }
+                res.map(|_| ())
+            }
+            Event::ClosePosition {
+                trader,
+                size,
+                quote_asset_limit,
+                partial,
+            } => {
+                let msg = ExecuteMsg::ClosePosition {
+                    vamm: markets.vamm.to_string(),
+                    size: *size,
+                    quote_asset_limit: *quote_asset_limit,
+                    partial: *partial,
+                    deadline: None,
+                    trader: None,
+                };
+                router
+                    .execute_contract(Addr::unchecked(trader), markets.engine.clone(), &msg, &[])
+                    .map(|_| ())
+            }
+            Event::AdvanceBlock { blocks, seconds } => {
+                router.update_block(|block| {
+                    block.height += blocks;
+                    block.time = block.time.plus_seconds(*seconds);
+                });
+                Ok(())
+            }
+            Event::SetOraclePrice { price } => {
+                let pricefeed = markets
+                    .pricefeed
+                    .clone()
+                    .ok_or("scenario has no oracle to quote prices on")?;
+                let msg = PricefeedExecuteMsg::AppendPrice {
+                    key: scenario.vamm.quote_asset.clone(),
+                    price: *price,
+                    timestamp: router.block_info().time.seconds(),
+                };
+                router
+                    .execute_contract(Addr::unchecked("owner"), pricefeed, &msg, &[])
+                    .map(|_| ())
+            }
+            Event::PayFunding {} => {
+                let msg = ExecuteMsg::PayFunding {
+                    vamm: markets.vamm.to_string(),
+                };
+                router
+                    .execute_contract(Addr::unchecked("owner"), markets.engine.clone(), &msg, &[])
+                    .map(|_| ())
+            }
+        };
+
+        match result {
+            Ok(()) => println!("[{}] {:?}: ok", index, event),
+            Err(e) => println!("[{}] {:?}: error: {}", index, event, e),
+        }
+    }
+
+    let state: StateResponse = router
+        .wrap()
+        .query_wasm_smart(&markets.vamm, &VammQueryMsg::State {})
+        .map_err(|e| e.to_string())?;
+    println!(
+        "vamm {}: quote_asset_reserve={} base_asset_reserve={}",
+        markets.vamm, state.quote_asset_reserve, state.base_asset_reserve
+    );
+
+    let funding: FundingResponse = router
+        .wrap()
+        .query_wasm_smart(
+            &markets.engine,
+            &QueryMsg::Funding {
+                vamm: markets.vamm.to_string(),
+            },
+        )
+        .map_err(|e| e.to_string())?;
+    println!(
+        "funding {}: cumulative_premium_fraction={} last_funding_time={}",
+        markets.vamm, funding.cumulative_premium_fraction, funding.last_funding_time
+    );
+
+    for trader in traders.iter() {
+        let position: PositionResponse = router
+            .wrap()
+            .query_wasm_smart(
+                &markets.engine,
+                &QueryMsg::Position {
+                    vamm: markets.vamm.to_string(),
+                    trader: trader.clone(),
+                },
+            )
+            .map_err(|e| e.to_string())?;
+        println!(
+            "position {}: size={} margin={} notional={} last_updated_premium_fraction={} timestamp={}",
+            trader,
+            position.size,
+            position.margin,
+            position.notional,
+            position.last_updated_premium_fraction,
+            position.timestamp
+        );
+    }
+
+    Ok(())
+}
@@ -1,25 +1,103 @@
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    from_binary, to_binary, Attribute, Binary, ContractResult, Deps, DepsMut, Env, Event,
-    MessageInfo, Reply, Response, StdError, StdResult, SubMsgExecutionResponse, Uint128,
+    from_binary, to_binary, Addr, Binary, ContractResult, Deps, DepsMut, Env, Event, MessageInfo,
+    Reply, Response, StdError, StdResult, SubMsgExecutionResponse, Uint128,
 };
+use cw2::{get_contract_version, set_contract_version};
 use cw20::Cw20ReceiveMsg;
-use margined_perp::margined_engine::{Cw20HookMsg, ExecuteMsg, InstantiateMsg, QueryMsg};
+use margined_perp::asset::AssetInfo;
+use margined_perp::cw721::Cw721ReceiveMsg;
+use margined_perp::decimals::mul_d;
+#[cfg(feature = "trace")]
+use margined_perp::events::namespaced_event;
+use margined_perp::events::{namespace_attributes, ENGINE_NAMESPACE};
+use margined_perp::margined_engine::{
+    Cw20HookMsg, Cw721HookMsg, ExecuteMsg, InstantiateMsg, MigrateMsg, PauseLevel, PnlCalcOption,
+    QueryMsg, QueuedTrade,
+};
+use margined_perp::margined_vamm::SwapResponse;
 #[cfg(not(feature = "library"))]
 use std::str::FromStr;
 
 use crate::error::ContractError;
 use crate::{
-    handle::{close_position, open_position, update_config},
-    query::{query_config, query_position, query_trader_balance_with_funding_payment},
-    reply::{decrease_position_reply, increase_position_reply, reverse_position_reply},
-    state::{read_config, store_config, store_vamm, Config},
+    handle::{
+        accept_ownership, accept_position, accept_vamm_ownership, add_fee_campaign,
+        add_holding_cap_exemption, add_liquidator, add_vamm, cancel_ownership_proposal,
+        cancel_position_transfer, cancel_queued_order, cancel_trigger_order,
+        claim_referral_rewards, close_position, commit_order, crank, deposit_collateral,
+        deposit_fees, deposit_for, deposit_insurance_fees, deposit_liquidity_fees,
+        deregister_contract_trader, distribute_fees, execute_queued_order, execute_trigger_order,
+        grant_trading_permission, liquidate, list_market, match_trigger_orders, open_position,
+        pay_funding, propose_market, propose_ownership, provide_liquidity, queue_order,
+        register_contract_trader, register_referrer, remove_collateral_asset, remove_fee_campaign,
+        remove_fee_tier, remove_holding_cap_exemption, remove_liquidator, remove_vamm,
+        request_unstake, reveal_order, revoke_trading_permission, set_cross_margin, set_fee_tier,
+        set_market_open, set_max_oracle_spread, set_operator, set_pause, set_referral_code,
+        set_signing_key, set_trigger_order, set_trigger_order_pair, settle_position,
+        shutdown_market, stake_insurance, sweep, transfer_position, unstake,
+        unwind_insurance_position, unwrap_position, update_account_settings,
+        update_collateral_asset, update_commit_reveal, update_config, update_fee_pool_config,
+        update_insurance_config, update_listing_config, update_maker_vault, update_margin_tiers,
+        update_market_caps, update_market_collateral, update_market_fees, update_open_interest_cap,
+        update_paused_operations, update_position_nft, update_referral_share, update_risk_params,
+        update_withdrawal_throttle, veto_market, withdraw_collateral, withdraw_liquidity,
+        withdraw_margin, wrap_position,
+    },
+    querier::query_vamm_config,
+    query::{
+        query_account_collateral, query_account_settings, query_all_vamms, query_bad_debt,
+        query_calc_fee, query_collateral_assets, query_commit_reveal, query_commitment_hash,
+        query_config, query_contract_traders, query_daily_stats, query_fee_campaigns,
+        query_fee_pool, query_free_collateral, query_funding, query_funding_preview,
+        query_funding_rate_history, query_holding_cap_exemptions, query_holding_capacity,
+        query_insurance_fund, query_insurance_stake, query_liquidation_price, query_liquidators,
+        query_liquidity_pool, query_liquidity_stake, query_listing_config, query_maker_vault,
+        query_margin_ratio, query_margin_requirement, query_margin_tiers, query_market_caps,
+        query_market_collateral, query_market_fees, query_market_proposal, query_market_status,
+        query_next_funding_time, query_open_interest, query_open_interest_cap,
+        query_open_interest_imbalance, query_order_commitment, query_paused_operations,
+        query_position, query_position_notional_and_unrealized_pnl, query_position_snapshot,
+        query_position_token, query_position_transfer, query_positions, query_queued_order,
+        query_referral_rewards, query_risk_params, query_settlement, query_signing_key,
+        query_simulate_close_position, query_simulate_open_position, query_state,
+        query_trade_history, query_trader_balance_with_funding_payment, query_trader_fee_rate,
+        query_trader_positions, query_trading_permission, query_trigger_orders,
+        query_withdrawal_throttle,
+    },
+    reply::{
+        close_position_reply, decrease_position_reply, increase_position_reply, liquidation_reply,
+        list_market_reply, reverse_position_reply, unwind_reply,
+    },
+    state::{
+        map_validate, migrate_legacy_storage, migrate_state, read_config, read_market_collateral,
+        read_position_nft, store_config, store_insurance_fund, store_state, store_vamm,
+        store_vamm_decimals, Config, InsuranceFund, State,
+    },
+    utils::{
+        require_deadline, require_direct_orders, resolve_trader, validate_risk_params,
+        verify_signed_order,
+    },
 };
 
 pub const SWAP_INCREASE_REPLY_ID: u64 = 1;
 pub const SWAP_DECREASE_REPLY_ID: u64 = 2;
 pub const SWAP_REVERSE_REPLY_ID: u64 = 3;
 pub const SWAP_CLOSE_REPLY_ID: u64 = 4;
+pub const LIST_MARKET_REPLY_ID: u64 = 5;
+pub const LIQUIDATION_REPLY_ID: u64 = 6;
+pub const UNWIND_REPLY_ID: u64 = 7;
+
+const CONTRACT_NAME: &str = "crates.io:margined-engine";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// a swap's reply id carries the id of its temporary swap above the reply
+// kind, so that swaps in flight at the same time never share state
+const REPLY_KIND_BITS: u64 = 8;
+
+pub fn swap_reply_id(kind: u64, swap_id: u64) -> u64 {
+    swap_id << REPLY_KIND_BITS | kind
+}
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
@@ -29,61 +107,441 @@ pub fn instantiate(
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     let decimals = Uint128::from(10u128.pow(msg.decimals as u32));
-    let eligible_collateral = deps.api.addr_validate(&msg.eligible_collateral)?;
+    msg.eligible_collateral.check(deps.api)?;
 
     // config parameters
     let config = Config {
         owner: info.sender,
-        eligible_collateral,
+        pending_owner: None,
+        operator: None,
+        eligible_collateral: msg.eligible_collateral,
         decimals,
         initial_margin_ratio: msg.initial_margin_ratio,
         maintenance_margin_ratio: msg.maintenance_margin_ratio,
         liquidation_fee: msg.liquidation_fee,
+        min_notional: Uint128::zero(),
+        fee_pool: None,
+        open_liquidation: false,
+        pause_level: PauseLevel::Full,
+        staking_contract: None,
+        max_oracle_spread: None,
+        liquidation_price_source: PnlCalcOption::SpotPrice,
     };
+    validate_risk_params(&config)?;
 
     store_config(deps.storage, &config)?;
+    store_insurance_fund(deps.storage, &InsuranceFund::new(decimals))?;
+    store_state(deps.storage, &State::default())?;
+
+    // register the default vamms, recording the precision of each so
+    // amounts can be normalised
+    for vamm in map_validate(deps.api, &msg.vamm)? {
+        let vamm_config = query_vamm_config(deps.as_ref(), vamm.to_string())?;
+        store_vamm_decimals(deps.storage, &vamm, vamm_config.decimals)?;
+        store_vamm(deps.storage, &vamm)?;
+    }
 
-    // store default vamms
-    store_vamm(deps, &msg.vamm)?;
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
 
     Ok(Response::default())
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn execute(deps: DepsMut, env: Env, info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
-    match msg {
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    // the totals are taken before legacy positions are moved, which adds them
+    migrate_state(deps.storage)?;
+
+    // deployments that predate versioning are still in the legacy layout
+    match get_contract_version(deps.storage) {
+        Ok(version) if version.contract != CONTRACT_NAME => {
+            return Err(
+                StdError::generic_err(format!("cannot migrate from {}", version.contract)).into(),
+            );
+        }
+        Ok(_) => {}
+        Err(_) => migrate_legacy_storage(deps.storage)?,
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let response =
+        Response::new().add_attributes(vec![("action", "migrate"), ("version", CONTRACT_VERSION)]);
+
+    Ok(namespace_attributes(ENGINE_NAMESPACE, response))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    let response = match msg {
         ExecuteMsg::Receive(msg) => receive_cw20(deps, env, info, msg),
-        ExecuteMsg::UpdateConfig { owner } => update_config(deps, info, owner),
+        ExecuteMsg::ReceiveNft(msg) => receive_nft(deps, info, msg),
+        ExecuteMsg::UpdateConfig {
+            eligible_collateral,
+            initial_margin_ratio,
+            maintenance_margin_ratio,
+            liquidation_fee,
+            min_notional,
+            fee_pool,
+            open_liquidation,
+            pause_level,
+            staking_contract,
+            liquidation_price_source,
+        } => update_config(
+            deps,
+            info,
+            eligible_collateral,
+            initial_margin_ratio,
+            maintenance_margin_ratio,
+            liquidation_fee,
+            min_notional,
+            fee_pool,
+            open_liquidation,
+            pause_level,
+            staking_contract,
+            liquidation_price_source,
+        ),
         ExecuteMsg::OpenPosition {
             vamm,
             side,
             quote_asset_amount,
             leverage,
+            base_asset_limit,
+            reduce_only,
+            deadline,
+            trader,
         } => {
-            let trader = info.sender.clone();
+            require_deadline(&env, deadline)?;
+            require_direct_orders(deps.storage, &deps.api.addr_validate(&vamm)?)?;
+            let notional = mul_d(
+                quote_asset_amount,
+                leverage,
+                read_config(deps.storage)?.decimals,
+            )?;
+            let trader = resolve_trader(deps.api, deps.storage, &info.sender, trader, notional)?;
             open_position(
                 deps,
                 env,
                 info,
+                None,
                 vamm,
                 trader.to_string(),
                 side,
                 quote_asset_amount,
                 leverage,
+                base_asset_limit,
+                reduce_only,
             )
         }
-        ExecuteMsg::ClosePosition { vamm } => {
-            let trader = info.sender.clone();
+        ExecuteMsg::ClosePosition {
+            vamm,
+            size,
+            quote_asset_limit,
+            partial,
+            deadline,
+            trader,
+        } => {
+            require_deadline(&env, deadline)?;
+            let trader = resolve_trader(
+                deps.api,
+                deps.storage,
+                &info.sender,
+                trader,
+                Uint128::zero(),
+            )?;
             close_position(
                 deps,
                 env,
                 info,
                 vamm,
                 trader.to_string(),
+                size,
+                quote_asset_limit,
+                partial,
                 SWAP_CLOSE_REPLY_ID,
             )
         }
-    }
+        ExecuteMsg::UpdateListingConfig {
+            vamm_code_id,
+            bond,
+            challenge_period,
+        } => update_listing_config(deps, info, vamm_code_id, bond, challenge_period),
+        ExecuteMsg::VetoMarket { proposal_id } => veto_market(deps, info, proposal_id),
+        ExecuteMsg::ListMarket { proposal_id } => list_market(deps, env, proposal_id),
+        ExecuteMsg::AddFeeCampaign {
+            start,
+            end,
+            vamms,
+            discount,
+        } => add_fee_campaign(deps, info, start, end, vamms, discount),
+        ExecuteMsg::RemoveFeeCampaign { campaign_id } => {
+            remove_fee_campaign(deps, info, campaign_id)
+        }
+        ExecuteMsg::UpdateAccountSettings { max_slippage } => {
+            update_account_settings(deps, info, max_slippage)
+        }
+        ExecuteMsg::SetCrossMargin { enabled } => set_cross_margin(deps, info, enabled),
+        ExecuteMsg::UpdateMarginTiers { vamm, tiers } => {
+            update_margin_tiers(deps, info, vamm, tiers)
+        }
+        ExecuteMsg::UpdateOpenInterestCap { share, floor } => {
+            update_open_interest_cap(deps, info, share, floor)
+        }
+        ExecuteMsg::Crank { vamm, max_ops } => crank(deps, env, info, vamm, max_ops),
+        ExecuteMsg::UpdateInsuranceConfig { unstake_cooldown } => {
+            update_insurance_config(deps, info, unstake_cooldown)
+        }
+        ExecuteMsg::RequestUnstake { amount } => request_unstake(deps, env, info, amount),
+        ExecuteMsg::Unstake {} => unstake(deps, env, info),
+        ExecuteMsg::UpdateFeePoolConfig {
+            insurance_share,
+            insurance_floor,
+            keeper_reward,
+        } => update_fee_pool_config(deps, info, insurance_share, insurance_floor, keeper_reward),
+        ExecuteMsg::DistributeFees {} => distribute_fees(deps),
+        ExecuteMsg::SetPause { paused } => set_pause(deps, info, paused),
+        ExecuteMsg::UpdatePausedOperations { paused } => {
+            update_paused_operations(deps, info, paused)
+        }
+        ExecuteMsg::DepositFor {
+            trader,
+            vamm,
+            amount,
+        } => deposit_for(deps, env, Some(info), vamm, trader, amount),
+        ExecuteMsg::ProposeOwnership { owner } => propose_ownership(deps, info, owner),
+        ExecuteMsg::AcceptOwnership {} => accept_ownership(deps, info),
+        ExecuteMsg::CancelOwnershipProposal {} => cancel_ownership_proposal(deps, info),
+        ExecuteMsg::SetOperator { operator } => set_operator(deps, info, operator),
+        ExecuteMsg::SetMaxOracleSpread { max_oracle_spread } => {
+            set_max_oracle_spread(deps, info, max_oracle_spread)
+        }
+        ExecuteMsg::GrantTradingPermission {
+            delegate,
+            notional_cap,
+        } => grant_trading_permission(deps, info, delegate, notional_cap),
+        ExecuteMsg::RevokeTradingPermission { delegate } => {
+            revoke_trading_permission(deps, info, delegate)
+        }
+        ExecuteMsg::SetSigningKey { pubkey } => set_signing_key(deps, info, pubkey),
+        ExecuteMsg::OpenPositionFor {
+            order,
+            pubkey,
+            signature,
+        } => {
+            let trader =
+                verify_signed_order(deps.api, deps.storage, &env, &order, &pubkey, &signature)?;
+            require_deadline(&env, order.deadline)?;
+            require_direct_orders(deps.storage, &deps.api.addr_validate(&order.vamm)?)?;
+            open_position(
+                deps,
+                env,
+                info,
+                None,
+                order.vamm,
+                trader.to_string(),
+                order.side,
+                order.quote_asset_amount,
+                order.leverage,
+                order.base_asset_limit,
+                order.reduce_only,
+            )
+        }
+        ExecuteMsg::UpdateMakerVault {
+            vamm,
+            vault,
+            fee_share,
+        } => update_maker_vault(deps, info, vamm, vault, fee_share),
+        ExecuteMsg::AcceptVammOwnership { vamm } => accept_vamm_ownership(deps, info, vamm),
+        ExecuteMsg::AddVamm { vamm, risk_params } => add_vamm(deps, info, vamm, risk_params),
+        ExecuteMsg::RemoveVamm { vamm } => remove_vamm(deps, info, vamm),
+        ExecuteMsg::AddLiquidator { liquidator } => add_liquidator(deps, info, liquidator),
+        ExecuteMsg::RemoveLiquidator { liquidator } => remove_liquidator(deps, info, liquidator),
+        ExecuteMsg::AddHoldingCapExemption { trader } => {
+            add_holding_cap_exemption(deps, info, trader)
+        }
+        ExecuteMsg::RemoveHoldingCapExemption { trader } => {
+            remove_holding_cap_exemption(deps, info, trader)
+        }
+        ExecuteMsg::UpdateMarketCollateral { vamm, collateral } => {
+            update_market_collateral(deps, info, vamm, collateral)
+        }
+        ExecuteMsg::UpdateCollateralAsset {
+            asset,
+            weight,
+            price,
+        } => update_collateral_asset(deps, info, asset, weight, price),
+        ExecuteMsg::RemoveCollateralAsset { asset } => remove_collateral_asset(deps, info, asset),
+        ExecuteMsg::DepositCollateral { asset, amount } => {
+            let trader = info.sender.to_string();
+            deposit_collateral(deps, env, Some(info), trader, asset, amount)
+        }
+        ExecuteMsg::WithdrawCollateral { asset, amount } => {
+            withdraw_collateral(deps, env, info, asset, amount)
+        }
+        ExecuteMsg::UpdateMarketFees {
+            vamm,
+            toll_ratio,
+            spread_ratio,
+        } => update_market_fees(deps, info, vamm, toll_ratio, spread_ratio),
+        ExecuteMsg::UpdateMarketCaps {
+            vamm,
+            open_interest_notional_cap,
+            max_position_size,
+            max_position_notional,
+        } => update_market_caps(
+            deps,
+            info,
+            vamm,
+            open_interest_notional_cap,
+            max_position_size,
+            max_position_notional,
+        ),
+        ExecuteMsg::UpdateRiskParams { vamm, risk_params } => {
+            update_risk_params(deps, info, vamm, risk_params)
+        }
+        ExecuteMsg::UpdateWithdrawalThrottle { throttle } => {
+            update_withdrawal_throttle(deps, info, throttle)
+        }
+        ExecuteMsg::SetMarketOpen { vamm, open } => set_market_open(deps, info, vamm, open),
+        ExecuteMsg::ShutdownMarket { vamm } => shutdown_market(deps, info, vamm),
+        ExecuteMsg::SettlePosition { vamm } => settle_position(deps, env, info, vamm),
+        ExecuteMsg::UpdatePositionNft { contract } => update_position_nft(deps, info, contract),
+        ExecuteMsg::WrapPosition { vamm } => wrap_position(deps, info, vamm),
+        ExecuteMsg::WithdrawLiquidity { vamm, amount } => {
+            withdraw_liquidity(deps, info, vamm, amount)
+        }
+        ExecuteMsg::TransferPosition { vamm, to } => transfer_position(deps, info, vamm, to),
+        ExecuteMsg::CancelPositionTransfer { vamm } => cancel_position_transfer(deps, info, vamm),
+        ExecuteMsg::AcceptPosition { vamm, from } => accept_position(deps, info, vamm, from),
+        ExecuteMsg::UpdateCommitReveal {
+            vamm,
+            expiry_blocks,
+        } => update_commit_reveal(deps, info, vamm, expiry_blocks),
+        ExecuteMsg::CommitOrder { vamm, commitment } => {
+            commit_order(deps, env, info, vamm, commitment)
+        }
+        ExecuteMsg::RevealOrder {
+            vamm,
+            side,
+            quote_asset_amount,
+            leverage,
+            base_asset_limit,
+            salt,
+        } => reveal_order(
+            deps,
+            env,
+            info,
+            vamm,
+            side,
+            quote_asset_amount,
+            leverage,
+            base_asset_limit,
+            salt,
+        ),
+        ExecuteMsg::RegisterContractTrader {
+            contract,
+            notional_cap,
+        } => register_contract_trader(deps, info, contract, notional_cap),
+        ExecuteMsg::DeregisterContractTrader { contract } => {
+            deregister_contract_trader(deps, info, contract)
+        }
+        ExecuteMsg::QueueOrder {
+            vamm,
+            side,
+            quote_asset_amount,
+            leverage,
+            base_asset_limit,
+            reduce_only,
+            deadline,
+        } => queue_order(
+            deps,
+            env,
+            info,
+            vamm,
+            QueuedTrade::Open {
+                side,
+                quote_asset_amount,
+                leverage,
+                base_asset_limit,
+                reduce_only,
+            },
+            deadline,
+        ),
+        ExecuteMsg::QueueClose {
+            vamm,
+            size,
+            quote_asset_limit,
+            deadline,
+        } => queue_order(
+            deps,
+            env,
+            info,
+            vamm,
+            QueuedTrade::Close {
+                size,
+                quote_asset_limit,
+            },
+            deadline,
+        ),
+        ExecuteMsg::CancelQueuedOrder { vamm } => cancel_queued_order(deps, info, vamm),
+        ExecuteMsg::ExecuteQueuedOrder { vamm, trader } => {
+            execute_queued_order(deps, env, info, vamm, trader)
+        }
+        ExecuteMsg::SetTriggerOrder {
+            vamm,
+            trigger_price,
+            order_type,
+            size,
+        } => set_trigger_order(deps, info, vamm, trigger_price, order_type, size),
+        ExecuteMsg::SetTriggerOrderPair {
+            vamm,
+            stop_loss,
+            take_profit,
+            size,
+        } => set_trigger_order_pair(deps, info, vamm, stop_loss, take_profit, size),
+        ExecuteMsg::CancelTriggerOrder { vamm, order_type } => {
+            cancel_trigger_order(deps, info, vamm, order_type)
+        }
+        ExecuteMsg::ExecuteTriggerOrder { vamm, trader } => {
+            execute_trigger_order(deps, env, info, vamm, trader)
+        }
+        ExecuteMsg::MatchTriggerOrders { vamm, long, short } => {
+            match_trigger_orders(deps, env, info, vamm, long, short)
+        }
+        ExecuteMsg::Sweep { asset, amount } => sweep(deps, env, info, asset, amount),
+        ExecuteMsg::Liquidate {
+            vamm,
+            trader,
+            takeover,
+        } => liquidate(deps, env, info, vamm, trader, takeover),
+        ExecuteMsg::UnwindInsurancePosition { vamm, size } => {
+            unwind_insurance_position(deps, env, info, vamm, size)
+        }
+        ExecuteMsg::PayFunding { vamm } => pay_funding(deps, env, info, vamm),
+        ExecuteMsg::RegisterReferrer { code } => register_referrer(deps, info, code),
+        ExecuteMsg::SetReferralCode { code } => set_referral_code(deps, info, code),
+        ExecuteMsg::UpdateReferralShare { referral_share } => {
+            update_referral_share(deps, info, referral_share)
+        }
+        ExecuteMsg::ClaimReferralRewards {} => claim_referral_rewards(deps, info),
+        ExecuteMsg::SetFeeTier {
+            min_stake,
+            discount,
+        } => set_fee_tier(deps, info, min_stake, discount),
+        ExecuteMsg::RemoveFeeTier { min_stake } => remove_fee_tier(deps, info, min_stake),
+        ExecuteMsg::DepositMargin { vamm, amount } => {
+            let trader = info.sender.to_string();
+            deposit_for(deps, env, Some(info), vamm, trader, amount)
+        }
+        ExecuteMsg::WithdrawMargin { vamm, amount } => {
+            withdraw_margin(deps, env, info, vamm, amount)
+        }
+    }?;
+
+    Ok(namespace_attributes(ENGINE_NAMESPACE, response))
 }
 
 pub fn receive_cw20(
@@ -91,92 +549,394 @@ pub fn receive_cw20(
     env: Env,
     info: MessageInfo,
     cw20_msg: Cw20ReceiveMsg,
-) -> StdResult<Response> {
-    // only asset contract can execute this message
+) -> Result<Response, ContractError> {
+    // only asset contract can execute this message, market hooks must be paid
+    // in the collateral backing that market and all others in the eligible
+    // collateral
     let config: Config = read_config(deps.storage)?;
-    if config.eligible_collateral != deps.api.addr_validate(info.sender.as_str())? {
-        return Err(StdError::generic_err("unauthorized"));
+    let hook: StdResult<Cw20HookMsg> = from_binary(&cw20_msg.msg);
+    let collateral = match &hook {
+        Ok(Cw20HookMsg::OpenPosition { vamm, .. })
+        | Ok(Cw20HookMsg::AddMargin { vamm })
+        | Ok(Cw20HookMsg::DepositFor { vamm, .. })
+        | Ok(Cw20HookMsg::ProvideLiquidity { vamm })
+        | Ok(Cw20HookMsg::DepositLiquidityFees { vamm }) => {
+            read_market_collateral(deps.storage, &deps.api.addr_validate(vamm)?)?
+        }
+        // any accepted collateral asset may be deposited to an account
+        Ok(Cw20HookMsg::DepositCollateral {}) => AssetInfo::Token {
+            contract_addr: info.sender.to_string(),
+        },
+        _ => config.eligible_collateral,
+    };
+    if !collateral.is_token(&info.sender) {
+        return Err(ContractError::Unauthorized {});
     }
 
-    match from_binary(&cw20_msg.msg) {
+    match hook {
         Ok(Cw20HookMsg::OpenPosition {
             vamm,
             side,
+            quote_asset_amount,
             leverage,
-        }) => open_position(
+            base_asset_limit,
+            reduce_only,
+            deadline,
+        }) => {
+            require_deadline(&env, deadline)?;
+            require_direct_orders(deps.storage, &deps.api.addr_validate(&vamm)?)?;
+            open_position(
+                deps,
+                env,
+                info,
+                Some(cw20_msg.amount),
+                vamm,
+                cw20_msg.sender,
+                side,
+                quote_asset_amount,
+                leverage,
+                base_asset_limit,
+                reduce_only,
+            )
+        }
+        Ok(Cw20HookMsg::ProposeMarket { market }) => {
+            propose_market(deps, env, cw20_msg.sender, cw20_msg.amount, market)
+        }
+        Ok(Cw20HookMsg::StakeInsurance {}) => {
+            stake_insurance(deps, cw20_msg.sender, cw20_msg.amount)
+        }
+        Ok(Cw20HookMsg::DepositInsuranceFees {}) => deposit_insurance_fees(deps, cw20_msg.amount),
+        Ok(Cw20HookMsg::DepositFees {}) => deposit_fees(deps, cw20_msg.amount),
+        Ok(Cw20HookMsg::AddMargin { vamm }) => {
+            deposit_for(deps, env, None, vamm, cw20_msg.sender, cw20_msg.amount)
+        }
+        Ok(Cw20HookMsg::DepositFor { trader, vamm }) => {
+            deposit_for(deps, env, None, vamm, trader, cw20_msg.amount)
+        }
+        Ok(Cw20HookMsg::ProvideLiquidity { vamm }) => {
+            provide_liquidity(deps, vamm, cw20_msg.sender, cw20_msg.amount)
+        }
+        Ok(Cw20HookMsg::DepositLiquidityFees { vamm }) => {
+            deposit_liquidity_fees(deps, vamm, cw20_msg.amount)
+        }
+        Ok(Cw20HookMsg::DepositCollateral {}) => deposit_collateral(
             deps,
             env,
-            info,
-            vamm,
+            None,
             cw20_msg.sender,
-            side,
-            cw20_msg.amount, // not needed, we should take from deposited amount or validate
-            leverage,
+            collateral,
+            cw20_msg.amount,
         ),
-        Err(_) => Err(StdError::generic_err("invalid cw20 hook message")),
+        Err(_) => Err(StdError::generic_err("invalid cw20 hook message").into()),
+    }
+}
+
+pub fn receive_nft(
+    deps: DepsMut,
+    info: MessageInfo,
+    cw721_msg: Cw721ReceiveMsg,
+) -> Result<Response, ContractError> {
+    // only the position nft contract can execute this message
+    if read_position_nft(deps.storage)? != Some(info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    match from_binary(&cw721_msg.msg) {
+        Ok(Cw721HookMsg::UnwrapPosition {}) => {
+            unwrap_position(deps, cw721_msg.sender, cw721_msg.token_id)
+        }
+        Err(_) => Err(StdError::generic_err("invalid cw721 hook message").into()),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::State {} => to_binary(&query_state(deps)?),
+        QueryMsg::WithdrawalThrottle { asset } => {
+            to_binary(&query_withdrawal_throttle(deps, env, asset)?)
+        }
         QueryMsg::Position { vamm, trader } => to_binary(&query_position(deps, vamm, trader)?),
+        QueryMsg::Positions {
+            vamm,
+            start_after,
+            limit,
+        } => to_binary(&query_positions(deps, vamm, start_after, limit)?),
         QueryMsg::TraderBalance { trader } => {
             to_binary(&query_trader_balance_with_funding_payment(deps, trader)?)
         }
+        QueryMsg::TraderPositions { trader } => to_binary(&query_trader_positions(deps, trader)?),
+        QueryMsg::TradeHistory {
+            trader,
+            start_after,
+            limit,
+        } => to_binary(&query_trade_history(deps, trader, start_after, limit)?),
+        QueryMsg::ListingConfig {} => to_binary(&query_listing_config(deps)?),
+        QueryMsg::MarketProposal { proposal_id } => {
+            to_binary(&query_market_proposal(deps, proposal_id)?)
+        }
+        QueryMsg::FeeCampaigns { start_after, limit } => {
+            to_binary(&query_fee_campaigns(deps, start_after, limit)?)
+        }
+        QueryMsg::CalcFee {
+            vamm,
+            quote_asset_amount,
+        } => to_binary(&query_calc_fee(deps, env, vamm, quote_asset_amount)?),
+        QueryMsg::AccountSettings { trader } => to_binary(&query_account_settings(deps, trader)?),
+        QueryMsg::MarginTiers { vamm } => to_binary(&query_margin_tiers(deps, vamm)?),
+        QueryMsg::MarginRequirement { vamm, notional } => {
+            to_binary(&query_margin_requirement(deps, vamm, notional)?)
+        }
+        QueryMsg::MarginRatio { vamm, trader } => {
+            to_binary(&query_margin_ratio(deps, vamm, trader)?)
+        }
+        QueryMsg::LiquidationPrice { vamm, trader } => {
+            to_binary(&query_liquidation_price(deps, vamm, trader)?)
+        }
+        QueryMsg::FreeCollateral { vamm, trader } => {
+            to_binary(&query_free_collateral(deps, vamm, trader)?)
+        }
+        QueryMsg::PositionNotionalAndUnrealizedPnl {
+            vamm,
+            trader,
+            calc_option,
+        } => to_binary(&query_position_notional_and_unrealized_pnl(
+            deps,
+            vamm,
+            trader,
+            calc_option,
+        )?),
+        QueryMsg::OpenInterest { vamm } => to_binary(&query_open_interest(deps, vamm)?),
+        QueryMsg::OpenInterestImbalance { vamm } => {
+            to_binary(&query_open_interest_imbalance(deps, vamm)?)
+        }
+        QueryMsg::MakerVault { vamm } => to_binary(&query_maker_vault(deps, vamm)?),
+        QueryMsg::Funding { vamm } => to_binary(&query_funding(deps, vamm)?),
+        QueryMsg::FundingRateHistory {
+            vamm,
+            start_after,
+            limit,
+        } => to_binary(&query_funding_rate_history(deps, vamm, start_after, limit)?),
+        QueryMsg::NextFundingTime { vamm } => to_binary(&query_next_funding_time(deps, vamm)?),
+        QueryMsg::FundingPreview { vamm, trader } => {
+            to_binary(&query_funding_preview(deps, env, vamm, trader)?)
+        }
+        QueryMsg::OpenInterestCap {} => to_binary(&query_open_interest_cap(deps)?),
+        QueryMsg::InsuranceFund {} => to_binary(&query_insurance_fund(deps)?),
+        QueryMsg::InsuranceStake { staker } => to_binary(&query_insurance_stake(deps, staker)?),
+        QueryMsg::FeePool {} => to_binary(&query_fee_pool(deps)?),
+        QueryMsg::DailyStats {
+            vamm,
+            start_after,
+            end_day,
+            limit,
+        } => to_binary(&query_daily_stats(deps, vamm, start_after, end_day, limit)?),
+        QueryMsg::PausedOperations {} => to_binary(&query_paused_operations(deps)?),
+        QueryMsg::MarketCollateral { vamm } => to_binary(&query_market_collateral(deps, vamm)?),
+        QueryMsg::CollateralAssets {} => to_binary(&query_collateral_assets(deps)?),
+        QueryMsg::AccountCollateral { trader } => {
+            to_binary(&query_account_collateral(deps, trader)?)
+        }
+        QueryMsg::MarketFees { vamm } => to_binary(&query_market_fees(deps, vamm)?),
+        QueryMsg::MarketCaps { vamm } => to_binary(&query_market_caps(deps, vamm)?),
+        QueryMsg::HoldingCapacity { vamm, trader } => {
+            to_binary(&query_holding_capacity(deps, vamm, trader)?)
+        }
+        QueryMsg::RiskParams { vamm } => to_binary(&query_risk_params(deps, vamm)?),
+        QueryMsg::MarketStatus { vamm } => to_binary(&query_market_status(deps, vamm)?),
+        QueryMsg::Settlement { vamm } => to_binary(&query_settlement(deps, vamm)?),
+        QueryMsg::PositionNft {} => to_binary(&read_position_nft(deps.storage)?),
+        QueryMsg::PositionToken { token_id } => to_binary(&query_position_token(deps, token_id)?),
+        QueryMsg::LiquidityPool { vamm } => to_binary(&query_liquidity_pool(deps, vamm)?),
+        QueryMsg::LiquidityStake { vamm, provider } => {
+            to_binary(&query_liquidity_stake(deps, vamm, provider)?)
+        }
+        QueryMsg::PositionTransfer { vamm, trader } => {
+            to_binary(&query_position_transfer(deps, vamm, trader)?)
+        }
+        QueryMsg::CommitReveal { vamm } => to_binary(&query_commit_reveal(deps, vamm)?),
+        QueryMsg::OrderCommitment { vamm, trader } => {
+            to_binary(&query_order_commitment(deps, vamm, trader)?)
+        }
+        QueryMsg::CommitmentHash {
+            vamm,
+            trader,
+            side,
+            quote_asset_amount,
+            leverage,
+            base_asset_limit,
+            salt,
+        } => to_binary(&query_commitment_hash(
+            deps,
+            vamm,
+            trader,
+            side,
+            quote_asset_amount,
+            leverage,
+            base_asset_limit,
+            salt,
+        )?),
+        QueryMsg::QueuedOrder { vamm, trader } => {
+            to_binary(&query_queued_order(deps, vamm, trader)?)
+        }
+        QueryMsg::TriggerOrders { vamm, trader } => {
+            to_binary(&query_trigger_orders(deps, vamm, trader)?)
+        }
+        QueryMsg::AllVamms { start_after, limit } => {
+            to_binary(&query_all_vamms(deps, start_after, limit)?)
+        }
+        QueryMsg::Liquidators { start_after, limit } => {
+            to_binary(&query_liquidators(deps, start_after, limit)?)
+        }
+        QueryMsg::HoldingCapExemptions { start_after, limit } => {
+            to_binary(&query_holding_cap_exemptions(deps, start_after, limit)?)
+        }
+        QueryMsg::ContractTraders { start_after, limit } => {
+            to_binary(&query_contract_traders(deps, start_after, limit)?)
+        }
+        QueryMsg::TradingPermission { trader, delegate } => {
+            to_binary(&query_trading_permission(deps, trader, delegate)?)
+        }
+        QueryMsg::SigningKey { trader } => to_binary(&query_signing_key(deps, trader)?),
+        QueryMsg::PositionSnapshot { trader } => {
+            to_binary(&query_position_snapshot(deps, env, trader)?)
+        }
+        QueryMsg::ReferralRewards { referrer } => {
+            to_binary(&query_referral_rewards(deps, referrer)?)
+        }
+        QueryMsg::TraderFeeRate { trader } => to_binary(&query_trader_fee_rate(deps, trader)?),
+        QueryMsg::BadDebt { vamm } => to_binary(&query_bad_debt(deps, vamm)?),
+        QueryMsg::SimulateOpenPosition {
+            vamm,
+            trader,
+            side,
+            quote_asset_amount,
+            leverage,
+        } => to_binary(&query_simulate_open_position(
+            deps,
+            env,
+            vamm,
+            trader,
+            side,
+            quote_asset_amount,
+            leverage,
+        )?),
+        QueryMsg::SimulateClosePosition { vamm, trader, size } => to_binary(
+            &query_simulate_close_position(deps, env, vamm, trader, size)?,
+        ),
     }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> StdResult<Response> {
-    match msg.result {
-        ContractResult::Ok(response) => match msg.id {
+pub fn reply(deps: DepsMut, env: Env, msg: Reply) -> Result<Response, ContractError> {
+    let kind = msg.id & ((1 << REPLY_KIND_BITS) - 1);
+    let swap_id = msg.id >> REPLY_KIND_BITS;
+    let response = match msg.result {
+        ContractResult::Ok(response) => match kind {
             SWAP_INCREASE_REPLY_ID => {
-                let (input, output) = parse_swap(response);
-                let response = increase_position_reply(deps, env, input, output)?;
-                Ok(response)
+                let (input, output) = parse_swap(response)?;
+                increase_position_reply(deps, env, swap_id, input, output)
             }
             SWAP_DECREASE_REPLY_ID => {
-                let (input, output) = parse_swap(response);
-                let response = decrease_position_reply(deps, env, input, output)?;
-                Ok(response)
+                let (input, output) = parse_swap(response)?;
+                decrease_position_reply(deps, env, swap_id, input, output)
             }
             SWAP_REVERSE_REPLY_ID => {
-                let (input, output) = parse_swap(response);
-                let response = reverse_position_reply(deps, env, input, output)?;
-                Ok(response)
+                let (input, output) = parse_swap(response)?;
+                reverse_position_reply(deps, env, swap_id, input, output)
+            }
+            SWAP_CLOSE_REPLY_ID => {
+                let (input, output) = parse_swap(response)?;
+                close_position_reply(deps, env, swap_id, input, output)
+            }
+            LIQUIDATION_REPLY_ID => {
+                let (input, output) = parse_swap(response)?;
+                liquidation_reply(deps, env, swap_id, input, output)
             }
-            _ => Err(StdError::generic_err(format!(
-                "reply (id {:?}) invalid",
-                msg.id
-            ))),
+            UNWIND_REPLY_ID => {
+                let (input, output) = parse_swap(response)?;
+                unwind_reply(deps, env, swap_id, input, output)
+            }
+            LIST_MARKET_REPLY_ID => {
+                let vamm = parse_instantiate(response)?;
+                let vamm = deps.api.addr_validate(vamm.as_str())?;
+                list_market_reply(deps, vamm)
+            }
+            _ => Err(ContractError::InvalidReplyId { id: msg.id }),
         },
-        ContractResult::Err(e) => Err(StdError::generic_err(format!(
-            "reply (id {:?}) error {:?}",
-            msg.id, e
-        ))),
+        ContractResult::Err(error) => Err(ContractError::ReplyFailed { id: msg.id, error }),
+    }?;
+
+    #[cfg(feature = "trace")]
+    let response = response.add_event(
+        namespaced_event(ENGINE_NAMESPACE, "trace").add_attribute("path", reply_path(kind)),
+    );
+
+    Ok(namespace_attributes(ENGINE_NAMESPACE, response))
+}
+
+// names the path through the engine a reply belongs to
+#[cfg(feature = "trace")]
+fn reply_path(kind: u64) -> &'static str {
+    match kind {
+        SWAP_INCREASE_REPLY_ID => "increase",
+        SWAP_DECREASE_REPLY_ID => "decrease",
+        SWAP_REVERSE_REPLY_ID => "reverse",
+        SWAP_CLOSE_REPLY_ID => "close",
+        LIQUIDATION_REPLY_ID => "liquidation",
+        UNWIND_REPLY_ID => "unwind",
+        LIST_MARKET_REPLY_ID => "list_market",
+        _ => "unknown",
     }
 }
 
-fn parse_swap(response: SubMsgExecutionResponse) -> (Uint128, Uint128) {
-    // Find swap inputs and output events
-    let wasm = response.events.iter().find(|&e| e.ty == "wasm");
-    let wasm = wasm.unwrap();
-    let input_str = read_event("input".to_string(), wasm).value;
-    let input: Uint128 = Uint128::from_str(&input_str).unwrap();
+pub(crate) fn parse_swap(
+    response: SubMsgExecutionResponse,
+) -> Result<(Uint128, Uint128), ContractError> {
+    // the vamm returns the swap amounts as data, events are only read for
+    // vamms that predate it
+    if let Some(data) = response.data {
+        let swap: SwapResponse = from_binary(&data)?;
+        return Ok((swap.input, swap.output));
+    }
+
+    // other contracts may emit wasm events in the same result, so only
+    // accept the one that carries the swap amounts
+    let wasm = response
+        .events
+        .iter()
+        .filter(|&e| e.ty == "wasm")
+        .find(|&e| e.attributes.iter().any(|attr| attr.key == "output"))
+        .ok_or(ContractError::InvalidSwapResponse {
+            key: "wasm event".to_string(),
+        })?;
+    let input = read_event("input", wasm)?;
+    let output = read_event("output", wasm)?;
 
-    let output_str = read_event("output".to_string(), wasm).value;
-    let output: Uint128 = Uint128::from_str(&output_str).unwrap();
+    Ok((input, output))
+}
 
-    (input, output)
+fn parse_instantiate(response: SubMsgExecutionResponse) -> StdResult<Addr> {
+    // the address attribute is named differently across wasmd versions
+    response
+        .events
+        .iter()
+        .filter(|&e| e.ty == "instantiate")
+        .flat_map(|e| e.attributes.iter())
+        .find(|&attr| attr.key == "_contract_address" || attr.key == "_contract_addr")
+        .map(|attr| Addr::unchecked(attr.value.clone()))
+        .ok_or_else(|| StdError::generic_err("instantiated contract address not found"))
 }
 
-fn read_event(key: String, event: &Event) -> Attribute {
-    let result = event
+fn read_event(key: &str, event: &Event) -> Result<Uint128, ContractError> {
+    let attr = event
         .attributes
         .iter()
         .find(|&attr| attr.key == key)
-        .unwrap();
-    result.clone()
+        .ok_or(ContractError::InvalidSwapResponse {
+            key: key.to_string(),
+        })?;
+
+    Ok(Uint128::from_str(&attr.value)?)
 }
@@ -1,4 +1,5 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{OverflowError, StdError, Timestamp, Uint128};
+use margined_perp::margined_engine::PausableOperation;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,8 +7,95 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
     #[error("Unauthorized")]
     Unauthorized {},
+
+    #[error("vAMM is not registered")]
+    VammNotRegistered {},
+
+    #[error("vAMM is already registered")]
+    VammAlreadyRegistered {},
+
+    #[error("{operation:?} operations are paused")]
+    Paused { operation: PausableOperation },
+
+    #[error("Trader has no open position")]
+    PositionNotFound {},
+
+    #[error("Position would be left with insufficient margin")]
+    InsufficientMargin {},
+
+    #[error("Invalid close size")]
+    InvalidCloseSize {},
+
+    #[error("Base asset amount {amount} exceeds slippage limit {limit}")]
+    BaseAssetLimit { amount: Uint128, limit: Uint128 },
+
+    #[error("Quote asset amount {amount} exceeds slippage limit {limit}")]
+    QuoteAssetLimit { amount: Uint128, limit: Uint128 },
+
+    #[error("Position size exceeds the market maximum of {max_position_size}")]
+    MaxPositionSize { max_position_size: Uint128 },
+
+    #[error("Position notional exceeds the market maximum of {max_position_notional}")]
+    MaxPositionNotional { max_position_notional: Uint128 },
+
+    #[error("Position notional is below the minimum of {min_notional}")]
+    BelowMinNotional { min_notional: Uint128 },
+
+    #[error("Leverage must be at least 1x")]
+    UnderLeverage {},
+
+    #[error("Leverage exceeds the maximum of {max_leverage} for this position size")]
+    OverLeverage { max_leverage: Uint128 },
+
+    #[error("Position notional exceeds the open interest cap of {max_notional}")]
+    OpenInterestCap { max_notional: Uint128 },
+
+    #[error("Total notional exceeds the contract trader cap of {notional_cap}")]
+    ContractTraderCap { notional_cap: Uint128 },
+
+    #[error("Notional exceeds what is left of the trading permission, {notional_cap}")]
+    TradingPermissionCap { notional_cap: Uint128 },
+
+    #[error("Payout exceeds what can leave the engine this period, {remaining}")]
+    WithdrawalThrottled { remaining: Uint128 },
+
+    #[error("Market open interest exceeds its cap of {open_interest_cap}")]
+    MarketOpenInterestCap { open_interest_cap: Uint128 },
+
+    #[error("Reduce only order would increase or flip the position")]
+    ReduceOnly {},
+
+    #[error("Spot price diverges from the index price by more than {max_oracle_spread}")]
+    OracleSpread { max_oracle_spread: Uint128 },
+
+    #[error("Order expired at its deadline of {deadline}")]
+    DeadlineExpired { deadline: Timestamp },
+
+    #[error("Order signature is invalid")]
+    InvalidSignature {},
+
+    #[error("Order nonce must be {nonce}")]
+    InvalidNonce { nonce: u64 },
+
+    #[error("Funds sent must be exactly {expected}")]
+    InvalidFunds { expected: String },
+
+    #[error("No temporary swap")]
+    NoTemporarySwap {},
+
+    #[error("Swap response is missing its {key}")]
+    InvalidSwapResponse { key: String },
+
+    #[error("Reply (id {id}) invalid")]
+    InvalidReplyId { id: u64 },
+
+    #[error("Reply (id {id}) error {error}")]
+    ReplyFailed { id: u64, error: String },
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
 }
@@ -1,31 +1,3154 @@
+use std::cmp::Ordering;
+
 use cosmwasm_std::{
-    to_binary, Addr, CosmosMsg, DepsMut, Env, MessageInfo, ReplyOn, Response, StdError, StdResult,
-    Storage, SubMsg, Uint128, WasmMsg,
+    to_binary, Addr, Attribute, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, ReplyOn,
+    Response, StdError, StdResult, Storage, SubMsg, Timestamp, Uint128, WasmMsg,
+};
+
+use crate::{
+    contract::{
+        swap_reply_id, LIQUIDATION_REPLY_ID, LIST_MARKET_REPLY_ID, SWAP_CLOSE_REPLY_ID,
+        SWAP_DECREASE_REPLY_ID, SWAP_INCREASE_REPLY_ID, SWAP_REVERSE_REPLY_ID, UNWIND_REPLY_ID,
+    },
+    error::ContractError,
+    querier::{
+        query_asset_balance, query_vamm_config, query_vamm_max_swap_output,
+        query_vamm_output_price, query_vamm_spot_price, query_vamm_state,
+    },
+    reply::{charge_fees, execute_transfer, execute_transfer_from, settle_close},
+    state::{
+        decrease_open_interest, delete_fee_campaign, has_position_tokens, is_holding_cap_exempt,
+        is_liquidator, is_vamm, move_position, next_fee_campaign_id, next_market_proposal_id,
+        next_position_token_id, read_account_settings, read_bad_debt, read_code_referrer,
+        read_collateral_asset, read_collateral_balance, read_commit_reveal, read_config,
+        read_contract_trader, read_fee_pool, read_fee_tiers, read_funding, read_insurance_fund,
+        read_insurance_stake, read_liabilities, read_liquidity_pool, read_liquidity_stake,
+        read_listing_config, read_market_caps, read_market_collateral, read_market_proposal,
+        read_market_reserve, read_open_interest, read_open_interest_cap, read_order_commitment,
+        read_position, read_position_nft, read_position_token, read_position_transfer,
+        read_queued_order, read_referral_code, read_referral_rewards, read_settlement,
+        read_trader_referrer, read_trigger_orders, read_vamm_positions, read_wrapped_position,
+        record_trade, remove_contract_trader, remove_maker_vault, remove_order_commitment,
+        remove_position_token, remove_position_transfer, remove_queued_order,
+        remove_referral_rewards, remove_trading_permission, store_account_settings, store_bad_debt,
+        store_collateral_asset, store_collateral_balance, store_commit_reveal, store_config,
+        store_contract_trader, store_fee_campaign, store_fee_pool, store_fee_tiers, store_funding,
+        store_funding_record, store_holding_cap_exemption, store_insurance_fund,
+        store_insurance_stake, store_liquidator, store_liquidity_pool, store_liquidity_stake,
+        store_listing_config, store_maker_vault, store_margin_tiers, store_market_caps,
+        store_market_collateral, store_market_fees, store_market_proposal, store_market_reserve,
+        store_open_interest_cap, store_order_commitment, store_paused_operations, store_position,
+        store_position_nft, store_position_token, store_position_transfer, store_queued_order,
+        store_referral_code, store_referral_share, store_risk_params, store_settlement,
+        store_signing_key, store_tmp_liquidator, store_tmp_listing, store_tmp_swap,
+        store_trader_referrer, store_trading_permission, store_trigger_orders, store_vamm,
+        store_vamm_decimals, AccountSettings, Config, ContractTrader, FeeCampaign, ListingConfig,
+        MakerVault, MarketCaps, MarketFees, MarketProposal, OpenInterestCap, OrderCommitment,
+        Position, PositionToken, QueuedOrder, Swap, TriggerOrders, UnstakeRequest,
+        MAX_REFERRAL_CODE_LENGTH,
+    },
+    throttle::{record_outflow, store_withdrawal_throttle},
+    utils::{
+        apply_funding, calc_fee, calc_settlement, cancel_resting_orders, collateral_shortfall,
+        collateral_value, direction_to_side, free_collateral, from_vamm_decimals, insurance_floor,
+        is_triggered, margin_ratio, margin_requirement, migrate_liquidity, next_premium_fraction,
+        open_positions, order_commitment_hash, pause_level_mask, pause_mask, position_changed,
+        remaining_margin, require_deadline, require_direct_orders,
+        require_eligible_collateral_unused, require_funds, require_not_paused, require_operator,
+        require_oracle_spread, require_unwrapped, require_vamm, risk_params, side_to_direction,
+        to_vamm_decimals, total_notional, traded, validate_market_risk_params,
+        validate_risk_params,
+    },
 };
+use margined_perp::asset::AssetInfo;
+use margined_perp::cw721::{Cw721ExecuteMsg, MintMsg};
+use margined_perp::decimals::{div_d, mul_d};
+use margined_perp::events::{FundingPayment, Liquidation, PositionChange};
+use margined_perp::integer::Integer;
+use margined_perp::margined_engine::{
+    CollateralAsset, CollateralPrice, FeeTier, FundingRecord, InsuranceFloor, MarginTier,
+    PausableOperation, PauseLevel, PnlCalcOption, ProposalStatus, QueuedTrade, RiskParams, Side,
+    TradeKind, TradeRecord, TriggerOrderType, WithdrawalThrottle,
+};
+use margined_perp::margined_vamm::{Direction, ExecuteMsg, InstantiateMsg as VammInstantiateMsg};
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    eligible_collateral: Option<AssetInfo>,
+    initial_margin_ratio: Option<Uint128>,
+    maintenance_margin_ratio: Option<Uint128>,
+    liquidation_fee: Option<Uint128>,
+    min_notional: Option<Uint128>,
+    fee_pool: Option<String>,
+    open_liquidation: Option<bool>,
+    pause_level: Option<PauseLevel>,
+    staking_contract: Option<String>,
+    liquidation_price_source: Option<PnlCalcOption>,
+) -> Result<Response, ContractError> {
+    let mut config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // change the collateral of markets without their own, the fee pool and
+    // the insurance fund, which cannot be done while any of them hold it
+    if let Some(eligible_collateral) = eligible_collateral {
+        eligible_collateral.check(deps.api)?;
+        require_eligible_collateral_unused(deps.storage)?;
+        config.eligible_collateral = eligible_collateral;
+    }
+
+    // change the margin ratios of markets without margin tiers
+    if let Some(initial_margin_ratio) = initial_margin_ratio {
+        config.initial_margin_ratio = initial_margin_ratio;
+    }
+
+    if let Some(maintenance_margin_ratio) = maintenance_margin_ratio {
+        config.maintenance_margin_ratio = maintenance_margin_ratio;
+    }
+
+    // change the share of a liquidated position's notional paid to the liquidator
+    if let Some(liquidation_fee) = liquidation_fee {
+        config.liquidation_fee = liquidation_fee;
+    }
+
+    validate_risk_params(&config)?;
+
+    // change minimum notional of new and increased positions
+    if let Some(min_notional) = min_notional {
+        config.min_notional = min_notional;
+    }
+
+    // change the address trading tolls are sent to
+    if let Some(fee_pool) = fee_pool {
+        config.fee_pool = Some(deps.api.addr_validate(&fee_pool)?);
+    }
+
+    // open liquidation to everyone, or restrict it to the whitelist
+    if let Some(open_liquidation) = open_liquidation {
+        config.open_liquidation = open_liquidation;
+    }
+
+    // change the operations left running while paused, taking effect the
+    // next time the engine is paused
+    if let Some(pause_level) = pause_level {
+        config.pause_level = pause_level;
+    }
+
+    // change the contract queried for the stakes that discount the toll
+    if let Some(staking_contract) = staking_contract {
+        config.staking_contract = Some(deps.api.addr_validate(&staking_contract)?);
+    }
+
+    // change the price positions are valued at when checking whether they can
+    // be liquidated, which the twap or index price keep a single large trade
+    // from moving
+    if let Some(liquidation_price_source) = liquidation_price_source {
+        config.liquidation_price_source = liquidation_price_source;
+    }
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "update_config")]))
+}
+
+pub fn update_listing_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm_code_id: u64,
+    bond: Uint128,
+    challenge_period: u64,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    store_listing_config(
+        deps.storage,
+        &ListingConfig {
+            vamm_code_id,
+            bond,
+            challenge_period,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![("action", "update_listing_config")]))
+}
+
+// Records a proposal to list a new market, the bond has already been
+// transferred to the engine via the cw20 hook
+pub fn propose_market(
+    deps: DepsMut,
+    env: Env,
+    proposer: String,
+    bond: Uint128,
+    market: VammInstantiateMsg,
+) -> Result<Response, ContractError> {
+    let proposer = deps.api.addr_validate(&proposer)?;
+    let listing = read_listing_config(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("market listing is not enabled"))?;
+
+    if bond < listing.bond {
+        return Err(
+            StdError::generic_err(format!("insufficient bond, requires {}", listing.bond)).into(),
+        );
+    }
+
+    let proposal_id = next_market_proposal_id(deps.storage)?;
+    store_market_proposal(
+        deps.storage,
+        &MarketProposal {
+            proposal_id,
+            proposer,
+            bond,
+            market,
+            created_at: env.block.time,
+            status: ProposalStatus::Pending,
+            vamm: None,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "propose_market"),
+        ("proposal_id", &proposal_id.to_string()),
+    ]))
+}
+
+// Rejects a pending proposal during its challenge period, the bond is slashed to the owner
+pub fn veto_market(
+    deps: DepsMut,
+    info: MessageInfo,
+    proposal_id: u64,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut proposal = read_market_proposal(deps.storage, proposal_id)?;
+    if proposal.status != ProposalStatus::Pending {
+        return Err(StdError::generic_err("proposal is not pending").into());
+    }
+
+    proposal.status = ProposalStatus::Vetoed;
+    store_market_proposal(deps.storage, &proposal)?;
+
+    let msg = execute_transfer(&config.eligible_collateral, &config.owner, proposal.bond)?;
+
+    Ok(Response::new().add_submessage(msg).add_attributes(vec![
+        ("action", "veto_market"),
+        ("proposal_id", &proposal_id.to_string()),
+    ]))
+}
+
+// Instantiates the vAMM of a proposal whose challenge period has passed,
+// registration and the bond refund happen in the reply
+pub fn list_market(deps: DepsMut, env: Env, proposal_id: u64) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    let listing = read_listing_config(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("market listing is not enabled"))?;
+
+    let proposal = read_market_proposal(deps.storage, proposal_id)?;
+    if proposal.status != ProposalStatus::Pending {
+        return Err(StdError::generic_err("proposal is not pending").into());
+    }
+
+    if env.block.time < proposal.created_at.plus_seconds(listing.challenge_period) {
+        return Err(StdError::generic_err("challenge period has not ended").into());
+    }
+
+    // the listed vamm only takes swaps from this engine
+    let market = VammInstantiateMsg {
+        engine: Some(env.contract.address.to_string()),
+        ..proposal.market
+    };
+
+    let instantiate_msg = WasmMsg::Instantiate {
+        admin: Some(config.owner.to_string()),
+        code_id: listing.vamm_code_id,
+        msg: to_binary(&market)?,
+        funds: vec![],
+        label: format!("vamm-{}", proposal_id),
+    };
+
+    let msg = SubMsg {
+        msg: CosmosMsg::Wasm(instantiate_msg),
+        gas_limit: None,
+        id: LIST_MARKET_REPLY_ID,
+        reply_on: ReplyOn::Success,
+    };
+
+    store_tmp_listing(deps.storage, proposal_id)?;
+
+    Ok(Response::new().add_submessage(msg).add_attributes(vec![
+        ("action", "list_market"),
+        ("proposal_id", &proposal_id.to_string()),
+    ]))
+}
+
+// Adds a time-boxed fee discount, optionally restricted to a set of vamms
+pub fn add_fee_campaign(
+    deps: DepsMut,
+    info: MessageInfo,
+    start: u64,
+    end: u64,
+    vamms: Option<Vec<String>>,
+    discount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if start >= end {
+        return Err(StdError::generic_err("campaign must end after it starts").into());
+    }
+
+    // the discount is a ratio, i.e. 100% is equal to the decimals
+    if discount > config.decimals {
+        return Err(StdError::generic_err("discount cannot exceed 100%").into());
+    }
+
+    let vamms = match vamms {
+        Some(vamms) => Some(
+            vamms
+                .iter()
+                .map(|vamm| deps.api.addr_validate(vamm))
+                .collect::<StdResult<Vec<Addr>>>()?,
+        ),
+        None => None,
+    };
+
+    let campaign_id = next_fee_campaign_id(deps.storage)?;
+    store_fee_campaign(
+        deps.storage,
+        &FeeCampaign {
+            campaign_id,
+            start,
+            end,
+            vamms,
+            discount,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "add_fee_campaign"),
+        ("campaign_id", &campaign_id.to_string()),
+    ]))
+}
+
+pub fn remove_fee_campaign(
+    deps: DepsMut,
+    info: MessageInfo,
+    campaign_id: u64,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    delete_fee_campaign(deps.storage, campaign_id);
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "remove_fee_campaign"),
+        ("campaign_id", &campaign_id.to_string()),
+    ]))
+}
+
+// Stores the trader's default maximum slippage, applied to any open position
+// that does not specify its own limit
+pub fn update_account_settings(
+    deps: DepsMut,
+    info: MessageInfo,
+    max_slippage: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+
+    // the slippage is a ratio, i.e. 100% is equal to the decimals
+    if let Some(max_slippage) = max_slippage {
+        if max_slippage > config.decimals {
+            return Err(StdError::generic_err("max slippage cannot exceed 100%").into());
+        }
+    }
+
+    let settings = read_account_settings(deps.storage, &info.sender)?;
+    store_account_settings(
+        deps.storage,
+        &info.sender,
+        &AccountSettings {
+            max_slippage,
+            ..settings
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![("action", "update_account_settings")]))
+}
+
+// Switches the sender's account between isolated margin, where each position
+// stands on its own margin, and cross margin, where the margin of all of its
+// positions is pooled. The mode cannot change under open positions
+pub fn set_cross_margin(
+    deps: DepsMut,
+    info: MessageInfo,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    if !open_positions(deps.storage, &info.sender)?.is_empty() {
+        return Err(StdError::generic_err("cannot change margin mode with open positions").into());
+    }
+
+    let mut settings = read_account_settings(deps.storage, &info.sender)?;
+    settings.cross_margin = enabled;
+    store_account_settings(deps.storage, &info.sender, &settings)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_cross_margin"),
+        ("trader", info.sender.as_str()),
+        ("enabled", &enabled.to_string()),
+    ]))
+}
+
+// Sets the size-tiered margin schedule of a vamm, larger positions must
+// carry at least as much margin as smaller ones
+pub fn update_margin_tiers(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    tiers: Vec<MarginTier>,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    for tier in tiers.iter() {
+        if tier.initial_margin_ratio > config.decimals
+            || tier.maintenance_margin_ratio > tier.initial_margin_ratio
+        {
+            return Err(StdError::generic_err(
+                "maintenance margin ratio cannot exceed initial margin ratio or 100%",
+            )
+            .into());
+        }
+    }
+
+    for pair in tiers.windows(2) {
+        if pair[0].notional >= pair[1].notional
+            || pair[0].initial_margin_ratio > pair[1].initial_margin_ratio
+            || pair[0].maintenance_margin_ratio > pair[1].maintenance_margin_ratio
+        {
+            return Err(StdError::generic_err(
+                "margin tiers must be ordered by notional with non-decreasing ratios",
+            )
+            .into());
+        }
+    }
+
+    store_margin_tiers(deps.storage, &vamm, &tiers)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_margin_tiers"),
+        ("vamm", vamm.as_str()),
+    ]))
+}
+
+// Caps the share of a market's open interest any single trader may hold
+pub fn update_open_interest_cap(
+    deps: DepsMut,
+    info: MessageInfo,
+    share: Uint128,
+    floor: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // the share is a ratio, i.e. 100% is equal to the decimals
+    if share > config.decimals {
+        return Err(StdError::generic_err("share cannot exceed 100%").into());
+    }
+
+    store_open_interest_cap(deps.storage, &OpenInterestCap { share, floor })?;
+
+    Ok(Response::new().add_attributes(vec![("action", "update_open_interest_cap")]))
+}
+
+// Replaces the set of paused operations, an empty list resumes everything
+pub fn update_paused_operations(
+    deps: DepsMut,
+    info: MessageInfo,
+    paused: Vec<PausableOperation>,
+) -> Result<Response, ContractError> {
+    require_operator(deps.storage, &info.sender)?;
+
+    let paused = paused
+        .iter()
+        .fold(0u8, |bitmap, op| bitmap | pause_mask(op));
+    store_paused_operations(deps.storage, paused)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "update_paused_operations")]))
+}
+
+// Trips or resets the circuit breaker, pausing every operation the configured
+// pause level does not allow
+pub fn set_pause(
+    deps: DepsMut,
+    info: MessageInfo,
+    paused: bool,
+) -> Result<Response, ContractError> {
+    require_operator(deps.storage, &info.sender)?;
+    let config = read_config(deps.storage)?;
+
+    let bitmap = if paused {
+        pause_level_mask(&config.pause_level)
+    } else {
+        0u8
+    };
+    store_paused_operations(deps.storage, bitmap)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_pause"),
+        ("paused", &paused.to_string()),
+    ]))
+}
+
+// Performs all due maintenance on a vamm in one call: pays funding once it is
+// due, then liquidates positions below their maintenance margin and executes
+// triggered orders, at most max_ops of each. The caller is paid as the keeper
+// of each, and the crank attributes report what was done so keepers know
+// whether to crank again
+pub fn crank(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    max_ops: u32,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    if max_ops == 0 {
+        return Err(StdError::generic_err("max_ops must be greater than zero").into());
+    }
+
+    let mut response = Response::new();
+    let mut merge = |other: Response| {
+        response = std::mem::take(&mut response)
+            .add_submessages(other.messages)
+            .add_attributes(other.attributes)
+            .add_events(other.events);
+    };
+
+    let funding_due = require_not_paused(deps.storage, PausableOperation::Funding).is_ok()
+        && query_vamm_config(deps.as_ref(), vamm.to_string())?
+            .price_feed
+            .is_some()
+        && env.block.time.seconds()
+            >= query_vamm_state(deps.as_ref(), vamm.to_string())?.next_funding_time;
+    if funding_due {
+        merge(pay_funding(
+            deps.branch(),
+            env.clone(),
+            info.clone(),
+            vamm.to_string(),
+        )?);
+    }
+
+    // liquidations are skipped while the caller may not liquidate, and orders
+    // while positions may not be closed
+    let config = read_config(deps.storage)?;
+    let can_liquidate = require_not_paused(deps.storage, PausableOperation::Liquidate).is_ok()
+        && (config.open_liquidation || is_liquidator(deps.storage, &info.sender));
+    let can_close = require_not_paused(deps.storage, PausableOperation::Close).is_ok();
+    let spot_price = query_vamm_spot_price(deps.as_ref(), vamm.to_string())?;
+    let spot_price = from_vamm_decimals(deps.storage, &vamm, spot_price)?;
+
+    let mut liquidations = 0u32;
+    let mut orders_executed = 0u32;
+    for position in read_vamm_positions(deps.storage, &vamm)? {
+        if liquidations == max_ops && orders_executed == max_ops {
+            break;
+        }
+        if position.size.is_zero() || position.trader == env.contract.address {
+            continue;
+        }
+        let trader = position.trader.to_string();
+
+        if can_liquidate
+            && liquidations < max_ops
+            && is_liquidatable(deps.as_ref(), position.clone())?
+        {
+            merge(liquidate(
+                deps.branch(),
+                env.clone(),
+                info.clone(),
+                vamm.to_string(),
+                trader,
+                false,
+            )?);
+            liquidations += 1;
+        } else if can_close
+            && orders_executed < max_ops
+            && read_wrapped_position(deps.storage, &vamm, &position.trader)?.is_none()
+            && has_triggered_order(deps.storage, &position, spot_price)?
+        {
+            merge(execute_trigger_order(
+                deps.branch(),
+                env.clone(),
+                info.clone(),
+                vamm.to_string(),
+                trader,
+            )?);
+            orders_executed += 1;
+        }
+    }
+
+    Ok(response.add_attributes(vec![
+        ("action", "crank".to_string()),
+        ("vamm", vamm.to_string()),
+        ("funding_settled", funding_due.to_string()),
+        ("orders_executed", orders_executed.to_string()),
+        ("liquidations", liquidations.to_string()),
+    ]))
+}
+
+// whether the position is below its maintenance margin ratio once its pending
+// funding is settled, as liquidate checks it
+fn is_liquidatable(deps: Deps, position: Position) -> StdResult<bool> {
+    let position = migrate_liquidity(deps, position)?;
+    let (position, _) = apply_funding(deps.storage, position)?;
+
+    let calc_option = read_config(deps.storage)?.liquidation_price_source;
+    let margin_ratio = margin_ratio(deps, &position.vamm, &position, &calc_option)?;
+    let requirement = margin_requirement(deps.storage, &position.vamm, position.notional)?;
+
+    Ok(margin_ratio < requirement.maintenance_margin_ratio)
+}
+
+pub fn update_insurance_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    unstake_cooldown: u64,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut fund = read_insurance_fund(deps.storage)?;
+    fund.unstake_cooldown = unstake_cooldown;
+    store_insurance_fund(deps.storage, &fund)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "update_insurance_config")]))
+}
+
+// Stakes collateral into the insurance fund, the collateral has already been
+// transferred to the engine via the cw20 hook
+pub fn stake_insurance(
+    deps: DepsMut,
+    staker: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let staker = deps.api.addr_validate(&staker)?;
+    let mut fund = read_insurance_fund(deps.storage)?;
+    let mut stake = read_insurance_stake(deps.storage, &staker)?;
+
+    // settle the stake at the current index before adding to it
+    stake.principal = fund.value_of(&stake).checked_add(amount)?;
+    stake.index = fund.index;
+    stake.epoch = fund.epoch;
+    fund.balance = fund.balance.checked_add(amount)?;
+
+    store_insurance_stake(deps.storage, &staker, &stake)?;
+    store_insurance_fund(deps.storage, &fund)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "stake_insurance"),
+        ("staker", staker.as_str()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Shares fees transferred via the cw20 hook between the insurance stakers
+pub fn deposit_insurance_fees(deps: DepsMut, amount: Uint128) -> Result<Response, ContractError> {
+    let mut fund = read_insurance_fund(deps.storage)?;
+    fund.distribute(amount)?;
+    store_insurance_fund(deps.storage, &fund)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "deposit_insurance_fees"),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Starts the cooldown on withdrawing part of a stake, the amount remains
+// staked and exposed to losses until it is withdrawn
+pub fn request_unstake(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let fund = read_insurance_fund(deps.storage)?;
+    let mut stake = read_insurance_stake(deps.storage, &info.sender)?;
+
+    if amount.is_zero() || amount > fund.value_of(&stake) {
+        return Err(StdError::generic_err("insufficient stake").into());
+    }
+
+    let available_at = env.block.time.plus_seconds(fund.unstake_cooldown);
+    stake.unstake = Some(UnstakeRequest {
+        amount,
+        available_at,
+    });
+    store_insurance_stake(deps.storage, &info.sender, &stake)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "request_unstake"),
+        ("amount", &amount.to_string()),
+        ("available_at", &available_at.seconds().to_string()),
+    ]))
+}
+
+// Withdraws a requested unstake once its cooldown has passed, losses taken
+// during the cooldown reduce what can be withdrawn
+pub fn unstake(deps: DepsMut, env: Env, info: MessageInfo) -> Result<Response, ContractError> {
+    let mut fund = read_insurance_fund(deps.storage)?;
+    let mut stake = read_insurance_stake(deps.storage, &info.sender)?;
+
+    let request = stake
+        .unstake
+        .clone()
+        .ok_or_else(|| StdError::generic_err("no unstake requested"))?;
+    if env.block.time < request.available_at {
+        return Err(StdError::generic_err("unstake cooldown has not ended").into());
+    }
+
+    let value = fund.value_of(&stake);
+    let amount = request.amount.min(value);
+
+    stake.principal = value.checked_sub(amount)?;
+    stake.index = fund.index;
+    stake.epoch = fund.epoch;
+    stake.unstake = None;
+    fund.balance = fund.balance.checked_sub(amount)?;
+
+    store_insurance_stake(deps.storage, &info.sender, &stake)?;
+    store_insurance_fund(deps.storage, &fund)?;
+
+    let mut response = Response::new();
+    if !amount.is_zero() {
+        let config = read_config(deps.storage)?;
+        response = response.add_submessage(execute_transfer(
+            &config.eligible_collateral,
+            &info.sender,
+            amount,
+        )?);
+    }
+
+    Ok(response.add_attributes(vec![("action", "unstake"), ("amount", &amount.to_string())]))
+}
+
+// Deposits liquidity into a market, scaling the reserves of its vamm up by the
+// amount deposited so the market deepens without moving the price
+pub fn provide_liquidity(
+    deps: DepsMut,
+    vamm: String,
+    provider: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let provider = deps.api.addr_validate(&provider)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("liquidity amount must be greater than zero").into());
+    }
+
+    let config = read_config(deps.storage)?;
+    let mut pool = read_liquidity_pool(deps.storage, &vamm)?;
+    let mut stake = read_liquidity_stake(deps.storage, &vamm, &provider)?;
+
+    // settle the stake at the current index before adding to it
+    stake.settle(&pool, config.decimals)?;
+    stake.amount = stake.amount.checked_add(amount)?;
+    pool.total = pool.total.checked_add(amount)?;
+
+    store_liquidity_stake(deps.storage, &vamm, &provider, &stake)?;
+    store_liquidity_pool(deps.storage, &vamm, &pool)?;
+
+    let state = query_vamm_state(deps.as_ref(), vamm.to_string())?;
+    let quote_asset_reserve =
+        state
+            .quote_asset_reserve
+            .checked_add(to_vamm_decimals(deps.storage, &vamm, amount)?)?;
+
+    Ok(Response::new()
+        .add_message(scale_reserves_msg(&vamm, quote_asset_reserve)?)
+        .add_attributes(vec![
+            ("action", "provide_liquidity"),
+            ("vamm", vamm.as_str()),
+            ("provider", provider.as_str()),
+            ("amount", &amount.to_string()),
+        ]))
+}
+
+// Shares fees transferred via the cw20 hook between a market's liquidity providers
+pub fn deposit_liquidity_fees(
+    deps: DepsMut,
+    vamm: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let config = read_config(deps.storage)?;
+
+    let mut pool = read_liquidity_pool(deps.storage, &vamm)?;
+    pool.distribute(amount, config.decimals)?;
+    store_liquidity_pool(deps.storage, &vamm, &pool)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "deposit_liquidity_fees"),
+        ("vamm", vamm.as_str()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Withdraws liquidity from a market together with the fees it has earned,
+// scaling the reserves of its vamm down by the amount withdrawn
+pub fn withdraw_liquidity(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let config = read_config(deps.storage)?;
+    let mut pool = read_liquidity_pool(deps.storage, &vamm)?;
+    let mut stake = read_liquidity_stake(deps.storage, &vamm, &info.sender)?;
+
+    if amount.is_zero() || amount > stake.amount {
+        return Err(StdError::generic_err("insufficient liquidity").into());
+    }
+
+    stake.settle(&pool, config.decimals)?;
+    let payout = amount.checked_add(stake.fees)?;
+    stake.amount = stake.amount.checked_sub(amount)?;
+    stake.fees = Uint128::zero();
+    pool.total = pool.total.checked_sub(amount)?;
+
+    store_liquidity_stake(deps.storage, &vamm, &info.sender, &stake)?;
+    store_liquidity_pool(deps.storage, &vamm, &pool)?;
+
+    let state = query_vamm_state(deps.as_ref(), vamm.to_string())?;
+    let quote_asset_reserve =
+        state
+            .quote_asset_reserve
+            .checked_sub(to_vamm_decimals(deps.storage, &vamm, amount)?)?;
+
+    let collateral = read_market_collateral(deps.storage, &vamm)?;
+
+    Ok(Response::new()
+        .add_message(scale_reserves_msg(&vamm, quote_asset_reserve)?)
+        .add_submessage(execute_transfer(&collateral, &info.sender, payout)?)
+        .add_attributes(vec![
+            ("action", "withdraw_liquidity"),
+            ("vamm", vamm.as_str()),
+            ("amount", &amount.to_string()),
+            ("fees", &payout.checked_sub(amount)?.to_string()),
+        ]))
+}
+
+fn scale_reserves_msg(vamm: &Addr, quote_asset_reserve: Uint128) -> StdResult<WasmMsg> {
+    Ok(WasmMsg::Execute {
+        contract_addr: vamm.to_string(),
+        funds: vec![],
+        msg: to_binary(&ExecuteMsg::ScaleReserves {
+            quote_asset_reserve,
+        })?,
+    })
+}
+
+pub fn update_fee_pool_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    insurance_share: Uint128,
+    insurance_floor: Option<InsuranceFloor>,
+    keeper_reward: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // the share is a ratio, i.e. 100% is equal to the decimals
+    if insurance_share > config.decimals {
+        return Err(StdError::generic_err("insurance share cannot exceed 100%").into());
+    }
+
+    let mut pool = read_fee_pool(deps.storage)?;
+    pool.insurance_share = insurance_share;
+    pool.insurance_floor = insurance_floor;
+    if let Some(keeper_reward) = keeper_reward {
+        pool.keeper_reward = keeper_reward;
+    }
+    store_fee_pool(deps.storage, &pool)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "update_fee_pool_config")]))
+}
+
+// Adds fees transferred via the cw20 hook to the fee pool
+pub fn deposit_fees(deps: DepsMut, amount: Uint128) -> Result<Response, ContractError> {
+    let mut pool = read_fee_pool(deps.storage)?;
+    pool.balance = pool.balance.checked_add(amount)?;
+    store_fee_pool(deps.storage, &pool)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "deposit_fees"),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Empties the fee pool. While the insurance fund is below its floor the fees
+// top up its reserve first, the rest is split between the insurance stakers
+// and the owner
+pub fn distribute_fees(deps: DepsMut) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    let mut pool = read_fee_pool(deps.storage)?;
+    let mut fund = read_insurance_fund(deps.storage)?;
+
+    if pool.balance.is_zero() {
+        return Err(StdError::generic_err("no fees to distribute").into());
+    }
+
+    let floor = insurance_floor(deps.storage, &pool.insurance_floor)?;
+    let top_up = floor.saturating_sub(fund.total()?).min(pool.balance);
+    fund.reserve = fund.reserve.checked_add(top_up)?;
+
+    let remaining = pool.balance.checked_sub(top_up)?;
+    let insurance_fees = remaining.multiply_ratio(pool.insurance_share, config.decimals);
+    if fund.balance.is_zero() {
+        fund.reserve = fund.reserve.checked_add(insurance_fees)?;
+    } else if !insurance_fees.is_zero() {
+        fund.distribute(insurance_fees)?;
+    }
+
+    let treasury_fees = remaining.checked_sub(insurance_fees)?;
+    pool.balance = Uint128::zero();
+
+    store_fee_pool(deps.storage, &pool)?;
+    store_insurance_fund(deps.storage, &fund)?;
+
+    let mut response = Response::new();
+    if !treasury_fees.is_zero() {
+        response = response.add_submessage(execute_transfer(
+            &config.eligible_collateral,
+            &config.owner,
+            treasury_fees,
+        )?);
+    }
+
+    Ok(response.add_attributes(vec![
+        ("action", "distribute_fees"),
+        ("insurance_top_up", &top_up.to_string()),
+        ("insurance_fees", &insurance_fees.to_string()),
+        ("treasury_fees", &treasury_fees.to_string()),
+    ]))
+}
+
+// Pays the keeper reward out of the fee pool to the caller of a
+// permissionless maintenance message, capped by what the pool holds
+fn pay_keeper(storage: &mut dyn Storage, keeper: &Addr) -> StdResult<Option<SubMsg>> {
+    let config = read_config(storage)?;
+    let mut pool = read_fee_pool(storage)?;
+
+    let reward = pool.keeper_reward.min(pool.balance);
+    if reward.is_zero() {
+        return Ok(None);
+    }
+
+    pool.balance = pool.balance.checked_sub(reward)?;
+    store_fee_pool(storage, &pool)?;
+
+    Ok(Some(execute_transfer(
+        &config.eligible_collateral,
+        keeper,
+        reward,
+    )?))
+}
+
+// Proposes the next owner of the engine, who takes over once they accept. A
+// new proposal replaces the last
+pub fn propose_ownership(
+    deps: DepsMut,
+    info: MessageInfo,
+    owner: String,
+) -> Result<Response, ContractError> {
+    let mut config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let owner = deps.api.addr_validate(&owner)?;
+    config.pending_owner = Some(owner.clone());
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "propose_ownership"),
+        ("owner", owner.as_str()),
+    ]))
+}
+
+// Hands the engine to the proposed owner, only they can accept
+pub fn accept_ownership(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let mut config = read_config(deps.storage)?;
+    if config.pending_owner.as_ref() != Some(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    config.owner = info.sender;
+    config.pending_owner = None;
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "accept_ownership"),
+        ("owner", config.owner.as_str()),
+    ]))
+}
+
+// Withdraws the proposal of the next owner
+pub fn cancel_ownership_proposal(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let mut config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    config.pending_owner = None;
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "cancel_ownership_proposal")]))
+}
+
+// Appoints the operator, or hands their powers back to the owner if none
+pub fn set_operator(
+    deps: DepsMut,
+    info: MessageInfo,
+    operator: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    config.operator = operator
+        .map(|operator| deps.api.addr_validate(&operator))
+        .transpose()?;
+    store_config(deps.storage, &config)?;
+
+    let operator = config.operator.map(|operator| operator.to_string());
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_operator"),
+        ("operator", operator.as_deref().unwrap_or("")),
+    ]))
+}
+
+// Sets how far a market's spot price may stray from its index price before
+// opens are rejected, or lifts the check if none
+pub fn set_max_oracle_spread(
+    deps: DepsMut,
+    info: MessageInfo,
+    max_oracle_spread: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let mut config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if let Some(max_oracle_spread) = max_oracle_spread {
+        if max_oracle_spread.is_zero() || max_oracle_spread > config.decimals {
+            return Err(ContractError::Std(StdError::generic_err(
+                "max oracle spread must be above zero and cannot exceed 100%",
+            )));
+        }
+    }
+
+    config.max_oracle_spread = max_oracle_spread;
+    store_config(deps.storage, &config)?;
+
+    let max_oracle_spread = max_oracle_spread.map(|spread| spread.to_string());
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_max_oracle_spread"),
+        (
+            "max_oracle_spread",
+            max_oracle_spread.as_deref().unwrap_or(""),
+        ),
+    ]))
+}
+
+// Accepts the ownership of a vamm proposed to the engine, which it needs to
+// scale the reserves and open or shut down the market
+pub fn accept_vamm_ownership(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let msg = WasmMsg::Execute {
+        contract_addr: vamm.to_string(),
+        funds: vec![],
+        msg: to_binary(&ExecuteMsg::AcceptOwnership {})?,
+    };
+
+    Ok(Response::new().add_message(msg).add_attributes(vec![
+        ("action", "accept_vamm_ownership"),
+        ("vamm", vamm.as_str()),
+    ]))
+}
+
+// Lets the delegate trade for the sender up to the notional cap
+pub fn grant_trading_permission(
+    deps: DepsMut,
+    info: MessageInfo,
+    delegate: String,
+    notional_cap: Uint128,
+) -> Result<Response, ContractError> {
+    let delegate = deps.api.addr_validate(&delegate)?;
+    if delegate == info.sender {
+        return Err(StdError::generic_err("cannot grant a trading permission to yourself").into());
+    }
+
+    store_trading_permission(deps.storage, &info.sender, &delegate, notional_cap)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "grant_trading_permission"),
+        ("trader", info.sender.as_str()),
+        ("delegate", delegate.as_str()),
+        ("notional_cap", &notional_cap.to_string()),
+    ]))
+}
+
+pub fn revoke_trading_permission(
+    deps: DepsMut,
+    info: MessageInfo,
+    delegate: String,
+) -> Result<Response, ContractError> {
+    let delegate = deps.api.addr_validate(&delegate)?;
+    remove_trading_permission(deps.storage, &info.sender, &delegate);
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "revoke_trading_permission"),
+        ("trader", info.sender.as_str()),
+        ("delegate", delegate.as_str()),
+    ]))
+}
+
+// Registers the public key that signs the sender's orders for relayers to
+// submit, or removes it so that no more can be
+pub fn set_signing_key(
+    deps: DepsMut,
+    info: MessageInfo,
+    pubkey: Option<Binary>,
+) -> Result<Response, ContractError> {
+    // secp256k1 keys are 33 bytes compressed or 65 uncompressed
+    if let Some(pubkey) = &pubkey {
+        if pubkey.len() != 33 && pubkey.len() != 65 {
+            return Err(StdError::generic_err("signing key must be a secp256k1 public key").into());
+        }
+    }
+
+    store_signing_key(deps.storage, &info.sender, pubkey.as_ref())?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_signing_key"),
+        ("trader", info.sender.as_str()),
+        (
+            "pubkey",
+            &pubkey.map(|pubkey| pubkey.to_base64()).unwrap_or_default(),
+        ),
+    ]))
+}
+
+// Registers a vAMM so that the engine serves its market
+pub fn add_vamm(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    risk_params: Option<RiskParams>,
+) -> Result<Response, ContractError> {
+    require_operator(deps.storage, &info.sender)?;
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    if is_vamm(deps.storage, &vamm) {
+        return Err(ContractError::VammAlreadyRegistered {});
+    }
+
+    // a market registered without overrides must not inherit those of an
+    // earlier registration
+    let risk_params = risk_params.unwrap_or_default();
+    validate_market_risk_params(&read_config(deps.storage)?, &risk_params)?;
+    store_risk_params(deps.storage, &vamm, &risk_params)?;
+
+    // record the precision of the vamm so amounts can be normalised
+    let vamm_config = query_vamm_config(deps.as_ref(), vamm.to_string())?;
+    store_vamm_decimals(deps.storage, &vamm, vamm_config.decimals)?;
+    store_vamm(deps.storage, &vamm)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "add_vamm"), ("vamm", vamm.as_str())]))
+}
+
+// Deregisters a vAMM, which cannot be done while it has open positions
+pub fn remove_vamm(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+) -> Result<Response, ContractError> {
+    require_operator(deps.storage, &info.sender)?;
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    if !read_open_interest(deps.storage, &vamm)?.is_zero() {
+        return Err(StdError::generic_err("cannot remove a market with open positions").into());
+    }
+
+    crate::state::remove_vamm(deps.storage, &vamm);
+
+    Ok(Response::new().add_attributes(vec![("action", "remove_vamm"), ("vamm", vamm.as_str())]))
+}
+
+pub fn add_liquidator(
+    deps: DepsMut,
+    info: MessageInfo,
+    liquidator: String,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let liquidator = deps.api.addr_validate(&liquidator)?;
+    store_liquidator(deps.storage, &liquidator)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "add_liquidator"),
+        ("liquidator", liquidator.as_str()),
+    ]))
+}
+
+pub fn remove_liquidator(
+    deps: DepsMut,
+    info: MessageInfo,
+    liquidator: String,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let liquidator = deps.api.addr_validate(&liquidator)?;
+    if !is_liquidator(deps.storage, &liquidator) {
+        return Err(StdError::generic_err("address is not a whitelisted liquidator").into());
+    }
+    crate::state::remove_liquidator(deps.storage, &liquidator);
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "remove_liquidator"),
+        ("liquidator", liquidator.as_str()),
+    ]))
+}
+
+pub fn add_holding_cap_exemption(
+    deps: DepsMut,
+    info: MessageInfo,
+    trader: String,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let trader = deps.api.addr_validate(&trader)?;
+    store_holding_cap_exemption(deps.storage, &trader)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "add_holding_cap_exemption"),
+        ("trader", trader.as_str()),
+    ]))
+}
+
+pub fn remove_holding_cap_exemption(
+    deps: DepsMut,
+    info: MessageInfo,
+    trader: String,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let trader = deps.api.addr_validate(&trader)?;
+    if !is_holding_cap_exempt(deps.storage, &trader) {
+        return Err(StdError::generic_err("trader is not exempt from holding caps").into());
+    }
+    crate::state::remove_holding_cap_exemption(deps.storage, &trader);
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "remove_holding_cap_exemption"),
+        ("trader", trader.as_str()),
+    ]))
+}
+
+// Opens a market for trading or closes it, the engine must own the vamm as it
+// does for markets listed through governance
+pub fn set_market_open(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    open: bool,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    Ok(Response::new()
+        .add_submessage(set_vamm_open(&vamm, open)?)
+        .add_attributes(vec![
+            ("action", "set_market_open"),
+            ("vamm", vamm.as_str()),
+            ("open", &open.to_string()),
+        ]))
+}
+
+// Shuts a market down for good through its vamm, which the engine must own
+pub fn shutdown_market(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    let msg = SubMsg::new(WasmMsg::Execute {
+        contract_addr: vamm.to_string(),
+        funds: vec![],
+        msg: to_binary(&ExecuteMsg::Shutdown {})?,
+    });
+
+    Ok(Response::new()
+        .add_submessage(msg)
+        .add_attributes(vec![("action", "shutdown_market"), ("vamm", vamm.as_str())]))
+}
+
+// Pays out the sender's position in a shut down market at the settlement
+// price, scaled down pro-rata if the market's margin cannot cover every
+// position
+pub fn settle_position(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = info.sender;
+    require_vamm(deps.storage, &vamm)?;
+    require_unwrapped(deps.storage, &vamm, &trader)?;
+
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => migrate_liquidity(deps.as_ref(), position)?,
+        _ => return Err(ContractError::PositionNotFound {}),
+    };
+    let (position, funding) = settle_funding(deps.storage, position)?;
+
+    let settlement = match read_settlement(deps.storage, &vamm)? {
+        Some(settlement) => settlement,
+        None => {
+            let settlement = calc_settlement(deps.as_ref(), &vamm)?;
+            store_settlement(deps.storage, &vamm, &settlement)?;
+            settlement
+        }
+    };
+
+    let config = read_config(deps.storage)?;
+    let notional = position
+        .size
+        .multiply_ratio(settlement.settlement_price, config.decimals);
+    let (margin, _) = remaining_margin(&position, notional);
+    let payout = margin.multiply_ratio(settlement.payout_ratio, config.decimals);
+
+    decrease_open_interest(deps.storage, &vamm, position.notional)?;
+    cancel_resting_orders(deps.storage, &vamm, &trader);
+    store_position(deps.storage, &clear_position(env, position)?)?;
+
+    let mut response = Response::new().add_attributes(vec![
+        ("action", "settle_position"),
+        ("vamm", vamm.as_str()),
+        ("trader", trader.as_str()),
+        ("payout", &payout.to_string()),
+    ]);
+    if !payout.is_zero() {
+        let collateral = read_market_collateral(deps.storage, &vamm)?;
+        response = response.add_submessage(execute_transfer(&collateral, &trader, payout)?);
+    }
+
+    Ok(response.add_attributes(funding))
+}
+
+// Sets the cw20 token that backs a market, margin for its positions is taken
+// in and paid out of that token. It cannot change while positions are open
+pub fn update_market_collateral(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    collateral: AssetInfo,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    collateral.check(deps.api)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    if !read_open_interest(deps.storage, &vamm)?.is_zero() {
+        return Err(StdError::generic_err(
+            "cannot change the collateral of a market with open positions",
+        )
+        .into());
+    }
+
+    store_market_collateral(deps.storage, &vamm, &collateral)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_market_collateral"),
+        ("vamm", vamm.as_str()),
+        ("collateral", &collateral.to_string()),
+    ]))
+}
+
+// Accepts an asset as account collateral or changes its weight, the weight is
+// the share of the asset's value that counts toward margin
+pub fn update_collateral_asset(
+    deps: DepsMut,
+    info: MessageInfo,
+    asset: AssetInfo,
+    weight: Uint128,
+    price: Option<CollateralPrice>,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    asset.check(deps.api)?;
+    if let Some(price) = &price {
+        deps.api.addr_validate(&price.pricefeed)?;
+    }
+
+    if weight > config.decimals {
+        return Err(StdError::generic_err("collateral weight cannot exceed one").into());
+    }
+
+    store_collateral_asset(
+        deps.storage,
+        &CollateralAsset {
+            asset: asset.clone(),
+            weight,
+            price,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_collateral_asset"),
+        ("asset", &asset.to_string()),
+        ("weight", &weight.to_string()),
+    ]))
+}
+
+// Stops accepting an asset as collateral, balances already deposited can still
+// be withdrawn but no longer count toward margin
+pub fn remove_collateral_asset(
+    deps: DepsMut,
+    info: MessageInfo,
+    asset: AssetInfo,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if read_collateral_asset(deps.storage, &asset)?.is_none() {
+        return Err(StdError::generic_err("asset is not accepted as collateral").into());
+    }
+
+    crate::state::remove_collateral_asset(deps.storage, &asset);
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "remove_collateral_asset"),
+        ("asset", &asset.to_string()),
+    ]))
+}
+
+// Deposits collateral against a trader's account, backing all of its positions.
+// Funds already sent through the cw20 hook have no depositor, otherwise they
+// are pulled from the depositor's allowance or sent along with the message
+pub fn deposit_collateral(
+    deps: DepsMut,
+    env: Env,
+    depositor: Option<MessageInfo>,
+    trader: String,
+    asset: AssetInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let trader = deps.api.addr_validate(&trader)?;
+    require_not_paused(deps.storage, PausableOperation::AddMargin)?;
+
+    if read_collateral_asset(deps.storage, &asset)?.is_none() {
+        return Err(StdError::generic_err("asset is not accepted as collateral").into());
+    }
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("deposit amount must be greater than zero").into());
+    }
+
+    let mut balance = read_collateral_balance(deps.storage, &trader, &asset)?;
+    balance.amount = balance.amount.checked_add(amount)?;
+    store_collateral_balance(deps.storage, &trader, &balance)?;
+
+    let mut response = Response::new();
+    if let Some(depositor) = depositor {
+        require_funds(&depositor, &asset, amount)?;
+        if !asset.is_native_token() {
+            response = response.add_submessage(execute_transfer_from(
+                &asset,
+                &depositor.sender,
+                &env.contract.address,
+                amount,
+            )?);
+        }
+    }
+
+    Ok(response.add_attributes(vec![
+        ("action", "deposit_collateral"),
+        ("trader", trader.as_str()),
+        ("asset", &asset.to_string()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Withdraws collateral from the sender's account, what remains must make up
+// any shortfall of its positions below their initial margin
+pub fn withdraw_collateral(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    asset: AssetInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let trader = info.sender;
+    require_not_paused(deps.storage, PausableOperation::RemoveMargin)?;
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("withdrawal amount must be greater than zero").into());
+    }
+
+    let mut balance = read_collateral_balance(deps.storage, &trader, &asset)?;
+    if amount > balance.amount {
+        return Err(StdError::generic_err("insufficient collateral balance").into());
+    }
+    balance.amount = balance.amount.checked_sub(amount)?;
+    store_collateral_balance(deps.storage, &trader, &balance)?;
+
+    if collateral_value(deps.as_ref(), &trader)? < collateral_shortfall(deps.as_ref(), &trader)? {
+        return Err(ContractError::InsufficientMargin {});
+    }
+    record_outflow(deps.storage, &env, &asset, amount)?;
+
+    let msg = execute_transfer(&asset, &trader, amount)?;
+
+    Ok(Response::new().add_submessage(msg).add_attributes(vec![
+        ("action", "withdraw_collateral"),
+        ("trader", trader.as_str()),
+        ("asset", &asset.to_string()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Overrides the toll and spread ratios of a market, a ratio left as None
+// falls back to the one set on the vamm
+pub fn update_market_fees(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    toll_ratio: Option<Uint128>,
+    spread_ratio: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    // the ratios are in the engine's decimals, i.e. 100% is equal to the decimals
+    if [toll_ratio, spread_ratio]
+        .iter()
+        .flatten()
+        .any(|ratio| *ratio > config.decimals)
+    {
+        return Err(StdError::generic_err("fee ratio cannot exceed 100%").into());
+    }
+
+    store_market_fees(
+        deps.storage,
+        &vamm,
+        &MarketFees {
+            toll_ratio,
+            spread_ratio,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_market_fees"),
+        ("vamm", vamm.as_str()),
+    ]))
+}
+
+// Sets the open interest, position size and position notional caps of a
+// market, protecting a young market from being dominated by a single trader
+pub fn update_market_caps(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    open_interest_notional_cap: Option<Uint128>,
+    max_position_size: Option<Uint128>,
+    max_position_notional: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    store_market_caps(
+        deps.storage,
+        &vamm,
+        &MarketCaps {
+            open_interest_notional_cap,
+            max_position_size,
+            max_position_notional,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_market_caps"),
+        ("vamm", vamm.as_str()),
+    ]))
+}
+
+// Overrides the config's risk parameters in a market, letting volatile
+// markets be held to stricter margins
+pub fn update_risk_params(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    risk_params: RiskParams,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    validate_market_risk_params(&config, &risk_params)?;
+    store_risk_params(deps.storage, &vamm, &risk_params)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_risk_params"),
+        ("vamm", vamm.as_str()),
+    ]))
+}
+
+// Limits the collateral that can be paid out to traders in each period,
+// without a throttle payouts are only limited by what traders hold
+pub fn update_withdrawal_throttle(
+    deps: DepsMut,
+    info: MessageInfo,
+    throttle: Option<WithdrawalThrottle>,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // the limit is a ratio, i.e. 100% is equal to the decimals
+    if let Some(throttle) = &throttle {
+        if throttle.limit_ratio > config.decimals {
+            return Err(StdError::generic_err("limit ratio cannot exceed 100%").into());
+        }
+    }
+
+    store_withdrawal_throttle(deps.storage, throttle.as_ref())?;
+
+    Ok(Response::new().add_attribute("action", "update_withdrawal_throttle"))
+}
+
+// Recovers assets sent to the engine by mistake, only what it holds above the
+// deposits, margin, funds and reserves it owes in the asset can be swept
+pub fn sweep(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    asset: AssetInfo,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    asset.check(deps.api)?;
+
+    let balance = query_asset_balance(deps.as_ref(), &asset, &env.contract.address)?;
+    let liabilities = read_liabilities(deps.storage, &asset)?;
+    let excess = balance.saturating_sub(liabilities);
+    if amount > excess {
+        return Err(StdError::generic_err(format!(
+            "cannot sweep more than the {} held above liabilities",
+            excess
+        ))
+        .into());
+    }
+
+    let msg = execute_transfer(&asset, &config.owner, amount)?;
+
+    Ok(Response::new().add_submessage(msg).add_attributes(vec![
+        ("action", "sweep"),
+        ("asset", &asset.to_string()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Sets the cw721 contract positions can be wrapped as tokens on, it cannot be
+// changed whilst any positions are wrapped
+pub fn update_position_nft(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if has_position_tokens(deps.storage) {
+        return Err(StdError::generic_err(
+            "cannot change the position nft whilst positions are wrapped",
+        )
+        .into());
+    }
+
+    let contract = deps.api.addr_validate(&contract)?;
+    store_position_nft(deps.storage, &contract)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_position_nft"),
+        ("contract", contract.as_str()),
+    ]))
+}
+
+// Mints a token representing the sender's position, the position can then
+// only be traded by whoever unwraps the token
+pub fn wrap_position(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = info.sender;
+    let nft = match read_position_nft(deps.storage)? {
+        Some(nft) => nft,
+        None => return Err(StdError::generic_err("position tokens are not enabled").into()),
+    };
+
+    match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => {}
+        _ => return Err(ContractError::PositionNotFound {}),
+    }
+    require_unwrapped(deps.storage, &vamm, &trader)?;
+
+    let token_id = next_position_token_id(deps.storage)?.to_string();
+    store_position_token(
+        deps.storage,
+        &token_id,
+        &PositionToken {
+            vamm: vamm.clone(),
+            trader: trader.clone(),
+        },
+    )?;
+
+    let mint_msg = WasmMsg::Execute {
+        contract_addr: nft.to_string(),
+        funds: vec![],
+        msg: to_binary(&Cw721ExecuteMsg::Mint(MintMsg {
+            token_id: token_id.clone(),
+            owner: trader.to_string(),
+            token_uri: None,
+            extension: None,
+        }))?,
+    };
+
+    Ok(Response::new().add_message(mint_msg).add_attributes(vec![
+        ("action", "wrap_position"),
+        ("vamm", vamm.as_str()),
+        ("trader", trader.as_str()),
+        ("token_id", &token_id),
+    ]))
+}
+
+// Burns a position token sent to the engine and hands the position to the
+// account that sent it, which must not hold a position in the market
+pub fn unwrap_position(
+    deps: DepsMut,
+    holder: String,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let holder = deps.api.addr_validate(&holder)?;
+    let token = match read_position_token(deps.storage, &token_id)? {
+        Some(token) => token,
+        None => return Err(StdError::generic_err("unknown position token").into()),
+    };
+
+    // funding accrued under the previous owner is settled before the move, and
+    // the orders they set against the position are cancelled with it
+    let mut funding = vec![];
+    if holder != token.trader {
+        if let Some(position) = read_position(deps.storage, &token.vamm, &token.trader)? {
+            let position = migrate_liquidity(deps.as_ref(), position)?;
+            funding = settle_funding(deps.storage, position)?.1;
+        }
+        move_position(deps.storage, &token.vamm, &token.trader, &holder)?;
+        cancel_resting_orders(deps.storage, &token.vamm, &token.trader);
+    }
+    remove_position_token(deps.storage, &token_id, &token);
+
+    let nft = read_position_nft(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("position tokens are not enabled"))?;
+    let burn_msg = WasmMsg::Execute {
+        contract_addr: nft.to_string(),
+        funds: vec![],
+        msg: to_binary(&Cw721ExecuteMsg::Burn {
+            token_id: token_id.clone(),
+        })?,
+    };
+
+    Ok(Response::new()
+        .add_message(burn_msg)
+        .add_attributes(vec![
+            ("action", "unwrap_position"),
+            ("vamm", token.vamm.as_str()),
+            ("trader", holder.as_str()),
+            ("token_id", &token_id),
+        ])
+        .add_attributes(funding))
+}
+
+// Offers the sender's position to another address, which takes it over once
+// it accepts. A later offer replaces an earlier one
+pub fn transfer_position(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    to: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let to = deps.api.addr_validate(&to)?;
+    let trader = info.sender;
+
+    if to == trader {
+        return Err(StdError::generic_err("cannot transfer a position to yourself").into());
+    }
+
+    match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => {}
+        _ => return Err(ContractError::PositionNotFound {}),
+    }
+    require_unwrapped(deps.storage, &vamm, &trader)?;
+
+    store_position_transfer(deps.storage, &vamm, &trader, &to)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "transfer_position"),
+        ("vamm", vamm.as_str()),
+        ("from", trader.as_str()),
+        ("to", to.as_str()),
+    ]))
+}
+
+pub fn cancel_position_transfer(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    if read_position_transfer(deps.storage, &vamm, &info.sender)?.is_none() {
+        return Err(StdError::generic_err("no position transfer to cancel").into());
+    }
+    remove_position_transfer(deps.storage, &vamm, &info.sender);
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "cancel_position_transfer"),
+        ("vamm", vamm.as_str()),
+        ("from", info.sender.as_str()),
+    ]))
+}
+
+// Takes over a position offered to the sender, the position must meet the
+// initial margin ratio once its funding is settled and fit within the sender's
+// contract trader cap. The orders resting on it are cancelled
+pub fn accept_position(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    from: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let from = deps.api.addr_validate(&from)?;
+    let to = info.sender;
+
+    if read_position_transfer(deps.storage, &vamm, &from)? != Some(to.clone()) {
+        return Err(StdError::generic_err("no position transfer to accept").into());
+    }
+    require_unwrapped(deps.storage, &vamm, &from)?;
+    require_not_paused(deps.storage, PausableOperation::Open)?;
+
+    let position = match read_position(deps.storage, &vamm, &from)? {
+        Some(position) if !position.size.is_zero() => migrate_liquidity(deps.as_ref(), position)?,
+        _ => return Err(StdError::generic_err("trader has no open position").into()),
+    };
+    let (position, funding) = settle_funding(deps.storage, position)?;
+
+    // the position is valued as it would be for liquidation, with its losses
+    let config = read_config(deps.storage)?;
+    let requirement = margin_requirement(deps.storage, &vamm, position.notional)?;
+    let margin_ratio = margin_ratio(
+        deps.as_ref(),
+        &vamm,
+        &position,
+        &config.liquidation_price_source,
+    )?;
+    if margin_ratio < requirement.initial_margin_ratio {
+        return Err(StdError::generic_err("position is below the initial margin ratio").into());
+    }
+
+    if let Some(ContractTrader {
+        notional_cap: Some(notional_cap),
+    }) = read_contract_trader(deps.storage, &to)?
+    {
+        let total_notional = total_notional(deps.storage, &to)?.checked_add(position.notional)?;
+        if total_notional > notional_cap {
+            return Err(ContractError::ContractTraderCap { notional_cap });
+        }
+    }
+
+    // the sender's orders were set against their own position
+    move_position(deps.storage, &vamm, &from, &to)?;
+    remove_position_transfer(deps.storage, &vamm, &from);
+    cancel_resting_orders(deps.storage, &vamm, &from);
+
+    Ok(Response::new()
+        .add_attributes(vec![
+            ("action", "accept_position"),
+            ("vamm", vamm.as_str()),
+            ("from", from.as_str()),
+            ("to", to.as_str()),
+        ])
+        .add_attributes(funding))
+}
+
+// Enables commit-reveal ordering on a market, commitments can be revealed
+// for up to expiry_blocks after the block they were made in. None disables it
+pub fn update_commit_reveal(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    expiry_blocks: Option<u64>,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    if expiry_blocks == Some(0) {
+        return Err(StdError::generic_err("expiry must be at least one block").into());
+    }
+
+    store_commit_reveal(deps.storage, &vamm, expiry_blocks)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_commit_reveal"),
+        ("vamm", vamm.as_str()),
+    ]))
+}
+
+// Records the hash of an order to be revealed in a later block, replacing
+// any earlier commitment on the market
+pub fn commit_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    commitment: Binary,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+    require_not_paused(deps.storage, PausableOperation::Open)?;
+
+    if read_commit_reveal(deps.storage, &vamm)?.is_none() {
+        return Err(StdError::generic_err("commit-reveal is not enabled on this market").into());
+    }
+
+    store_order_commitment(
+        deps.storage,
+        &vamm,
+        &info.sender,
+        &OrderCommitment {
+            commitment,
+            height: env.block.height,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![("action", "commit_order"), ("vamm", vamm.as_str())]))
+}
+
+// Opens the committed order at the current price, the order must match the
+// commitment and be revealed after the commitment block but before it expires
+#[allow(clippy::too_many_arguments)]
+pub fn reveal_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    side: Side,
+    quote_asset_amount: Uint128,
+    leverage: Uint128,
+    base_asset_limit: Option<Uint128>,
+    salt: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    let expiry_blocks = read_commit_reveal(deps.storage, &vamm)?
+        .ok_or_else(|| StdError::generic_err("commit-reveal is not enabled on this market"))?;
+    let commitment = read_order_commitment(deps.storage, &vamm, &info.sender)?
+        .ok_or_else(|| StdError::generic_err("no order commitment"))?;
+
+    if env.block.height <= commitment.height {
+        return Err(StdError::generic_err(
+            "order cannot be revealed in the block it was committed",
+        )
+        .into());
+    }
+    if env.block.height > commitment.height + expiry_blocks {
+        return Err(StdError::generic_err("order commitment has expired").into());
+    }
+
+    let hash = order_commitment_hash(
+        &vamm,
+        &info.sender,
+        &side,
+        quote_asset_amount,
+        leverage,
+        base_asset_limit,
+        &salt,
+    );
+    if hash != commitment.commitment {
+        return Err(StdError::generic_err("order does not match commitment").into());
+    }
+
+    remove_order_commitment(deps.storage, &vamm, &info.sender);
+
+    let trader = info.sender.to_string();
+    open_position(
+        deps,
+        env,
+        info,
+        None,
+        vamm.to_string(),
+        trader,
+        side,
+        quote_asset_amount,
+        leverage,
+        base_asset_limit,
+        false,
+    )
+}
+
+// Registers a smart contract as a trader, with an optional cap on the total
+// notional it may hold across all markets
+pub fn register_contract_trader(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+    notional_cap: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let contract = deps.api.addr_validate(&contract)?;
+    store_contract_trader(deps.storage, &contract, &ContractTrader { notional_cap })?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "register_contract_trader"),
+        ("contract", contract.as_str()),
+    ]))
+}
+
+pub fn deregister_contract_trader(
+    deps: DepsMut,
+    info: MessageInfo,
+    contract: String,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let contract = deps.api.addr_validate(&contract)?;
+    if read_contract_trader(deps.storage, &contract)?.is_none() {
+        return Err(StdError::generic_err("contract is not a registered trader").into());
+    }
+    remove_contract_trader(deps.storage, &contract);
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "deregister_contract_trader"),
+        ("contract", contract.as_str()),
+    ]))
+}
+
+// Records an order to be filled by a keeper in a later block, so that it
+// cannot be sandwiched within the block it was submitted in. Opens in native
+// collateral markets cannot be queued, their margin must be sent with the
+// order that opens them
+pub fn queue_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    trade: QueuedTrade,
+    deadline: Option<Timestamp>,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+    require_deadline(&env, deadline)?;
+
+    match trade {
+        QueuedTrade::Open { .. } => {
+            require_direct_orders(deps.storage, &vamm)?;
+            require_not_paused(deps.storage, PausableOperation::Open)?;
+            if read_market_collateral(deps.storage, &vamm)?.is_native_token() {
+                return Err(StdError::generic_err(
+                    "orders cannot be queued in a native collateral market",
+                )
+                .into());
+            }
+        }
+        QueuedTrade::Close { .. } => {
+            require_not_paused(deps.storage, PausableOperation::Close)?;
+            match read_position(deps.storage, &vamm, &info.sender)? {
+                Some(position) if !position.size.is_zero() => {}
+                _ => return Err(ContractError::PositionNotFound {}),
+            }
+        }
+    }
+
+    if read_queued_order(deps.storage, &vamm, &info.sender)?.is_some() {
+        return Err(StdError::generic_err("an order is already queued").into());
+    }
+
+    store_queued_order(
+        deps.storage,
+        &vamm,
+        &info.sender,
+        &QueuedOrder {
+            trade,
+            height: env.block.height,
+            deadline,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "queue_order"),
+        ("vamm", vamm.as_str()),
+        ("trader", info.sender.as_str()),
+    ]))
+}
+
+pub fn cancel_queued_order(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    if read_queued_order(deps.storage, &vamm, &info.sender)?.is_none() {
+        return Err(StdError::generic_err("no queued order").into());
+    }
+
+    remove_queued_order(deps.storage, &vamm, &info.sender);
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "cancel_queued_order"),
+        ("vamm", vamm.as_str()),
+    ]))
+}
+
+// Fills a queued order at the current price, callable by anyone from the
+// block after it was queued until its deadline. The trader's slippage bounds
+// still apply
+pub fn execute_queued_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    trader: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let order = read_queued_order(deps.storage, &vamm, &trader)?
+        .ok_or_else(|| StdError::generic_err("no queued order"))?;
+    if env.block.height <= order.height {
+        return Err(
+            StdError::generic_err("order cannot be filled in the block it was queued").into(),
+        );
+    }
+
+    require_deadline(&env, order.deadline)?;
+
+    remove_queued_order(deps.storage, &vamm, &trader);
+
+    match order.trade {
+        QueuedTrade::Open {
+            side,
+            quote_asset_amount,
+            leverage,
+            base_asset_limit,
+            reduce_only,
+        } => open_position(
+            deps,
+            env,
+            info,
+            None,
+            vamm.to_string(),
+            trader.to_string(),
+            side,
+            quote_asset_amount,
+            leverage,
+            base_asset_limit,
+            reduce_only,
+        ),
+        QueuedTrade::Close {
+            size,
+            quote_asset_limit,
+        } => close_position(
+            deps,
+            env,
+            info,
+            vamm.to_string(),
+            trader.to_string(),
+            size,
+            quote_asset_limit,
+            false,
+            SWAP_CLOSE_REPLY_ID,
+        ),
+    }
+}
+
+// Sets the price at which a keeper may close the size of the trader's
+// position, the whole of it if none, replacing any order of the same type and
+// unlinking it from a pair. Orders that would already be triggered at the spot
+// price are rejected
+pub fn set_trigger_order(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    trigger_price: Uint128,
+    order_type: TriggerOrderType,
+    size: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+    require_trigger_order(
+        deps.as_ref(),
+        &vamm,
+        &info.sender,
+        &order_type,
+        trigger_price,
+        size,
+    )?;
+
+    let mut orders = read_trigger_orders(deps.storage, &vamm, &info.sender)?;
+    match order_type {
+        TriggerOrderType::StopLoss => {
+            orders.stop_loss = Some(trigger_price);
+            orders.stop_loss_size = size;
+        }
+        TriggerOrderType::TakeProfit => {
+            orders.take_profit = Some(trigger_price);
+            orders.take_profit_size = size;
+        }
+    }
+    orders.linked = false;
+    store_trigger_orders(deps.storage, &vamm, &info.sender, &orders)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_trigger_order"),
+        ("vamm", vamm.as_str()),
+        ("trader", info.sender.as_str()),
+        ("trigger_price", &trigger_price.to_string()),
+    ]))
+}
+
+// Sets a stop loss and take profit on the same size as a pair, replacing any
+// orders there were, so that executing either cancels the other
+pub fn set_trigger_order_pair(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    stop_loss: Uint128,
+    take_profit: Uint128,
+    size: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+    require_trigger_order(
+        deps.as_ref(),
+        &vamm,
+        &info.sender,
+        &TriggerOrderType::StopLoss,
+        stop_loss,
+        size,
+    )?;
+    require_trigger_order(
+        deps.as_ref(),
+        &vamm,
+        &info.sender,
+        &TriggerOrderType::TakeProfit,
+        take_profit,
+        size,
+    )?;
+
+    store_trigger_orders(
+        deps.storage,
+        &vamm,
+        &info.sender,
+        &TriggerOrders {
+            stop_loss: Some(stop_loss),
+            take_profit: Some(take_profit),
+            stop_loss_size: size,
+            take_profit_size: size,
+            linked: true,
+        },
+    )?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_trigger_order_pair"),
+        ("vamm", vamm.as_str()),
+        ("trader", info.sender.as_str()),
+        ("stop_loss", &stop_loss.to_string()),
+        ("take_profit", &take_profit.to_string()),
+    ]))
+}
+
+// Errors unless the trader has a position the order could close the size of
+// and the spot price has not already reached its trigger price
+fn require_trigger_order(
+    deps: Deps,
+    vamm: &Addr,
+    trader: &Addr,
+    order_type: &TriggerOrderType,
+    trigger_price: Uint128,
+    size: Option<Uint128>,
+) -> Result<(), ContractError> {
+    let position = match read_position(deps.storage, vamm, trader)? {
+        Some(position) if !position.size.is_zero() => position,
+        _ => return Err(ContractError::PositionNotFound {}),
+    };
+    if let Some(size) = size {
+        if size.is_zero() || size > position.size {
+            return Err(ContractError::InvalidCloseSize {});
+        }
+    }
+
+    let spot_price = query_vamm_spot_price(deps, vamm.to_string())?;
+    let spot_price = from_vamm_decimals(deps.storage, vamm, spot_price)?;
+    if trigger_price.is_zero()
+        || is_triggered(&position.direction, order_type, trigger_price, spot_price)
+    {
+        return Err(StdError::generic_err("trigger price would execute immediately").into());
+    }
+
+    Ok(())
+}
+
+pub fn cancel_trigger_order(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    order_type: TriggerOrderType,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    let mut orders = read_trigger_orders(deps.storage, &vamm, &info.sender)?;
+    let order = match order_type {
+        TriggerOrderType::StopLoss => {
+            orders.stop_loss_size = None;
+            orders.stop_loss.take()
+        }
+        TriggerOrderType::TakeProfit => {
+            orders.take_profit_size = None;
+            orders.take_profit.take()
+        }
+    };
+    if order.is_none() {
+        return Err(StdError::generic_err("no trigger order").into());
+    }
+    orders.linked = false;
+    store_trigger_orders(deps.storage, &vamm, &info.sender, &orders)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "cancel_trigger_order"),
+        ("vamm", vamm.as_str()),
+    ]))
+}
+
+// Closes the size of a trader's position an order was set for once the spot
+// price has reached its trigger price, callable by anyone
+pub fn execute_trigger_order(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    trader: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let spot_price = query_vamm_spot_price(deps.as_ref(), vamm.to_string())?;
+    let spot_price = from_vamm_decimals(deps.storage, &vamm, spot_price)?;
+    let (_, size) = take_triggered_order(deps.storage, &vamm, &trader, spot_price)?;
+    let reward = pay_keeper(deps.storage, &info.sender)?;
+
+    let response = close_position(
+        deps,
+        env,
+        info,
+        vamm.to_string(),
+        trader.to_string(),
+        Some(size),
+        None,
+        false,
+        SWAP_CLOSE_REPLY_ID,
+    )?;
+
+    Ok(response.add_submessages(reward))
+}
+
+// Fills the triggered orders of a long and a short against each other at the
+// spot price, without moving the vamm or charging its fees, and closes what
+// is left of the larger on the vamm. Callable by anyone
+pub fn match_trigger_orders(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    long: String,
+    short: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let long = deps.api.addr_validate(&long)?;
+    let short = deps.api.addr_validate(&short)?;
+    require_vamm(deps.storage, &vamm)?;
+    require_not_paused(deps.storage, PausableOperation::Close)?;
+    require_unwrapped(deps.storage, &vamm, &long)?;
+    require_unwrapped(deps.storage, &vamm, &short)?;
+
+    let config = read_config(deps.storage)?;
+    let spot_price = query_vamm_spot_price(deps.as_ref(), vamm.to_string())?;
+    let spot_price = from_vamm_decimals(deps.storage, &vamm, spot_price)?;
+    let (long_position, long_size) = take_triggered_order(deps.storage, &vamm, &long, spot_price)?;
+    let (short_position, short_size) =
+        take_triggered_order(deps.storage, &vamm, &short, spot_price)?;
+    if long_position.direction != Direction::AddToAmm
+        || short_position.direction != Direction::RemoveFromAmm
+    {
+        return Err(StdError::generic_err("orders must close a long and a short").into());
+    }
+
+    let mut response = Response::new();
+    let mut sizes = vec![];
+    for (position, size) in [(long_position, long_size), (short_position, short_size)] {
+        let position = migrate_liquidity(deps.as_ref(), position)?;
+        let (position, funding) = settle_funding(deps.storage, position)?;
+        store_position(deps.storage, &position)?;
+        sizes.push(size.min(position.size));
+        response = response.add_attributes(funding);
+    }
+    let (long_size, short_size) = (sizes[0], sizes[1]);
+
+    let matched = long_size.min(short_size);
+    let notional = mul_d(matched, spot_price, config.decimals)?;
+    for (trader, side) in [(&long, Side::BUY), (&short, Side::SELL)] {
+        let settled = settle_close(
+            deps.branch(),
+            env.clone(),
+            &vamm,
+            trader,
+            side,
+            matched,
+            notional,
+            false,
+        )?;
+        response = response
+            .add_submessages(settled.messages)
+            .add_attributes(settled.attributes);
+    }
+
+    // the rest of the larger order is closed on the vamm
+    let residual = match long_size.cmp(&short_size) {
+        Ordering::Greater => Some((&long, long_size - matched)),
+        Ordering::Less => Some((&short, short_size - matched)),
+        Ordering::Equal => None,
+    };
+    if let Some((trader, size)) = residual {
+        let closed = close_position(
+            deps.branch(),
+            env,
+            info.clone(),
+            vamm.to_string(),
+            trader.to_string(),
+            Some(size),
+            None,
+            false,
+            SWAP_CLOSE_REPLY_ID,
+        )?;
+        response = response.add_submessages(closed.messages);
+    }
+
+    let reward = pay_keeper(deps.storage, &info.sender)?;
+    Ok(response.add_submessages(reward).add_attributes(vec![
+        ("action", "match_trigger_orders"),
+        ("vamm", vamm.as_str()),
+        ("matched_size", &matched.to_string()),
+        ("price", &spot_price.to_string()),
+    ]))
+}
+
+// whether either of the trader's trigger orders has been reached at the price
+fn has_triggered_order(
+    storage: &dyn Storage,
+    position: &Position,
+    spot_price: Uint128,
+) -> StdResult<bool> {
+    let orders = read_trigger_orders(storage, &position.vamm, &position.trader)?;
+    let reached = |order_type: &TriggerOrderType, trigger_price: Option<Uint128>| {
+        trigger_price.is_some_and(|trigger_price| {
+            is_triggered(&position.direction, order_type, trigger_price, spot_price)
+        })
+    };
+
+    Ok(reached(&TriggerOrderType::StopLoss, orders.stop_loss)
+        || reached(&TriggerOrderType::TakeProfit, orders.take_profit))
+}
+
+// Takes the trader's trigger order the spot price has reached, returning the
+// position and the size the order closes. The other order of a linked pair is
+// cancelled with it, otherwise it is left standing
+fn take_triggered_order(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+    spot_price: Uint128,
+) -> Result<(Position, Uint128), ContractError> {
+    let position = match read_position(storage, vamm, trader)? {
+        Some(position) if !position.size.is_zero() => position,
+        _ => return Err(ContractError::PositionNotFound {}),
+    };
+
+    let mut orders = read_trigger_orders(storage, vamm, trader)?;
+    let reached = |order_type: &TriggerOrderType, trigger_price: Option<Uint128>| {
+        trigger_price.is_some_and(|trigger_price| {
+            is_triggered(&position.direction, order_type, trigger_price, spot_price)
+        })
+    };
+    let size = if reached(&TriggerOrderType::StopLoss, orders.stop_loss) {
+        orders.stop_loss = None;
+        orders.stop_loss_size.take()
+    } else if reached(&TriggerOrderType::TakeProfit, orders.take_profit) {
+        orders.take_profit = None;
+        orders.take_profit_size.take()
+    } else {
+        return Err(StdError::generic_err("no trigger order has been reached").into());
+    };
+
+    if orders.linked {
+        orders = TriggerOrders::default();
+    }
+    store_trigger_orders(storage, vamm, trader, &orders)?;
+
+    // the position may have been reduced since the order was set
+    let size = size.map_or(position.size, |size| size.min(position.size));
+    Ok((position, size))
+}
+
+// Adds margin to a trader's position, anyone can top up a position but only
+// the trader can ever withdraw it. Funds already sent through the cw20 hook
+// have no depositor, otherwise they are pulled from the depositor's allowance
+// or, for native collateral, sent along with the message
+pub fn deposit_for(
+    deps: DepsMut,
+    env: Env,
+    depositor: Option<MessageInfo>,
+    vamm: String,
+    trader: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+    require_vamm(deps.storage, &vamm)?;
+    require_not_paused(deps.storage, PausableOperation::AddMargin)?;
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("deposit amount must be greater than zero").into());
+    }
+
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => position,
+        _ => return Err(ContractError::PositionNotFound {}),
+    };
+    let (mut position, funding) = settle_funding(deps.storage, position)?;
+
+    position.margin = position.margin.checked_add(amount)?;
+    store_position(deps.storage, &position)?;
+
+    let mut response = Response::new();
+    if let Some(depositor) = depositor {
+        let collateral = read_market_collateral(deps.storage, &vamm)?;
+        require_funds(&depositor, &collateral, amount)?;
+        if !collateral.is_native_token() {
+            response = response.add_submessage(execute_transfer_from(
+                &collateral,
+                &depositor.sender,
+                &env.contract.address,
+                amount,
+            )?);
+        }
+    }
+
+    Ok(response
+        .add_attributes(vec![
+            ("action", "deposit_for"),
+            ("trader", trader.as_str()),
+            ("vamm", vamm.as_str()),
+            ("amount", &amount.to_string()),
+        ])
+        .add_attributes(funding))
+}
+
+// Withdraws margin from the sender's position, as long as what remains keeps
+// the position above its initial margin ratio
+pub fn withdraw_margin(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = info.sender;
+    require_vamm(deps.storage, &vamm)?;
+    require_not_paused(deps.storage, PausableOperation::RemoveMargin)?;
+    require_unwrapped(deps.storage, &vamm, &trader)?;
+
+    // margin is left in a closed market to settle its positions
+    if !query_vamm_state(deps.as_ref(), vamm.to_string())?.open {
+        return Err(StdError::generic_err("market is not open").into());
+    }
+
+    if amount.is_zero() {
+        return Err(StdError::generic_err("withdrawal amount must be greater than zero").into());
+    }
+
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => migrate_liquidity(deps.as_ref(), position)?,
+        _ => return Err(ContractError::PositionNotFound {}),
+    };
+    let (mut position, funding) = settle_funding(deps.storage, position)?;
+
+    // the position must be left within the maximum leverage of its tier
+    if amount > free_collateral(deps.as_ref(), &vamm, &position)? {
+        return Err(ContractError::InsufficientMargin {});
+    }
+    position.margin = position.margin.checked_sub(amount)?;
+
+    store_position(deps.storage, &position)?;
+    let collateral = read_market_collateral(deps.storage, &vamm)?;
+    record_outflow(deps.storage, &env, &collateral, amount)?;
+
+    let msg = execute_transfer(&collateral, &trader, amount)?;
+
+    Ok(Response::new()
+        .add_submessage(msg)
+        .add_attributes(vec![
+            ("action", "withdraw_margin"),
+            ("trader", trader.as_str()),
+            ("vamm", vamm.as_str()),
+            ("amount", &amount.to_string()),
+        ])
+        .add_attributes(funding))
+}
+
+// Liquidates a position whose margin, after its unrealized losses, has fallen
+// below the maintenance margin ratio by closing it through the vamm
+pub fn liquidate(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    trader: String,
+    takeover: bool,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+    require_vamm(deps.storage, &vamm)?;
+    require_not_paused(deps.storage, PausableOperation::Liquidate)?;
+
+    // the insurance fund's own position is unwound rather than liquidated
+    if trader == env.contract.address {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // only whitelisted keepers may liquidate until it is opened to everyone
+    if !read_config(deps.storage)?.open_liquidation && !is_liquidator(deps.storage, &info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => migrate_liquidity(deps.as_ref(), position)?,
+        _ => return Err(ContractError::PositionNotFound {}),
+    };
+    let (position, funding) = settle_funding(deps.storage, position)?;
+
+    let calc_option = read_config(deps.storage)?.liquidation_price_source;
+    let margin_ratio = margin_ratio(deps.as_ref(), &vamm, &position, &calc_option)?;
+    let requirement = margin_requirement(deps.storage, &vamm, position.notional)?;
+    if margin_ratio >= requirement.maintenance_margin_ratio {
+        return Err(StdError::generic_err("position is above the maintenance margin ratio").into());
+    }
+
+    if takeover {
+        let response = take_over_position(deps, env, &info.sender, position, margin_ratio)?;
+        return Ok(response.add_attributes(funding));
+    }
+
+    let amount = to_vamm_decimals(deps.storage, &vamm, position.size)?;
+    let swap_msg = WasmMsg::Execute {
+        contract_addr: vamm.to_string(),
+        funds: vec![],
+        msg: to_binary(&ExecuteMsg::SwapOutput {
+            direction: position.direction.clone(),
+            base_asset_amount: amount,
+        })?,
+    };
+
+    let swap_id = store_tmp_swap(
+        deps.storage,
+        &Swap {
+            vamm: vamm.clone(),
+            trader: trader.clone(),
+            side: direction_to_side(position.direction),
+            quote_asset_amount: Uint128::zero(),
+            leverage: Uint128::zero(),
+            open_notional: Uint128::zero(),
+            base_asset_limit: None,
+            quote_asset_limit: None,
+            fee: Uint128::zero(),
+        },
+    )?;
+
+    let msg = SubMsg {
+        msg: CosmosMsg::Wasm(swap_msg),
+        gas_limit: None,
+        id: swap_reply_id(LIQUIDATION_REPLY_ID, swap_id),
+        reply_on: ReplyOn::Always,
+    };
+    store_tmp_liquidator(deps.storage, swap_id, &info.sender)?;
+
+    Ok(Response::new()
+        .add_submessage(msg)
+        .add_attributes(vec![
+            ("action", "liquidate"),
+            ("vamm", vamm.as_str()),
+            ("trader", trader.as_str()),
+            ("margin_ratio", &margin_ratio.to_string()),
+        ])
+        .add_attributes(funding))
+}
+
+// Moves a position below its maintenance margin to the insurance fund at its
+// bankruptcy price, the price at which its margin is exhausted, so the trader
+// loses their margin but the vamm is not moved. The fund holds the position
+// under the engine's address with no margin, so that unwinding it realizes
+// the fund's gain or loss against the bankruptcy price
+fn take_over_position(
+    deps: DepsMut,
+    env: Env,
+    liquidator: &Addr,
+    position: Position,
+    margin_ratio: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    let vamm = position.vamm.clone();
+
+    // the fund is held in the eligible collateral so only backs its markets
+    if read_market_collateral(deps.storage, &vamm)? != config.eligible_collateral {
+        return Err(StdError::generic_err(
+            "only markets backed by the eligible collateral can be taken over",
+        )
+        .into());
+    }
+
+    // and it must be able to carry the position it takes on
+    let requirement = margin_requirement(deps.storage, &vamm, position.notional)?;
+    let required = mul_d(
+        position.notional,
+        requirement.maintenance_margin_ratio,
+        config.decimals,
+    )?;
+    if read_insurance_fund(deps.storage)?.total()? < required {
+        return Err(StdError::generic_err("insurance fund cannot carry the position").into());
+    }
+
+    let fund_address = env.contract.address.clone();
+    let side = direction_to_side(position.direction.clone());
+    let mut fund_position = get_position(
+        env.clone(),
+        deps.storage,
+        &vamm,
+        &fund_address,
+        side.clone(),
+    )?;
+    if !fund_position.size.is_zero() {
+        fund_position = migrate_liquidity(deps.as_ref(), fund_position)?;
+        if fund_position.direction != position.direction {
+            return Err(StdError::generic_err(
+                "insurance fund holds an opposite position in the market",
+            )
+            .into());
+        }
+    }
+
+    let bankruptcy_notional = match position.direction {
+        Direction::AddToAmm => position.notional.saturating_sub(position.margin),
+        Direction::RemoveFromAmm => position.notional.checked_add(position.margin)?,
+    };
+
+    // any funding the fund's position pays or is owed is paid to and from the
+    // fund itself, so it is simply brought up to date
+    fund_position.direction = position.direction.clone();
+    fund_position.size = fund_position.size.checked_add(position.size)?;
+    fund_position.notional = fund_position.notional.checked_add(bankruptcy_notional)?;
+    fund_position.last_updated_premium_fraction = position.last_updated_premium_fraction;
+    fund_position.liquidity_history_index = position.liquidity_history_index;
+    fund_position.timestamp = env.block.time;
+    store_position(deps.storage, &fund_position)?;
+
+    let realized_pnl = Integer::new_negative(position.margin);
+    record_trade(
+        deps.storage,
+        TradeRecord {
+            realized_pnl,
+            ..traded(
+                TradeKind::Liquidation,
+                &position,
+                position.size,
+                bankruptcy_notional,
+                config.decimals,
+                env.block.time,
+            )
+        },
+    )?;
 
-use crate::{
-    contract::{SWAP_DECREASE_REPLY_ID, SWAP_INCREASE_REPLY_ID, SWAP_REVERSE_REPLY_ID},
-    querier::query_vamm_output_price,
-    state::{read_config, read_position, store_config, store_tmp_swap, Config, Position, Swap},
-    utils::{direction_to_side, require_vamm, side_to_direction, switch_direction},
-};
-use margined_perp::margined_engine::Side;
-use margined_perp::margined_vamm::{Direction, ExecuteMsg};
+    let before = position.clone();
+    let position = clear_position(env, position)?;
+    store_position(deps.storage, &position)?;
+    cancel_resting_orders(deps.storage, &vamm, &position.trader);
+
+    let reward = pay_keeper(deps.storage, liquidator)?;
+
+    Ok(Response::new()
+        .add_submessages(reward)
+        .add_attributes(vec![
+            ("action", "liquidate"),
+            ("vamm", vamm.as_str()),
+            ("trader", position.trader.as_str()),
+            ("margin_ratio", &margin_ratio.to_string()),
+            ("takeover", "true"),
+        ])
+        .add_attributes(
+            PositionChange {
+                realized_pnl,
+                ..position_changed(&before, &position)
+            }
+            .attributes(),
+        )
+        .add_attributes(
+            Liquidation {
+                vamm,
+                trader: position.trader,
+                liquidator: liquidator.clone(),
+                liquidation_fee: Uint128::zero(),
+                bad_debt: Uint128::zero(),
+            }
+            .attributes(),
+        ))
+}
+
+// Closes the size of the position the insurance fund took over on the vamm,
+// the whole of it if none. Only the owner and liquidators may unwind it
+pub fn unwind_insurance_position(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+    size: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+    require_not_paused(deps.storage, PausableOperation::Liquidate)?;
 
-pub fn update_config(deps: DepsMut, info: MessageInfo, owner: String) -> StdResult<Response> {
     let config = read_config(deps.storage)?;
-    if info.sender != config.owner {
-        return Err(StdError::generic_err("unauthorized"));
+    if info.sender != config.owner && !is_liquidator(deps.storage, &info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let fund_address = env.contract.address;
+    let position = match read_position(deps.storage, &vamm, &fund_address)? {
+        Some(position) if !position.size.is_zero() => migrate_liquidity(deps.as_ref(), position)?,
+        _ => return Err(ContractError::PositionNotFound {}),
+    };
+    let size = size.unwrap_or(position.size);
+    if size.is_zero() || size > position.size {
+        return Err(ContractError::InvalidCloseSize {});
+    }
+
+    let swap_msg = WasmMsg::Execute {
+        contract_addr: vamm.to_string(),
+        funds: vec![],
+        msg: to_binary(&ExecuteMsg::SwapOutput {
+            direction: position.direction.clone(),
+            base_asset_amount: to_vamm_decimals(deps.storage, &vamm, size)?,
+        })?,
+    };
+
+    let swap_id = store_tmp_swap(
+        deps.storage,
+        &Swap {
+            vamm: vamm.clone(),
+            trader: fund_address,
+            side: direction_to_side(position.direction),
+            quote_asset_amount: Uint128::zero(),
+            leverage: Uint128::zero(),
+            open_notional: Uint128::zero(),
+            base_asset_limit: None,
+            quote_asset_limit: None,
+            fee: Uint128::zero(),
+        },
+    )?;
+
+    let msg = SubMsg {
+        msg: CosmosMsg::Wasm(swap_msg),
+        gas_limit: None,
+        id: swap_reply_id(UNWIND_REPLY_ID, swap_id),
+        reply_on: ReplyOn::Always,
+    };
+
+    Ok(Response::new().add_submessage(msg).add_attributes(vec![
+        ("action", "unwind_insurance_position"),
+        ("vamm", vamm.as_str()),
+        ("size", &size.to_string()),
+    ]))
+}
+
+// Settles a funding round, the premium fraction is the difference between the
+// mark and index twaps scaled to the time since the last round, at most a
+// funding period. It is added to the vamm's cumulative premium fraction and
+// each position pays or receives its share in proportion to its size the next
+// time it is touched
+pub fn pay_funding(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    vamm: String,
+) -> Result<Response, ContractError> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+    require_not_paused(deps.storage, PausableOperation::Funding)?;
+
+    let vamm_config = query_vamm_config(deps.as_ref(), vamm.to_string())?;
+    let vamm_state = query_vamm_state(deps.as_ref(), vamm.to_string())?;
+    if vamm_config.price_feed.is_none() {
+        return Err(StdError::generic_err("market has no price feed").into());
     }
 
-    let new_config = Config {
-        owner: deps.api.addr_validate(&owner).unwrap(),
-        ..config
+    // the vamm keeps the schedule, and moves it on as the round is settled
+    let now = env.block.time.seconds();
+    if now < vamm_state.next_funding_time {
+        return Err(StdError::generic_err(format!(
+            "funding is not due until {}",
+            vamm_state.next_funding_time
+        ))
+        .into());
+    }
+    let settle_msg = WasmMsg::Execute {
+        contract_addr: vamm.to_string(),
+        funds: vec![],
+        msg: to_binary(&ExecuteMsg::SettleFunding {})?,
     };
 
-    store_config(deps.storage, &new_config)?;
+    let mut funding = read_funding(deps.storage, &vamm)?;
+    let (premium_fraction, mark_twap, index_twap) =
+        next_premium_fraction(deps.as_ref(), &env, &vamm)?;
+    let longs_pay = premium_fraction.is_positive();
+
+    funding.cumulative_premium_fraction = funding
+        .cumulative_premium_fraction
+        .checked_add(premium_fraction)?;
+    funding.last_funding_time = now;
+    funding.rounds += 1;
+    store_funding(deps.storage, &vamm, &funding)?;
+    store_funding_record(
+        deps.storage,
+        &vamm,
+        &FundingRecord {
+            round: funding.rounds,
+            timestamp: env.block.time,
+            premium_fraction,
+            mark_twap,
+            index_twap,
+        },
+    )?;
+
+    let reward = pay_keeper(deps.storage, &info.sender)?;
+
+    Ok(Response::new()
+        .add_message(settle_msg)
+        .add_submessages(reward)
+        .add_attributes(
+            FundingPayment {
+                vamm,
+                mark_twap,
+                index_twap,
+                premium_fraction,
+                payer: if longs_pay { Side::BUY } else { Side::SELL },
+            }
+            .attributes(),
+        ))
+}
+
+// Settles the funding a position has accrued since it was last touched into
+// its margin and stores it. Where the market uses the eligible collateral the
+// payment goes to the insurance fund, or is drawn from it when received. Other
+// markets settle against their own reserve instead. Any part of a received
+// payment that cannot be covered is not credited and is recorded as bad debt
+fn settle_funding(
+    storage: &mut dyn Storage,
+    position: Position,
+) -> StdResult<(Position, Vec<Attribute>)> {
+    let before = position.clone();
+    let (mut position, payment) = apply_funding(storage, position)?;
+    store_position(storage, &position)?;
+    if payment.is_zero() {
+        return Ok((position, vec![]));
+    }
+
+    let config = read_config(storage)?;
+    let mut uncovered = Uint128::zero();
+    if read_market_collateral(storage, &position.vamm)? == config.eligible_collateral {
+        let mut fund = read_insurance_fund(storage)?;
+        if payment.is_positive() {
+            fund.reserve = fund.reserve.checked_add(payment.value())?;
+        } else {
+            uncovered = fund.absorb_loss(payment.value(), config.decimals);
+        }
+        store_insurance_fund(storage, &fund)?;
+    } else {
+        // other markets pay funding out of what their own positions have paid
+        // in, any part of a payment beyond that is not credited
+        let reserve = read_market_reserve(storage, &position.vamm)?;
+        let reserve = if payment.is_positive() {
+            reserve.checked_add(payment.value())?
+        } else {
+            let covered = payment.value().min(reserve);
+            uncovered = payment.value() - covered;
+            reserve - covered
+        };
+        store_market_reserve(storage, &position.vamm, reserve)?;
+    }
+
+    // a payment the engine does not hold is taken back off the margin it was
+    // credited to, and recorded against the market
+    if !uncovered.is_zero() {
+        position.margin = position.margin.checked_sub(uncovered)?;
+        store_position(storage, &position)?;
 
-    Ok(Response::default())
+        let mut bad_debt = read_bad_debt(storage, &position.vamm)?;
+        bad_debt.realized = bad_debt.realized.checked_add(uncovered)?;
+        bad_debt.uncovered = bad_debt.uncovered.checked_add(uncovered)?;
+        store_bad_debt(storage, &position.vamm, &bad_debt)?;
+    }
+
+    // the payment is reported as received, so negative when paid
+    let change = position_changed(&before, &position);
+    let attributes = PositionChange {
+        funding_payment: change.margin_delta,
+        bad_debt: uncovered,
+        ..change
+    }
+    .attributes();
+
+    Ok((position, attributes))
 }
 
 // Opens a position
@@ -34,81 +3157,237 @@ pub fn update_config(deps: DepsMut, info: MessageInfo, owner: String) -> StdResu
 pub fn open_position(
     deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
+    sent: Option<Uint128>,
     vamm: String,
     trader: String,
     side: Side,
     quote_asset_amount: Uint128,
     leverage: Uint128,
-) -> StdResult<Response> {
+    base_asset_limit: Option<Uint128>,
+    reduce_only: bool,
+) -> Result<Response, ContractError> {
     let vamm = deps.api.addr_validate(&vamm)?;
     let trader = deps.api.addr_validate(&trader)?;
     require_vamm(deps.storage, &vamm)?;
+    require_not_paused(deps.storage, PausableOperation::Open)?;
+    require_unwrapped(deps.storage, &vamm, &trader)?;
+
+    // reduce only orders may still wind down positions while the market is
+    // away from its index
+    if !reduce_only {
+        require_oracle_spread(deps.as_ref(), &vamm)?;
+    }
 
     let config: Config = read_config(deps.storage)?;
 
+    // positions cannot be opened for less than their margin
+    if leverage < config.decimals {
+        return Err(ContractError::UnderLeverage {});
+    }
+
     // calc the input amount wrt to leverage and decimals
-    let open_notional = quote_asset_amount
-        .checked_mul(leverage)?
-        .checked_div(config.decimals)?;
+    let open_notional = mul_d(quote_asset_amount, leverage, config.decimals)?;
+
+    // fall back to the trader's default slippage if no limit was given
+    let base_asset_limit = match base_asset_limit {
+        Some(limit) => Some(limit),
+        None => default_base_asset_limit(&deps, &vamm, &trader, &side, open_notional)?,
+    };
 
-    let position: Position = get_position(env.clone(), deps.storage, &vamm, &trader, side.clone());
+    let position: Position = get_position(env.clone(), deps.storage, &vamm, &trader, side.clone())?;
+    let position = migrate_liquidity(deps.as_ref(), position)?;
+    let (position, funding) = settle_funding(deps.storage, position)?;
 
+    // a closed position keeps its old direction, trading either side of it
+    // opens a new position
     let mut is_increase: bool = true;
-    if !(position.direction == Direction::AddToAmm && side == Side::BUY
+    if !(position.size.is_zero()
+        || position.direction == Direction::AddToAmm && side == Side::BUY
         || position.direction == Direction::RemoveFromAmm && side == Side::SELL)
     {
         is_increase = false;
     }
 
-    let msg: SubMsg;
+    // a reduce only order must trade against an open position for less than
+    // it is worth, anything more would flip it and should be a close instead
+    if reduce_only {
+        if is_increase || position.size.is_zero() {
+            return Err(ContractError::ReduceOnly {});
+        }
+
+        let current_notional = query_vamm_output_price(
+            deps.as_ref(),
+            vamm.to_string(),
+            position.direction.clone(),
+            to_vamm_decimals(deps.storage, &vamm, position.size)?,
+        )?;
+        let current_notional = from_vamm_decimals(deps.storage, &vamm, current_notional)?;
+        if open_notional >= current_notional {
+            return Err(ContractError::ReduceOnly {});
+        }
+    }
+
     if is_increase {
-        msg = internal_increase_position(vamm.clone(), side.clone(), open_notional);
-    } else {
-        msg = open_reverse_position(
-            &deps,
-            env,
-            vamm.clone(),
-            trader.clone(),
-            side.clone(),
+        require_increase_allowed(
+            deps.as_ref(),
+            &vamm,
+            &trader,
+            position.notional,
             open_notional,
-        );
+            leverage,
+        )?;
     }
 
-    store_tmp_swap(
+    // the fees on the opened notional are paid on top of the margin
+    let fees = calc_fee(deps.as_ref(), &env, &vamm, Some(&trader), open_notional)?;
+    let collateral = read_market_collateral(deps.storage, &vamm)?;
+
+    let swap_id = store_tmp_swap(
         deps.storage,
         &Swap {
-            vamm,
-            trader,
-            side,
+            vamm: vamm.clone(),
+            trader: trader.clone(),
+            side: side.clone(),
             quote_asset_amount,
             leverage,
             open_notional,
+            base_asset_limit,
+            quote_asset_limit: None,
+            fee: fees.toll_fee.checked_add(fees.spread_fee)?,
         },
     )?;
 
-    Ok(Response::new()
+    let msg = if is_increase {
+        internal_increase_position(deps.storage, vamm.clone(), side, open_notional, swap_id)?
+    } else {
+        open_reverse_position(
+            &deps,
+            env.clone(),
+            vamm.clone(),
+            trader.clone(),
+            side,
+            open_notional,
+            swap_id,
+        )?
+    };
+
+    let mut due = fees.toll_fee.checked_add(fees.spread_fee)?;
+    if is_increase {
+        due = due.checked_add(quote_asset_amount)?;
+    }
+    match sent {
+        // funds sent ahead through a cw20 hook must be exactly what is due
+        Some(sent) if sent != due => {
+            return Err(ContractError::InvalidFunds {
+                expected: due.to_string(),
+            })
+        }
+        Some(_) => {}
+        None => require_funds(&info, &collateral, due)?,
+    }
+
+    let fee_msgs = charge_fees(deps.storage, &env, &vamm, &trader, &fees, sent.is_none())?;
+    let mut response = Response::new().add_submessages(fee_msgs);
+
+    // the margin is escrowed before the swap is dispatched so a position is
+    // never opened without it, should the swap fail its reply errors and the
+    // escrow is rolled back with the rest of the order
+    if is_increase && sent.is_none() && !collateral.is_native_token() {
+        response = response.add_submessage(execute_transfer_from(
+            &collateral,
+            &trader,
+            &env.contract.address,
+            quote_asset_amount,
+        )?);
+    }
+
+    Ok(response
         .add_submessage(msg)
-        .add_attributes(vec![("action", "open_position")]))
+        .add_attributes(vec![
+            ("action", "open_position".to_string()),
+            ("toll_fee", fees.toll_fee.to_string()),
+            ("spread_fee", fees.spread_fee.to_string()),
+        ])
+        .add_attributes(funding))
+}
+
+// Derives a base asset limit from the trader's max slippage against the
+// current spot price, returns None if the trader has no default set
+fn default_base_asset_limit(
+    deps: &DepsMut,
+    vamm: &Addr,
+    trader: &Addr,
+    side: &Side,
+    open_notional: Uint128,
+) -> StdResult<Option<Uint128>> {
+    let max_slippage = match read_account_settings(deps.storage, trader)?.max_slippage {
+        Some(max_slippage) => max_slippage,
+        None => return Ok(None),
+    };
+
+    let config = read_config(deps.storage)?;
+    let spot_price = query_vamm_spot_price(deps.as_ref(), vamm.to_string())?;
+    let spot_price = from_vamm_decimals(deps.storage, vamm, spot_price)?;
+    let expected = div_d(open_notional, spot_price, config.decimals)?;
+
+    // buys can receive less base asset and sells can give up more
+    let tolerance = match side {
+        Side::BUY => config.decimals.checked_sub(max_slippage)?,
+        Side::SELL => config.decimals.checked_add(max_slippage)?,
+    };
+
+    Ok(Some(mul_d(expected, tolerance, config.decimals)?))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn close_position(
     deps: DepsMut,
     _env: Env,
     _info: MessageInfo,
     vamm: String,
     trader: String,
+    size: Option<Uint128>,
+    quote_asset_limit: Option<Uint128>,
+    partial: bool,
     id: u64,
-) -> StdResult<Response> {
+) -> Result<Response, ContractError> {
     // validate address inputs
     let vamm = deps.api.addr_validate(&vamm)?;
     let trader = deps.api.addr_validate(&trader)?;
+    require_not_paused(deps.storage, PausableOperation::Close)?;
+    require_unwrapped(deps.storage, &vamm, &trader)?;
 
     // read the position for the trader from vamm
-    let position = read_position(deps.storage, &vamm, &trader)?.unwrap();
+    let position = read_position(deps.storage, &vamm, &trader)?
+        .filter(|position| !position.size.is_zero())
+        .ok_or(ContractError::PositionNotFound {})?;
+    let position = migrate_liquidity(deps.as_ref(), position)?;
+    let (position, funding) = settle_funding(deps.storage, position)?;
 
-    let direction: Direction = switch_direction(position.direction.clone());
-    let amount = position.size;
+    // without a size the whole position is closed
+    let size = size.unwrap_or(position.size);
+    if size.is_zero() || size > position.size {
+        return Err(ContractError::InvalidCloseSize {});
+    }
+
+    // swap output directions refer to the base asset, so closing a long adds
+    // its base back to the amm
+    let direction: Direction = position.direction.clone();
+    let mut amount = to_vamm_decimals(deps.storage, &vamm, size)?;
+
+    // a partial close takes only as much of the size as the vamm can swap
+    // this block within its fluctuation limit, leaving the rest open
+    if partial {
+        if let Some(max) =
+            query_vamm_max_swap_output(deps.as_ref(), vamm.to_string(), direction.clone())?
+        {
+            amount = amount.min(max);
+        }
+        if from_vamm_decimals(deps.storage, &vamm, amount)?.is_zero() {
+            return Err(ContractError::InvalidCloseSize {});
+        }
+    }
 
     let swap_msg = WasmMsg::Execute {
         contract_addr: vamm.to_string(),
@@ -119,26 +3398,139 @@ pub fn close_position(
         })?,
     };
 
+    let swap_id = store_tmp_swap(
+        deps.storage,
+        &Swap {
+            vamm,
+            trader,
+            side: direction_to_side(position.direction),
+            quote_asset_amount: Uint128::zero(),
+            leverage: Uint128::zero(),
+            open_notional: Uint128::zero(),
+            base_asset_limit: None,
+            quote_asset_limit,
+            fee: Uint128::zero(),
+        },
+    )?;
+
     let msg = SubMsg {
         msg: CosmosMsg::Wasm(swap_msg),
         gas_limit: None, // probably should set a limit in the config
-        id,
+        id: swap_reply_id(id, swap_id),
         reply_on: ReplyOn::Always,
     };
 
-    // tmp_store_swap(deps.storage, &position)?;
-
     Ok(Response::new()
         .add_attributes(vec![("action", "close_position")])
+        .add_attributes(funding)
         .add_submessage(msg))
 }
 
 // Increase the position, just basically wraps swap input though it may do more in the future
-pub fn internal_increase_position(vamm: Addr, side: Side, open_notional: Uint128) -> SubMsg {
-    swap_input(&vamm, side, open_notional, SWAP_INCREASE_REPLY_ID).unwrap()
+pub fn internal_increase_position(
+    storage: &dyn Storage,
+    vamm: Addr,
+    side: Side,
+    open_notional: Uint128,
+    swap_id: u64,
+) -> StdResult<SubMsg> {
+    swap_input(
+        storage,
+        &vamm,
+        side,
+        open_notional,
+        swap_reply_id(SWAP_INCREASE_REPLY_ID, swap_id),
+    )
+}
+
+// Reduces the position, or closes it first if the order would flip it
+// Checks that the trader may hold the position once its notional of
+// position_notional is increased by the open_notional at the leverage, used
+// both when increasing and for what is left over once a position is flipped
+pub fn require_increase_allowed(
+    deps: Deps,
+    vamm: &Addr,
+    trader: &Addr,
+    position_notional: Uint128,
+    open_notional: Uint128,
+    leverage: Uint128,
+) -> Result<(), ContractError> {
+    let config = read_config(deps.storage)?;
+    let notional = position_notional.checked_add(open_notional)?;
+
+    // dust positions are never worth liquidating so cannot be opened
+    if notional < config.min_notional {
+        return Err(ContractError::BelowMinNotional {
+            min_notional: config.min_notional,
+        });
+    }
+
+    // the margin ratio of the position is 1 / leverage, which must cover the
+    // initial margin ratio of the tier the increased position falls into
+    let requirement = margin_requirement(deps.storage, vamm, notional)?;
+    if mul_d(leverage, requirement.initial_margin_ratio, config.decimals)? > config.decimals {
+        return Err(ContractError::OverLeverage {
+            max_leverage: config
+                .decimals
+                .multiply_ratio(config.decimals, requirement.initial_margin_ratio),
+        });
+    }
+
+    // and the market may cap leverage below what its margin allows
+    if let Some(max_leverage) = risk_params(deps.storage, vamm)?.max_leverage {
+        if leverage > max_leverage {
+            return Err(ContractError::OverLeverage { max_leverage });
+        }
+    }
+
+    // the market as a whole may be capped while it is young
+    if let Some(open_interest_cap) =
+        read_market_caps(deps.storage, vamm)?.open_interest_notional_cap
+    {
+        let open_interest = read_open_interest(deps.storage, vamm)?.checked_add(open_notional)?;
+        if open_interest > open_interest_cap {
+            return Err(ContractError::MarketOpenInterestCap { open_interest_cap });
+        }
+    }
+
+    // nor may a single trader hold more than the market allows, unless
+    // exempt
+    if let Some(max_position_notional) = read_market_caps(deps.storage, vamm)?.max_position_notional
+    {
+        if notional > max_position_notional && !is_holding_cap_exempt(deps.storage, trader) {
+            return Err(ContractError::MaxPositionNotional {
+                max_position_notional,
+            });
+        }
+    }
+
+    // no trader may hold more than the capped share of the market's open
+    // interest, though positions up to the floor are always allowed so
+    // that new markets can be bootstrapped
+    if let Some(cap) = read_open_interest_cap(deps.storage)? {
+        let open_interest = read_open_interest(deps.storage, vamm)?.checked_add(open_notional)?;
+        let max_notional = cap
+            .floor
+            .max(open_interest.multiply_ratio(cap.share, config.decimals));
+        if notional > max_notional {
+            return Err(ContractError::OpenInterestCap { max_notional });
+        }
+    }
+
+    // registered contract traders may be capped across all markets
+    if let Some(ContractTrader {
+        notional_cap: Some(notional_cap),
+    }) = read_contract_trader(deps.storage, trader)?
+    {
+        let total_notional = total_notional(deps.storage, trader)?.checked_add(open_notional)?;
+        if total_notional > notional_cap {
+            return Err(ContractError::ContractTraderCap { notional_cap });
+        }
+    }
+
+    Ok(())
 }
 
-// Increase the position, just basically wraps swap input though it may do more in the future
 fn open_reverse_position(
     deps: &DepsMut,
     env: Env,
@@ -146,37 +3538,47 @@ fn open_reverse_position(
     trader: Addr,
     side: Side,
     open_notional: Uint128,
-) -> SubMsg {
-    let msg: SubMsg;
-    let position: Position = get_position(env, deps.storage, &vamm, &trader, side.clone());
+    swap_id: u64,
+) -> StdResult<SubMsg> {
+    let position: Position = get_position(env, deps.storage, &vamm, &trader, side.clone())?;
     let current_notional = query_vamm_output_price(
-        deps,
+        deps.as_ref(),
         vamm.to_string(),
         position.direction.clone(),
-        position.size,
-    )
-    .unwrap();
+        to_vamm_decimals(deps.storage, &vamm, position.size)?,
+    )?;
+    let current_notional = from_vamm_decimals(deps.storage, &vamm, current_notional)?;
 
-    // if position.notional > open_notional {
     if current_notional > open_notional {
-        // then we are opening a new position or adding to an existing
-        msg = swap_input(&vamm, side, open_notional, SWAP_DECREASE_REPLY_ID).unwrap();
+        // the position is only reduced
+        swap_input(
+            deps.storage,
+            &vamm,
+            side,
+            open_notional,
+            swap_reply_id(SWAP_DECREASE_REPLY_ID, swap_id),
+        )
     } else {
         // first close position swap out the entire position
-        msg = swap_output(
+        swap_output(
+            deps.storage,
             &vamm,
-            direction_to_side(position.direction.clone()),
+            direction_to_side(position.direction),
             position.size,
-            SWAP_REVERSE_REPLY_ID,
+            swap_reply_id(SWAP_REVERSE_REPLY_ID, swap_id),
         )
-        .unwrap();
     }
-
-    msg
 }
 
-fn swap_input(vamm: &Addr, side: Side, open_notional: Uint128, id: u64) -> StdResult<SubMsg> {
+fn swap_input(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    side: Side,
+    open_notional: Uint128,
+    id: u64,
+) -> StdResult<SubMsg> {
     let direction: Direction = side_to_direction(side);
+    let open_notional = to_vamm_decimals(storage, vamm, open_notional)?;
 
     let swap_msg = WasmMsg::Execute {
         contract_addr: vamm.to_string(),
@@ -197,8 +3599,15 @@ fn swap_input(vamm: &Addr, side: Side, open_notional: Uint128, id: u64) -> StdRe
     Ok(execute_submsg)
 }
 
-fn swap_output(vamm: &Addr, side: Side, open_notional: Uint128, id: u64) -> StdResult<SubMsg> {
+fn swap_output(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    side: Side,
+    open_notional: Uint128,
+    id: u64,
+) -> StdResult<SubMsg> {
     let direction: Direction = side_to_direction(side);
+    let open_notional = to_vamm_decimals(storage, vamm, open_notional)?;
 
     let swap_msg = WasmMsg::Execute {
         contract_addr: vamm.to_string(),
@@ -225,9 +3634,9 @@ pub fn get_position(
     vamm: &Addr,
     trader: &Addr,
     side: Side,
-) -> Position {
+) -> StdResult<Position> {
     // read the position for the trader from vamm
-    let current_position = read_position(storage, vamm, trader).unwrap();
+    let current_position = read_position(storage, vamm, trader)?;
     let mut position = Position::default();
 
     // so if the position returned is None then its new
@@ -243,7 +3652,7 @@ pub fn get_position(
         position.timestamp = env.block.time;
     }
 
-    position
+    Ok(position)
 }
 
 // this resets the main variables of a position
@@ -255,3 +3664,208 @@ pub fn clear_position(env: Env, mut position: Position) -> StdResult<Position> {
 
     Ok(position)
 }
+
+pub fn set_vamm_open(vamm: &Addr, open: bool) -> StdResult<SubMsg> {
+    Ok(SubMsg::new(WasmMsg::Execute {
+        contract_addr: vamm.to_string(),
+        funds: vec![],
+        msg: to_binary(&ExecuteMsg::SetOpen { open })?,
+    }))
+}
+
+// Registers the sender as a referrer under the code, which traders then set
+// to be referred by them
+pub fn register_referrer(
+    deps: DepsMut,
+    info: MessageInfo,
+    code: String,
+) -> Result<Response, ContractError> {
+    if code.is_empty() || code.len() > MAX_REFERRAL_CODE_LENGTH {
+        return Err(StdError::generic_err(format!(
+            "referral code must be between 1 and {} characters",
+            MAX_REFERRAL_CODE_LENGTH
+        ))
+        .into());
+    }
+
+    if read_referral_code(deps.storage, &info.sender)?.is_some() {
+        return Err(StdError::generic_err("sender is already a referrer").into());
+    }
+
+    if read_code_referrer(deps.storage, &code)?.is_some() {
+        return Err(StdError::generic_err("referral code is taken").into());
+    }
+
+    store_referral_code(deps.storage, &info.sender, &code)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "register_referrer"),
+        ("referrer", info.sender.as_str()),
+        ("code", &code),
+    ]))
+}
+
+// Refers the sender to the referrer registered under the code, a trader is
+// only ever referred once
+pub fn set_referral_code(
+    deps: DepsMut,
+    info: MessageInfo,
+    code: String,
+) -> Result<Response, ContractError> {
+    let referrer = read_code_referrer(deps.storage, &code)?
+        .ok_or_else(|| StdError::generic_err("unknown referral code"))?;
+
+    if referrer == info.sender {
+        return Err(StdError::generic_err("traders cannot refer themselves").into());
+    }
+
+    if read_trader_referrer(deps.storage, &info.sender)?.is_some() {
+        return Err(StdError::generic_err("referral code is already set").into());
+    }
+
+    store_trader_referrer(deps.storage, &info.sender, &referrer)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_referral_code"),
+        ("trader", info.sender.as_str()),
+        ("referrer", referrer.as_str()),
+    ]))
+}
+
+pub fn update_referral_share(
+    deps: DepsMut,
+    info: MessageInfo,
+    referral_share: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // the share is a ratio, i.e. 100% is equal to the decimals
+    if referral_share > config.decimals {
+        return Err(StdError::generic_err("referral share cannot exceed 100%").into());
+    }
+
+    store_referral_share(deps.storage, referral_share)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_referral_share"),
+        ("referral_share", &referral_share.to_string()),
+    ]))
+}
+
+// Sets the maker vault of a market, which is paid its share of the toll as
+// it is charged
+pub fn update_maker_vault(
+    deps: DepsMut,
+    info: MessageInfo,
+    vamm: String,
+    vault: Option<String>,
+    fee_share: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let vamm = deps.api.addr_validate(&vamm)?;
+    require_vamm(deps.storage, &vamm)?;
+
+    // the share is a ratio, i.e. 100% is equal to the decimals
+    if fee_share > config.decimals {
+        return Err(StdError::generic_err("fee share cannot exceed 100%").into());
+    }
+
+    match vault {
+        Some(vault) => {
+            let vault = deps.api.addr_validate(&vault)?;
+            store_maker_vault(deps.storage, &vamm, &MakerVault { vault, fee_share })?;
+        }
+        None => remove_maker_vault(deps.storage, &vamm),
+    }
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "update_maker_vault"),
+        ("vamm", vamm.as_str()),
+        ("fee_share", &fee_share.to_string()),
+    ]))
+}
+
+// Pays out the referral rewards accrued to the sender in every asset
+pub fn claim_referral_rewards(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let rewards = read_referral_rewards(deps.storage, &info.sender)?;
+    if rewards.iter().all(|reward| reward.amount.is_zero()) {
+        return Err(StdError::generic_err("no referral rewards to claim").into());
+    }
+
+    remove_referral_rewards(deps.storage, &info.sender)?;
+
+    let msgs = rewards
+        .iter()
+        .filter(|reward| !reward.amount.is_zero())
+        .map(|reward| execute_transfer(&reward.asset, &info.sender, reward.amount))
+        .collect::<StdResult<Vec<SubMsg>>>()?;
+
+    Ok(Response::new().add_submessages(msgs).add_attributes(vec![
+        ("action", "claim_referral_rewards"),
+        ("referrer", info.sender.as_str()),
+    ]))
+}
+
+// Sets the toll discount of traders staking at least the minimum stake
+pub fn set_fee_tier(
+    deps: DepsMut,
+    info: MessageInfo,
+    min_stake: Uint128,
+    discount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // the discount is a ratio, i.e. 100% is equal to the decimals
+    if discount > config.decimals {
+        return Err(StdError::generic_err("discount cannot exceed 100%").into());
+    }
+
+    let mut tiers = read_fee_tiers(deps.storage)?;
+    tiers.retain(|tier| tier.min_stake != min_stake);
+    tiers.push(FeeTier {
+        min_stake,
+        discount,
+    });
+    tiers.sort_by_key(|tier| tier.min_stake);
+    store_fee_tiers(deps.storage, &tiers)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_fee_tier"),
+        ("min_stake", &min_stake.to_string()),
+        ("discount", &discount.to_string()),
+    ]))
+}
+
+pub fn remove_fee_tier(
+    deps: DepsMut,
+    info: MessageInfo,
+    min_stake: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut tiers = read_fee_tiers(deps.storage)?;
+    let count = tiers.len();
+    tiers.retain(|tier| tier.min_stake != min_stake);
+    if tiers.len() == count {
+        return Err(StdError::generic_err("fee tier not found").into());
+    }
+    store_fee_tiers(deps.storage, &tiers)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "remove_fee_tier"),
+        ("min_stake", &min_stake.to_string()),
+    ]))
+}
@@ -5,6 +5,7 @@ mod querier;
 mod query;
 mod reply;
 mod state;
+mod throttle;
 mod utils;
 
 #[cfg(test)]
@@ -1,21 +1,96 @@
 // Contains queries for external contracts
-use cosmwasm_std::{to_binary, DepsMut, QueryRequest, StdResult, Uint128, WasmQuery};
+use cosmwasm_std::{to_binary, Addr, Deps, QueryRequest, StdResult, Uint128, WasmQuery};
+use cw20::{BalanceResponse, Cw20QueryMsg};
 
-use margined_perp::margined_vamm::{Direction, QueryMsg, StateResponse};
+use serde::Deserialize;
+
+use margined_perp::asset::AssetInfo;
+use margined_perp::margined_pricefeed::{
+    ConfigResponse as PricefeedConfigResponse, QueryMsg as PricefeedQueryMsg,
+};
+use margined_perp::margined_vamm::{
+    CalcFeeResponse, ConfigResponse, Direction, LiquidityHistoryResponse, QueryMsg,
+    SettlementPriceResponse, StateResponse,
+};
+use margined_perp::pagination::MAX_LIMIT;
+use margined_perp::staking::{StakedResponse, StakingQueryMsg};
+
+// returns the balance the address holds of the asset
+pub fn query_asset_balance(deps: Deps, asset: &AssetInfo, address: &Addr) -> StdResult<Uint128> {
+    match asset {
+        AssetInfo::Token { contract_addr } => {
+            let res: BalanceResponse =
+                deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                    contract_addr: contract_addr.to_string(),
+                    msg: to_binary(&Cw20QueryMsg::Balance {
+                        address: address.to_string(),
+                    })?,
+                }))?;
+            Ok(res.balance)
+        }
+        AssetInfo::NativeToken { denom } => Ok(deps.querier.query_balance(address, denom)?.amount),
+    }
+}
+
+// returns the amount the address has staked in the staking contract
+pub fn query_staked(deps: Deps, staking_contract: String, address: String) -> StdResult<Uint128> {
+    let res: StakedResponse = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: staking_contract,
+        msg: to_binary(&StakingQueryMsg::Staked { address })?,
+    }))?;
+
+    Ok(res.stake)
+}
+
+// returns the config of the vamm, including its decimals
+pub fn query_vamm_config(deps: Deps, address: String) -> StdResult<ConfigResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&QueryMsg::Config {})?,
+    }))
+}
+
+// returns the changes to k of the vamm after the given index
+pub fn query_vamm_liquidity_history(
+    deps: Deps,
+    address: String,
+    start_after: Option<u64>,
+) -> StdResult<LiquidityHistoryResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&QueryMsg::LiquidityHistory {
+            start_after,
+            limit: Some(MAX_LIMIT),
+        })?,
+    }))
+}
 
 // returns the state of the request vamm
 // can be used to calculate the input and outputs
-pub fn _query_vamm_state(deps: &DepsMut, address: String) -> StdResult<StateResponse> {
+pub fn query_vamm_state(deps: Deps, address: String) -> StdResult<StateResponse> {
     deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
         contract_addr: address,
         msg: to_binary(&QueryMsg::State {})?,
     }))
 }
 
+// returns the base asset the vamm would exchange for the quote asset amount
+pub fn query_vamm_input_price(
+    deps: Deps,
+    address: String,
+    direction: Direction,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&QueryMsg::InputPrice { direction, amount })?,
+    }))
+}
+
 // returns the state of the request vamm
 // can be used to calculate the input and outputs
 pub fn query_vamm_output_price(
-    deps: &DepsMut,
+    deps: Deps,
     address: String,
     direction: Direction,
     amount: Uint128,
@@ -25,3 +100,111 @@ pub fn query_vamm_output_price(
         msg: to_binary(&QueryMsg::OutputPrice { direction, amount })?,
     }))
 }
+
+// returns the twap over the interval of the quote the vamm would give for the
+// base amount
+pub fn query_vamm_output_twap(
+    deps: Deps,
+    address: String,
+    direction: Direction,
+    amount: Uint128,
+    interval: u64,
+) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&QueryMsg::OutputTwap {
+            direction,
+            amount,
+            interval,
+        })?,
+    }))
+}
+
+// returns the most base the vamm lets be swapped out in the direction this
+// block within its fluctuation limit, None if it has no limit
+pub fn query_vamm_max_swap_output(
+    deps: Deps,
+    address: String,
+    direction: Direction,
+) -> StdResult<Option<Uint128>> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&QueryMsg::MaxSwapOutput { direction })?,
+    }))
+}
+
+// returns the price the vamm was shut down at, None while it is running
+pub fn query_vamm_settlement_price(deps: Deps, address: String) -> StdResult<Option<Uint128>> {
+    let res: SettlementPriceResponse =
+        deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: address,
+            msg: to_binary(&QueryMsg::SettlementPrice {})?,
+        }))?;
+
+    Ok(res.settlement_price)
+}
+
+// returns the spot price of the vamm, i.e. quote per base
+pub fn query_vamm_spot_price(deps: Deps, address: String) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&QueryMsg::SpotPrice {})?,
+    }))
+}
+
+// returns the toll and spread fees the vamm charges for the amount
+pub fn query_vamm_calc_fee(
+    deps: Deps,
+    address: String,
+    quote_asset_amount: Uint128,
+) -> StdResult<CalcFeeResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&QueryMsg::CalcFee { quote_asset_amount })?,
+    }))
+}
+
+// returns the twap of the vamm's mark price over the interval
+pub fn query_vamm_twap_price(deps: Deps, address: String, interval: u64) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&QueryMsg::TwapPrice { interval })?,
+    }))
+}
+
+// returns the config of the price feed, including its decimals
+pub fn query_pricefeed_config(deps: Deps, address: String) -> StdResult<PricefeedConfigResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&PricefeedQueryMsg::Config {})?,
+    }))
+}
+
+// the latest round of a price feed, only the price is of use to the engine
+#[derive(Deserialize)]
+struct PriceData {
+    price: Uint128,
+}
+
+// returns the latest index price of the asset
+pub fn query_pricefeed_price(deps: Deps, address: String, key: String) -> StdResult<Uint128> {
+    let res: PriceData = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&PricefeedQueryMsg::GetPrice { key })?,
+    }))?;
+
+    Ok(res.price)
+}
+
+// returns the twap of the index price of the asset over the interval
+pub fn query_pricefeed_twap_price(
+    deps: Deps,
+    address: String,
+    key: String,
+    interval: u64,
+) -> StdResult<Uint128> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: address,
+        msg: to_binary(&PricefeedQueryMsg::GetTwapPrice { key, interval })?,
+    }))
+}
@@ -1,7 +1,63 @@
-use cosmwasm_std::{Deps, StdResult, Uint128};
-use margined_perp::margined_engine::{ConfigResponse, PositionResponse};
+use cosmwasm_std::{Binary, Deps, Env, StdError, StdResult, Uint128};
+use margined_perp::asset::AssetInfo;
+use margined_perp::decimals::{div_d, mul_d};
+use margined_perp::integer::Integer;
+use margined_perp::margined_engine::{
+    AccountCollateralResponse, AccountSettingsResponse, AllVammsResponse, BadDebtResponse,
+    CollateralAssetsResponse, CommitRevealResponse, ConfigResponse, ContractTraderResponse,
+    ContractTradersResponse, DailyStatsListResponse, DailyStatsResponse, FeeCampaignResponse,
+    FeeCampaignsResponse, FeePoolResponse, FreeCollateralResponse, FundingPreviewResponse,
+    FundingRateHistoryResponse, FundingResponse, HoldingCapExemptionsResponse,
+    HoldingCapacityResponse, InsuranceFundResponse, InsuranceStakeResponse,
+    LiquidationPriceResponse, LiquidatorsResponse, LiquidityPoolResponse, LiquidityStakeResponse,
+    ListingConfigResponse, MakerVaultResponse, MarginRatioResponse, MarginRequirementResponse,
+    MarginTiersResponse, MarketCapsResponse, MarketCollateralResponse, MarketFeesResponse,
+    MarketProposalResponse, MarketStatusResponse, NextFundingTimeResponse, OpenInterestCapResponse,
+    OpenInterestImbalanceResponse, OpenInterestResponse, OrderCommitmentResponse,
+    PausedOperationsResponse, PnlCalcOption, PositionInfo,
+    PositionNotionalAndUnrealizedPnlResponse, PositionResponse, PositionSnapshot,
+    PositionSnapshotResponse, PositionTokenResponse, PositionTransferResponse, PositionsResponse,
+    QueuedOrderResponse, ReferralRewardsResponse, RiskParamsResponse, SettlementResponse, Side,
+    SigningKeyResponse, SimulationResponse, StateResponse, TradeHistoryResponse,
+    TraderFeeRateResponse, TraderPositionsResponse, TradingPermissionResponse,
+    TriggerOrdersResponse, WithdrawalThrottleResponse,
+};
+use margined_perp::margined_vamm::{CalcFeeResponse, Direction};
+use margined_perp::pagination::{next_key, page_limit, DEFAULT_LIMIT, MAX_LIMIT};
 
-use crate::state::{read_config, read_position, read_vamm, Config};
+// default and maximum number of days returned by a stats query
+const DEFAULT_STATS_LIMIT: u32 = 30;
+const MAX_STATS_LIMIT: u32 = 365;
+
+use crate::{
+    handle::get_position,
+    querier::{
+        query_vamm_config, query_vamm_input_price, query_vamm_output_price, query_vamm_state,
+    },
+    state::{
+        is_holding_cap_exempt, is_vamm, read_account_settings, read_all_vamms, read_bad_debt,
+        read_collateral_assets, read_collateral_balances, read_commit_reveal, read_config,
+        read_contract_traders, read_daily_stats, read_fee_campaigns_page, read_fee_pool,
+        read_fees_paid, read_funding, read_funding_history, read_holding_cap_exemptions,
+        read_insurance_fund, read_insurance_stake, read_liquidators, read_liquidity_pool,
+        read_liquidity_stake, read_listing_config, read_maker_vault, read_margin_tiers,
+        read_market_caps, read_market_collateral, read_market_fees, read_market_proposal,
+        read_market_reserve, read_open_interest, read_open_interest_cap, read_open_positions,
+        read_order_commitment, read_order_nonce, read_paused_operations, read_position,
+        read_position_token, read_position_transfer, read_queued_order, read_referral_code,
+        read_referral_rewards, read_referral_share, read_settlement, read_signing_key, read_state,
+        read_totals, read_trade_history, read_trading_permission, read_trigger_orders,
+        read_vamm_positions, read_vamms, Config, Position, TOTAL_DEPOSITS, TOTAL_MARGIN,
+    },
+    throttle::{read_withdrawal_throttle, remaining_outflow},
+    utils::{
+        add_pnl, apply_funding, calc_fee, collateral_value, direction_to_side, free_collateral,
+        from_vamm_decimals, liquidation_price, margin_ratio, margin_requirement, migrate_liquidity,
+        next_premium_fraction, open_positions, order_commitment_hash, paused_operations,
+        pending_funding, position_notional, remaining_margin, risk_params, side_to_direction,
+        split_pnl, staking_discount, to_vamm_decimals, unrealized_pnl, Reserves,
+    },
+};
 
 /// Queries contract Config
 pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
@@ -9,10 +65,90 @@ pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
 
     Ok(ConfigResponse {
         owner: config.owner,
+        pending_owner: config.pending_owner,
+        operator: config.operator,
         eligible_collateral: config.eligible_collateral,
+        decimals: config.decimals,
+        initial_margin_ratio: config.initial_margin_ratio,
+        maintenance_margin_ratio: config.maintenance_margin_ratio,
+        liquidation_fee: config.liquidation_fee,
+        min_notional: config.min_notional,
+        fee_pool: config.fee_pool,
+        open_liquidation: config.open_liquidation,
+        pause_level: config.pause_level,
+        staking_contract: config.staking_contract,
+        max_oracle_spread: config.max_oracle_spread,
+        liquidation_price_source: config.liquidation_price_source,
+    })
+}
+
+/// Queries the totals across every market and account
+pub fn query_state(deps: Deps) -> StdResult<StateResponse> {
+    let state = read_state(deps.storage)?;
+    let fund = read_insurance_fund(deps.storage)?;
+
+    Ok(StateResponse {
+        total_deposits: read_totals(deps.storage, &TOTAL_DEPOSITS)?,
+        total_margin: read_totals(deps.storage, &TOTAL_MARGIN)?,
+        insurance_balance: fund.balance.checked_add(fund.reserve)?,
+        outstanding_notional: state.outstanding_notional,
+    })
+}
+
+/// Queries the registered vAMMs
+pub fn query_all_vamms(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<AllVammsResponse> {
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    let limit = page_limit(limit, DEFAULT_LIMIT, MAX_LIMIT);
+
+    let vamms = read_vamms(deps.storage, start_after, limit)?;
+    let next_key = next_key(&vamms, limit, |vamm| vamm.clone());
+
+    Ok(AllVammsResponse { vamms, next_key })
+}
+
+/// Queries the whitelisted liquidators
+pub fn query_liquidators(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<LiquidatorsResponse> {
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    let limit = page_limit(limit, DEFAULT_LIMIT, MAX_LIMIT);
+
+    let liquidators = read_liquidators(deps.storage, start_after, limit)?;
+    let next_key = next_key(&liquidators, limit, |liquidator| liquidator.clone());
+
+    Ok(LiquidatorsResponse {
+        liquidators,
+        next_key,
     })
 }
 
+/// Queries the traders exempt from holding caps
+pub fn query_holding_cap_exemptions(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<HoldingCapExemptionsResponse> {
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    let limit = page_limit(limit, DEFAULT_LIMIT, MAX_LIMIT);
+
+    let traders = read_holding_cap_exemptions(deps.storage, start_after, limit)?;
+    let next_key = next_key(&traders, limit, |trader| trader.clone());
+
+    Ok(HoldingCapExemptionsResponse { traders, next_key })
+}
+
 /// Queries contract State
 pub fn query_position(deps: Deps, vamm: String, trader: String) -> StdResult<PositionResponse> {
     // read the msg.senders position
@@ -21,26 +157,1143 @@ pub fn query_position(deps: Deps, vamm: String, trader: String) -> StdResult<Pos
         &deps.api.addr_validate(&vamm)?,
         &deps.api.addr_validate(&trader)?,
     )?
-    .unwrap();
+    .ok_or_else(|| StdError::generic_err("trader has no open position"))?;
+    let position = migrate_liquidity(deps, position)?;
 
     Ok(PositionResponse {
         size: position.size,
         margin: position.margin,
         notional: position.notional,
-        premium_fraction: position.premium_fraction,
+        last_updated_premium_fraction: position.last_updated_premium_fraction,
         liquidity_history_index: position.liquidity_history_index,
         timestamp: position.timestamp,
     })
 }
 
-/// Queries traders position across all vamms
+/// Queries a page of the open positions in a market, ordered by trader
+pub fn query_positions(
+    deps: Deps,
+    vamm: String,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<PositionsResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    let limit = page_limit(limit, DEFAULT_LIMIT, MAX_LIMIT);
+
+    let positions = read_open_positions(deps.storage, &vamm, start_after, limit)?
+        .into_iter()
+        .map(position_info)
+        .collect::<Vec<PositionInfo>>();
+    let next_key = next_key(&positions, limit, |position| position.trader.clone());
+
+    Ok(PositionsResponse {
+        positions,
+        next_key,
+    })
+}
+
+fn position_info(position: Position) -> PositionInfo {
+    PositionInfo {
+        side: direction_to_side(position.direction),
+        vamm: position.vamm,
+        trader: position.trader,
+        size: position.size,
+        margin: position.margin,
+        notional: position.notional,
+        last_updated_premium_fraction: position.last_updated_premium_fraction,
+        timestamp: position.timestamp,
+    }
+}
+
+/// Queries the margin of the trader's positions across all vamms once their
+/// pending funding is settled
 pub fn query_trader_balance_with_funding_payment(deps: Deps, trader: String) -> StdResult<Uint128> {
+    let trader = deps.api.addr_validate(&trader)?;
     let mut margin = Uint128::zero();
-    let vamm_list = read_vamm(deps.storage)?;
-    for vamm in vamm_list.vamm.iter() {
-        let position = query_position(deps, vamm.to_string(), trader.clone())?;
-        margin = margin.checked_add(position.margin)?;
+    for vamm in read_all_vamms(deps.storage)?.iter() {
+        if let Some(position) = read_position(deps.storage, vamm, &trader)? {
+            let (position, _) = apply_funding(deps.storage, position)?;
+            margin = margin.checked_add(position.margin)?;
+        }
     }
 
     Ok(margin)
 }
+
+/// Queries every open position of the trader and the margin held across them
+pub fn query_trader_positions(deps: Deps, trader: String) -> StdResult<TraderPositionsResponse> {
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let mut positions = vec![];
+    let mut total_margin = Uint128::zero();
+    for vamm in read_all_vamms(deps.storage)?.iter() {
+        let position = match read_position(deps.storage, vamm, &trader)? {
+            Some(position) if !position.size.is_zero() => position,
+            _ => continue,
+        };
+
+        total_margin = total_margin.checked_add(position.margin)?;
+        positions.push(position_info(position));
+    }
+
+    Ok(TraderPositionsResponse {
+        trader,
+        positions,
+        total_margin,
+    })
+}
+
+/// Queries a page of the trader's most recent trades, oldest first
+pub fn query_trade_history(
+    deps: Deps,
+    trader: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<TradeHistoryResponse> {
+    let trader = deps.api.addr_validate(&trader)?;
+    let limit = page_limit(limit, DEFAULT_LIMIT, MAX_LIMIT);
+
+    let trades = read_trade_history(deps.storage, &trader, start_after, limit)?;
+    let next_key = next_key(&trades, limit, |trade| trade.trade_id);
+
+    Ok(TradeHistoryResponse { trades, next_key })
+}
+
+/// Queries the referrer's code and the rewards they have yet to claim
+pub fn query_referral_rewards(deps: Deps, referrer: String) -> StdResult<ReferralRewardsResponse> {
+    let referrer = deps.api.addr_validate(&referrer)?;
+
+    Ok(ReferralRewardsResponse {
+        code: read_referral_code(deps.storage, &referrer)?,
+        referral_share: read_referral_share(deps.storage)?,
+        rewards: read_referral_rewards(deps.storage, &referrer)?,
+    })
+}
+
+/// Queries the trader's stake and the toll discount it earns
+pub fn query_trader_fee_rate(deps: Deps, trader: String) -> StdResult<TraderFeeRateResponse> {
+    let trader = deps.api.addr_validate(&trader)?;
+    let (stake, discount) = staking_discount(deps, &trader)?;
+
+    Ok(TraderFeeRateResponse { stake, discount })
+}
+
+/// Queries a snapshot of every open position of the trader, for statements
+pub fn query_position_snapshot(
+    deps: Deps,
+    env: Env,
+    trader: String,
+) -> StdResult<PositionSnapshotResponse> {
+    let config = read_config(deps.storage)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let mut positions = vec![];
+    for vamm in read_all_vamms(deps.storage)?.iter() {
+        let position = match read_position(deps.storage, vamm, &trader)? {
+            Some(position) if !position.size.is_zero() => position,
+            _ => continue,
+        };
+
+        positions.push(PositionSnapshot {
+            vamm: vamm.clone(),
+            side: direction_to_side(position.direction),
+            size: position.size,
+            margin: position.margin,
+            notional: position.notional,
+            entry_price: position
+                .notional
+                .multiply_ratio(config.decimals, position.size),
+            // funding is not yet charged so none has accrued
+            accrued_funding: Uint128::zero(),
+            fees_paid: read_fees_paid(deps.storage, vamm, &trader)?,
+            opened_at: position.timestamp,
+        });
+    }
+
+    Ok(PositionSnapshotResponse {
+        trader,
+        timestamp: env.block.time,
+        positions,
+    })
+}
+
+/// Queries the collateral token backing a market
+pub fn query_collateral_assets(deps: Deps) -> StdResult<CollateralAssetsResponse> {
+    Ok(CollateralAssetsResponse {
+        assets: read_collateral_assets(deps.storage)?,
+    })
+}
+
+// returns the trader's collateral balances and their value after haircuts
+pub fn query_account_collateral(
+    deps: Deps,
+    trader: String,
+) -> StdResult<AccountCollateralResponse> {
+    let trader = deps.api.addr_validate(&trader)?;
+
+    Ok(AccountCollateralResponse {
+        balances: read_collateral_balances(deps.storage, &trader)?,
+        weighted_value: collateral_value(deps, &trader)?,
+    })
+}
+
+/// Queries the collateral backing a market and the reserve it holds of it
+pub fn query_market_collateral(deps: Deps, vamm: String) -> StdResult<MarketCollateralResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let collateral = read_market_collateral(deps.storage, &vamm)?;
+    let reserve = read_market_reserve(deps.storage, &vamm)?;
+
+    Ok(MarketCollateralResponse {
+        vamm,
+        collateral,
+        reserve,
+    })
+}
+
+/// Queries the fee ratios overriding those of a market's vamm
+pub fn query_market_fees(deps: Deps, vamm: String) -> StdResult<MarketFeesResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let fees = read_market_fees(deps.storage, &vamm)?;
+
+    Ok(MarketFeesResponse {
+        vamm,
+        toll_ratio: fees.toll_ratio,
+        spread_ratio: fees.spread_ratio,
+    })
+}
+
+/// Queries the open interest and position size caps of a market
+pub fn query_market_caps(deps: Deps, vamm: String) -> StdResult<MarketCapsResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let caps = read_market_caps(deps.storage, &vamm)?;
+
+    Ok(MarketCapsResponse {
+        vamm,
+        open_interest_notional_cap: caps.open_interest_notional_cap,
+        max_position_size: caps.max_position_size,
+        max_position_notional: caps.max_position_notional,
+    })
+}
+
+/// Queries the notional a trader may still open in a market under its max
+/// position notional
+pub fn query_holding_capacity(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+) -> StdResult<HoldingCapacityResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+    let max_position_notional = read_market_caps(deps.storage, &vamm)?.max_position_notional;
+    let exempt = is_holding_cap_exempt(deps.storage, &trader);
+    let position_notional = read_position(deps.storage, &vamm, &trader)?
+        .map(|position| position.notional)
+        .unwrap_or_default();
+
+    let remaining = match max_position_notional {
+        Some(max_position_notional) if !exempt => {
+            Some(max_position_notional.saturating_sub(position_notional))
+        }
+        _ => None,
+    };
+
+    Ok(HoldingCapacityResponse {
+        max_position_notional,
+        exempt,
+        position_notional,
+        remaining,
+    })
+}
+
+/// Queries the withdrawal throttle and what can still be paid out of an asset
+/// this period, the eligible collateral unless given
+pub fn query_withdrawal_throttle(
+    deps: Deps,
+    env: Env,
+    asset: Option<AssetInfo>,
+) -> StdResult<WithdrawalThrottleResponse> {
+    let asset = match asset {
+        Some(asset) => asset,
+        None => read_config(deps.storage)?.eligible_collateral,
+    };
+
+    Ok(WithdrawalThrottleResponse {
+        throttle: read_withdrawal_throttle(deps.storage)?,
+        remaining: remaining_outflow(deps.storage, &env, &asset)?,
+    })
+}
+
+/// Queries the risk parameters in force in a market
+pub fn query_risk_params(deps: Deps, vamm: String) -> StdResult<RiskParamsResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    if !is_vamm(deps.storage, &vamm) {
+        return Err(StdError::generic_err("vAMM is not registered"));
+    }
+
+    risk_params(deps.storage, &vamm)
+}
+
+/// Queries whether a market's vamm can be traded
+pub fn query_market_status(deps: Deps, vamm: String) -> StdResult<MarketStatusResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    if !is_vamm(deps.storage, &vamm) {
+        return Err(StdError::generic_err("vAMM is not registered"));
+    }
+
+    Ok(MarketStatusResponse {
+        open: query_vamm_state(deps, vamm.to_string())?.open,
+        paused: query_vamm_config(deps, vamm.to_string())?.paused,
+        vamm,
+    })
+}
+
+/// Queries the terms a shut down market is settled on, once fixed
+pub fn query_settlement(deps: Deps, vamm: String) -> StdResult<Option<SettlementResponse>> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    Ok(
+        read_settlement(deps.storage, &vamm)?.map(|settlement| SettlementResponse {
+            vamm,
+            settlement_price: settlement.settlement_price,
+            payout_ratio: settlement.payout_ratio,
+        }),
+    )
+}
+
+/// Queries the position a token represents
+pub fn query_position_token(deps: Deps, token_id: String) -> StdResult<PositionTokenResponse> {
+    let token = match read_position_token(deps.storage, &token_id)? {
+        Some(token) => token,
+        None => return Err(StdError::generic_err("unknown position token")),
+    };
+
+    Ok(PositionTokenResponse {
+        token_id,
+        vamm: token.vamm,
+        trader: token.trader,
+    })
+}
+
+/// Queries a position a trader has offered to another, None if there is none
+pub fn query_position_transfer(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+) -> StdResult<Option<PositionTransferResponse>> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let from = deps.api.addr_validate(&trader)?;
+    let to = read_position_transfer(deps.storage, &vamm, &from)?;
+
+    Ok(to.map(|to| PositionTransferResponse { vamm, from, to }))
+}
+
+/// Queries the liquidity deposited in a market
+pub fn query_liquidity_pool(deps: Deps, vamm: String) -> StdResult<LiquidityPoolResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let pool = read_liquidity_pool(deps.storage, &vamm)?;
+
+    Ok(LiquidityPoolResponse {
+        vamm,
+        total: pool.total,
+    })
+}
+
+/// Queries a provider's liquidity in a market and the fees it has earned
+pub fn query_liquidity_stake(
+    deps: Deps,
+    vamm: String,
+    provider: String,
+) -> StdResult<LiquidityStakeResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let provider = deps.api.addr_validate(&provider)?;
+    let config = read_config(deps.storage)?;
+
+    let pool = read_liquidity_pool(deps.storage, &vamm)?;
+    let mut stake = read_liquidity_stake(deps.storage, &vamm, &provider)?;
+    stake.settle(&pool, config.decimals)?;
+
+    Ok(LiquidityStakeResponse {
+        amount: stake.amount,
+        fees: stake.fees,
+    })
+}
+
+/// Queries the commit-reveal settings of a market, None if it is disabled
+pub fn query_commit_reveal(deps: Deps, vamm: String) -> StdResult<Option<CommitRevealResponse>> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let expiry_blocks = read_commit_reveal(deps.storage, &vamm)?;
+
+    Ok(expiry_blocks.map(|expiry_blocks| CommitRevealResponse { expiry_blocks }))
+}
+
+/// Queries a trader's outstanding order commitment on a market
+pub fn query_order_commitment(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+) -> StdResult<OrderCommitmentResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let commitment = read_order_commitment(deps.storage, &vamm, &trader)?
+        .ok_or_else(|| StdError::generic_err("no order commitment"))?;
+    let expiry_blocks = read_commit_reveal(deps.storage, &vamm)?.unwrap_or_default();
+
+    Ok(OrderCommitmentResponse {
+        commitment: commitment.commitment,
+        committed_at: commitment.height,
+        expires_at: commitment.height + expiry_blocks,
+    })
+}
+
+/// Queries the commitment hash of an order
+#[allow(clippy::too_many_arguments)]
+pub fn query_commitment_hash(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+    side: Side,
+    quote_asset_amount: Uint128,
+    leverage: Uint128,
+    base_asset_limit: Option<Uint128>,
+    salt: String,
+) -> StdResult<Binary> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    Ok(order_commitment_hash(
+        &vamm,
+        &trader,
+        &side,
+        quote_asset_amount,
+        leverage,
+        base_asset_limit,
+        &salt,
+    ))
+}
+
+/// Queries the registered contract traders
+pub fn query_contract_traders(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<ContractTradersResponse> {
+    let start_after = start_after
+        .map(|addr| deps.api.addr_validate(&addr))
+        .transpose()?;
+    let limit = page_limit(limit, DEFAULT_LIMIT, MAX_LIMIT);
+
+    let traders: Vec<ContractTraderResponse> =
+        read_contract_traders(deps.storage, start_after, limit)?
+            .into_iter()
+            .map(|(contract, trader)| ContractTraderResponse {
+                contract,
+                notional_cap: trader.notional_cap,
+            })
+            .collect();
+    let next_key = next_key(&traders, limit, |trader| trader.contract.clone());
+
+    Ok(ContractTradersResponse { traders, next_key })
+}
+
+/// Queries a trader's order waiting to be filled on a market
+pub fn query_queued_order(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+) -> StdResult<QueuedOrderResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let order = read_queued_order(deps.storage, &vamm, &trader)?
+        .ok_or_else(|| StdError::generic_err("no queued order"))?;
+
+    Ok(QueuedOrderResponse {
+        trade: order.trade,
+        queued_at: order.height,
+        deadline: order.deadline,
+    })
+}
+
+pub fn query_trigger_orders(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+) -> StdResult<TriggerOrdersResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let orders = read_trigger_orders(deps.storage, &vamm, &trader)?;
+
+    Ok(TriggerOrdersResponse {
+        stop_loss: orders.stop_loss,
+        take_profit: orders.take_profit,
+        stop_loss_size: orders.stop_loss_size,
+        take_profit_size: orders.take_profit_size,
+        linked: orders.linked,
+    })
+}
+
+/// Queries the market listing parameters
+pub fn query_listing_config(deps: Deps) -> StdResult<ListingConfigResponse> {
+    let listing = read_listing_config(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("market listing is not enabled"))?;
+
+    Ok(ListingConfigResponse {
+        vamm_code_id: listing.vamm_code_id,
+        bond: listing.bond,
+        challenge_period: listing.challenge_period,
+    })
+}
+
+/// Queries a market listing proposal
+pub fn query_market_proposal(deps: Deps, proposal_id: u64) -> StdResult<MarketProposalResponse> {
+    let proposal = read_market_proposal(deps.storage, proposal_id)?;
+
+    Ok(MarketProposalResponse {
+        proposal_id: proposal.proposal_id,
+        proposer: proposal.proposer,
+        bond: proposal.bond,
+        market: proposal.market,
+        created_at: proposal.created_at,
+        status: proposal.status,
+        vamm: proposal.vamm,
+    })
+}
+
+/// Queries all fee campaigns, including those not yet started or expired
+pub fn query_fee_campaigns(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<FeeCampaignsResponse> {
+    let limit = page_limit(limit, DEFAULT_LIMIT, MAX_LIMIT);
+
+    let campaigns: Vec<FeeCampaignResponse> =
+        read_fee_campaigns_page(deps.storage, start_after, limit)?
+            .into_iter()
+            .map(|campaign| FeeCampaignResponse {
+                campaign_id: campaign.campaign_id,
+                start: campaign.start,
+                end: campaign.end,
+                vamms: campaign.vamms,
+                discount: campaign.discount,
+            })
+            .collect();
+    let next_key = next_key(&campaigns, limit, |campaign| campaign.campaign_id);
+
+    Ok(FeeCampaignsResponse {
+        campaigns,
+        next_key,
+    })
+}
+
+/// Queries the fees for an amount on a vamm, including any campaign discount
+pub fn query_calc_fee(
+    deps: Deps,
+    env: Env,
+    vamm: String,
+    quote_asset_amount: Uint128,
+) -> StdResult<CalcFeeResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    calc_fee(deps, &env, &vamm, None, quote_asset_amount)
+}
+
+/// Queries a trader's account settings
+pub fn query_account_settings(deps: Deps, trader: String) -> StdResult<AccountSettingsResponse> {
+    let trader = deps.api.addr_validate(&trader)?;
+    let settings = read_account_settings(deps.storage, &trader)?;
+
+    Ok(AccountSettingsResponse {
+        max_slippage: settings.max_slippage,
+        cross_margin: settings.cross_margin,
+    })
+}
+
+/// Queries the margin tiers of a vamm
+pub fn query_margin_tiers(deps: Deps, vamm: String) -> StdResult<MarginTiersResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    Ok(MarginTiersResponse {
+        tiers: read_margin_tiers(deps.storage, &vamm)?,
+    })
+}
+
+/// Queries the margin ratios required of a position with the given notional
+pub fn query_margin_requirement(
+    deps: Deps,
+    vamm: String,
+    notional: Uint128,
+) -> StdResult<MarginRequirementResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    margin_requirement(deps.storage, &vamm, notional)
+}
+
+/// Queries the value of a trader's position and its unrealized pnl
+pub fn query_position_notional_and_unrealized_pnl(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+    calc_option: PnlCalcOption,
+) -> StdResult<PositionNotionalAndUnrealizedPnlResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => migrate_liquidity(deps, position)?,
+        _ => {
+            return Ok(PositionNotionalAndUnrealizedPnlResponse {
+                position_notional: Uint128::zero(),
+                unrealized_pnl: Integer::zero(),
+            })
+        }
+    };
+
+    let position_notional = position_notional(deps, &vamm, &position, &calc_option)?;
+    Ok(PositionNotionalAndUnrealizedPnlResponse {
+        position_notional,
+        unrealized_pnl: unrealized_pnl(&position, position_notional),
+    })
+}
+
+/// Queries the current margin ratio of a trader's position
+pub fn query_margin_ratio(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+) -> StdResult<MarginRatioResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => migrate_liquidity(deps, position)?,
+        _ => return Err(StdError::generic_err("trader has no open position")),
+    };
+    let requirement = margin_requirement(deps.storage, &vamm, position.notional)?;
+    let calc_option = read_config(deps.storage)?.liquidation_price_source;
+
+    Ok(MarginRatioResponse {
+        margin_ratio: margin_ratio(deps, &vamm, &position, &calc_option)?,
+        maintenance_margin_ratio: requirement.maintenance_margin_ratio,
+    })
+}
+
+/// Queries the margin a trader can withdraw from their position
+pub fn query_free_collateral(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+) -> StdResult<FreeCollateralResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let free_collateral = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => {
+            free_collateral(deps, &vamm, &migrate_liquidity(deps, position)?)?
+        }
+        _ => Uint128::zero(),
+    };
+
+    Ok(FreeCollateralResponse { free_collateral })
+}
+
+/// Queries the total open notional of a vamm
+pub fn query_open_interest(deps: Deps, vamm: String) -> StdResult<OpenInterestResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    Ok(OpenInterestResponse {
+        open_interest: read_open_interest(deps.storage, &vamm)?,
+    })
+}
+
+/// Queries the open notional of the longs and shorts of a vamm
+pub fn query_open_interest_imbalance(
+    deps: Deps,
+    vamm: String,
+) -> StdResult<OpenInterestImbalanceResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    let mut long = Uint128::zero();
+    let mut short = Uint128::zero();
+    for position in read_vamm_positions(deps.storage, &vamm)? {
+        match position.direction {
+            Direction::AddToAmm => long = long.checked_add(position.notional)?,
+            Direction::RemoveFromAmm => short = short.checked_add(position.notional)?,
+        }
+    }
+
+    Ok(OpenInterestImbalanceResponse { long, short })
+}
+
+/// Queries the maker vault of a vamm and its share of the toll
+pub fn query_maker_vault(deps: Deps, vamm: String) -> StdResult<MakerVaultResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+
+    Ok(match read_maker_vault(deps.storage, &vamm)? {
+        Some(maker_vault) => MakerVaultResponse {
+            vault: Some(maker_vault.vault),
+            fee_share: maker_vault.fee_share,
+        },
+        None => MakerVaultResponse {
+            vault: None,
+            fee_share: Uint128::zero(),
+        },
+    })
+}
+
+/// Queries the premium fractions paid in a vamm's funding rounds
+pub fn query_funding(deps: Deps, vamm: String) -> StdResult<FundingResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let funding = read_funding(deps.storage, &vamm)?;
+
+    Ok(FundingResponse {
+        cumulative_premium_fraction: funding.cumulative_premium_fraction,
+        last_funding_time: funding.last_funding_time,
+    })
+}
+
+/// Queries what is left of the notional a delegate may open for a trader
+pub fn query_trading_permission(
+    deps: Deps,
+    trader: String,
+    delegate: String,
+) -> StdResult<TradingPermissionResponse> {
+    let trader = deps.api.addr_validate(&trader)?;
+    let delegate = deps.api.addr_validate(&delegate)?;
+
+    Ok(TradingPermissionResponse {
+        notional_cap: read_trading_permission(deps.storage, &trader, &delegate)?
+            .unwrap_or_default(),
+    })
+}
+
+/// Queries the key that signs a trader's orders and the nonce their next
+/// must carry
+pub fn query_signing_key(deps: Deps, trader: String) -> StdResult<SigningKeyResponse> {
+    let trader = deps.api.addr_validate(&trader)?;
+
+    Ok(SigningKeyResponse {
+        pubkey: read_signing_key(deps.storage, &trader)?,
+        nonce: read_order_nonce(deps.storage, &trader)?,
+    })
+}
+
+/// Queries the funding rounds of a vamm, oldest first
+pub fn query_funding_rate_history(
+    deps: Deps,
+    vamm: String,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<FundingRateHistoryResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let limit = page_limit(limit, DEFAULT_LIMIT, MAX_LIMIT);
+
+    let rounds = read_funding_history(deps.storage, &vamm, start_after, limit)?;
+    let next_key = next_key(&rounds, limit, |record| record.round);
+
+    Ok(FundingRateHistoryResponse { rounds, next_key })
+}
+
+/// Queries when funding can next be paid in a vamm
+pub fn query_next_funding_time(deps: Deps, vamm: String) -> StdResult<NextFundingTimeResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let next_funding_time = query_vamm_state(deps, vamm.to_string())?.next_funding_time;
+
+    Ok(NextFundingTimeResponse { next_funding_time })
+}
+
+/// Queries the premium fraction the next funding round would settle at now,
+/// and what the trader's position would pay in it
+pub fn query_funding_preview(
+    deps: Deps,
+    env: Env,
+    vamm: String,
+    trader: String,
+) -> StdResult<FundingPreviewResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+    let config = read_config(deps.storage)?;
+
+    let (premium_fraction, mark_twap, index_twap) = next_premium_fraction(deps, &env, &vamm)?;
+    let payment = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) => position
+            .signed_size()
+            .mul_ratio(premium_fraction, config.decimals),
+        None => Integer::zero(),
+    };
+
+    Ok(FundingPreviewResponse {
+        premium_fraction,
+        mark_twap,
+        index_twap,
+        next_funding_time: query_vamm_state(deps, vamm.to_string())?.next_funding_time,
+        payment,
+    })
+}
+
+/// Queries the bad debt realized in a market and how much of it the
+/// insurance fund has covered
+pub fn query_bad_debt(deps: Deps, vamm: String) -> StdResult<BadDebtResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let bad_debt = read_bad_debt(deps.storage, &vamm)?;
+
+    Ok(BadDebtResponse {
+        vamm,
+        realized: bad_debt.realized,
+        insured: bad_debt.insured,
+        uncovered: bad_debt.uncovered,
+    })
+}
+
+/// Queries the open interest concentration cap, if one is set
+pub fn query_open_interest_cap(deps: Deps) -> StdResult<Option<OpenInterestCapResponse>> {
+    let cap = read_open_interest_cap(deps.storage)?;
+
+    Ok(cap.map(|cap| OpenInterestCapResponse {
+        share: cap.share,
+        floor: cap.floor,
+    }))
+}
+
+/// Queries the operations that are currently paused
+pub fn query_paused_operations(deps: Deps) -> StdResult<PausedOperationsResponse> {
+    let paused = read_paused_operations(deps.storage)?;
+
+    Ok(PausedOperationsResponse {
+        paused: paused_operations(paused),
+    })
+}
+
+/// Queries the insurance fund
+pub fn query_insurance_fund(deps: Deps) -> StdResult<InsuranceFundResponse> {
+    let fund = read_insurance_fund(deps.storage)?;
+
+    Ok(InsuranceFundResponse {
+        balance: fund.balance,
+        reserve: fund.reserve,
+        index: fund.index,
+        epoch: fund.epoch,
+        unstake_cooldown: fund.unstake_cooldown,
+    })
+}
+
+/// Queries the current value of a staker's insurance stake
+pub fn query_insurance_stake(deps: Deps, staker: String) -> StdResult<InsuranceStakeResponse> {
+    let staker = deps.api.addr_validate(&staker)?;
+    let fund = read_insurance_fund(deps.storage)?;
+    let stake = read_insurance_stake(deps.storage, &staker)?;
+
+    Ok(InsuranceStakeResponse {
+        value: fund.value_of(&stake),
+        unstake_amount: stake.unstake.as_ref().map(|request| request.amount),
+        unstake_available_at: stake.unstake.as_ref().map(|request| request.available_at),
+    })
+}
+
+/// Queries the fee pool and how it is distributed
+pub fn query_fee_pool(deps: Deps) -> StdResult<FeePoolResponse> {
+    let pool = read_fee_pool(deps.storage)?;
+
+    Ok(FeePoolResponse {
+        balance: pool.balance,
+        insurance_share: pool.insurance_share,
+        insurance_floor: pool.insurance_floor,
+        keeper_reward: pool.keeper_reward,
+    })
+}
+
+/// Queries the daily trading stats of a vamm, days are counted from the unix epoch
+pub fn query_daily_stats(
+    deps: Deps,
+    vamm: String,
+    start_after: Option<u64>,
+    end_day: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<DailyStatsListResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let limit = page_limit(limit, DEFAULT_STATS_LIMIT, MAX_STATS_LIMIT);
+
+    let stats: Vec<DailyStatsResponse> =
+        read_daily_stats(deps.storage, &vamm, start_after, end_day, limit)?
+            .into_iter()
+            .map(|stats| DailyStatsResponse {
+                day: stats.day,
+                volume: stats.volume,
+                peak_open_interest: stats.peak_open_interest,
+                fees: stats.fees,
+            })
+            .collect();
+    let next_key = next_key(&stats, limit, |stats| stats.day);
+
+    Ok(DailyStatsListResponse { stats, next_key })
+}
+
+/// Simulates opening a position, the order is priced against the vamm as it
+/// stands and the resulting position valued at the reserves it leaves
+pub fn query_simulate_open_position(
+    deps: Deps,
+    env: Env,
+    vamm: String,
+    trader: String,
+    side: Side,
+    quote_asset_amount: Uint128,
+    leverage: Uint128,
+) -> StdResult<SimulationResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+    let config = read_config(deps.storage)?;
+
+    let open_notional = mul_d(quote_asset_amount, leverage, config.decimals)?;
+    let fees = calc_fee(deps, &env, &vamm, Some(&trader), open_notional)?;
+    let position = get_position(env, deps.storage, &vamm, &trader, side.clone())?;
+    let mut position = migrate_liquidity(deps, position)?;
+
+    let direction = side_to_direction(side);
+    let mut reserves = Reserves::query(deps, &vamm)?;
+    let mut exchanged_size = Uint128::zero();
+    let mut realized_pnl = Integer::zero();
+    let mut remaining = open_notional;
+
+    // an order against the position reduces it, or closes it and opens what
+    // is left on the other side
+    if !position.size.is_zero() && position.direction != direction {
+        let current_notional = query_vamm_output_price(
+            deps,
+            vamm.to_string(),
+            position.direction.clone(),
+            to_vamm_decimals(deps.storage, &vamm, position.size)?,
+        )?;
+        let current_notional = from_vamm_decimals(deps.storage, &vamm, current_notional)?;
+
+        if current_notional > open_notional {
+            let size = query_vamm_input_price(
+                deps,
+                vamm.to_string(),
+                direction.clone(),
+                to_vamm_decimals(deps.storage, &vamm, open_notional)?,
+            )?;
+            let size = from_vamm_decimals(deps.storage, &vamm, size)?;
+            reserves.open(&direction, open_notional, size)?;
+
+            let remaining_size = position.size.checked_sub(size)?;
+            let remaining_value = reserves.notional_for(&position.direction, remaining_size)?;
+            let (pnl, notional) = split_pnl(&position, size, open_notional, remaining_value)?;
+            position.margin = add_pnl(position.margin, pnl).0;
+            position.size = remaining_size;
+            position.notional = notional;
+
+            exchanged_size = size;
+            realized_pnl = pnl;
+            remaining = Uint128::zero();
+        } else {
+            reserves.close(&position.direction, current_notional, position.size)?;
+            realized_pnl = unrealized_pnl(&position, current_notional);
+            exchanged_size = position.size;
+
+            // as when reversing, nothing is opened from a dust remainder
+            remaining = open_notional.checked_sub(current_notional)?;
+            if remaining.checked_div(leverage)?.is_zero() {
+                remaining = Uint128::zero();
+            }
+
+            position.size = Uint128::zero();
+            position.margin = Uint128::zero();
+            position.notional = Uint128::zero();
+        }
+    }
+
+    if !remaining.is_zero() {
+        let size = if exchanged_size.is_zero() {
+            let size = query_vamm_input_price(
+                deps,
+                vamm.to_string(),
+                direction.clone(),
+                to_vamm_decimals(deps.storage, &vamm, remaining)?,
+            )?;
+            from_vamm_decimals(deps.storage, &vamm, size)?
+        } else {
+            reserves.size_for(&direction, remaining)?
+        };
+        reserves.open(&direction, remaining, size)?;
+
+        position.size = position.size.checked_add(size)?;
+        position.notional = position.notional.checked_add(remaining)?;
+        position.margin = div_d(position.notional, leverage, config.decimals)?;
+        position.direction = direction;
+        exchanged_size = exchanged_size.checked_add(size)?;
+    }
+
+    simulation_response(
+        deps,
+        &position,
+        &reserves,
+        exchanged_size,
+        open_notional,
+        fees,
+        realized_pnl,
+    )
+}
+
+/// Simulates closing all or part of a position, as when opening the position
+/// left is valued at the reserves the close leaves
+pub fn query_simulate_close_position(
+    deps: Deps,
+    env: Env,
+    vamm: String,
+    trader: String,
+    size: Option<Uint128>,
+) -> StdResult<SimulationResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let mut position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => migrate_liquidity(deps, position)?,
+        _ => return Err(StdError::generic_err("trader has no open position")),
+    };
+
+    let size = size.unwrap_or(position.size);
+    if size.is_zero() || size > position.size {
+        return Err(StdError::generic_err("invalid close size"));
+    }
+
+    let notional = query_vamm_output_price(
+        deps,
+        vamm.to_string(),
+        position.direction.clone(),
+        to_vamm_decimals(deps.storage, &vamm, size)?,
+    )?;
+    let notional = from_vamm_decimals(deps.storage, &vamm, notional)?;
+    let mut reserves = Reserves::query(deps, &vamm)?;
+    reserves.close(&position.direction, notional, size)?;
+
+    let remaining_size = position.size.checked_sub(size)?;
+    let remaining_value = reserves.notional_for(&position.direction, remaining_size)?;
+    let (realized_pnl, remaining_notional) = split_pnl(&position, size, notional, remaining_value)?;
+    let released = position.margin.multiply_ratio(size, position.size);
+    position.size = remaining_size;
+    position.margin = position.margin.checked_sub(released)?;
+    position.notional = remaining_notional;
+
+    // the fees come out of the margin released
+    let (payout, _) = add_pnl(released, realized_pnl);
+    let fees = calc_fee(deps, &env, &vamm, Some(&trader), notional)?;
+    let toll_fee = fees.toll_fee.min(payout);
+    let fees = CalcFeeResponse {
+        toll_fee,
+        spread_fee: fees.spread_fee.min(payout.checked_sub(toll_fee)?),
+    };
+
+    simulation_response(
+        deps,
+        &position,
+        &reserves,
+        size,
+        notional,
+        fees,
+        realized_pnl,
+    )
+}
+
+// values what is left of a simulated position at the reserves the order leaves
+fn simulation_response(
+    deps: Deps,
+    position: &Position,
+    reserves: &Reserves,
+    exchanged_size: Uint128,
+    exchanged_notional: Uint128,
+    fees: CalcFeeResponse,
+    realized_pnl: Integer,
+) -> StdResult<SimulationResponse> {
+    let config = read_config(deps.storage)?;
+    let entry_price = if exchanged_size.is_zero() {
+        Uint128::zero()
+    } else {
+        exchanged_notional.multiply_ratio(config.decimals, exchanged_size)
+    };
+
+    // the account's collateral backs the position as when checking its margin
+    let (margin_ratio, liquidation_price) = if position.size.is_zero() {
+        (Uint128::zero(), None)
+    } else {
+        let collateral = collateral_value(deps, &position.trader)?;
+        let value = reserves.notional_for(&position.direction, position.size)?;
+        let (margin, bad_debt) = remaining_margin(position, value);
+        (
+            margin
+                .checked_add(collateral)?
+                .saturating_sub(bad_debt)
+                .multiply_ratio(config.decimals, value),
+            liquidation_price(
+                deps.storage,
+                position,
+                position.margin.checked_add(collateral)?,
+                Uint128::zero(),
+                reserves,
+            )?,
+        )
+    };
+
+    Ok(SimulationResponse {
+        exchanged_position_size: exchanged_size,
+        exchanged_quote_asset_amount: exchanged_notional,
+        entry_price,
+        toll_fee: fees.toll_fee,
+        spread_fee: fees.spread_fee,
+        realized_pnl,
+        size: position.size,
+        margin: position.margin,
+        margin_ratio,
+        liquidation_price,
+    })
+}
+
+/// Queries the mark price at which a trader's position would fall to its
+/// maintenance margin ratio once the funding it has yet to settle is paid
+pub fn query_liquidation_price(
+    deps: Deps,
+    vamm: String,
+    trader: String,
+) -> StdResult<LiquidationPriceResponse> {
+    let vamm = deps.api.addr_validate(&vamm)?;
+    let trader = deps.api.addr_validate(&trader)?;
+
+    let position = match read_position(deps.storage, &vamm, &trader)? {
+        Some(position) if !position.size.is_zero() => migrate_liquidity(deps, position)?,
+        _ => return Err(StdError::generic_err("trader has no open position")),
+    };
+
+    // the account's collateral backs the position, as do the other positions
+    // of a cross margined account
+    let (margin, mut bad_debt) =
+        add_pnl(position.margin, -pending_funding(deps.storage, &position)?);
+    let mut margin = margin.checked_add(collateral_value(deps, &trader)?)?;
+    let mut other_notional = Uint128::zero();
+    if read_account_settings(deps.storage, &trader)?.cross_margin {
+        for other in open_positions(deps.storage, &trader)? {
+            if other.vamm == vamm {
+                continue;
+            }
+
+            let notional = position_notional(deps, &other.vamm, &other, &PnlCalcOption::SpotPrice)?;
+            let (remaining, debt) = remaining_margin(&other, notional);
+            margin = margin.checked_add(remaining)?;
+            bad_debt = bad_debt.checked_add(debt)?;
+            other_notional = other_notional.checked_add(notional)?;
+        }
+    }
+
+    Ok(LiquidationPriceResponse {
+        liquidation_price: liquidation_price(
+            deps.storage,
+            &position,
+            margin.saturating_sub(bad_debt),
+            other_notional,
+            &Reserves::query(deps, &vamm)?,
+        )?,
+    })
+}
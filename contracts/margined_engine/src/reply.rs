@@ -1,157 +1,810 @@
 use cosmwasm_std::{
-    to_binary, Addr, CosmosMsg, DepsMut, Env, ReplyOn, Response, StdError, StdResult, Storage,
-    SubMsg, Uint128, WasmMsg,
+    to_binary, Addr, BankMsg, Coin, CosmosMsg, Deps, DepsMut, Env, ReplyOn, Response, StdError,
+    StdResult, Storage, SubMsg, Uint128, WasmMsg,
 };
 use cw20::Cw20ExecuteMsg;
 
 use crate::{
-    handle::{clear_position, get_position, internal_increase_position},
-    state::{read_config, read_tmp_swap, remove_tmp_swap, store_position, store_tmp_swap},
-    utils::side_to_direction,
+    error::ContractError,
+    handle::{
+        clear_position, get_position, internal_increase_position, require_increase_allowed,
+        set_vamm_open,
+    },
+    querier::query_vamm_output_price,
+    state::{
+        add_fees_paid, add_referral_reward, decrease_open_interest, increase_open_interest,
+        read_account_settings, read_bad_debt, read_collateral_asset, read_collateral_balances,
+        read_config, read_fee_pool, read_funding, read_insurance_fund, read_maker_vault,
+        read_market_caps, read_market_collateral, read_market_proposal, read_market_reserve,
+        read_referral_share, read_tmp_liquidator, read_tmp_listing, read_tmp_swap,
+        read_trader_referrer, record_daily_fees, record_daily_stats, record_trade,
+        remove_tmp_liquidator, remove_tmp_listing, remove_tmp_swap, store_bad_debt,
+        store_collateral_balance, store_fee_pool, store_insurance_fund, store_market_proposal,
+        store_market_reserve, store_position, store_vamm, store_vamm_decimals, update_tmp_swap,
+        Position,
+    },
+    throttle::record_outflow,
+    utils::{
+        add_pnl, calc_fee, cancel_resting_orders, collateral_price, from_vamm_decimals,
+        open_positions, position_changed, remaining_margin, require_base_asset_limit,
+        require_quote_asset_limit, risk_params, side_to_direction, split_pnl, to_vamm_decimals,
+        traded, unrealized_pnl,
+    },
+};
+use margined_perp::asset::AssetInfo;
+use margined_perp::decimals::{div_d, mul_d};
+use margined_perp::events::{Liquidation, PositionChange};
+use margined_perp::integer::Integer;
+use margined_perp::{
+    margined_engine::{ProposalStatus, Side, TradeKind, TradeRecord},
+    margined_vamm::CalcFeeResponse,
 };
 
 // Increases position after successful execution of the swap
 pub fn increase_position_reply(
     deps: DepsMut,
     env: Env,
+    swap_id: u64,
     _input: Uint128,
     output: Uint128,
-) -> StdResult<Response> {
+) -> Result<Response, ContractError> {
     let config = read_config(deps.storage)?;
-    let tmp_swap = read_tmp_swap(deps.storage)?;
-    if tmp_swap.is_none() {
-        return Err(StdError::generic_err("no temporary position"));
-    }
+    let swap = read_tmp_swap(deps.storage, swap_id)?.ok_or(ContractError::NoTemporarySwap {})?;
+    let output = from_vamm_decimals(deps.storage, &swap.vamm, output)?;
+    require_base_asset_limit(&swap.side, output, swap.base_asset_limit)?;
 
-    let swap = tmp_swap.unwrap();
     let mut position = get_position(
         env.clone(),
         deps.storage,
         &swap.vamm,
         &swap.trader,
         swap.side.clone(),
-    );
+    )?;
+    let before = position.clone();
+
+    // a new position only pays funding from the rounds after it is opened
+    if position.size.is_zero() {
+        position.last_updated_premium_fraction =
+            read_funding(deps.storage, &swap.vamm)?.cumulative_premium_fraction;
+    }
 
     // now update the position
     position.size = position.size.checked_add(output)?;
     position.notional = position.notional.checked_add(swap.open_notional)?;
     position.direction = side_to_direction(swap.side);
 
-    // TODO make my own decimal math lib
-    position.margin = position
-        .notional
-        .checked_mul(config.decimals)?
-        .checked_div(swap.leverage)?;
+    // the size is only known once swapped so its cap is enforced here
+    if let Some(max_position_size) = read_market_caps(deps.storage, &swap.vamm)?.max_position_size {
+        if position.size > max_position_size {
+            return Err(ContractError::MaxPositionSize { max_position_size });
+        }
+    }
+
+    // only the escrowed margin is credited, any margin already deposited
+    // stays with the position whatever leverage the addition was made at
+    position.margin = position.margin.checked_add(swap.quote_asset_amount)?;
 
     store_position(deps.storage, &position)?;
-
-    // create transfer message
-    let msg = execute_transfer_from(
+    increase_open_interest(deps.storage, &swap.vamm, swap.open_notional)?;
+    record_daily_stats(deps.storage, &swap.vamm, env.block.time, swap.open_notional)?;
+    record_trade(
         deps.storage,
-        &swap.trader,
-        &env.contract.address,
-        position.margin,
-    )
-    .unwrap();
+        TradeRecord {
+            fee: swap.fee,
+            ..traded(
+                TradeKind::Open,
+                &position,
+                output,
+                swap.open_notional,
+                config.decimals,
+                env.block.time,
+            )
+        },
+    )?;
 
-    remove_tmp_swap(deps.storage);
+    remove_tmp_swap(deps.storage, swap_id);
 
-    Ok(Response::new().add_submessage(msg))
+    // the margin was escrowed before the swap so is already held
+    Ok(Response::new().add_attributes(
+        PositionChange {
+            fee: swap.fee,
+            ..position_changed(&before, &position)
+        }
+        .attributes(),
+    ))
 }
 
-// Decreases position after successful execution of the swap
+// Decreases position after successful execution of the swap, the PnL of the
+// fraction closed is realized into the margin that stays with the position
 pub fn decrease_position_reply(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
+    swap_id: u64,
     _input: Uint128,
     output: Uint128,
-) -> StdResult<Response> {
-    let tmp_swap = read_tmp_swap(deps.storage)?;
-    if tmp_swap.is_none() {
-        return Err(StdError::generic_err("no temporary position"));
-    }
+) -> Result<Response, ContractError> {
+    let swap = read_tmp_swap(deps.storage, swap_id)?.ok_or(ContractError::NoTemporarySwap {})?;
+    let config = read_config(deps.storage)?;
+    let output = from_vamm_decimals(deps.storage, &swap.vamm, output)?;
+    require_base_asset_limit(&swap.side, output, swap.base_asset_limit)?;
 
-    let swap = tmp_swap.unwrap();
     let mut position = get_position(
-        env,
+        env.clone(),
         deps.storage,
         &swap.vamm,
         &swap.trader,
         swap.side.clone(),
-    );
+    )?;
 
-    // now update the position
+    let before = position.clone();
+    let (realized_pnl, notional) =
+        realize_pnl(deps.as_ref(), &position, output, swap.open_notional)?;
+    let (margin, bad_debt) = add_pnl(position.margin, realized_pnl);
+
+    // now update the position, the realized PnL stays in its margin
+    decrease_open_interest(
+        deps.storage,
+        &swap.vamm,
+        position.notional.saturating_sub(notional),
+    )?;
     position.size = position.size.checked_sub(output)?;
-    position.notional = position.notional.checked_sub(swap.open_notional)?;
+    position.notional = notional;
+    position.margin = margin;
 
     store_position(deps.storage, &position)?;
+    if position.size.is_zero() {
+        cancel_resting_orders(deps.storage, &swap.vamm, &swap.trader);
+    }
+    record_daily_stats(deps.storage, &swap.vamm, env.block.time, swap.open_notional)?;
+    record_trade(
+        deps.storage,
+        TradeRecord {
+            fee: swap.fee,
+            realized_pnl,
+            ..traded(
+                TradeKind::Close,
+                &before,
+                output,
+                swap.open_notional,
+                config.decimals,
+                env.block.time,
+            )
+        },
+    )?;
+
+    let (bad_debt, seizures) = cover_bad_debt(deps.branch(), &swap.trader, &swap.vamm, bad_debt)?;
 
     // remove the tmp position
-    remove_tmp_swap(deps.storage);
+    remove_tmp_swap(deps.storage, swap_id);
 
-    Ok(Response::new())
+    Ok(Response::new().add_submessages(seizures).add_attributes(
+        PositionChange {
+            realized_pnl,
+            bad_debt,
+            fee: swap.fee,
+            ..position_changed(&before, &position)
+        }
+        .attributes(),
+    ))
 }
 
-// Decreases position after successful execution of the swap
+// Closes the position after successful execution of the swap, paying out its
+// margin and realized PnL, and opens the remainder on the other side
 pub fn reverse_position_reply(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
+    swap_id: u64,
     _input: Uint128,
     output: Uint128,
-) -> StdResult<Response> {
-    let response: Response = Response::new();
-    let tmp_swap = read_tmp_swap(deps.storage)?;
-    if tmp_swap.is_none() {
-        return Err(StdError::generic_err("no temporary position"));
-    }
-
-    let mut swap = tmp_swap.unwrap();
+) -> Result<Response, ContractError> {
+    let mut swap =
+        read_tmp_swap(deps.storage, swap_id)?.ok_or(ContractError::NoTemporarySwap {})?;
+    let config = read_config(deps.storage)?;
+    let output = from_vamm_decimals(deps.storage, &swap.vamm, output)?;
     let mut position = get_position(
         env.clone(),
         deps.storage,
         &swap.vamm,
         &swap.trader,
         swap.side.clone(),
+    )?;
+    let realized_pnl = unrealized_pnl(&position, output);
+    let (margin_amount, bad_debt) = remaining_margin(&position, output);
+    decrease_open_interest(deps.storage, &swap.vamm, position.notional)?;
+    record_daily_stats(deps.storage, &swap.vamm, env.block.time, output)?;
+    record_trade(
+        deps.storage,
+        TradeRecord {
+            fee: swap.fee,
+            realized_pnl,
+            ..traded(
+                TradeKind::Close,
+                &position,
+                position.size,
+                output,
+                config.decimals,
+                env.block.time,
+            )
+        },
+    )?;
+
+    let before = position.clone();
+    position = clear_position(env.clone(), position)?;
+    store_position(deps.storage, &position)?;
+
+    // the fees are reported with the close, not again with the new side
+    let (bad_debt, seizures) = cover_bad_debt(deps.branch(), &swap.trader, &swap.vamm, bad_debt)?;
+    let mut response = Response::new().add_submessages(seizures).add_attributes(
+        PositionChange {
+            realized_pnl,
+            bad_debt,
+            fee: swap.fee,
+            ..position_changed(&before, &position)
+        }
+        .attributes(),
     );
-    let margin_amount = position.margin;
+    swap.fee = Uint128::zero();
+    if !margin_amount.is_zero() {
+        let collateral = read_market_collateral(deps.storage, &swap.vamm)?;
+        record_outflow(deps.storage, &env, &collateral, margin_amount)?;
+        response =
+            response.add_submessage(execute_transfer(&collateral, &swap.trader, margin_amount)?);
+    }
+
+    // the notional left once the old side is closed opens the new side, the
+    // close can only return more than the order when the price moved within
+    // the block and then there is nothing left over
+    let open_notional = swap.open_notional.saturating_sub(output);
+    swap.open_notional = open_notional;
+    if open_notional
+        .checked_div(swap.leverage)
+        .map_err(StdError::from)?
+        .is_zero()
+    {
+        cancel_resting_orders(deps.storage, &swap.vamm, &swap.trader);
+        remove_tmp_swap(deps.storage, swap_id);
+    } else {
+        // the margin of the flipped position would have to be drawn from
+        // the trader, which native collateral cannot be once it was sent
+        if read_market_collateral(deps.storage, &swap.vamm)?.is_native_token() {
+            return Err(StdError::generic_err(
+                "positions backed by native collateral must be closed before reversing",
+            )
+            .into());
+        }
+
+        // the new side is held to the same limits as any other increase
+        require_increase_allowed(
+            deps.as_ref(),
+            &swap.vamm,
+            &swap.trader,
+            position.notional,
+            open_notional,
+            swap.leverage,
+        )?;
 
-    position = clear_position(env, position)?;
+        // the limit was quoted for the whole notional, not the remainder
+        swap.base_asset_limit = None;
+        swap.quote_asset_amount = div_d(open_notional, swap.leverage, config.decimals)?;
+        update_tmp_swap(deps.storage, swap_id, &swap)?;
 
-    let msg: SubMsg;
-    // now increase the position again if there is additional position
-    let open_notional: Uint128;
-    if swap.open_notional > output {
-        open_notional = swap.open_notional.checked_sub(output)?;
-        swap.open_notional = swap.open_notional.checked_sub(output)?;
+        // the margin of the new side is escrowed before it is swapped, as
+        // when opening
+        let collateral = read_market_collateral(deps.storage, &swap.vamm)?;
+        response = response.add_submessage(execute_transfer_from(
+            &collateral,
+            &swap.trader,
+            &env.contract.address,
+            swap.quote_asset_amount,
+        )?);
+
+        response = response.add_submessage(internal_increase_position(
+            deps.storage,
+            swap.vamm,
+            swap.side,
+            open_notional,
+            swap_id,
+        )?);
+    }
+
+    Ok(response)
+}
+
+// Splits the PnL of a position between the size closed for the exchanged
+// notional and the remainder, valued at what the remainder is now worth
+fn realize_pnl(
+    deps: Deps,
+    position: &Position,
+    size: Uint128,
+    exchanged: Uint128,
+) -> StdResult<(Integer, Uint128)> {
+    let remaining_size = position.size.checked_sub(size)?;
+    let remaining_value = if remaining_size.is_zero() {
+        Uint128::zero()
     } else {
-        open_notional = output.checked_sub(swap.open_notional)?;
-        swap.open_notional = output.checked_sub(swap.open_notional)?;
+        let value = query_vamm_output_price(
+            deps,
+            position.vamm.to_string(),
+            position.direction.clone(),
+            to_vamm_decimals(deps.storage, &position.vamm, remaining_size)?,
+        )?;
+        from_vamm_decimals(deps.storage, &position.vamm, value)?
+    };
+
+    split_pnl(position, size, exchanged, remaining_value)
+}
+
+// Registers the newly instantiated vAMM and refunds the proposer's bond
+pub fn list_market_reply(deps: DepsMut, vamm: Addr) -> Result<Response, ContractError> {
+    let proposal_id = read_tmp_listing(deps.storage)?
+        .ok_or_else(|| StdError::generic_err("no temporary listing"))?;
+
+    let mut proposal = read_market_proposal(deps.storage, proposal_id)?;
+    proposal.status = ProposalStatus::Listed;
+    proposal.vamm = Some(vamm.clone());
+    store_market_proposal(deps.storage, &proposal)?;
+
+    store_vamm(deps.storage, &vamm)?;
+    store_vamm_decimals(
+        deps.storage,
+        &vamm,
+        Uint128::from(10u128.pow(proposal.market.decimals as u32)),
+    )?;
+
+    let config = read_config(deps.storage)?;
+    let msg = execute_transfer(
+        &config.eligible_collateral,
+        &proposal.proposer,
+        proposal.bond,
+    )?;
+
+    remove_tmp_listing(deps.storage);
+
+    // listed markets are not tradable until the owner opens them
+    Ok(Response::new()
+        .add_submessage(msg)
+        .add_submessage(set_vamm_open(&vamm, false)?)
+        .add_attributes(vec![
+            ("action", "list_market_reply"),
+            ("proposal_id", &proposal_id.to_string()),
+            ("vamm", vamm.as_str()),
+        ]))
+}
+
+// Closes all or part of a position once swapped on the vamm
+pub fn close_position_reply(
+    deps: DepsMut,
+    env: Env,
+    swap_id: u64,
+    input: Uint128,
+    output: Uint128,
+) -> Result<Response, ContractError> {
+    let swap = read_tmp_swap(deps.storage, swap_id)?.ok_or(ContractError::NoTemporarySwap {})?;
+    let input = from_vamm_decimals(deps.storage, &swap.vamm, input)?;
+    let output = from_vamm_decimals(deps.storage, &swap.vamm, output)?;
+    require_quote_asset_limit(&swap.side, output, swap.quote_asset_limit)?;
+    remove_tmp_swap(deps.storage, swap_id);
+
+    settle_close(
+        deps,
+        env,
+        &swap.vamm,
+        &swap.trader,
+        swap.side,
+        input,
+        output,
+        true,
+    )
+}
+
+// Settles the close of the size of a position for the exchanged notional, the
+// margin and notional are released in proportion to the size closed and paid
+// out with the PnL they realize, less the fees when charged
+#[allow(clippy::too_many_arguments)]
+pub fn settle_close(
+    mut deps: DepsMut,
+    env: Env,
+    vamm: &Addr,
+    trader: &Addr,
+    side: Side,
+    input: Uint128,
+    output: Uint128,
+    charge: bool,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    let mut position = get_position(env.clone(), deps.storage, vamm, trader, side)?;
+
+    let before = position.clone();
+    let input = input.min(position.size);
+    let (realized_pnl, notional) = realize_pnl(deps.as_ref(), &position, input, output)?;
+    let released = position.margin.multiply_ratio(input, position.size);
+
+    decrease_open_interest(
+        deps.storage,
+        vamm,
+        position.notional.saturating_sub(notional),
+    )?;
+    position.size = position.size.checked_sub(input)?;
+    position.margin = position.margin.checked_sub(released)?;
+    position.notional = notional;
+    position.timestamp = env.block.time;
+
+    store_position(deps.storage, &position)?;
+    if position.size.is_zero() {
+        cancel_resting_orders(deps.storage, vamm, trader);
     }
-    if open_notional.checked_div(swap.leverage)? == Uint128::zero() {
-        // create transfer message
-        msg = execute_transfer(deps.storage, &swap.trader, margin_amount).unwrap();
-        remove_tmp_swap(deps.storage);
+    record_daily_stats(deps.storage, vamm, env.block.time, output)?;
+
+    // the margin released is paid out with the PnL realized, losses beyond it
+    // are made up elsewhere
+    let (margin, bad_debt) = add_pnl(released, realized_pnl);
+    let (bad_debt, seizures) = cover_bad_debt(deps.branch(), trader, vamm, bad_debt)?;
+
+    // the fees on the closed notional come out of the margin released
+    let fees = if charge {
+        calc_fee(deps.as_ref(), &env, vamm, Some(trader), output)?
+    } else {
+        CalcFeeResponse {
+            toll_fee: Uint128::zero(),
+            spread_fee: Uint128::zero(),
+        }
+    };
+    let toll_fee = fees.toll_fee.min(margin);
+    let spread_fee = fees.spread_fee.min(margin.checked_sub(toll_fee)?);
+    let fees = CalcFeeResponse {
+        toll_fee,
+        spread_fee,
+    };
+    let fee_msgs = charge_fees(deps.storage, &env, vamm, trader, &fees, false)?;
+    let margin = margin.checked_sub(toll_fee)?.checked_sub(spread_fee)?;
+    record_trade(
+        deps.storage,
+        TradeRecord {
+            fee: toll_fee.checked_add(spread_fee)?,
+            realized_pnl,
+            ..traded(
+                TradeKind::Close,
+                &before,
+                input,
+                output,
+                config.decimals,
+                env.block.time,
+            )
+        },
+    )?;
+
+    let mut response = Response::new()
+        .add_submessages(seizures)
+        .add_submessages(fee_msgs)
+        .add_attributes(
+            PositionChange {
+                realized_pnl,
+                bad_debt,
+                fee: toll_fee.checked_add(spread_fee)?,
+                ..position_changed(&before, &position)
+            }
+            .attributes(),
+        )
+        .add_attributes(vec![
+            ("toll_fee", toll_fee.to_string()),
+            ("spread_fee", spread_fee.to_string()),
+        ]);
+    if !margin.is_zero() {
+        let collateral = read_market_collateral(deps.storage, vamm)?;
+        record_outflow(deps.storage, &env, &collateral, margin)?;
+        response = response.add_submessage(execute_transfer(&collateral, trader, margin)?);
+    }
+
+    Ok(response)
+}
+
+// Settles a liquidated position, the liquidator is paid the liquidation fee
+// from the margin that remains and the rest goes to the insurance fund, which
+// in turn absorbs any bad debt the trader's collateral cannot make up
+pub fn liquidation_reply(
+    mut deps: DepsMut,
+    env: Env,
+    swap_id: u64,
+    _input: Uint128,
+    output: Uint128,
+) -> Result<Response, ContractError> {
+    let swap = read_tmp_swap(deps.storage, swap_id)?.ok_or(ContractError::NoTemporarySwap {})?;
+    let liquidator = read_tmp_liquidator(deps.storage, swap_id)?
+        .ok_or_else(|| StdError::generic_err("no temporary liquidator"))?;
+    let output = from_vamm_decimals(deps.storage, &swap.vamm, output)?;
+
+    let config = read_config(deps.storage)?;
+    let position = get_position(
+        env.clone(),
+        deps.storage,
+        &swap.vamm,
+        &swap.trader,
+        swap.side.clone(),
+    )?;
+    let (margin, bad_debt) = remaining_margin(&position, output);
+
+    let liquidation_fee = output
+        .multiply_ratio(
+            risk_params(deps.storage, &swap.vamm)?.liquidation_fee,
+            config.decimals,
+        )
+        .min(margin);
+    let remainder = margin.checked_sub(liquidation_fee)?;
+
+    // the insurance fund is held in the eligible collateral so it only backs
+    // the markets that use it, other markets keep the remainder themselves
+    let collateral = read_market_collateral(deps.storage, &swap.vamm)?;
+    if collateral == config.eligible_collateral {
+        let mut fund = read_insurance_fund(deps.storage)?;
+        fund.reserve = fund.reserve.checked_add(remainder)?;
+        store_insurance_fund(deps.storage, &fund)?;
     } else {
-        store_tmp_swap(deps.storage, &swap)?;
+        let reserve = read_market_reserve(deps.storage, &swap.vamm)?.checked_add(remainder)?;
+        store_market_reserve(deps.storage, &swap.vamm, reserve)?;
+    }
+    let (uncovered, seizures) = cover_bad_debt(deps.branch(), &swap.trader, &swap.vamm, bad_debt)?;
+
+    decrease_open_interest(deps.storage, &swap.vamm, position.notional)?;
+    record_daily_stats(deps.storage, &swap.vamm, env.block.time, output)?;
+
+    let realized_pnl = unrealized_pnl(&position, output);
+    record_trade(
+        deps.storage,
+        TradeRecord {
+            fee: liquidation_fee,
+            realized_pnl,
+            ..traded(
+                TradeKind::Liquidation,
+                &position,
+                position.size,
+                output,
+                config.decimals,
+                env.block.time,
+            )
+        },
+    )?;
+
+    let before = position.clone();
+    let position = clear_position(env, position)?;
+    store_position(deps.storage, &position)?;
+    cancel_resting_orders(deps.storage, &swap.vamm, &swap.trader);
 
-        msg = internal_increase_position(swap.vamm, swap.side, open_notional)
-        // msg = internal_increase_position(swap.vamm, switch_side(swap.side), open_notional)
+    remove_tmp_swap(deps.storage, swap_id);
+    remove_tmp_liquidator(deps.storage, swap_id);
+
+    let mut response = Response::new().add_submessages(seizures).add_attributes(
+        PositionChange {
+            realized_pnl,
+            bad_debt: uncovered,
+            ..position_changed(&before, &position)
+        }
+        .attributes(),
+    );
+    if !liquidation_fee.is_zero() {
+        response =
+            response.add_submessage(execute_transfer(&collateral, &liquidator, liquidation_fee)?);
     }
 
+    Ok(response.add_attributes(
+        Liquidation {
+            vamm: swap.vamm,
+            trader: swap.trader,
+            liquidator,
+            liquidation_fee,
+            bad_debt: uncovered,
+        }
+        .attributes(),
+    ))
+}
+
+// Settles the part of the insurance fund's position unwound on the vamm. The
+// position has no margin so the PnL realized against its bankruptcy price is
+// the fund's gain, or a loss taken from it like bad debt
+pub fn unwind_reply(
+    mut deps: DepsMut,
+    env: Env,
+    swap_id: u64,
+    input: Uint128,
+    output: Uint128,
+) -> Result<Response, ContractError> {
+    let swap = read_tmp_swap(deps.storage, swap_id)?.ok_or(ContractError::NoTemporarySwap {})?;
+    let config = read_config(deps.storage)?;
+    let input = from_vamm_decimals(deps.storage, &swap.vamm, input)?;
+    let output = from_vamm_decimals(deps.storage, &swap.vamm, output)?;
+
+    let mut position = get_position(
+        env.clone(),
+        deps.storage,
+        &swap.vamm,
+        &swap.trader,
+        swap.side.clone(),
+    )?;
+
+    let before = position.clone();
+    let input = input.min(position.size);
+    let (realized_pnl, notional) = realize_pnl(deps.as_ref(), &position, input, output)?;
+
+    decrease_open_interest(
+        deps.storage,
+        &swap.vamm,
+        position.notional.saturating_sub(notional),
+    )?;
+    position.size = position.size.checked_sub(input)?;
+    position.notional = notional;
+    position.last_updated_premium_fraction =
+        read_funding(deps.storage, &swap.vamm)?.cumulative_premium_fraction;
+    position.timestamp = env.block.time;
     store_position(deps.storage, &position)?;
+    record_daily_stats(deps.storage, &swap.vamm, env.block.time, output)?;
+
+    let (gain, loss) = add_pnl(Uint128::zero(), realized_pnl);
+    if !gain.is_zero() {
+        let mut fund = read_insurance_fund(deps.storage)?;
+        fund.reserve = fund.reserve.checked_add(gain)?;
+        store_insurance_fund(deps.storage, &fund)?;
+    }
+    let (bad_debt, seizures) = cover_bad_debt(deps.branch(), &swap.trader, &swap.vamm, loss)?;
 
-    Ok(response.add_submessage(msg))
+    record_trade(
+        deps.storage,
+        TradeRecord {
+            realized_pnl,
+            ..traded(
+                TradeKind::Close,
+                &before,
+                input,
+                output,
+                config.decimals,
+                env.block.time,
+            )
+        },
+    )?;
+    remove_tmp_swap(deps.storage, swap_id);
+
+    Ok(Response::new().add_submessages(seizures).add_attributes(
+        PositionChange {
+            realized_pnl,
+            bad_debt,
+            ..position_changed(&before, &position)
+        }
+        .attributes(),
+    ))
 }
 
-fn execute_transfer_from(
-    storage: &dyn Storage,
+// Makes up the bad debt of a position from, in turn, the margin of a cross
+// margined trader's other positions, their collateral and the insurance fund
+// of markets backed by the eligible collateral, or the reserve of other
+// markets. Returns the debt left uncovered and the transfers of any collateral
+// seized
+fn cover_bad_debt(
+    mut deps: DepsMut,
+    trader: &Addr,
+    vamm: &Addr,
+    bad_debt: Uint128,
+) -> StdResult<(Uint128, Vec<SubMsg>)> {
+    if bad_debt.is_zero() {
+        return Ok((bad_debt, vec![]));
+    }
+
+    let bad_debt = draw_pooled_margin(deps.storage, trader, vamm, bad_debt)?;
+    let (mut uncovered, seizures) = seize_collateral(deps.branch(), trader, bad_debt)?;
+
+    if uncovered.is_zero() {
+        return Ok((uncovered, seizures));
+    }
+
+    let mut realized = read_bad_debt(deps.storage, vamm)?;
+    realized.realized = realized.realized.checked_add(uncovered)?;
+
+    let config = read_config(deps.storage)?;
+    if read_market_collateral(deps.storage, vamm)? == config.eligible_collateral {
+        let mut fund = read_insurance_fund(deps.storage)?;
+        let insured = uncovered.checked_sub(fund.absorb_loss(uncovered, config.decimals))?;
+        realized.insured = realized.insured.checked_add(insured)?;
+        uncovered = uncovered.checked_sub(insured)?;
+        store_insurance_fund(deps.storage, &fund)?;
+    } else {
+        let reserve = read_market_reserve(deps.storage, vamm)?;
+        let insured = uncovered.min(reserve);
+        realized.insured = realized.insured.checked_add(insured)?;
+        uncovered = uncovered.checked_sub(insured)?;
+        store_market_reserve(deps.storage, vamm, reserve.checked_sub(insured)?)?;
+    }
+
+    realized.uncovered = realized.uncovered.checked_add(uncovered)?;
+    store_bad_debt(deps.storage, vamm, &realized)?;
+
+    Ok((uncovered, seizures))
+}
+
+// Draws on the margin of a cross margined trader's other positions to make up
+// the bad debt of one, only positions in markets backed by the same collateral
+// are drawn on. Returns the debt left uncovered
+fn draw_pooled_margin(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    vamm: &Addr,
+    bad_debt: Uint128,
+) -> StdResult<Uint128> {
+    if bad_debt.is_zero() || !read_account_settings(storage, trader)?.cross_margin {
+        return Ok(bad_debt);
+    }
+
+    let collateral = read_market_collateral(storage, vamm)?;
+    let mut uncovered = bad_debt;
+    for mut position in open_positions(storage, trader)? {
+        if position.vamm == *vamm || read_market_collateral(storage, &position.vamm)? != collateral
+        {
+            continue;
+        }
+
+        let drawn = position.margin.min(uncovered);
+        position.margin = position.margin.checked_sub(drawn)?;
+        uncovered = uncovered.checked_sub(drawn)?;
+        store_position(storage, &position)?;
+    }
+
+    Ok(uncovered)
+}
+
+// Seizes a liquidated trader's collateral to make up bad debt, taking each
+// asset in turn at its weighted value. The seized assets go to the owner,
+// returns the debt left uncovered and the transfers
+fn seize_collateral(
+    deps: DepsMut,
+    trader: &Addr,
+    bad_debt: Uint128,
+) -> StdResult<(Uint128, Vec<SubMsg>)> {
+    let config = read_config(deps.storage)?;
+    let mut uncovered = bad_debt;
+    let mut msgs = vec![];
+    for mut balance in read_collateral_balances(deps.storage, trader)? {
+        if uncovered.is_zero() {
+            break;
+        }
+
+        let asset = match read_collateral_asset(deps.storage, &balance.asset)? {
+            Some(asset) => asset,
+            None => continue,
+        };
+        let price = mul_d(
+            collateral_price(deps.as_ref(), &asset)?,
+            asset.weight,
+            config.decimals,
+        )?;
+        if price.is_zero() {
+            continue;
+        }
+
+        let seized = div_d(uncovered, price, config.decimals)?.min(balance.amount);
+        uncovered = uncovered.saturating_sub(mul_d(seized, price, config.decimals)?);
+        if seized.is_zero() {
+            continue;
+        }
+
+        balance.amount = balance.amount.checked_sub(seized)?;
+        store_collateral_balance(deps.storage, trader, &balance)?;
+        msgs.push(execute_transfer(&balance.asset, &config.owner, seized)?);
+    }
+
+    Ok((uncovered, msgs))
+}
+
+// native collateral cannot be drawn from the owner, it must have been sent
+// to the engine with the message and is paid on from there
+pub fn execute_transfer_from(
+    collateral: &AssetInfo,
     owner: &Addr,
     receiver: &Addr,
     amount: Uint128,
 ) -> StdResult<SubMsg> {
-    let config = read_config(storage)?;
+    let contract_addr = match collateral {
+        AssetInfo::Token { contract_addr } => contract_addr,
+        AssetInfo::NativeToken { .. } => return execute_transfer(collateral, receiver, amount),
+    };
+
     let msg = WasmMsg::Execute {
-        contract_addr: config.eligible_collateral.to_string(),
+        contract_addr: contract_addr.to_string(),
         funds: vec![],
         msg: to_binary(&Cw20ExecuteMsg::TransferFrom {
             owner: owner.to_string(),
@@ -170,19 +823,31 @@ fn execute_transfer_from(
     Ok(transfer_msg)
 }
 
-fn execute_transfer(storage: &dyn Storage, receiver: &Addr, amount: Uint128) -> StdResult<SubMsg> {
-    let config = read_config(storage)?;
-    let msg = WasmMsg::Execute {
-        contract_addr: config.eligible_collateral.to_string(),
-        funds: vec![],
-        msg: to_binary(&Cw20ExecuteMsg::Transfer {
-            recipient: receiver.to_string(),
-            amount,
-        })?,
+pub fn execute_transfer(
+    collateral: &AssetInfo,
+    receiver: &Addr,
+    amount: Uint128,
+) -> StdResult<SubMsg> {
+    let msg = match collateral {
+        AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: contract_addr.to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: receiver.to_string(),
+                amount,
+            })?,
+        }),
+        AssetInfo::NativeToken { denom } => CosmosMsg::Bank(BankMsg::Send {
+            to_address: receiver.to_string(),
+            amount: vec![Coin {
+                denom: denom.to_string(),
+                amount,
+            }],
+        }),
     };
 
     let transfer_msg = SubMsg {
-        msg: CosmosMsg::Wasm(msg),
+        msg,
         gas_limit: None, // probably should set a limit in the config
         id: 0u64,
         reply_on: ReplyOn::Never,
@@ -190,3 +855,84 @@ fn execute_transfer(storage: &dyn Storage, receiver: &Addr, amount: Uint128) ->
 
     Ok(transfer_msg)
 }
+
+// Routes the fees charged on a trade, taking them from the trader's wallet
+// when opening and from the engine's holdings when closing. The referrer's
+// share of the toll is held for them, the rest goes to the fee pool address
+// if one is configured, otherwise the fees accrue to the internal fee pool
+// and insurance fund of markets backed by the eligible collateral and to the
+// owner for the rest
+pub fn charge_fees(
+    storage: &mut dyn Storage,
+    env: &Env,
+    vamm: &Addr,
+    trader: &Addr,
+    fees: &CalcFeeResponse,
+    from_trader: bool,
+) -> StdResult<Vec<SubMsg>> {
+    let config = read_config(storage)?;
+    let collateral = read_market_collateral(storage, vamm)?;
+    let backed = collateral == config.eligible_collateral;
+
+    // native fees are attached to the message so are already held
+    let from_trader = from_trader && !collateral.is_native_token();
+
+    let mut transfers: Vec<(Addr, Uint128)> = vec![];
+    let mut retained = Uint128::zero();
+
+    // the referrer's share of the toll is held until they claim it
+    let mut toll_fee = fees.toll_fee;
+    if let Some(referrer) = read_trader_referrer(storage, trader)? {
+        let reward = toll_fee.multiply_ratio(read_referral_share(storage)?, config.decimals);
+        add_referral_reward(storage, &referrer, &collateral, reward)?;
+        toll_fee = toll_fee.checked_sub(reward)?;
+        retained = retained.checked_add(reward)?;
+    }
+
+    // the maker vault's share is paid to it straight away
+    if let Some(maker_vault) = read_maker_vault(storage, vamm)? {
+        let share = toll_fee.multiply_ratio(maker_vault.fee_share, config.decimals);
+        transfers.push((maker_vault.vault, share));
+        toll_fee = toll_fee.checked_sub(share)?;
+    }
+
+    match &config.fee_pool {
+        Some(fee_pool) => transfers.push((fee_pool.clone(), toll_fee)),
+        None if backed => {
+            let mut pool = read_fee_pool(storage)?;
+            pool.balance = pool.balance.checked_add(toll_fee)?;
+            store_fee_pool(storage, &pool)?;
+            retained = retained.checked_add(toll_fee)?;
+        }
+        None => transfers.push((config.owner.clone(), toll_fee)),
+    }
+
+    if backed {
+        let mut fund = read_insurance_fund(storage)?;
+        fund.reserve = fund.reserve.checked_add(fees.spread_fee)?;
+        store_insurance_fund(storage, &fund)?;
+        retained = retained.checked_add(fees.spread_fee)?;
+    } else {
+        transfers.push((config.owner, fees.spread_fee));
+    }
+
+    let total = fees.toll_fee.checked_add(fees.spread_fee)?;
+    add_fees_paid(storage, vamm, trader, total)?;
+    record_daily_fees(storage, vamm, env.block.time, total)?;
+
+    if from_trader {
+        transfers.push((env.contract.address.clone(), retained));
+    }
+
+    transfers
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(recipient, amount)| {
+            if from_trader {
+                execute_transfer_from(&collateral, trader, &recipient, amount)
+            } else {
+                execute_transfer(&collateral, &recipient, amount)
+            }
+        })
+        .collect()
+}
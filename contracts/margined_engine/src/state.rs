@@ -1,61 +1,570 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Api, DepsMut, StdResult, Storage, Timestamp, Uint128};
-use cosmwasm_storage::{
-    bucket, bucket_read, singleton, singleton_read, Bucket, ReadonlyBucket, Singleton,
-};
-use cw_storage_plus::Item;
+use cosmwasm_std::{Addr, Api, Binary, Order, StdError, StdResult, Storage, Timestamp, Uint128};
+use cw_storage_plus::{Bound, Item, Map, U64Key};
 
-use margined_perp::margined_engine::Side;
-use margined_perp::margined_vamm::Direction;
+use margined_perp::asset::AssetInfo;
+use margined_perp::integer::Integer;
+use margined_perp::margined_engine::{
+    AssetTotal, CollateralAsset, CollateralBalance, FeeTier, FundingRecord, InsuranceFloor,
+    MarginTier, PauseLevel, PnlCalcOption, ProposalStatus, QueuedTrade, ReferralReward, RiskParams,
+    Side, TradeRecord,
+};
+use margined_perp::margined_vamm::{Direction, InstantiateMsg as VammInstantiateMsg};
 
-use sha3::{Digest, Sha3_256};
+pub const CONFIG: Item<Config> = Item::new("config");
+pub const STATE: Item<State> = Item::new("state");
+pub const TOTAL_DEPOSITS: Map<&str, Uint128> = Map::new("total_deposits");
+pub const TOTAL_MARGIN: Map<&str, Uint128> = Map::new("total_margin");
+pub const POSITIONS: Map<(&Addr, &Addr), Position> = Map::new("positions");
+pub const VAMMS: Map<&Addr, bool> = Map::new("vamms");
+pub const LIQUIDATORS: Map<&Addr, bool> = Map::new("liquidators");
+pub const HOLDING_CAP_EXEMPTIONS: Map<&Addr, bool> = Map::new("holding_cap_exemptions");
+pub const VAMM_DECIMALS: Map<&Addr, Uint128> = Map::new("vamm_decimals");
+pub const MARKET_COLLATERAL: Map<&Addr, AssetInfo> = Map::new("market_collateral");
+pub const MARKET_FEES: Map<&Addr, MarketFees> = Map::new("market_fees");
+pub const MARKET_RESERVES: Map<&Addr, Uint128> = Map::new("market_reserves");
+pub const MARKET_CAPS: Map<&Addr, MarketCaps> = Map::new("market_caps");
+pub const RISK_PARAMS: Map<&Addr, RiskParams> = Map::new("risk_params");
+pub const SETTLEMENTS: Map<&Addr, Settlement> = Map::new("settlements");
+pub const COLLATERAL_ASSETS: Map<&str, CollateralAsset> = Map::new("collateral_assets");
+pub const COLLATERAL_BALANCES: Map<(&Addr, &str), CollateralBalance> =
+    Map::new("collateral_balances");
+pub const POSITION_NFT: Item<Addr> = Item::new("position_nft");
+pub const POSITION_TOKENS: Map<&str, PositionToken> = Map::new("position_tokens");
+pub const WRAPPED_POSITIONS: Map<(&Addr, &Addr), String> = Map::new("wrapped_positions");
+pub const POSITION_TOKEN_COUNT: Item<u64> = Item::new("position_token_count");
+pub const POSITION_TRANSFERS: Map<(&Addr, &Addr), Addr> = Map::new("position_transfers");
+pub const COMMIT_REVEAL: Map<&Addr, u64> = Map::new("commit_reveal");
+pub const ORDER_COMMITMENTS: Map<(&Addr, &Addr), OrderCommitment> = Map::new("order_commitments");
+pub const CONTRACT_TRADERS: Map<&Addr, ContractTrader> = Map::new("contract_traders");
+pub const TRADING_PERMISSIONS: Map<(&Addr, &Addr), Uint128> = Map::new("trading_permissions");
+pub const SIGNING_KEYS: Map<&Addr, Binary> = Map::new("signing_keys");
+pub const ORDER_NONCES: Map<&Addr, u64> = Map::new("order_nonces");
+pub const QUEUED_ORDERS: Map<(&Addr, &Addr), QueuedOrder> = Map::new("queued_orders");
+pub const TRIGGER_ORDERS: Map<(&Addr, &Addr), TriggerOrders> = Map::new("trigger_orders");
+pub const LISTING_CONFIG: Item<ListingConfig> = Item::new("listing_config");
+pub const MARKET_PROPOSALS: Map<U64Key, MarketProposal> = Map::new("market_proposals");
+pub const MARKET_PROPOSAL_COUNT: Item<u64> = Item::new("market_proposal_count");
+pub const TMP_LISTING: Item<u64> = Item::new("tmp_listing");
+pub const TMP_LIQUIDATORS: Map<U64Key, Addr> = Map::new("tmp_liquidators");
+pub const TMP_SWAPS: Map<U64Key, Swap> = Map::new("tmp_swaps");
+pub const TMP_SWAP_COUNTER: Item<u64> = Item::new("tmp_swap_counter");
+pub const FEE_CAMPAIGNS: Map<U64Key, FeeCampaign> = Map::new("fee_campaigns");
+pub const FEE_CAMPAIGN_COUNT: Item<u64> = Item::new("fee_campaign_count");
+pub const ACCOUNT_SETTINGS: Map<&Addr, AccountSettings> = Map::new("account_settings");
+pub const MARGIN_TIERS: Map<&Addr, Vec<MarginTier>> = Map::new("margin_tiers");
+pub const OPEN_INTEREST: Map<&Addr, Uint128> = Map::new("open_interest");
+pub const FUNDING: Map<&Addr, Funding> = Map::new("funding");
+pub const FUNDING_HISTORY: Map<(&Addr, U64Key), FundingRecord> = Map::new("funding_history");
+pub const BAD_DEBT: Map<&Addr, BadDebt> = Map::new("bad_debt");
+pub const OPEN_INTEREST_CAP: Item<OpenInterestCap> = Item::new("open_interest_cap");
+pub const INSURANCE_FUND: Item<InsuranceFund> = Item::new("insurance_fund");
+pub const INSURANCE_STAKES: Map<&Addr, InsuranceStake> = Map::new("insurance_stakes");
+pub const FEE_POOL: Item<FeePool> = Item::new("fee_pool");
+pub const LIQUIDITY_POOLS: Map<&Addr, LiquidityPool> = Map::new("liquidity_pools");
+pub const LIQUIDITY_STAKES: Map<(&Addr, &Addr), LiquidityStake> = Map::new("liquidity_stakes");
+pub const PAUSED_OPERATIONS: Item<u8> = Item::new("paused_operations");
+pub const FEES_PAID: Map<(&Addr, &Addr), Uint128> = Map::new("fees_paid");
+pub const DAILY_STATS: Map<(&Addr, U64Key), DailyStats> = Map::new("daily_stats");
+pub const TRADE_HISTORY: Map<(&Addr, U64Key), TradeRecord> = Map::new("trade_history");
+pub const TRADE_COUNT: Map<&Addr, u64> = Map::new("trade_count");
+pub const FEE_TIERS: Item<Vec<FeeTier>> = Item::new("fee_tiers");
+pub const REFERRAL_SHARE: Item<Uint128> = Item::new("referral_share");
+pub const MAKER_VAULTS: Map<&Addr, MakerVault> = Map::new("maker_vaults");
+pub const REFERRAL_CODES: Map<&str, Addr> = Map::new("referral_codes");
+pub const REFERRERS: Map<&Addr, String> = Map::new("referrers");
+pub const TRADER_REFERRERS: Map<&Addr, Addr> = Map::new("trader_referrers");
+pub const REFERRAL_REWARDS: Map<(&Addr, &str), ReferralReward> = Map::new("referral_rewards");
 
-pub static KEY_CONFIG: &[u8] = b"config";
-pub static KEY_POSITION: &[u8] = b"position";
-pub static KEY_TMP_SWAP: &[u8] = b"tmp-position";
-pub const VAMM_LIST: Item<VammList> = Item::new("admin_list");
+pub const SECONDS_PER_DAY: u64 = 86_400;
+// the number of trades kept in a trader's history, older trades are dropped
+pub const MAX_TRADE_HISTORY: u64 = 100;
+pub const MAX_REFERRAL_CODE_LENGTH: usize = 32;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub owner: Addr,
-    pub eligible_collateral: Addr,
+    // proposed as the next owner, who must accept before taking over
+    pub pending_owner: Option<Addr>,
+    // pauses the engine and adds or removes vamms in place of the owner
+    #[serde(default)]
+    pub operator: Option<Addr>,
+    pub eligible_collateral: AssetInfo,
     pub decimals: Uint128,
     pub initial_margin_ratio: Uint128,
     pub maintenance_margin_ratio: Uint128,
     pub liquidation_fee: Uint128,
+    pub min_notional: Uint128,
+    pub fee_pool: Option<Addr>,
+    pub open_liquidation: bool,
+    pub pause_level: PauseLevel,
+    pub staking_contract: Option<Addr>,
+    // opens are rejected while the spot price strays further than this ratio
+    // from the index price
+    #[serde(default)]
+    pub max_oracle_spread: Option<Uint128>,
+    // the price positions are valued at when checking whether they can be
+    // liquidated
+    #[serde(default)]
+    pub liquidation_price_source: PnlCalcOption,
 }
 
 pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
-    singleton(storage, KEY_CONFIG).save(config)
+    CONFIG.save(storage, config)
 }
 
 pub fn read_config(storage: &dyn Storage) -> StdResult<Config> {
-    singleton_read(storage, KEY_CONFIG).load()
+    CONFIG.load(storage)
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct VammList {
-    pub vamm: Vec<Addr>,
+// running totals across every market, kept as positions are stored. The
+// deposits and margin are totalled by asset in TOTAL_DEPOSITS and TOTAL_MARGIN
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct State {
+    pub outstanding_notional: Uint128,
+}
+
+pub fn store_state(storage: &mut dyn Storage, state: &State) -> StdResult<()> {
+    STATE.save(storage, state)
+}
+
+pub fn read_state(storage: &dyn Storage) -> StdResult<State> {
+    Ok(STATE.may_load(storage)?.unwrap_or_default())
+}
+
+// adds to and takes from the running total of an asset
+fn update_total(
+    storage: &mut dyn Storage,
+    totals: &Map<&str, Uint128>,
+    asset: &AssetInfo,
+    added: Uint128,
+    removed: Uint128,
+) -> StdResult<()> {
+    let asset = asset.to_string();
+    let total = totals
+        .may_load(storage, &asset)?
+        .unwrap_or_default()
+        .checked_add(added)?
+        .checked_sub(removed)?;
+    totals.save(storage, &asset, &total)
+}
+
+pub fn read_total_deposits(storage: &dyn Storage, asset: &AssetInfo) -> StdResult<Uint128> {
+    Ok(TOTAL_DEPOSITS
+        .may_load(storage, &asset.to_string())?
+        .unwrap_or_default())
+}
+
+pub fn read_total_margin(storage: &dyn Storage, asset: &AssetInfo) -> StdResult<Uint128> {
+    Ok(TOTAL_MARGIN
+        .may_load(storage, &asset.to_string())?
+        .unwrap_or_default())
+}
+
+// returns every asset's running total, zero totals included
+pub fn read_totals(
+    storage: &dyn Storage,
+    totals: &Map<&str, Uint128>,
+) -> StdResult<Vec<AssetTotal>> {
+    totals
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (asset, amount) = item?;
+            let asset = String::from_utf8(asset).map_err(StdError::invalid_utf8)?;
+            Ok(AssetTotal { asset, amount })
+        })
+        .collect()
 }
 
-impl VammList {
-    /// returns true if the address is a registered vamm
-    pub fn is_vamm(&self, addr: &str) -> bool {
-        self.vamm.iter().any(|a| a.as_ref() == addr)
+// totals the positions and deposits of a deployment from before they were
+// tracked, must run before any position is stored
+pub fn migrate_state(storage: &mut dyn Storage) -> StdResult<()> {
+    if STATE.may_load(storage)?.is_some() {
+        return Ok(());
     }
+
+    let mut state = State::default();
+    let positions = POSITIONS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, position) in positions {
+        let collateral = read_market_collateral(storage, &position.vamm)?;
+        update_total(
+            storage,
+            &TOTAL_MARGIN,
+            &collateral,
+            position.margin,
+            Uint128::zero(),
+        )?;
+        state.outstanding_notional = state.outstanding_notional.checked_add(position.notional)?;
+    }
+    let balances = COLLATERAL_BALANCES
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (_, balance) in balances {
+        update_total(
+            storage,
+            &TOTAL_DEPOSITS,
+            &balance.asset,
+            balance.amount,
+            Uint128::zero(),
+        )?;
+    }
+
+    store_state(storage, &state)
 }
 
-pub fn store_vamm(deps: DepsMut, input: &[String]) -> StdResult<()> {
-    let cfg = VammList {
-        vamm: map_validate(deps.api, input)?,
-    };
-    VAMM_LIST.save(deps.storage, &cfg)
+pub fn store_vamm(storage: &mut dyn Storage, vamm: &Addr) -> StdResult<()> {
+    VAMMS.save(storage, vamm, &true)
+}
+
+pub fn remove_vamm(storage: &mut dyn Storage, vamm: &Addr) {
+    VAMMS.remove(storage, vamm)
+}
+
+/// returns true if the address is a registered vamm
+pub fn is_vamm(storage: &dyn Storage, vamm: &Addr) -> bool {
+    VAMMS.has(storage, vamm)
+}
+
+// returns a page of registered vamms ordered by address
+pub fn read_vamms(
+    storage: &dyn Storage,
+    start_after: Option<Addr>,
+    limit: usize,
+) -> StdResult<Vec<Addr>> {
+    Ok(VAMMS
+        .keys(
+            storage,
+            start_after.map(|addr| Bound::exclusive(addr.as_bytes())),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|key| Addr::unchecked(String::from_utf8_lossy(&key)))
+        .collect())
+}
+
+// returns every registered vamm, for iterating over all markets
+pub fn read_all_vamms(storage: &dyn Storage) -> StdResult<Vec<Addr>> {
+    read_vamms(storage, None, usize::MAX)
+}
+
+pub fn store_liquidator(storage: &mut dyn Storage, liquidator: &Addr) -> StdResult<()> {
+    LIQUIDATORS.save(storage, liquidator, &true)
+}
+
+pub fn remove_liquidator(storage: &mut dyn Storage, liquidator: &Addr) {
+    LIQUIDATORS.remove(storage, liquidator)
+}
+
+/// returns true if the address is a whitelisted liquidator
+pub fn is_liquidator(storage: &dyn Storage, liquidator: &Addr) -> bool {
+    LIQUIDATORS.has(storage, liquidator)
+}
+
+// returns a page of whitelisted liquidators ordered by address
+pub fn read_liquidators(
+    storage: &dyn Storage,
+    start_after: Option<Addr>,
+    limit: usize,
+) -> StdResult<Vec<Addr>> {
+    Ok(LIQUIDATORS
+        .keys(
+            storage,
+            start_after.map(|addr| Bound::exclusive(addr.as_bytes())),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|key| Addr::unchecked(String::from_utf8_lossy(&key)))
+        .collect())
+}
+
+pub fn store_holding_cap_exemption(storage: &mut dyn Storage, trader: &Addr) -> StdResult<()> {
+    HOLDING_CAP_EXEMPTIONS.save(storage, trader, &true)
+}
+
+pub fn remove_holding_cap_exemption(storage: &mut dyn Storage, trader: &Addr) {
+    HOLDING_CAP_EXEMPTIONS.remove(storage, trader)
+}
+
+/// returns true if the trader may hold more than a market's max position notional
+pub fn is_holding_cap_exempt(storage: &dyn Storage, trader: &Addr) -> bool {
+    HOLDING_CAP_EXEMPTIONS.has(storage, trader)
+}
+
+// returns a page of the traders exempt from holding caps ordered by address
+pub fn read_holding_cap_exemptions(
+    storage: &dyn Storage,
+    start_after: Option<Addr>,
+    limit: usize,
+) -> StdResult<Vec<Addr>> {
+    Ok(HOLDING_CAP_EXEMPTIONS
+        .keys(
+            storage,
+            start_after.map(|addr| Bound::exclusive(addr.as_bytes())),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|key| Addr::unchecked(String::from_utf8_lossy(&key)))
+        .collect())
+}
+
+pub fn store_vamm_decimals(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    decimals: Uint128,
+) -> StdResult<()> {
+    VAMM_DECIMALS.save(storage, vamm, &decimals)
+}
+
+// vamms registered before their precision was recorded share the engine's
+pub fn read_vamm_decimals(storage: &dyn Storage, vamm: &Addr) -> StdResult<Uint128> {
+    match VAMM_DECIMALS.may_load(storage, vamm)? {
+        Some(decimals) => Ok(decimals),
+        None => Ok(read_config(storage)?.decimals),
+    }
+}
+
+pub fn store_market_collateral(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    collateral: &AssetInfo,
+) -> StdResult<()> {
+    MARKET_COLLATERAL.save(storage, vamm, collateral)
+}
+
+/// returns true if the market has its own collateral
+pub fn has_market_collateral(storage: &dyn Storage, vamm: &Addr) -> bool {
+    MARKET_COLLATERAL.has(storage, vamm)
+}
+
+// markets are backed by the eligible collateral unless set otherwise
+pub fn read_market_collateral(storage: &dyn Storage, vamm: &Addr) -> StdResult<AssetInfo> {
+    match MARKET_COLLATERAL.may_load(storage, vamm)? {
+        Some(collateral) => Ok(collateral),
+        None => Ok(read_config(storage)?.eligible_collateral),
+    }
+}
+
+// the collateral a market not backed by the eligible collateral holds for
+// itself, as the insurance fund only holds the eligible collateral. It takes in
+// paid funding and what liquidations leave, and covers received funding and
+// bad debt
+pub fn read_market_reserve(storage: &dyn Storage, vamm: &Addr) -> StdResult<Uint128> {
+    Ok(MARKET_RESERVES.may_load(storage, vamm)?.unwrap_or_default())
+}
+
+pub fn store_market_reserve(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    reserve: Uint128,
+) -> StdResult<()> {
+    MARKET_RESERVES.save(storage, vamm, &reserve)
+}
+
+// what the engine owes in the asset, the deposits and margin held in it, the
+// insurance fund and fee pool when it is the eligible collateral, and the
+// reserves, liquidity and referral rewards of markets it backs
+pub fn read_liabilities(storage: &dyn Storage, asset: &AssetInfo) -> StdResult<Uint128> {
+    let mut liabilities =
+        read_total_deposits(storage, asset)?.checked_add(read_total_margin(storage, asset)?)?;
+
+    if read_config(storage)?.eligible_collateral == *asset {
+        let fund = read_insurance_fund(storage)?;
+        liabilities = liabilities
+            .checked_add(fund.balance)?
+            .checked_add(fund.reserve)?
+            .checked_add(read_fee_pool(storage)?.balance)?;
+    }
+
+    let vamms = VAMMS
+        .keys(storage, None, None, Order::Ascending)
+        .map(|key| Addr::unchecked(String::from_utf8_lossy(&key)))
+        .collect::<Vec<_>>();
+    for vamm in vamms {
+        if read_market_collateral(storage, &vamm)? == *asset {
+            liabilities = liabilities
+                .checked_add(read_market_reserve(storage, &vamm)?)?
+                .checked_add(read_liquidity_pool(storage, &vamm)?.total)?;
+        }
+    }
+
+    for item in REFERRAL_REWARDS.range(storage, None, None, Order::Ascending) {
+        let (_, reward) = item?;
+        if reward.asset == *asset {
+            liabilities = liabilities.checked_add(reward.amount)?;
+        }
+    }
+
+    Ok(liabilities)
+}
+
+// collateral assets are keyed by their token address or denom
+pub fn store_collateral_asset(storage: &mut dyn Storage, asset: &CollateralAsset) -> StdResult<()> {
+    COLLATERAL_ASSETS.save(storage, &asset.asset.to_string(), asset)
+}
+
+pub fn remove_collateral_asset(storage: &mut dyn Storage, asset: &AssetInfo) {
+    COLLATERAL_ASSETS.remove(storage, &asset.to_string())
+}
+
+pub fn read_collateral_asset(
+    storage: &dyn Storage,
+    asset: &AssetInfo,
+) -> StdResult<Option<CollateralAsset>> {
+    COLLATERAL_ASSETS.may_load(storage, &asset.to_string())
+}
+
+pub fn read_collateral_assets(storage: &dyn Storage) -> StdResult<Vec<CollateralAsset>> {
+    COLLATERAL_ASSETS
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, asset)| asset))
+        .collect()
+}
+
+// empty balances are removed so that only held assets are listed
+pub fn store_collateral_balance(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    balance: &CollateralBalance,
+) -> StdResult<()> {
+    let asset = balance.asset.to_string();
+
+    let previous = read_collateral_balance(storage, trader, &balance.asset)?;
+    update_total(
+        storage,
+        &TOTAL_DEPOSITS,
+        &balance.asset,
+        balance.amount,
+        previous.amount,
+    )?;
+
+    if balance.amount.is_zero() {
+        COLLATERAL_BALANCES.remove(storage, (trader, &asset));
+        return Ok(());
+    }
+
+    COLLATERAL_BALANCES.save(storage, (trader, &asset), balance)
+}
+
+pub fn read_collateral_balance(
+    storage: &dyn Storage,
+    trader: &Addr,
+    asset: &AssetInfo,
+) -> StdResult<CollateralBalance> {
+    Ok(COLLATERAL_BALANCES
+        .may_load(storage, (trader, &asset.to_string()))?
+        .unwrap_or(CollateralBalance {
+            asset: asset.clone(),
+            amount: Uint128::zero(),
+        }))
+}
+
+pub fn read_collateral_balances(
+    storage: &dyn Storage,
+    trader: &Addr,
+) -> StdResult<Vec<CollateralBalance>> {
+    COLLATERAL_BALANCES
+        .prefix(trader)
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, balance)| balance))
+        .collect()
+}
+
+// fee ratios that replace the vamm's own for a market, None keeps the vamm's
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MarketFees {
+    pub toll_ratio: Option<Uint128>,
+    pub spread_ratio: Option<Uint128>,
+}
+
+pub fn store_market_fees(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    fees: &MarketFees,
+) -> StdResult<()> {
+    if fees == &MarketFees::default() {
+        MARKET_FEES.remove(storage, vamm);
+        return Ok(());
+    }
+
+    MARKET_FEES.save(storage, vamm, fees)
+}
+
+pub fn read_market_fees(storage: &dyn Storage, vamm: &Addr) -> StdResult<MarketFees> {
+    Ok(MARKET_FEES.may_load(storage, vamm)?.unwrap_or_default())
+}
+
+// limits on a market's total open notional, on the base asset size of a
+// single position and on the notional a single trader holds, None leaves the
+// limit off
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct MarketCaps {
+    pub open_interest_notional_cap: Option<Uint128>,
+    pub max_position_size: Option<Uint128>,
+    #[serde(default)]
+    pub max_position_notional: Option<Uint128>,
+}
+
+pub fn store_market_caps(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    caps: &MarketCaps,
+) -> StdResult<()> {
+    if caps == &MarketCaps::default() {
+        MARKET_CAPS.remove(storage, vamm);
+        return Ok(());
+    }
+
+    MARKET_CAPS.save(storage, vamm, caps)
+}
+
+// the terms positions in a shut down market are settled on, fixed by the first
+// settlement. Each position is paid its value at the settlement price scaled
+// by the payout ratio, which is below one if the margin held for the market
+// cannot cover every position
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Settlement {
+    pub settlement_price: Uint128,
+    pub payout_ratio: Uint128,
+}
+
+pub fn store_settlement(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    settlement: &Settlement,
+) -> StdResult<()> {
+    SETTLEMENTS.save(storage, vamm, settlement)
+}
+
+pub fn read_settlement(storage: &dyn Storage, vamm: &Addr) -> StdResult<Option<Settlement>> {
+    SETTLEMENTS.may_load(storage, vamm)
+}
+
+pub fn read_market_caps(storage: &dyn Storage, vamm: &Addr) -> StdResult<MarketCaps> {
+    Ok(MARKET_CAPS.may_load(storage, vamm)?.unwrap_or_default())
 }
 
-pub fn read_vamm(storage: &dyn Storage) -> StdResult<VammList> {
-    VAMM_LIST.load(storage)
+pub fn store_risk_params(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    params: &RiskParams,
+) -> StdResult<()> {
+    if params == &RiskParams::default() {
+        RISK_PARAMS.remove(storage, vamm);
+        return Ok(());
+    }
+
+    RISK_PARAMS.save(storage, vamm, params)
+}
+
+pub fn read_risk_params(storage: &dyn Storage, vamm: &Addr) -> StdResult<RiskParams> {
+    Ok(RISK_PARAMS.may_load(storage, vamm)?.unwrap_or_default())
 }
 
 pub fn map_validate(api: &dyn Api, input: &[String]) -> StdResult<Vec<Addr>> {
@@ -70,11 +579,23 @@ pub struct Position {
     pub size: Uint128,
     pub margin: Uint128,
     pub notional: Uint128,
-    pub premium_fraction: Uint128,
+    // the cumulative premium fraction its funding was last settled at
+    #[serde(alias = "premium_fraction")]
+    pub last_updated_premium_fraction: Integer,
     pub liquidity_history_index: Uint128,
     pub timestamp: Timestamp,
 }
 
+impl Position {
+    // the size of the position, negative for shorts
+    pub fn signed_size(&self) -> Integer {
+        match self.direction {
+            Direction::AddToAmm => Integer::new_positive(self.size),
+            Direction::RemoveFromAmm => Integer::new_negative(self.size),
+        }
+    }
+}
+
 impl Default for Position {
     fn default() -> Position {
         Position {
@@ -84,71 +605,1201 @@ impl Default for Position {
             size: Uint128::zero(),
             margin: Uint128::zero(),
             notional: Uint128::zero(),
-            premium_fraction: Uint128::zero(),
+            last_updated_premium_fraction: Integer::zero(),
             liquidity_history_index: Uint128::zero(),
             timestamp: Timestamp::from_seconds(0),
         }
     }
 }
 
-fn position_bucket(storage: &mut dyn Storage) -> Bucket<Position> {
-    bucket(storage, KEY_POSITION)
+pub fn store_position(storage: &mut dyn Storage, position: &Position) -> StdResult<()> {
+    let previous = read_position(storage, &position.vamm, &position.trader)?.unwrap_or_default();
+    let collateral = read_market_collateral(storage, &position.vamm)?;
+    update_total(
+        storage,
+        &TOTAL_MARGIN,
+        &collateral,
+        position.margin,
+        previous.margin,
+    )?;
+    let mut state = read_state(storage)?;
+    state.outstanding_notional = state
+        .outstanding_notional
+        .checked_add(position.notional)?
+        .checked_sub(previous.notional)?;
+    store_state(storage, &state)?;
+
+    POSITIONS.save(storage, (&position.vamm, &position.trader), position)
+}
+
+pub fn read_position(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+) -> StdResult<Option<Position>> {
+    POSITIONS.may_load(storage, (vamm, trader))
+}
+
+// returns every position held in the vamm, closed positions included
+pub fn read_vamm_positions(storage: &dyn Storage, vamm: &Addr) -> StdResult<Vec<Position>> {
+    POSITIONS
+        .prefix(vamm)
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, position)| position))
+        .collect()
 }
 
-fn position_bucket_read(storage: &dyn Storage) -> ReadonlyBucket<Position> {
-    bucket_read(storage, KEY_POSITION)
+// returns a page of the open positions in the vamm ordered by trader
+pub fn read_open_positions(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    start_after: Option<Addr>,
+    limit: usize,
+) -> StdResult<Vec<Position>> {
+    POSITIONS
+        .prefix(vamm)
+        .range(
+            storage,
+            start_after.map(|trader| Bound::exclusive(trader.as_bytes())),
+            None,
+            Order::Ascending,
+        )
+        .filter(|item| match item {
+            Ok((_, position)) => !position.size.is_zero(),
+            Err(_) => true,
+        })
+        .take(limit)
+        .map(|item| item.map(|(_, position)| position))
+        .collect()
 }
 
-pub fn store_position(storage: &mut dyn Storage, position: &Position) -> StdResult<()> {
-    // hash the vAMM and trader together to get a unique position key
-    let mut hasher = Sha3_256::new();
+pub fn remove_position(storage: &mut dyn Storage, vamm: &Addr, trader: &Addr) -> StdResult<()> {
+    if let Some(position) = read_position(storage, vamm, trader)? {
+        let collateral = read_market_collateral(storage, vamm)?;
+        update_total(
+            storage,
+            &TOTAL_MARGIN,
+            &collateral,
+            Uint128::zero(),
+            position.margin,
+        )?;
+        let mut state = read_state(storage)?;
+        state.outstanding_notional = state.outstanding_notional.checked_sub(position.notional)?;
+        store_state(storage, &state)?;
+    }
 
-    // write input message
-    hasher.update(position.vamm.as_bytes());
-    hasher.update(position.trader.as_bytes());
+    POSITIONS.remove(storage, (vamm, trader));
+    Ok(())
+}
+
+// Deployments from before the contract was versioned kept the config in a
+// cosmwasm-storage singleton and positions in a bucket keyed by a hash of the
+// vamm and trader. Both prefix their namespace the same way a map does, so
+// they are read as maps here, the singleton under an empty key
+const LEGACY_CONFIG: Map<&[u8], Config> = Map::new("config");
+const LEGACY_POSITIONS: Map<&[u8], Position> = Map::new("position");
+
+// moves the config and positions of a legacy deployment into the current
+// layout, positions are re-keyed by the vamm and trader they hold
+pub fn migrate_legacy_storage(storage: &mut dyn Storage) -> StdResult<()> {
+    if let Some(config) = LEGACY_CONFIG.may_load(storage, b"")? {
+        CONFIG.save(storage, &config)?;
+        LEGACY_CONFIG.remove(storage, b"");
+    }
 
-    // read hash digest
-    let hash = hasher.finalize();
+    let legacy = LEGACY_POSITIONS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(Vec<u8>, Position)>>>()?;
+    for (key, position) in legacy {
+        store_position(storage, &position)?;
+        LEGACY_POSITIONS.remove(storage, &key);
+    }
 
-    position_bucket(storage).save(&hash, position)
+    Ok(())
 }
 
-pub fn read_position(
+// re-keys a trader's position to another trader, who must not hold an open
+// position in the market
+pub fn move_position(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    from: &Addr,
+    to: &Addr,
+) -> StdResult<()> {
+    if let Some(existing) = read_position(storage, vamm, to)? {
+        if !existing.size.is_zero() {
+            return Err(StdError::generic_err(
+                "recipient already has a position in this market",
+            ));
+        }
+    }
+
+    let mut position = match read_position(storage, vamm, from)? {
+        Some(position) => position,
+        None => return Err(StdError::generic_err("trader has no open position")),
+    };
+    remove_position(storage, vamm, from)?;
+
+    position.trader = to.clone();
+    store_position(storage, &position)
+}
+
+// a position a trader has offered to another, keyed by vamm and the offering trader
+pub fn store_position_transfer(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    from: &Addr,
+    to: &Addr,
+) -> StdResult<()> {
+    POSITION_TRANSFERS.save(storage, (vamm, from), to)
+}
+
+pub fn remove_position_transfer(storage: &mut dyn Storage, vamm: &Addr, from: &Addr) {
+    POSITION_TRANSFERS.remove(storage, (vamm, from))
+}
+
+pub fn read_position_transfer(
     storage: &dyn Storage,
     vamm: &Addr,
-    trader: &Addr,
-) -> StdResult<Option<Position>> {
-    // hash the vAMM and trader together to get a unique position key
-    let mut hasher = Sha3_256::new();
+    from: &Addr,
+) -> StdResult<Option<Addr>> {
+    POSITION_TRANSFERS.may_load(storage, (vamm, from))
+}
 
-    // write input message
-    hasher.update(vamm.as_bytes());
-    hasher.update(trader.as_bytes());
+// the cw721 contract positions are minted as tokens on, the engine is its minter
+pub fn store_position_nft(storage: &mut dyn Storage, contract: &Addr) -> StdResult<()> {
+    POSITION_NFT.save(storage, contract)
+}
 
-    // read hash digest
-    let hash = hasher.finalize();
-    position_bucket_read(storage).may_load(&hash)
+pub fn read_position_nft(storage: &dyn Storage) -> StdResult<Option<Addr>> {
+    POSITION_NFT.may_load(storage)
 }
 
+// a position wrapped as a token, whilst wrapped it is controlled by the holder
+// of the token rather than the trader it is stored under
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct Swap {
+pub struct PositionToken {
     pub vamm: Addr,
     pub trader: Addr,
-    pub side: Side,
-    pub quote_asset_amount: Uint128,
-    pub leverage: Uint128,
-    pub open_notional: Uint128,
 }
 
-pub fn store_tmp_swap(storage: &mut dyn Storage, swap: &Swap) -> StdResult<()> {
-    singleton(storage, KEY_TMP_SWAP).save(swap)
+pub fn next_position_token_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let id = POSITION_TOKEN_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+    POSITION_TOKEN_COUNT.save(storage, &id)?;
+
+    Ok(id)
+}
+
+pub fn store_position_token(
+    storage: &mut dyn Storage,
+    token_id: &str,
+    token: &PositionToken,
+) -> StdResult<()> {
+    WRAPPED_POSITIONS.save(storage, (&token.vamm, &token.trader), &token_id.to_string())?;
+    POSITION_TOKENS.save(storage, token_id, token)
+}
+
+pub fn remove_position_token(storage: &mut dyn Storage, token_id: &str, token: &PositionToken) {
+    WRAPPED_POSITIONS.remove(storage, (&token.vamm, &token.trader));
+    POSITION_TOKENS.remove(storage, token_id)
+}
+
+pub fn read_position_token(
+    storage: &dyn Storage,
+    token_id: &str,
+) -> StdResult<Option<PositionToken>> {
+    POSITION_TOKENS.may_load(storage, token_id)
+}
+
+// returns the id of the token a position is wrapped as, if any
+pub fn read_wrapped_position(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+) -> StdResult<Option<String>> {
+    WRAPPED_POSITIONS.may_load(storage, (vamm, trader))
+}
+
+pub fn has_position_tokens(storage: &dyn Storage) -> bool {
+    POSITION_TOKENS
+        .keys(storage, None, None, Order::Ascending)
+        .next()
+        .is_some()
+}
+
+pub fn add_fees_paid(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+    fees: Uint128,
+) -> StdResult<()> {
+    let paid = read_fees_paid(storage, vamm, trader)?.checked_add(fees)?;
+    FEES_PAID.save(storage, (vamm, trader), &paid)
+}
+
+// the trading fees a trader has paid on a vamm over the life of their positions
+pub fn read_fees_paid(storage: &dyn Storage, vamm: &Addr, trader: &Addr) -> StdResult<Uint128> {
+    Ok(FEES_PAID
+        .may_load(storage, (vamm, trader))?
+        .unwrap_or_default())
+}
+
+// markets with commit-reveal enabled only accept revealed orders, the value
+// is the number of blocks a commitment stays valid for
+pub fn store_commit_reveal(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    expiry_blocks: Option<u64>,
+) -> StdResult<()> {
+    match expiry_blocks {
+        Some(expiry_blocks) => COMMIT_REVEAL.save(storage, vamm, &expiry_blocks),
+        None => {
+            COMMIT_REVEAL.remove(storage, vamm);
+            Ok(())
+        }
+    }
+}
+
+pub fn read_commit_reveal(storage: &dyn Storage, vamm: &Addr) -> StdResult<Option<u64>> {
+    COMMIT_REVEAL.may_load(storage, vamm)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrderCommitment {
+    pub commitment: Binary,
+    pub height: u64,
+}
+
+pub fn store_order_commitment(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+    commitment: &OrderCommitment,
+) -> StdResult<()> {
+    ORDER_COMMITMENTS.save(storage, (vamm, trader), commitment)
+}
+
+pub fn remove_order_commitment(storage: &mut dyn Storage, vamm: &Addr, trader: &Addr) {
+    ORDER_COMMITMENTS.remove(storage, (vamm, trader))
+}
+
+pub fn read_order_commitment(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+) -> StdResult<Option<OrderCommitment>> {
+    ORDER_COMMITMENTS.may_load(storage, (vamm, trader))
+}
+
+// a smart contract permitted to trade, such as a vault, optionally capped in
+// the total notional it may hold across all markets
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ContractTrader {
+    pub notional_cap: Option<Uint128>,
+}
+
+pub fn store_contract_trader(
+    storage: &mut dyn Storage,
+    contract: &Addr,
+    trader: &ContractTrader,
+) -> StdResult<()> {
+    CONTRACT_TRADERS.save(storage, contract, trader)
+}
+
+pub fn remove_contract_trader(storage: &mut dyn Storage, contract: &Addr) {
+    CONTRACT_TRADERS.remove(storage, contract)
+}
+
+pub fn read_contract_trader(
+    storage: &dyn Storage,
+    contract: &Addr,
+) -> StdResult<Option<ContractTrader>> {
+    CONTRACT_TRADERS.may_load(storage, contract)
+}
+
+// keyed by the trader and then the delegate it granted the notional cap to
+pub fn store_trading_permission(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    delegate: &Addr,
+    notional_cap: Uint128,
+) -> StdResult<()> {
+    TRADING_PERMISSIONS.save(storage, (trader, delegate), &notional_cap)
+}
+
+pub fn remove_trading_permission(storage: &mut dyn Storage, trader: &Addr, delegate: &Addr) {
+    TRADING_PERMISSIONS.remove(storage, (trader, delegate))
+}
+
+pub fn read_trading_permission(
+    storage: &dyn Storage,
+    trader: &Addr,
+    delegate: &Addr,
+) -> StdResult<Option<Uint128>> {
+    TRADING_PERMISSIONS.may_load(storage, (trader, delegate))
+}
+
+pub fn store_signing_key(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    pubkey: Option<&Binary>,
+) -> StdResult<()> {
+    match pubkey {
+        Some(pubkey) => SIGNING_KEYS.save(storage, trader, pubkey),
+        None => {
+            SIGNING_KEYS.remove(storage, trader);
+            Ok(())
+        }
+    }
+}
+
+pub fn read_signing_key(storage: &dyn Storage, trader: &Addr) -> StdResult<Option<Binary>> {
+    SIGNING_KEYS.may_load(storage, trader)
 }
 
-pub fn remove_tmp_swap(storage: &mut dyn Storage) {
-    let mut store: Singleton<Swap> = singleton(storage, KEY_TMP_SWAP);
-    store.remove()
+// nonces outlive the signing key, so orders signed by a key that is removed
+// and registered again cannot be replayed
+pub fn store_order_nonce(storage: &mut dyn Storage, trader: &Addr, nonce: u64) -> StdResult<()> {
+    ORDER_NONCES.save(storage, trader, &nonce)
+}
+
+pub fn read_order_nonce(storage: &dyn Storage, trader: &Addr) -> StdResult<u64> {
+    Ok(ORDER_NONCES.may_load(storage, trader)?.unwrap_or_default())
+}
+
+// returns a page of contract traders ordered by address
+pub fn read_contract_traders(
+    storage: &dyn Storage,
+    start_after: Option<Addr>,
+    limit: usize,
+) -> StdResult<Vec<(Addr, ContractTrader)>> {
+    CONTRACT_TRADERS
+        .range(
+            storage,
+            start_after.map(|addr| Bound::exclusive(addr.as_bytes())),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            item.map(|(key, trader)| (Addr::unchecked(String::from_utf8_lossy(&key)), trader))
+        })
+        .collect()
+}
+
+// an order waiting to be filled by a keeper at a later block's price
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QueuedOrder {
+    pub trade: QueuedTrade,
+    pub height: u64,
+    pub deadline: Option<Timestamp>,
 }
 
-pub fn read_tmp_swap(storage: &dyn Storage) -> StdResult<Option<Swap>> {
-    singleton_read(storage, KEY_TMP_SWAP).load()
+pub fn store_queued_order(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+    order: &QueuedOrder,
+) -> StdResult<()> {
+    QUEUED_ORDERS.save(storage, (vamm, trader), order)
+}
+
+pub fn remove_queued_order(storage: &mut dyn Storage, vamm: &Addr, trader: &Addr) {
+    QUEUED_ORDERS.remove(storage, (vamm, trader))
+}
+
+pub fn read_queued_order(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+) -> StdResult<Option<QueuedOrder>> {
+    QUEUED_ORDERS.may_load(storage, (vamm, trader))
+}
+
+// the trigger prices at which a keeper may close a trader's position, and the
+// size each closes, the whole position if none. Linked orders are a pair where
+// executing either cancels the other
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct TriggerOrders {
+    pub stop_loss: Option<Uint128>,
+    pub take_profit: Option<Uint128>,
+    #[serde(default)]
+    pub stop_loss_size: Option<Uint128>,
+    #[serde(default)]
+    pub take_profit_size: Option<Uint128>,
+    #[serde(default)]
+    pub linked: bool,
+}
+
+pub fn store_trigger_orders(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+    orders: &TriggerOrders,
+) -> StdResult<()> {
+    if orders.stop_loss.is_none() && orders.take_profit.is_none() {
+        TRIGGER_ORDERS.remove(storage, (vamm, trader));
+        return Ok(());
+    }
+
+    TRIGGER_ORDERS.save(storage, (vamm, trader), orders)
+}
+
+pub fn remove_trigger_orders(storage: &mut dyn Storage, vamm: &Addr, trader: &Addr) {
+    TRIGGER_ORDERS.remove(storage, (vamm, trader))
+}
+
+pub fn read_trigger_orders(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    trader: &Addr,
+) -> StdResult<TriggerOrders> {
+    Ok(TRIGGER_ORDERS
+        .may_load(storage, (vamm, trader))?
+        .unwrap_or_default())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Swap {
+    pub vamm: Addr,
+    pub trader: Addr,
+    pub side: Side,
+    pub quote_asset_amount: Uint128,
+    pub leverage: Uint128,
+    pub open_notional: Uint128,
+    pub base_asset_limit: Option<Uint128>,
+    pub quote_asset_limit: Option<Uint128>,
+    // the fees charged up front, reported with the position change
+    pub fee: Uint128,
+}
+
+// stores a swap until its reply arrives, returning the id the reply carries
+pub fn store_tmp_swap(storage: &mut dyn Storage, swap: &Swap) -> StdResult<u64> {
+    let swap_id = TMP_SWAP_COUNTER.may_load(storage)?.unwrap_or_default() + 1;
+    TMP_SWAP_COUNTER.save(storage, &swap_id)?;
+    TMP_SWAPS.save(storage, U64Key::new(swap_id), swap)?;
+
+    Ok(swap_id)
+}
+
+pub fn update_tmp_swap(storage: &mut dyn Storage, swap_id: u64, swap: &Swap) -> StdResult<()> {
+    TMP_SWAPS.save(storage, U64Key::new(swap_id), swap)
+}
+
+pub fn remove_tmp_swap(storage: &mut dyn Storage, swap_id: u64) {
+    TMP_SWAPS.remove(storage, U64Key::new(swap_id))
+}
+
+pub fn read_tmp_swap(storage: &dyn Storage, swap_id: u64) -> StdResult<Option<Swap>> {
+    TMP_SWAPS.may_load(storage, U64Key::new(swap_id))
+}
+
+// the liquidator is kept with the swap it is paid for, a crank liquidates
+// several positions at once
+pub fn store_tmp_liquidator(
+    storage: &mut dyn Storage,
+    swap_id: u64,
+    liquidator: &Addr,
+) -> StdResult<()> {
+    TMP_LIQUIDATORS.save(storage, U64Key::new(swap_id), liquidator)
+}
+
+pub fn remove_tmp_liquidator(storage: &mut dyn Storage, swap_id: u64) {
+    TMP_LIQUIDATORS.remove(storage, U64Key::new(swap_id))
+}
+
+pub fn read_tmp_liquidator(storage: &dyn Storage, swap_id: u64) -> StdResult<Option<Addr>> {
+    TMP_LIQUIDATORS.may_load(storage, U64Key::new(swap_id))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListingConfig {
+    pub vamm_code_id: u64,
+    pub bond: Uint128,
+    pub challenge_period: u64,
+}
+
+pub fn store_listing_config(storage: &mut dyn Storage, config: &ListingConfig) -> StdResult<()> {
+    LISTING_CONFIG.save(storage, config)
+}
+
+pub fn read_listing_config(storage: &dyn Storage) -> StdResult<Option<ListingConfig>> {
+    LISTING_CONFIG.may_load(storage)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketProposal {
+    pub proposal_id: u64,
+    pub proposer: Addr,
+    pub bond: Uint128,
+    pub market: VammInstantiateMsg,
+    pub created_at: Timestamp,
+    pub status: ProposalStatus,
+    pub vamm: Option<Addr>,
+}
+
+pub fn store_market_proposal(
+    storage: &mut dyn Storage,
+    proposal: &MarketProposal,
+) -> StdResult<()> {
+    MARKET_PROPOSALS.save(storage, U64Key::new(proposal.proposal_id), proposal)
+}
+
+pub fn read_market_proposal(storage: &dyn Storage, proposal_id: u64) -> StdResult<MarketProposal> {
+    MARKET_PROPOSALS.load(storage, U64Key::new(proposal_id))
+}
+
+// returns the next proposal id, starting from 1
+pub fn next_market_proposal_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let id = MARKET_PROPOSAL_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+    MARKET_PROPOSAL_COUNT.save(storage, &id)?;
+
+    Ok(id)
+}
+
+pub fn store_tmp_listing(storage: &mut dyn Storage, proposal_id: u64) -> StdResult<()> {
+    TMP_LISTING.save(storage, &proposal_id)
+}
+
+pub fn remove_tmp_listing(storage: &mut dyn Storage) {
+    TMP_LISTING.remove(storage)
+}
+
+pub fn read_tmp_listing(storage: &dyn Storage) -> StdResult<Option<u64>> {
+    TMP_LISTING.may_load(storage)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeCampaign {
+    pub campaign_id: u64,
+    pub start: u64,
+    pub end: u64,
+    pub vamms: Option<Vec<Addr>>,
+    pub discount: Uint128,
+}
+
+impl FeeCampaign {
+    /// returns true if the campaign discounts fees of the vamm at the given time
+    pub fn applies(&self, vamm: &Addr, time: u64) -> bool {
+        let in_market = match &self.vamms {
+            Some(vamms) => vamms.contains(vamm),
+            None => true,
+        };
+
+        in_market && self.start <= time && time < self.end
+    }
+}
+
+pub fn store_fee_campaign(storage: &mut dyn Storage, campaign: &FeeCampaign) -> StdResult<()> {
+    FEE_CAMPAIGNS.save(storage, U64Key::new(campaign.campaign_id), campaign)
+}
+
+pub fn delete_fee_campaign(storage: &mut dyn Storage, campaign_id: u64) {
+    FEE_CAMPAIGNS.remove(storage, U64Key::new(campaign_id))
+}
+
+pub fn read_fee_campaigns(storage: &dyn Storage) -> StdResult<Vec<FeeCampaign>> {
+    FEE_CAMPAIGNS
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, campaign)| campaign))
+        .collect()
+}
+
+// returns a page of campaigns ordered by id
+pub fn read_fee_campaigns_page(
+    storage: &dyn Storage,
+    start_after: Option<u64>,
+    limit: usize,
+) -> StdResult<Vec<FeeCampaign>> {
+    FEE_CAMPAIGNS
+        .range(
+            storage,
+            start_after.map(Bound::exclusive_int),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, campaign)| campaign))
+        .collect()
+}
+
+// returns the next fee campaign id, starting from 1
+pub fn next_fee_campaign_id(storage: &mut dyn Storage) -> StdResult<u64> {
+    let id = FEE_CAMPAIGN_COUNT.may_load(storage)?.unwrap_or_default() + 1;
+    FEE_CAMPAIGN_COUNT.save(storage, &id)?;
+
+    Ok(id)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct AccountSettings {
+    pub max_slippage: Option<Uint128>,
+    pub cross_margin: bool,
+}
+
+pub fn store_account_settings(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    settings: &AccountSettings,
+) -> StdResult<()> {
+    ACCOUNT_SETTINGS.save(storage, trader, settings)
+}
+
+// returns the trader's settings, or the defaults if they never set any
+pub fn read_account_settings(storage: &dyn Storage, trader: &Addr) -> StdResult<AccountSettings> {
+    Ok(ACCOUNT_SETTINGS
+        .may_load(storage, trader)?
+        .unwrap_or_default())
+}
+
+pub fn store_margin_tiers(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    tiers: &[MarginTier],
+) -> StdResult<()> {
+    MARGIN_TIERS.save(storage, vamm, &tiers.to_vec())
+}
+
+// returns the vamm's margin tiers ordered by notional, empty if none are set
+pub fn read_margin_tiers(storage: &dyn Storage, vamm: &Addr) -> StdResult<Vec<MarginTier>> {
+    Ok(MARGIN_TIERS.may_load(storage, vamm)?.unwrap_or_default())
+}
+
+// returns the total notional of all positions open on the vamm
+pub fn read_open_interest(storage: &dyn Storage, vamm: &Addr) -> StdResult<Uint128> {
+    Ok(OPEN_INTEREST.may_load(storage, vamm)?.unwrap_or_default())
+}
+
+pub fn increase_open_interest(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    amount: Uint128,
+) -> StdResult<()> {
+    let open_interest = read_open_interest(storage, vamm)?.checked_add(amount)?;
+    OPEN_INTEREST.save(storage, vamm, &open_interest)
+}
+
+pub fn decrease_open_interest(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    amount: Uint128,
+) -> StdResult<()> {
+    let open_interest = read_open_interest(storage, vamm)?.saturating_sub(amount);
+    OPEN_INTEREST.save(storage, vamm, &open_interest)
+}
+
+// the premium longs have paid shorts per unit of size over all funding rounds
+// of a vamm, negative when shorts have paid more
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct Funding {
+    pub cumulative_premium_fraction: Integer,
+    pub last_funding_time: u64,
+    // the number of funding rounds paid
+    #[serde(default)]
+    pub rounds: u64,
+}
+
+pub fn store_funding(storage: &mut dyn Storage, vamm: &Addr, funding: &Funding) -> StdResult<()> {
+    FUNDING.save(storage, vamm, funding)
+}
+
+pub fn read_funding(storage: &dyn Storage, vamm: &Addr) -> StdResult<Funding> {
+    Ok(FUNDING.may_load(storage, vamm)?.unwrap_or_default())
+}
+
+pub fn store_funding_record(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    record: &FundingRecord,
+) -> StdResult<()> {
+    FUNDING_HISTORY.save(storage, (vamm, U64Key::new(record.round)), record)
+}
+
+// returns the funding rounds of a vamm after start_after, oldest first
+pub fn read_funding_history(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    start_after: Option<u64>,
+    limit: usize,
+) -> StdResult<Vec<FundingRecord>> {
+    FUNDING_HISTORY
+        .prefix(vamm)
+        .range(
+            storage,
+            start_after.map(Bound::exclusive_int),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, record)| record))
+        .collect()
+}
+
+// the bad debt realized in a market once the margin of the positions it came
+// from, the trader's other margin and their collateral had been used up
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct BadDebt {
+    pub realized: Uint128,
+    pub insured: Uint128,
+    pub uncovered: Uint128,
+}
+
+pub fn store_bad_debt(storage: &mut dyn Storage, vamm: &Addr, bad_debt: &BadDebt) -> StdResult<()> {
+    BAD_DEBT.save(storage, vamm, bad_debt)
+}
+
+pub fn read_bad_debt(storage: &dyn Storage, vamm: &Addr) -> StdResult<BadDebt> {
+    Ok(BAD_DEBT.may_load(storage, vamm)?.unwrap_or_default())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OpenInterestCap {
+    pub share: Uint128,
+    pub floor: Uint128,
+}
+
+pub fn store_open_interest_cap(storage: &mut dyn Storage, cap: &OpenInterestCap) -> StdResult<()> {
+    OPEN_INTEREST_CAP.save(storage, cap)
+}
+
+pub fn read_open_interest_cap(storage: &dyn Storage) -> StdResult<Option<OpenInterestCap>> {
+    OPEN_INTEREST_CAP.may_load(storage)
+}
+
+// The staked balance is tracked with an index, the value of one unit staked
+// at the start of the epoch. Fees raise the index and losses lower it, so a
+// stake is worth principal * index / the index when it was last settled. A
+// loss that wipes out the stakers starts a new epoch, zeroing older stakes.
+// The reserve is protocol owned and only covers losses beyond the stakers
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InsuranceFund {
+    pub balance: Uint128,
+    pub reserve: Uint128,
+    pub index: Uint128,
+    pub epoch: u64,
+    pub unstake_cooldown: u64,
+}
+
+impl InsuranceFund {
+    pub fn new(decimals: Uint128) -> Self {
+        InsuranceFund {
+            balance: Uint128::zero(),
+            reserve: Uint128::zero(),
+            index: decimals,
+            epoch: 0u64,
+            unstake_cooldown: 0u64,
+        }
+    }
+
+    /// returns the staked balance and the reserve together
+    pub fn total(&self) -> StdResult<Uint128> {
+        Ok(self.balance.checked_add(self.reserve)?)
+    }
+
+    /// returns the current value of a stake
+    pub fn value_of(&self, stake: &InsuranceStake) -> Uint128 {
+        if stake.epoch != self.epoch || stake.index.is_zero() {
+            return Uint128::zero();
+        }
+
+        stake
+            .principal
+            .multiply_ratio(self.index, stake.index)
+            .min(self.balance)
+    }
+
+    /// shares fees between all stakers pro rata
+    pub fn distribute(&mut self, fees: Uint128) -> StdResult<()> {
+        if self.balance.is_zero() {
+            return Err(StdError::generic_err("insurance fund has no stakers"));
+        }
+
+        let balance = self.balance.checked_add(fees)?;
+        self.index = self.index.multiply_ratio(balance, self.balance);
+        self.balance = balance;
+
+        Ok(())
+    }
+
+    /// takes a loss pro rata from all stakers, then from the reserve, and
+    /// returns the part the fund could not cover, this is only reached once
+    /// bad debt is realised
+    pub fn absorb_loss(&mut self, loss: Uint128, decimals: Uint128) -> Uint128 {
+        if loss < self.balance {
+            let balance = self.balance - loss;
+            self.index = self.index.multiply_ratio(balance, self.balance);
+            self.balance = balance;
+
+            return Uint128::zero();
+        }
+
+        let remaining = loss - self.balance;
+        if !self.balance.is_zero() {
+            self.balance = Uint128::zero();
+            self.index = decimals;
+            self.epoch += 1;
+        }
+
+        let covered = remaining.min(self.reserve);
+        self.reserve -= covered;
+
+        remaining - covered
+    }
+}
+
+pub fn store_insurance_fund(storage: &mut dyn Storage, fund: &InsuranceFund) -> StdResult<()> {
+    INSURANCE_FUND.save(storage, fund)
+}
+
+pub fn read_insurance_fund(storage: &dyn Storage) -> StdResult<InsuranceFund> {
+    INSURANCE_FUND.load(storage)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UnstakeRequest {
+    pub amount: Uint128,
+    pub available_at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct InsuranceStake {
+    pub principal: Uint128,
+    pub index: Uint128,
+    pub epoch: u64,
+    pub unstake: Option<UnstakeRequest>,
+}
+
+pub fn store_insurance_stake(
+    storage: &mut dyn Storage,
+    staker: &Addr,
+    stake: &InsuranceStake,
+) -> StdResult<()> {
+    INSURANCE_STAKES.save(storage, staker, stake)
+}
+
+pub fn read_insurance_stake(storage: &dyn Storage, staker: &Addr) -> StdResult<InsuranceStake> {
+    Ok(INSURANCE_STAKES
+        .may_load(storage, staker)?
+        .unwrap_or_default())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct FeePool {
+    pub balance: Uint128,
+    pub insurance_share: Uint128,
+    pub insurance_floor: Option<InsuranceFloor>,
+    // paid to whoever calls a permissionless maintenance message
+    #[serde(default)]
+    pub keeper_reward: Uint128,
+}
+
+pub fn store_fee_pool(storage: &mut dyn Storage, pool: &FeePool) -> StdResult<()> {
+    FEE_POOL.save(storage, pool)
+}
+
+pub fn read_fee_pool(storage: &dyn Storage) -> StdResult<FeePool> {
+    Ok(FEE_POOL.may_load(storage)?.unwrap_or_default())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DailyStats {
+    pub day: u64,
+    pub volume: Uint128,
+    pub peak_open_interest: Uint128,
+    pub fees: Uint128,
+}
+
+// adds a trade to the stats of the day it happened on, days are counted
+// from the unix epoch, call after the open interest has been updated
+pub fn record_daily_stats(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    time: Timestamp,
+    volume: Uint128,
+) -> StdResult<()> {
+    let open_interest = read_open_interest(storage, vamm)?;
+    let mut stats = read_day_stats(storage, vamm, time)?;
+    stats.volume = stats.volume.checked_add(volume)?;
+    stats.peak_open_interest = stats.peak_open_interest.max(open_interest);
+
+    DAILY_STATS.save(storage, (vamm, U64Key::new(stats.day)), &stats)
+}
+
+// adds the trading fees charged on a trade to the stats of its day
+pub fn record_daily_fees(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    time: Timestamp,
+    fees: Uint128,
+) -> StdResult<()> {
+    let mut stats = read_day_stats(storage, vamm, time)?;
+    stats.fees = stats.fees.checked_add(fees)?;
+
+    DAILY_STATS.save(storage, (vamm, U64Key::new(stats.day)), &stats)
+}
+
+fn read_day_stats(storage: &dyn Storage, vamm: &Addr, time: Timestamp) -> StdResult<DailyStats> {
+    let day = time.seconds() / SECONDS_PER_DAY;
+
+    Ok(DAILY_STATS
+        .may_load(storage, (vamm, U64Key::new(day)))?
+        .unwrap_or(DailyStats {
+            day,
+            volume: Uint128::zero(),
+            peak_open_interest: Uint128::zero(),
+            fees: Uint128::zero(),
+        }))
+}
+
+// returns the stats of days with activity after start_after, up to and
+// including the end day
+pub fn read_daily_stats(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    start_after: Option<u64>,
+    end_day: Option<u64>,
+    limit: usize,
+) -> StdResult<Vec<DailyStats>> {
+    DAILY_STATS
+        .prefix(vamm)
+        .range(
+            storage,
+            start_after.map(Bound::exclusive_int),
+            end_day.map(Bound::inclusive_int),
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, stats)| stats))
+        .collect()
+}
+
+// appends a trade to the trader's history, numbering it and dropping the
+// oldest trade once the history is full
+pub fn record_trade(storage: &mut dyn Storage, trade: TradeRecord) -> StdResult<()> {
+    let trader = trade.trader.clone();
+    let trade_id = TRADE_COUNT.may_load(storage, &trader)?.unwrap_or_default() + 1;
+    TRADE_COUNT.save(storage, &trader, &trade_id)?;
+
+    if trade_id > MAX_TRADE_HISTORY {
+        TRADE_HISTORY.remove(
+            storage,
+            (&trader, U64Key::new(trade_id - MAX_TRADE_HISTORY)),
+        );
+    }
+    TRADE_HISTORY.save(
+        storage,
+        (&trader, U64Key::new(trade_id)),
+        &TradeRecord { trade_id, ..trade },
+    )
+}
+
+// returns the trader's trades after start_after, oldest first
+pub fn read_trade_history(
+    storage: &dyn Storage,
+    trader: &Addr,
+    start_after: Option<u64>,
+    limit: usize,
+) -> StdResult<Vec<TradeRecord>> {
+    TRADE_HISTORY
+        .prefix(trader)
+        .range(
+            storage,
+            start_after.map(Bound::exclusive_int),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| item.map(|(_, trade)| trade))
+        .collect()
+}
+
+// the fee tiers are kept ordered by their minimum stake
+pub fn store_fee_tiers(storage: &mut dyn Storage, tiers: &[FeeTier]) -> StdResult<()> {
+    FEE_TIERS.save(storage, &tiers.to_vec())
+}
+
+pub fn read_fee_tiers(storage: &dyn Storage) -> StdResult<Vec<FeeTier>> {
+    Ok(FEE_TIERS.may_load(storage)?.unwrap_or_default())
+}
+
+pub fn store_referral_share(storage: &mut dyn Storage, share: Uint128) -> StdResult<()> {
+    REFERRAL_SHARE.save(storage, &share)
+}
+
+pub fn read_referral_share(storage: &dyn Storage) -> StdResult<Uint128> {
+    Ok(REFERRAL_SHARE.may_load(storage)?.unwrap_or_default())
+}
+
+// the vault backstopping a market and its share of the market's toll
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MakerVault {
+    pub vault: Addr,
+    pub fee_share: Uint128,
+}
+
+pub fn store_maker_vault(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    maker_vault: &MakerVault,
+) -> StdResult<()> {
+    MAKER_VAULTS.save(storage, vamm, maker_vault)
+}
+
+pub fn remove_maker_vault(storage: &mut dyn Storage, vamm: &Addr) {
+    MAKER_VAULTS.remove(storage, vamm)
+}
+
+pub fn read_maker_vault(storage: &dyn Storage, vamm: &Addr) -> StdResult<Option<MakerVault>> {
+    MAKER_VAULTS.may_load(storage, vamm)
+}
+
+// registers the referrer under the code, each referrer has a single code
+pub fn store_referral_code(
+    storage: &mut dyn Storage,
+    referrer: &Addr,
+    code: &str,
+) -> StdResult<()> {
+    REFERRAL_CODES.save(storage, code, referrer)?;
+    REFERRERS.save(storage, referrer, &code.to_string())
+}
+
+pub fn read_referral_code(storage: &dyn Storage, referrer: &Addr) -> StdResult<Option<String>> {
+    REFERRERS.may_load(storage, referrer)
+}
+
+pub fn read_code_referrer(storage: &dyn Storage, code: &str) -> StdResult<Option<Addr>> {
+    REFERRAL_CODES.may_load(storage, code)
+}
+
+pub fn store_trader_referrer(
+    storage: &mut dyn Storage,
+    trader: &Addr,
+    referrer: &Addr,
+) -> StdResult<()> {
+    TRADER_REFERRERS.save(storage, trader, referrer)
+}
+
+pub fn read_trader_referrer(storage: &dyn Storage, trader: &Addr) -> StdResult<Option<Addr>> {
+    TRADER_REFERRERS.may_load(storage, trader)
+}
+
+pub fn add_referral_reward(
+    storage: &mut dyn Storage,
+    referrer: &Addr,
+    asset: &AssetInfo,
+    amount: Uint128,
+) -> StdResult<()> {
+    let key = asset.to_string();
+    let mut reward = REFERRAL_REWARDS
+        .may_load(storage, (referrer, &key))?
+        .unwrap_or(ReferralReward {
+            asset: asset.clone(),
+            amount: Uint128::zero(),
+        });
+    reward.amount = reward.amount.checked_add(amount)?;
+
+    REFERRAL_REWARDS.save(storage, (referrer, &key), &reward)
+}
+
+pub fn read_referral_rewards(
+    storage: &dyn Storage,
+    referrer: &Addr,
+) -> StdResult<Vec<ReferralReward>> {
+    REFERRAL_REWARDS
+        .prefix(referrer)
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(_, reward)| reward))
+        .collect()
+}
+
+pub fn remove_referral_rewards(storage: &mut dyn Storage, referrer: &Addr) -> StdResult<()> {
+    for reward in read_referral_rewards(storage, referrer)? {
+        REFERRAL_REWARDS.remove(storage, (referrer, &reward.asset.to_string()));
+    }
+
+    Ok(())
+}
+
+// the paused operations are stored as a bitmap, see utils::pause_mask
+pub fn store_paused_operations(storage: &mut dyn Storage, paused: u8) -> StdResult<()> {
+    PAUSED_OPERATIONS.save(storage, &paused)
+}
+
+pub fn read_paused_operations(storage: &dyn Storage) -> StdResult<u8> {
+    Ok(PAUSED_OPERATIONS.may_load(storage)?.unwrap_or_default())
+}
+
+// collateral deposited to deepen a market, the reserves of the vamm are scaled
+// up by each deposit and down by each withdrawal. Fees paid to the pool are
+// tracked by an index of fees per unit of liquidity
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct LiquidityPool {
+    pub total: Uint128,
+    pub fee_index: Uint128,
+}
+
+impl LiquidityPool {
+    /// shares fees between all providers pro rata
+    pub fn distribute(&mut self, fees: Uint128, decimals: Uint128) -> StdResult<()> {
+        if self.total.is_zero() {
+            return Err(StdError::generic_err("market has no liquidity providers"));
+        }
+
+        self.fee_index = self
+            .fee_index
+            .checked_add(fees.multiply_ratio(decimals, self.total))?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct LiquidityStake {
+    pub amount: Uint128,
+    pub fee_index: Uint128,
+    pub fees: Uint128,
+}
+
+impl LiquidityStake {
+    /// credits the fees earned since the stake was last settled
+    pub fn settle(&mut self, pool: &LiquidityPool, decimals: Uint128) -> StdResult<()> {
+        let earned = self
+            .amount
+            .multiply_ratio(pool.fee_index.checked_sub(self.fee_index)?, decimals);
+        self.fees = self.fees.checked_add(earned)?;
+        self.fee_index = pool.fee_index;
+
+        Ok(())
+    }
+}
+
+pub fn store_liquidity_pool(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    pool: &LiquidityPool,
+) -> StdResult<()> {
+    LIQUIDITY_POOLS.save(storage, vamm, pool)
+}
+
+pub fn read_liquidity_pool(storage: &dyn Storage, vamm: &Addr) -> StdResult<LiquidityPool> {
+    Ok(LIQUIDITY_POOLS.may_load(storage, vamm)?.unwrap_or_default())
+}
+
+pub fn store_liquidity_stake(
+    storage: &mut dyn Storage,
+    vamm: &Addr,
+    provider: &Addr,
+    stake: &LiquidityStake,
+) -> StdResult<()> {
+    LIQUIDITY_STAKES.save(storage, (vamm, provider), stake)
+}
+
+pub fn read_liquidity_stake(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    provider: &Addr,
+) -> StdResult<LiquidityStake> {
+    Ok(LIQUIDITY_STAKES
+        .may_load(storage, (vamm, provider))?
+        .unwrap_or_default())
 }
@@ -0,0 +1,578 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{coins, to_binary, Addr, Uint128};
+use cw20::{Cw20Coin, Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::Executor;
+use margined_perp::asset::AssetInfo;
+use margined_perp::margined_engine::{
+    AccountCollateralResponse, AssetTotal, Cw20HookMsg, ExecuteMsg, InsuranceFundResponse,
+    MarginRatioResponse, MarginTier, MarketCollateralResponse, QueryMsg, Side, StateResponse,
+};
+
+// creates a second collateral token held by alice, with an allowance to the
+// engine, and backs the vamm with it
+fn atom_margined_market(env: &mut setup::TestingEnv) -> Addr {
+    let atom = env
+        .router
+        .instantiate_contract(
+            env.usdc.id,
+            env.owner.clone(),
+            &cw20_base::msg::InstantiateMsg {
+                name: "ATOM".to_string(),
+                symbol: "ATOM".to_string(),
+                decimals: 9,
+                initial_balances: vec![Cw20Coin {
+                    address: env.alice.to_string(),
+                    amount: to_decimals(1000),
+                }],
+                mint: None,
+                marketing: None,
+            },
+            &[],
+            "atom",
+            None,
+        )
+        .unwrap();
+
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(1000),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), atom.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::UpdateMarketCollateral {
+        vamm: env.vamm.addr.to_string(),
+        collateral: AssetInfo::Token {
+            contract_addr: atom.to_string(),
+        },
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    atom
+}
+
+#[test]
+fn test_market_margin_is_taken_in_its_collateral() {
+    let mut env = setup::setup();
+
+    // markets default to the eligible collateral
+    let res: MarketCollateralResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarketCollateral {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        res.collateral,
+        AssetInfo::Token {
+            contract_addr: env.usdc.addr.to_string(),
+        }
+    );
+
+    let atom = atom_margined_market(&mut env);
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let atom = Cw20Contract(atom);
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    assert_eq!(
+        atom.balance(&env.router, env.engine.addr.clone()).unwrap(),
+        to_decimals(10)
+    );
+    assert!(usdc
+        .balance(&env.router, env.engine.addr.clone())
+        .unwrap()
+        .is_zero());
+
+    // and the margin is totalled in atom
+    let state: StateResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::State {})
+        .unwrap();
+    assert_eq!(
+        state.total_margin,
+        vec![AssetTotal {
+            asset: atom.addr().to_string(),
+            amount: to_decimals(10),
+        }]
+    );
+
+    // the backing cannot change under open positions
+    let msg = ExecuteMsg::UpdateMarketCollateral {
+        vamm: env.vamm.addr.to_string(),
+        collateral: AssetInfo::Token {
+            contract_addr: env.usdc.addr.to_string(),
+        },
+    };
+    let res = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_market_hooks_reject_other_collateral() {
+    let mut env = setup::setup();
+    atom_margined_market(&mut env);
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // usdc cannot top up an atom margined position
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(5),
+        msg: to_binary(&Cw20HookMsg::DepositFor {
+            trader: env.alice.to_string(),
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap(),
+    };
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_native_collateral_is_sent_with_the_message() {
+    let mut env = setup::setup();
+    env.router
+        .init_bank_balance(&env.alice, coins(to_decimals(100).u128(), "uusd"))
+        .unwrap();
+
+    let msg = ExecuteMsg::UpdateMarketCollateral {
+        vamm: env.vamm.addr.to_string(),
+        collateral: AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        },
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the margin must be attached, it is not drawn from an allowance
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+    env.router
+        .execute_contract(
+            env.alice.clone(),
+            env.engine.addr.clone(),
+            &msg,
+            &coins(to_decimals(10).u128(), "uusd"),
+        )
+        .unwrap();
+
+    let msg = ExecuteMsg::DepositMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(5),
+    };
+    env.router
+        .execute_contract(
+            env.alice.clone(),
+            env.engine.addr.clone(),
+            &msg,
+            &coins(to_decimals(5).u128(), "uusd"),
+        )
+        .unwrap();
+
+    let balance = env
+        .router
+        .wrap()
+        .query_balance(&env.engine.addr, "uusd")
+        .unwrap();
+    assert_eq!(balance.amount, to_decimals(15));
+
+    // withdrawals and closes are paid out through the bank
+    let msg = ExecuteMsg::WithdrawMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(5),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let balance = env.router.wrap().query_balance(&env.alice, "uusd").unwrap();
+    assert_eq!(balance.amount, Uint128::new(99_999_999_992));
+
+    // once closed the long can be followed by a short, opened with its margin
+    let held = env
+        .router
+        .wrap()
+        .query_balance(&env.engine.addr, "uusd")
+        .unwrap()
+        .amount;
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(
+            env.alice.clone(),
+            env.engine.addr.clone(),
+            &msg,
+            &coins(to_decimals(10).u128(), "uusd"),
+        )
+        .unwrap();
+
+    let position = env.position(&env.alice);
+    assert!(!position.size.is_zero());
+    assert_eq!(position.margin, to_decimals(10));
+    let balance = env
+        .router
+        .wrap()
+        .query_balance(&env.engine.addr, "uusd")
+        .unwrap();
+    assert_eq!(balance.amount, held + to_decimals(10));
+}
+
+#[test]
+fn test_account_collateral_backs_positions_at_its_weight() {
+    let mut env = setup::setup();
+    let atom = AssetInfo::NativeToken {
+        denom: "uatom".to_string(),
+    };
+    env.router
+        .init_bank_balance(&env.alice, coins(to_decimals(100).u128(), "uatom"))
+        .unwrap();
+
+    // only accepted assets can be deposited
+    let msg = ExecuteMsg::DepositCollateral {
+        asset: atom.clone(),
+        amount: to_decimals(20),
+    };
+    let res = env.router.execute_contract(
+        env.alice.clone(),
+        env.engine.addr.clone(),
+        &msg,
+        &coins(to_decimals(20).u128(), "uatom"),
+    );
+    assert!(res.is_err());
+
+    // valued at par with half of it counting toward margin
+    let msg = ExecuteMsg::UpdateCollateralAsset {
+        asset: atom.clone(),
+        weight: to_decimals(1) / Uint128::from(2u64),
+        price: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let query = QueryMsg::MarginRatio {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+    };
+    let res: MarginRatioResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &query)
+        .unwrap();
+    assert_eq!(res.margin_ratio, Uint128::from(499_999_999u64));
+
+    let msg = ExecuteMsg::DepositCollateral {
+        asset: atom.clone(),
+        amount: to_decimals(20),
+    };
+    env.router
+        .execute_contract(
+            env.alice.clone(),
+            env.engine.addr.clone(),
+            &msg,
+            &coins(to_decimals(20).u128(), "uatom"),
+        )
+        .unwrap();
+
+    let res: AccountCollateralResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::AccountCollateral {
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.weighted_value, to_decimals(10));
+
+    // the 10 margin and 10 of weighted collateral back 20 of notional
+    let res: MarginRatioResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &query)
+        .unwrap();
+    assert_eq!(res.margin_ratio, to_decimals(1));
+
+    // no more can be withdrawn than was deposited
+    let msg = ExecuteMsg::WithdrawCollateral {
+        asset: atom.clone(),
+        amount: to_decimals(21),
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    let msg = ExecuteMsg::WithdrawCollateral {
+        asset: atom,
+        amount: to_decimals(20),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let balance = env
+        .router
+        .wrap()
+        .query_balance(&env.alice, "uatom")
+        .unwrap();
+    assert_eq!(balance.amount, to_decimals(100));
+}
+
+#[test]
+fn test_funding_is_settled_against_the_market_reserve() {
+    let mut env = setup::setup();
+    setup::setup_price_feed(&mut env);
+    let atom = atom_margined_market(&mut env);
+    let alice = env.alice.clone();
+
+    let add_margin = |env: &mut setup::TestingEnv| {
+        let msg = Cw20ExecuteMsg::Send {
+            contract: env.engine.addr.to_string(),
+            amount: Uint128::from(1u128),
+            msg: to_binary(&Cw20HookMsg::AddMargin {
+                vamm: env.vamm.addr.to_string(),
+            })
+            .unwrap(),
+        };
+        env.router
+            .execute_contract(env.alice.clone(), atom.clone(), &msg, &[])
+            .unwrap();
+    };
+    let reserve = |env: &setup::TestingEnv| {
+        let res: MarketCollateralResponse = env
+            .router
+            .wrap()
+            .query_wasm_smart(
+                &env.engine.addr,
+                &QueryMsg::MarketCollateral {
+                    vamm: env.vamm.addr.to_string(),
+                },
+            )
+            .unwrap();
+        res.reserve
+    };
+    let pay_funding = |env: &mut setup::TestingEnv| {
+        env.advance_time(3_600);
+        let msg = ExecuteMsg::PayFunding {
+            vamm: env.vamm.addr.to_string(),
+        };
+        env.router
+            .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+            .unwrap();
+    };
+
+    // alice's long pays funding above the index, which the market keeps
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let margin = env.position(&alice).margin;
+    pay_funding(&mut env);
+    add_margin(&mut env);
+    let paid = margin + Uint128::from(1u128) - env.position(&alice).margin;
+    assert!(!paid.is_zero());
+    assert_eq!(reserve(&env), paid);
+
+    // once the index is above the mark alice's long receives funding, but
+    // only as much as the market holds
+    env.set_oracle_price(Uint128::from(20_000_000u128));
+    let margin = env.position(&alice).margin;
+    pay_funding(&mut env);
+    add_margin(&mut env);
+    assert_eq!(
+        env.position(&alice).margin,
+        margin + paid + Uint128::from(1u128)
+    );
+    assert!(reserve(&env).is_zero());
+}
+
+#[test]
+fn test_liquidation_remainder_is_kept_by_the_market() {
+    let mut env = setup::setup();
+    let atom = atom_margined_market(&mut env);
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+
+    let msg = ExecuteMsg::UpdateMarginTiers {
+        vamm: env.vamm.addr.to_string(),
+        tiers: vec![MarginTier {
+            notional: Uint128::zero(),
+            initial_margin_ratio: Uint128::from(100_000_000u128),
+            maintenance_margin_ratio: Uint128::from(100_000_000u128),
+        }],
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let msg = ExecuteMsg::AddLiquidator {
+        liquidator: env.owner.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // bob is given atom to short with
+    let msg = Cw20ExecuteMsg::Transfer {
+        recipient: bob.to_string(),
+        amount: to_decimals(20),
+    };
+    env.router
+        .execute_contract(alice.clone(), atom.clone(), &msg, &[])
+        .unwrap();
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(20),
+        expires: None,
+    };
+    env.router
+        .execute_contract(bob.clone(), atom.clone(), &msg, &[])
+        .unwrap();
+
+    // alice's long is left with around 3.3 of her 20 margin after bob's
+    // short, below the 10% maintenance margin
+    for (trader, side) in [(&alice, Side::BUY), (&bob, Side::SELL)] {
+        let msg = ExecuteMsg::OpenPosition {
+            vamm: env.vamm.addr.to_string(),
+            side,
+            quote_asset_amount: to_decimals(20),
+            leverage: to_decimals(5),
+            base_asset_limit: None,
+            reduce_only: false,
+            deadline: None,
+            trader: None,
+        };
+        env.router
+            .execute_contract(trader.clone(), env.engine.addr.clone(), &msg, &[])
+            .unwrap();
+    }
+    let msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: alice.to_string(),
+        takeover: false,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the liquidator is paid in atom and the market keeps what is left of
+    // alice's margin, none of which reaches the insurance fund
+    let atom = Cw20Contract(atom);
+    let fee = atom.balance(&env.router, env.owner.clone()).unwrap();
+    assert!(!fee.is_zero());
+    let res: MarketCollateralResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarketCollateral {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert!(res.reserve > to_decimals(3) && res.reserve < to_decimals(4));
+    let fund: InsuranceFundResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::InsuranceFund {})
+        .unwrap();
+    assert!(fund.reserve.is_zero());
+    assert_eq!(
+        atom.balance(&env.router, env.engine.addr.clone()).unwrap(),
+        to_decimals(40) - fee
+    );
+}
@@ -0,0 +1,155 @@
+use crate::testing::setup::{self, to_decimals};
+use crate::utils::order_commitment_hash;
+use cosmwasm_std::{Addr, Binary, Uint128};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{ExecuteMsg, PositionResponse, QueryMsg, Side};
+
+fn enable_commit_reveal(env: &mut setup::TestingEnv) {
+    let msg = ExecuteMsg::UpdateCommitReveal {
+        vamm: env.vamm.addr.to_string(),
+        expiry_blocks: Some(5),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn commit(env: &mut setup::TestingEnv, salt: &str) {
+    let commitment: Binary = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::CommitmentHash {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+                side: Side::BUY,
+                quote_asset_amount: to_decimals(10),
+                leverage: to_decimals(2),
+                base_asset_limit: None,
+                salt: salt.to_string(),
+            },
+        )
+        .unwrap();
+
+    let msg = ExecuteMsg::CommitOrder {
+        vamm: env.vamm.addr.to_string(),
+        commitment,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+// returns whether the order was revealed and executed
+fn reveal(env: &mut setup::TestingEnv, quote_asset_amount: Uint128) -> bool {
+    let msg = ExecuteMsg::RevealOrder {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount,
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        salt: "secret".to_string(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .is_ok()
+}
+
+fn next_blocks(env: &mut setup::TestingEnv, blocks: u64) {
+    env.router.update_block(|block| {
+        block.height += blocks;
+        block.time = block.time.plus_seconds(5 * blocks);
+    });
+}
+
+#[test]
+fn test_commit_reveal_order() {
+    let mut env = setup::setup();
+    enable_commit_reveal(&mut env);
+
+    // direct orders are rejected once enabled
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    commit(&mut env, "secret");
+
+    // not in the same block, nor for a different order
+    assert!(!reveal(&mut env, to_decimals(10)));
+    next_blocks(&mut env, 1);
+    assert!(!reveal(&mut env, to_decimals(11)));
+
+    assert!(reveal(&mut env, to_decimals(10)));
+    let position: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(position.notional, to_decimals(20));
+
+    // the commitment is spent
+    assert!(!reveal(&mut env, to_decimals(10)));
+}
+
+#[test]
+fn test_commitment_expires() {
+    let mut env = setup::setup();
+
+    // cannot commit until enabled on the market
+    let msg = ExecuteMsg::CommitOrder {
+        vamm: env.vamm.addr.to_string(),
+        commitment: Binary::from(b"hash".as_ref()),
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    enable_commit_reveal(&mut env);
+    commit(&mut env, "secret");
+
+    next_blocks(&mut env, 6);
+    assert!(!reveal(&mut env, to_decimals(10)));
+}
+
+#[test]
+fn test_commitments_do_not_collide() {
+    let vamm = Addr::unchecked("vamm");
+    let trader = Addr::unchecked("trader");
+    let hash = |base_asset_limit: Option<Uint128>, salt: &str| {
+        order_commitment_hash(
+            &vamm,
+            &trader,
+            &Side::BUY,
+            to_decimals(10),
+            to_decimals(5),
+            base_asset_limit,
+            salt,
+        )
+    };
+
+    // a limit that encodes as the start of the other order's salt
+    let limit = Uint128::from(u128::from_be_bytes([b'a'; 16]));
+    assert_ne!(
+        hash(Some(limit), "salt"),
+        hash(None, "aaaaaaaaaaaaaaaasalt")
+    );
+}
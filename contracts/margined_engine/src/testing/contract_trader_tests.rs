@@ -0,0 +1,115 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Addr;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ContractTraderResponse, ContractTradersResponse, ExecuteMsg, QueryMsg, Side,
+};
+
+#[test]
+fn test_contract_trader_notional_cap() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    // alice stands in for a vault contract, capped at 30 notional
+    let msg = ExecuteMsg::RegisterContractTrader {
+        contract: env.alice.to_string(),
+        notional_cap: Some(to_decimals(30)),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+
+    // a further 20 notional would take the total to 40
+    let err = env.open_position(&alice, Side::BUY, 10, 2).unwrap_err();
+    assert_eq!(
+        err,
+        format!(
+            "Total notional exceeds the contract trader cap of {}",
+            to_decimals(30)
+        )
+    );
+
+    // once deregistered the contract is no longer capped
+    let msg = ExecuteMsg::DeregisterContractTrader {
+        contract: env.alice.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+}
+
+#[test]
+fn test_contract_trader_registry() {
+    let mut env = setup::setup();
+
+    // only the owner may register contract traders
+    let msg = ExecuteMsg::RegisterContractTrader {
+        contract: "vault".to_string(),
+        notional_cap: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    for contract in ["vault", "structured", "basket"] {
+        let msg = ExecuteMsg::RegisterContractTrader {
+            contract: contract.to_string(),
+            notional_cap: Some(to_decimals(100)),
+        };
+        env.router
+            .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+            .unwrap();
+    }
+
+    let res: ContractTradersResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::ContractTraders {
+                start_after: None,
+                limit: Some(2),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        res.traders,
+        vec![
+            ContractTraderResponse {
+                contract: Addr::unchecked("basket"),
+                notional_cap: Some(to_decimals(100)),
+            },
+            ContractTraderResponse {
+                contract: Addr::unchecked("structured"),
+                notional_cap: Some(to_decimals(100)),
+            },
+        ]
+    );
+    assert_eq!(res.next_key, Some(Addr::unchecked("structured")));
+
+    let res: ContractTradersResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::ContractTraders {
+                start_after: res.next_key.map(|addr| addr.to_string()),
+                limit: Some(2),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.traders.len(), 1);
+    assert_eq!(res.traders[0].contract, Addr::unchecked("vault"));
+    assert_eq!(res.next_key, None);
+
+    // deregistering an unknown contract fails
+    let msg = ExecuteMsg::DeregisterContractTrader {
+        contract: "unknown".to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+}
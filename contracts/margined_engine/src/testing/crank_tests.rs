@@ -0,0 +1,164 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{Addr, Event, Uint128};
+use cw20::Cw20ExecuteMsg;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, FundingResponse, MarginTier, QueryMsg, Side, TriggerOrderType,
+};
+
+fn crank(env: &mut setup::TestingEnv, keeper: &Addr) -> Event {
+    let msg = ExecuteMsg::Crank {
+        vamm: env.vamm.addr.to_string(),
+        max_ops: 10,
+    };
+    let res = env
+        .router
+        .execute_contract(keeper.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    res.events
+        .into_iter()
+        .find(|e| e.ty == "wasm-margined-engine/crank")
+        .unwrap()
+}
+
+fn attribute(event: &Event, key: &str) -> String {
+    event
+        .attributes
+        .iter()
+        .find(|attr| attr.key == key)
+        .unwrap()
+        .value
+        .clone()
+}
+
+#[test]
+fn test_crank_pays_funding_once_due() {
+    let mut env = setup::setup();
+    setup::setup_price_feed(&mut env);
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+
+    let event = crank(&mut env, &bob);
+    assert_eq!(attribute(&event, "funding_settled"), "false");
+
+    env.advance_time(3_600);
+    let event = crank(&mut env, &bob);
+    assert_eq!(attribute(&event, "funding_settled"), "true");
+
+    let funding: FundingResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Funding {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert!(funding.cumulative_premium_fraction.is_positive());
+
+    // the round has moved the schedule on, so the next crank has nothing to pay
+    let event = crank(&mut env, &bob);
+    assert_eq!(attribute(&event, "funding_settled"), "false");
+}
+
+#[test]
+fn test_crank_executes_triggered_orders() {
+    let mut env = setup::setup();
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+
+    // alice's long takes the price to around 12.1
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+    let msg = ExecuteMsg::SetTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        trigger_price: Uint128::from(11_500_000_000u128),
+        order_type: TriggerOrderType::StopLoss,
+        size: None,
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let event = crank(&mut env, &bob);
+    assert_eq!(attribute(&event, "orders_executed"), "0");
+
+    // bob's short takes it to around 11.0, through alice's stop loss
+    env.open_position(&bob, Side::SELL, 10, 5).unwrap();
+    let event = crank(&mut env, &bob);
+    assert_eq!(attribute(&event, "orders_executed"), "1");
+    assert_eq!(attribute(&event, "liquidations"), "0");
+    assert!(env.position(&alice).size.is_zero());
+    assert!(!env.position(&bob).size.is_zero());
+}
+
+#[test]
+fn test_crank_liquidates_positions() {
+    let mut env = setup::setup();
+    let (alice, bob, owner) = (env.alice.clone(), env.bob.clone(), env.owner.clone());
+
+    let msg = ExecuteMsg::UpdateMarginTiers {
+        vamm: env.vamm.addr.to_string(),
+        tiers: vec![MarginTier {
+            notional: Uint128::zero(),
+            initial_margin_ratio: Uint128::from(100_000_000u128),
+            maintenance_margin_ratio: Uint128::from(100_000_000u128),
+        }],
+    };
+    env.router
+        .execute_contract(owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let msg = ExecuteMsg::AddLiquidator {
+        liquidator: owner.to_string(),
+    };
+    env.router
+        .execute_contract(owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // carol's short takes both longs below the 10% maintenance margin
+    let carol = Addr::unchecked("carol");
+    let msg = Cw20ExecuteMsg::Transfer {
+        recipient: carol.to_string(),
+        amount: to_decimals(100),
+    };
+    env.router
+        .execute_contract(bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+    env.open_position(&alice, Side::BUY, 10, 5).unwrap();
+    env.open_position(&bob, Side::BUY, 10, 5).unwrap();
+    env.open_position(&carol, Side::SELL, 30, 5).unwrap();
+
+    // only whitelisted liquidators liquidate through the crank
+    let event = crank(&mut env, &bob);
+    assert_eq!(attribute(&event, "liquidations"), "0");
+    assert!(!env.position(&alice).size.is_zero());
+
+    let event = crank(&mut env, &owner);
+    assert_eq!(attribute(&event, "liquidations"), "2");
+    assert!(env.position(&alice).size.is_zero());
+    assert!(env.position(&bob).size.is_zero());
+    assert!(!env.position(&carol).size.is_zero());
+}
+
+#[test]
+fn test_crank_requires_registered_vamm() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::Crank {
+        vamm: env.usdc.addr.to_string(),
+        max_ops: 10,
+    };
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    let msg = ExecuteMsg::Crank {
+        vamm: env.vamm.addr.to_string(),
+        max_ops: 0,
+    };
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
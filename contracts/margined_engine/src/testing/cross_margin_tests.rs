@@ -0,0 +1,111 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{Addr, Uint128};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    AccountSettingsResponse, ExecuteMsg, MarginRatioResponse, QueryMsg, Side,
+};
+use margined_perp::margined_vamm::InstantiateMsg as VammInstantiateMsg;
+
+// lists a second market alongside the one from setup
+fn add_vamm(env: &mut setup::TestingEnv) -> Addr {
+    let vamm = env
+        .router
+        .instantiate_contract(
+            env.vamm.id,
+            env.owner.clone(),
+            &VammInstantiateMsg {
+                decimals: 9u8,
+                quote_asset: "ETH".to_string(),
+                base_asset: "USD".to_string(),
+                quote_asset_reserve: to_decimals(1_000),
+                base_asset_reserve: to_decimals(100),
+                funding_period: 3_600 as u64,
+                funding_buffer_period: 1_800_u64,
+                toll_ratio: Uint128::zero(),
+                spread_ratio: Uint128::zero(),
+                engine: Some(env.engine.addr.to_string()),
+            },
+            &[],
+            "vamm",
+            None,
+        )
+        .unwrap();
+
+    let msg = ExecuteMsg::AddVamm {
+        vamm: vamm.to_string(),
+        risk_params: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    vamm
+}
+
+fn margin_ratio(env: &setup::TestingEnv, vamm: &Addr) -> Uint128 {
+    let res: MarginRatioResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarginRatio {
+                vamm: vamm.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    res.margin_ratio
+}
+
+#[test]
+fn test_cross_margin_pools_positions() {
+    let mut env = setup::setup();
+    let vamm = add_vamm(&mut env);
+
+    let msg = ExecuteMsg::SetCrossMargin { enabled: true };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res: AccountSettingsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::AccountSettings {
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert!(res.cross_margin);
+
+    let alice = env.alice.clone();
+    let first = env.vamm.addr.clone();
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+    env.open_position_with(
+        &vamm,
+        &alice,
+        Side::BUY,
+        to_decimals(10),
+        to_decimals(2),
+        None,
+        false,
+    )
+    .unwrap();
+
+    // the mode cannot change under open positions
+    let msg = ExecuteMsg::SetCrossMargin { enabled: false };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    // bob buying into the first market puts alice's position there in profit
+    let bob = env.bob.clone();
+    env.open_position(&bob, Side::BUY, 10, 2).unwrap();
+
+    // which raises the margin ratio of both of her positions alike, where an
+    // untouched isolated position would have stayed at half
+    let ratio = margin_ratio(&env, &first);
+    assert_eq!(margin_ratio(&env, &vamm), ratio);
+    assert!(ratio > to_decimals(1) / Uint128::from(2u64));
+}
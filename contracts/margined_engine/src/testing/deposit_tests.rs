@@ -0,0 +1,298 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{to_binary, Uint128};
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{Cw20HookMsg, ExecuteMsg, PositionResponse, QueryMsg, Side};
+
+fn open_alice_position(env: &mut setup::TestingEnv) {
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn alice_margin(env: &setup::TestingEnv) -> Uint128 {
+    let res: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    res.margin
+}
+
+#[test]
+fn test_deposit_for_another_trader() {
+    let mut env = setup::setup();
+    open_alice_position(&mut env);
+    assert_eq!(alice_margin(&env), to_decimals(10));
+
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    let bob_balance = usdc.balance(&env.router, env.bob.clone()).unwrap();
+
+    // bob tops up alice through the cw20 hook
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(5),
+        msg: to_binary(&Cw20HookMsg::DepositFor {
+            trader: env.alice.to_string(),
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap(),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(alice_margin(&env), to_decimals(15));
+
+    // and again from bob's allowance
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(5),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+    let msg = ExecuteMsg::DepositFor {
+        trader: env.alice.to_string(),
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(5),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(alice_margin(&env), to_decimals(20));
+
+    assert_eq!(
+        usdc.balance(&env.router, env.bob.clone()).unwrap(),
+        bob_balance.checked_sub(to_decimals(10)).unwrap()
+    );
+}
+
+#[test]
+fn test_deposit_for_requires_open_position() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::DepositFor {
+        trader: env.alice.to_string(),
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(5),
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    // the deposit is reverted if the depositor has no allowance
+    open_alice_position(&mut env);
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+    assert_eq!(alice_margin(&env), to_decimals(10));
+}
+
+#[test]
+fn test_deposit_and_withdraw_margin() {
+    let mut env = setup::setup();
+    open_alice_position(&mut env);
+
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+
+    let msg = ExecuteMsg::DepositMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(5),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(alice_margin(&env), to_decimals(15));
+
+    // bob has no position to withdraw from
+    let msg = ExecuteMsg::WithdrawMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(5),
+    };
+    let err = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Trader has no open position");
+
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(alice_margin(&env), to_decimals(10));
+    assert_eq!(
+        usdc.balance(&env.router, env.alice.clone()).unwrap(),
+        alice_balance
+    );
+
+    // the position cannot be left without margin
+    let msg = ExecuteMsg::WithdrawMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(10),
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Position would be left with insufficient margin"
+    );
+
+    let msg = ExecuteMsg::WithdrawMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(11),
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Position would be left with insufficient margin"
+    );
+}
+
+#[test]
+fn test_open_position_and_add_margin_through_the_cw20_hook() {
+    let mut env = setup::setup();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    let bob_balance = usdc.balance(&env.router, env.bob.clone()).unwrap();
+
+    let engine = env.engine.addr.to_string();
+    let send = |amount: Uint128, msg: &Cw20HookMsg| Cw20ExecuteMsg::Send {
+        contract: engine.clone(),
+        amount,
+        msg: to_binary(msg).unwrap(),
+    };
+    let open = Cw20HookMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+    };
+
+    // the amount sent must be exactly the margin due
+    let err = env
+        .router
+        .execute_contract(
+            env.bob.clone(),
+            env.usdc.addr.clone(),
+            &send(to_decimals(11), &open),
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Funds sent must be exactly 10000000000");
+
+    // bob has given no allowance, the margin sent is all that is taken
+    let msg = send(to_decimals(10), &open);
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = send(
+        to_decimals(5),
+        &Cw20HookMsg::AddMargin {
+            vamm: env.vamm.addr.to_string(),
+        },
+    );
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.bob.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.margin, to_decimals(15));
+    assert_eq!(
+        usdc.balance(&env.router, env.bob.clone()).unwrap(),
+        bob_balance.checked_sub(to_decimals(15)).unwrap()
+    );
+}
+
+#[test]
+fn test_margin_is_escrowed_before_the_swap() {
+    let mut env = setup::setup();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    let bob_balance = usdc.balance(&env.router, env.bob.clone()).unwrap();
+
+    // bob has given no allowance so the escrow fails and nothing is opened
+    let vamm = env.vamm.addr.to_string();
+    let open = |base_asset_limit| ExecuteMsg::OpenPosition {
+        vamm: vamm.clone(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    let res =
+        env.router
+            .execute_contract(env.bob.clone(), env.engine.addr.clone(), &open(None), &[]);
+    assert!(res.is_err());
+    let res: Result<PositionResponse, _> = env.router.wrap().query_wasm_smart(
+        &env.engine.addr,
+        &QueryMsg::Position {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.bob.to_string(),
+        },
+    );
+    assert!(res.is_err());
+
+    // once escrowed, a failed swap rolls the escrow back
+    let allowance = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(10),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &allowance, &[])
+        .unwrap();
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &open(Some(to_decimals(100))),
+        &[],
+    );
+    assert!(res.is_err());
+    assert_eq!(
+        usdc.balance(&env.router, env.bob.clone()).unwrap(),
+        bob_balance
+    );
+    assert_eq!(
+        usdc.balance(&env.router, env.engine.addr.clone()).unwrap(),
+        Uint128::zero()
+    );
+}
@@ -0,0 +1,178 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Event;
+use cw_multi_test::Executor;
+use margined_perp::events::SCHEMA_VERSION;
+use margined_perp::margined_engine::{ExecuteMsg, Side};
+
+fn attribute<'a>(event: &'a Event, key: &str) -> &'a str {
+    &event
+        .attributes
+        .iter()
+        .find(|attr| attr.key == key)
+        .unwrap()
+        .value
+}
+
+#[test]
+fn test_events_are_namespaced_and_versioned() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let open = res
+        .events
+        .iter()
+        .find(|e| e.ty == "wasm-margined-engine/open_position")
+        .unwrap();
+    assert_eq!(attribute(open, "schema_version"), SCHEMA_VERSION);
+
+    // the reply reports the position after the swap
+    let changed = res
+        .events
+        .iter()
+        .find(|e| e.ty == "wasm-margined-engine/position_changed")
+        .unwrap();
+    assert_eq!(attribute(changed, "schema_version"), SCHEMA_VERSION);
+    assert_eq!(attribute(changed, "trader"), env.alice.as_str());
+    assert_eq!(attribute(changed, "notional"), to_decimals(20).to_string());
+    assert_eq!(attribute(changed, "margin"), to_decimals(10).to_string());
+
+    // only the action is left on the plain wasm event
+    let wasm = res
+        .events
+        .iter()
+        .find(|e| {
+            e.ty == "wasm"
+                && e.attributes
+                    .iter()
+                    .any(|attr| attr.key == "action" && attr.value == "position_changed")
+        })
+        .unwrap();
+    assert!(wasm.attributes.iter().all(|attr| attr.key != "trader"));
+}
+
+#[test]
+fn test_position_changes_carry_deltas_and_the_vamm_swap() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let changed = res
+        .events
+        .iter()
+        .find(|e| e.ty == "wasm-margined-engine/position_changed")
+        .unwrap();
+    assert_eq!(attribute(changed, "side"), "long");
+    assert_eq!(attribute(changed, "size_delta"), attribute(changed, "size"));
+    assert_eq!(
+        attribute(changed, "margin_delta"),
+        to_decimals(10).to_string()
+    );
+    assert_eq!(attribute(changed, "realized_pnl"), "0");
+    assert_eq!(attribute(changed, "funding_payment"), "0");
+
+    // the vamm reports the swap under its own namespace
+    let swap = res
+        .events
+        .iter()
+        .find(|e| e.ty == "wasm-margined-vamm/swap_input")
+        .unwrap();
+    assert_eq!(attribute(swap, "schema_version"), SCHEMA_VERSION);
+    assert_eq!(attribute(swap, "direction"), "add_to_amm");
+    assert_eq!(attribute(swap, "input"), to_decimals(20).to_string());
+    assert_eq!(
+        attribute(swap, "quote_asset_reserve"),
+        to_decimals(1020).to_string()
+    );
+
+    // closing gives the size and margin back as negative deltas
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let changed = res
+        .events
+        .iter()
+        .find(|e| e.ty == "wasm-margined-engine/position_changed")
+        .unwrap();
+    assert_eq!(attribute(changed, "size"), "0");
+    assert_eq!(
+        attribute(changed, "margin_delta"),
+        format!("-{}", to_decimals(10))
+    );
+}
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_replies_are_traced() {
+    let mut env = setup::setup();
+
+    let open = |side: Side, quote_asset_amount| ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side,
+        quote_asset_amount,
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    let long = open(Side::BUY, to_decimals(10));
+    let flip = open(Side::SELL, to_decimals(20));
+
+    let paths = |res: &cw_multi_test::AppResponse| {
+        res.events
+            .iter()
+            .filter(|e| e.ty == "wasm-margined-engine/trace")
+            .map(|e| attribute(e, "path").to_string())
+            .collect::<Vec<String>>()
+    };
+
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &long, &[])
+        .unwrap();
+    assert_eq!(paths(&res), vec!["increase"]);
+
+    // selling twice the position closes it and opens the rest as a short
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &flip, &[])
+        .unwrap();
+    assert_eq!(paths(&res), vec!["reverse", "increase"]);
+}
@@ -0,0 +1,237 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, FeeCampaignsResponse, MarketFeesResponse, QueryMsg,
+};
+use margined_perp::margined_vamm::{CalcFeeResponse, ExecuteMsg as VammExecuteMsg};
+
+// sets a 1% toll and 2% spread on the vamm
+fn enable_fees(env: &mut setup::TestingEnv) {
+    let msg = VammExecuteMsg::UpdateConfig {
+        toll_ratio: Some(Uint128::from(10_000_000u128)),
+        spread_ratio: Some(Uint128::from(20_000_000u128)),
+        price_feed: None,
+        fluctuation_limit_ratio: None,
+        engine: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.vamm.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn calc_fee(env: &setup::TestingEnv) -> CalcFeeResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::CalcFee {
+                vamm: env.vamm.addr.to_string(),
+                quote_asset_amount: to_decimals(100),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_fee_campaign_discounts_fees_while_active() {
+    let mut env = setup::setup();
+    enable_fees(&mut env);
+
+    let now = env.router.block_info().time.seconds();
+    let msg = ExecuteMsg::AddFeeCampaign {
+        start: now + 100,
+        end: now + 200,
+        vamms: None,
+        discount: Uint128::from(500_000_000u128),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // not started yet
+    let res = calc_fee(&env);
+    assert_eq!(res.toll_fee, to_decimals(1));
+    assert_eq!(res.spread_fee, to_decimals(2));
+
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(150);
+    });
+
+    let res = calc_fee(&env);
+    assert_eq!(res.toll_fee, Uint128::from(500_000_000u128));
+    assert_eq!(res.spread_fee, to_decimals(1));
+
+    // fees revert once the campaign ends
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(100);
+    });
+
+    let res = calc_fee(&env);
+    assert_eq!(res.toll_fee, to_decimals(1));
+    assert_eq!(res.spread_fee, to_decimals(2));
+}
+
+#[test]
+fn test_fee_campaign_only_applies_to_listed_markets() {
+    let mut env = setup::setup();
+    enable_fees(&mut env);
+
+    let now = env.router.block_info().time.seconds();
+    let msg = ExecuteMsg::AddFeeCampaign {
+        start: now,
+        end: now + 100,
+        vamms: Some(vec![env.usdc.addr.to_string()]),
+        discount: to_decimals(1),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res = calc_fee(&env);
+    assert_eq!(res.toll_fee, to_decimals(1));
+    assert_eq!(res.spread_fee, to_decimals(2));
+}
+
+#[test]
+fn test_fee_campaign_validation() {
+    let mut env = setup::setup();
+
+    let now = env.router.block_info().time.seconds();
+
+    // only the owner can add campaigns
+    let msg = ExecuteMsg::AddFeeCampaign {
+        start: now,
+        end: now + 100,
+        vamms: None,
+        discount: to_decimals(1),
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    // the window must not be empty
+    let msg = ExecuteMsg::AddFeeCampaign {
+        start: now + 100,
+        end: now + 100,
+        vamms: None,
+        discount: to_decimals(1),
+    };
+    let res = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    // the discount cannot exceed 100%
+    let msg = ExecuteMsg::AddFeeCampaign {
+        start: now,
+        end: now + 100,
+        vamms: None,
+        discount: to_decimals(2),
+    };
+    let res = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_remove_fee_campaign() {
+    let mut env = setup::setup();
+    enable_fees(&mut env);
+
+    let now = env.router.block_info().time.seconds();
+    let msg = ExecuteMsg::AddFeeCampaign {
+        start: now,
+        end: now + 100,
+        vamms: None,
+        discount: to_decimals(1),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res = calc_fee(&env);
+    assert_eq!(res.toll_fee, Uint128::zero());
+    assert_eq!(res.spread_fee, Uint128::zero());
+
+    let msg = ExecuteMsg::RemoveFeeCampaign { campaign_id: 1u64 };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res: FeeCampaignsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::FeeCampaigns {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert!(res.campaigns.is_empty());
+
+    let res = calc_fee(&env);
+    assert_eq!(res.toll_fee, to_decimals(1));
+    assert_eq!(res.spread_fee, to_decimals(2));
+}
+
+#[test]
+fn test_market_fees_override_vamm_fees() {
+    let mut env = setup::setup();
+    enable_fees(&mut env);
+
+    // halve the toll, keeping the vamm's spread
+    let msg = ExecuteMsg::UpdateMarketFees {
+        vamm: env.vamm.addr.to_string(),
+        toll_ratio: Some(Uint128::from(5_000_000u128)),
+        spread_ratio: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res = calc_fee(&env);
+    assert_eq!(res.toll_fee, Uint128::from(500_000_000u128));
+    assert_eq!(res.spread_fee, to_decimals(2));
+
+    let res: MarketFeesResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarketFees {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.toll_ratio, Some(Uint128::from(5_000_000u128)));
+    assert_eq!(res.spread_ratio, None);
+
+    // ratios above 100% are rejected
+    let msg = ExecuteMsg::UpdateMarketFees {
+        vamm: env.vamm.addr.to_string(),
+        toll_ratio: None,
+        spread_ratio: Some(to_decimals(2)),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    // clearing the overrides restores the vamm's fees
+    let msg = ExecuteMsg::UpdateMarketFees {
+        vamm: env.vamm.addr.to_string(),
+        toll_ratio: None,
+        spread_ratio: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res = calc_fee(&env);
+    assert_eq!(res.toll_fee, to_decimals(1));
+    assert_eq!(res.spread_fee, to_decimals(2));
+}
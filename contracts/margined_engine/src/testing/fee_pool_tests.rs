@@ -0,0 +1,291 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{to_binary, Addr, Uint128};
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::Executor;
+use margined_perp::asset::AssetInfo;
+use margined_perp::margined_engine::{
+    Cw20HookMsg, ExecuteMsg, FeePoolResponse, InsuranceFloor, InsuranceFundResponse,
+    InsuranceStakeResponse, QueryMsg, Side,
+};
+use margined_perp::margined_vamm::ExecuteMsg as VammExecuteMsg;
+
+fn send(env: &mut setup::TestingEnv, amount: Uint128, msg: Cw20HookMsg) {
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount,
+        msg: to_binary(&msg).unwrap(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn configure(env: &mut setup::TestingEnv, insurance_floor: InsuranceFloor) {
+    let msg = ExecuteMsg::UpdateFeePoolConfig {
+        insurance_share: Uint128::from(500_000_000u128),
+        insurance_floor: Some(insurance_floor),
+        keeper_reward: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn distribute(env: &mut setup::TestingEnv) {
+    env.router
+        .execute_contract(
+            env.bob.clone(),
+            env.engine.addr.clone(),
+            &ExecuteMsg::DistributeFees {},
+            &[],
+        )
+        .unwrap();
+}
+
+fn insurance_fund(env: &setup::TestingEnv) -> InsuranceFundResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::InsuranceFund {})
+        .unwrap()
+}
+
+#[test]
+fn test_fees_top_up_insurance_below_floor() {
+    let mut env = setup::setup();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    configure(
+        &mut env,
+        InsuranceFloor::Absolute {
+            amount: to_decimals(100),
+        },
+    );
+    send(&mut env, to_decimals(40), Cw20HookMsg::StakeInsurance {});
+    send(&mut env, to_decimals(100), Cw20HookMsg::DepositFees {});
+
+    let res: FeePoolResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::FeePool {})
+        .unwrap();
+    assert_eq!(res.balance, to_decimals(100));
+
+    // 60 restores the floor, the remaining 40 is split evenly
+    distribute(&mut env);
+
+    let res = insurance_fund(&env);
+    assert_eq!(res.reserve, to_decimals(60));
+    assert_eq!(res.balance, to_decimals(60));
+    assert_eq!(
+        usdc.balance(&env.router, env.owner.clone()).unwrap(),
+        to_decimals(20)
+    );
+
+    let res: InsuranceStakeResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::InsuranceStake {
+                staker: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.value, to_decimals(60));
+
+    // above the floor all fees are split
+    send(&mut env, to_decimals(100), Cw20HookMsg::DepositFees {});
+    distribute(&mut env);
+
+    let res = insurance_fund(&env);
+    assert_eq!(res.reserve, to_decimals(60));
+    assert_eq!(res.balance, to_decimals(110));
+    assert_eq!(
+        usdc.balance(&env.router, env.owner.clone()).unwrap(),
+        to_decimals(70)
+    );
+}
+
+#[test]
+fn test_insurance_floor_relative_to_open_interest() {
+    let mut env = setup::setup();
+
+    configure(
+        &mut env,
+        InsuranceFloor::OpenInterestRatio {
+            ratio: Uint128::from(100_000_000u128),
+        },
+    );
+
+    // 300 notional of open interest puts the floor at 30
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(30),
+        leverage: to_decimals(10),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    send(&mut env, to_decimals(50), Cw20HookMsg::DepositFees {});
+    distribute(&mut env);
+
+    // without stakers the insurance share also goes to the reserve
+    let res = insurance_fund(&env);
+    assert_eq!(res.reserve, to_decimals(40));
+    assert_eq!(res.balance, Uint128::zero());
+}
+
+#[test]
+fn test_distribute_fees_requires_fees() {
+    let mut env = setup::setup();
+
+    let res = env.router.execute_contract(
+        env.bob.clone(),
+        env.engine.addr.clone(),
+        &ExecuteMsg::DistributeFees {},
+        &[],
+    );
+    assert!(res.is_err());
+
+    let msg = ExecuteMsg::UpdateFeePoolConfig {
+        insurance_share: Uint128::zero(),
+        insurance_floor: None,
+        keeper_reward: None,
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_trading_fees_are_collected_on_open_and_close() {
+    let mut env = setup::setup();
+    let msg = VammExecuteMsg::UpdateConfig {
+        toll_ratio: Some(Uint128::from(10_000_000u128)),
+        spread_ratio: Some(Uint128::from(20_000_000u128)),
+        price_feed: None,
+        fluctuation_limit_ratio: None,
+        engine: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.vamm.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    let balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+
+    // a 1% toll and 2% spread on 300 of notional are paid on top of the margin
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(
+        usdc.balance(&env.router, env.alice.clone()).unwrap(),
+        balance - to_decimals(69)
+    );
+
+    let pool: FeePoolResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::FeePool {})
+        .unwrap();
+    assert_eq!(pool.balance, to_decimals(3));
+    let fund: InsuranceFundResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::InsuranceFund {})
+        .unwrap();
+    assert_eq!(fund.reserve, to_decimals(6));
+
+    // once a fee pool address is set the toll is sent there instead
+    let fee_pool = Addr::unchecked("fee_pool");
+    let msg = ExecuteMsg::UpdateConfig {
+        eligible_collateral: None,
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: None,
+        fee_pool: Some(fee_pool.to_string()),
+        open_liquidation: None,
+        pause_level: None,
+        staking_contract: None,
+        liquidation_price_source: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let toll_fee = res
+        .events
+        .iter()
+        .flat_map(|event| event.attributes.iter())
+        .find(|attr| attr.key == "toll_fee")
+        .map(|attr| Uint128::from(attr.value.parse::<u128>().unwrap()))
+        .unwrap();
+    assert!(!toll_fee.is_zero());
+    assert_eq!(usdc.balance(&env.router, fee_pool).unwrap(), toll_fee);
+
+    let pool: FeePoolResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::FeePool {})
+        .unwrap();
+    assert_eq!(pool.balance, to_decimals(3));
+}
+
+#[test]
+fn test_eligible_collateral_cannot_change_while_held() {
+    let mut env = setup::setup();
+    send(&mut env, to_decimals(10), Cw20HookMsg::DepositFees {});
+
+    let msg = ExecuteMsg::UpdateConfig {
+        eligible_collateral: Some(AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        }),
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: None,
+        fee_pool: None,
+        open_liquidation: None,
+        pause_level: None,
+        staking_contract: None,
+        liquidation_price_source: None,
+    };
+    let err = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: cannot change the eligible collateral while it is in use"
+    );
+}
@@ -0,0 +1,208 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{
+    to_binary, Addr, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdResult, Uint128,
+};
+use cw20::Cw20Contract;
+use cw_multi_test::{Contract, ContractWrapper, Executor};
+use cw_storage_plus::Map;
+use margined_perp::margined_engine::{ExecuteMsg, QueryMsg, Side, TraderFeeRateResponse};
+use margined_perp::margined_vamm::ExecuteMsg as VammExecuteMsg;
+use margined_perp::staking::{StakedResponse, StakingQueryMsg};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// a bare staking contract, answering with the stakes it was instantiated with
+
+const STAKES: Map<&str, Uint128> = Map::new("stakes");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+struct StakingInstantiateMsg {
+    stakes: Vec<(String, Uint128)>,
+}
+
+fn staking_instantiate(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: StakingInstantiateMsg,
+) -> StdResult<Response> {
+    for (address, stake) in msg.stakes {
+        STAKES.save(deps.storage, &address, &stake)?;
+    }
+
+    Ok(Response::new())
+}
+
+fn staking_execute(
+    _deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    _msg: Empty,
+) -> StdResult<Response> {
+    Ok(Response::new())
+}
+
+fn staking_query(deps: Deps, _env: Env, msg: StakingQueryMsg) -> StdResult<Binary> {
+    match msg {
+        StakingQueryMsg::Staked { address } => to_binary(&StakedResponse {
+            stake: STAKES.may_load(deps.storage, &address)?.unwrap_or_default(),
+        }),
+    }
+}
+
+fn contract_staking() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new_with_empty(
+        staking_execute,
+        staking_instantiate,
+        staking_query,
+    ))
+}
+
+// deploys the staking contract with alice's stake and points the engine at it
+fn enable_staking(env: &mut setup::TestingEnv, stake: Uint128) -> Addr {
+    let staking_id = env.router.store_code(contract_staking());
+    let staking = env
+        .router
+        .instantiate_contract(
+            staking_id,
+            env.owner.clone(),
+            &StakingInstantiateMsg {
+                stakes: vec![(env.alice.to_string(), stake)],
+            },
+            &[],
+            "staking",
+            None,
+        )
+        .unwrap();
+
+    let msg = ExecuteMsg::UpdateConfig {
+        eligible_collateral: None,
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: None,
+        fee_pool: None,
+        open_liquidation: None,
+        pause_level: None,
+        staking_contract: Some(staking.to_string()),
+        liquidation_price_source: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    staking
+}
+
+fn set_fee_tier(env: &mut setup::TestingEnv, min_stake: Uint128, discount: Uint128) {
+    let msg = ExecuteMsg::SetFeeTier {
+        min_stake,
+        discount,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn fee_rate(env: &setup::TestingEnv, trader: &Addr) -> TraderFeeRateResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::TraderFeeRate {
+                trader: trader.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_stakers_are_discounted_by_their_fee_tier() {
+    let mut env = setup::setup();
+    enable_staking(&mut env, to_decimals(500));
+
+    // 20% off from 100 staked and 50% from 1000
+    set_fee_tier(&mut env, to_decimals(1000), Uint128::from(500_000_000u128));
+    set_fee_tier(&mut env, to_decimals(100), Uint128::from(200_000_000u128));
+
+    let alice = env.alice.clone();
+    assert_eq!(
+        fee_rate(&env, &alice),
+        TraderFeeRateResponse {
+            stake: to_decimals(500),
+            discount: Uint128::from(200_000_000u128),
+        }
+    );
+    let bob = env.bob.clone();
+    assert_eq!(fee_rate(&env, &bob).discount, Uint128::zero());
+
+    // a 1% toll and 2% spread on 300 of notional, only the toll is discounted
+    let msg = VammExecuteMsg::UpdateConfig {
+        toll_ratio: Some(Uint128::from(10_000_000u128)),
+        spread_ratio: Some(Uint128::from(20_000_000u128)),
+        price_feed: None,
+        fluctuation_limit_ratio: None,
+        engine: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.vamm.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    let balance = usdc.balance(&env.router, alice.clone()).unwrap();
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(
+        usdc.balance(&env.router, alice.clone()).unwrap(),
+        balance - Uint128::from(68_400_000_000u128)
+    );
+
+    // without the tier alice's stake earns no discount
+    let msg = ExecuteMsg::RemoveFeeTier {
+        min_stake: to_decimals(100),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(fee_rate(&env, &alice).discount, Uint128::zero());
+
+    let res = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_fee_tiers_are_owner_only() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::SetFeeTier {
+        min_stake: to_decimals(100),
+        discount: Uint128::from(200_000_000u128),
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    // no more than 100% can be discounted
+    let msg = ExecuteMsg::SetFeeTier {
+        min_stake: to_decimals(100),
+        discount: to_decimals(2),
+    };
+    let res = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
@@ -0,0 +1,515 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{to_binary, Addr, Uint128};
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::Executor;
+use margined_perp::integer::Integer;
+use margined_perp::margined_engine::{
+    BadDebtResponse, Cw20HookMsg, ExecuteMsg, FeePoolResponse, FundingPreviewResponse,
+    FundingRateHistoryResponse, FundingResponse, InsuranceFundResponse, NextFundingTimeResponse,
+    PnlCalcOption, PositionNotionalAndUnrealizedPnlResponse, QueryMsg, Side,
+};
+use margined_perp::margined_vamm::QueryMsg as VammQueryMsg;
+
+fn pay_funding(env: &mut setup::TestingEnv) -> Result<(), String> {
+    let msg = ExecuteMsg::PayFunding {
+        vamm: env.vamm.addr.to_string(),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[test]
+fn test_pay_funding_requires_price_feed() {
+    let mut env = setup::setup();
+
+    let err = pay_funding(&mut env).unwrap_err();
+    assert_eq!(err, "Generic error: market has no price feed");
+}
+
+#[test]
+fn test_longs_pay_shorts_above_index() {
+    let mut env = setup::setup();
+    setup::setup_price_feed(&mut env);
+
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+    env.open_position(&bob, Side::SELL, 10, 5).unwrap();
+    let long = env.position(&alice);
+    let short = env.position(&bob);
+
+    // the mark stays above the index of 10 for the whole funding period
+    env.advance_time(3_600);
+    let mark: Uint128 = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.vamm.addr, &VammQueryMsg::SpotPrice {})
+        .unwrap();
+    assert!(mark > to_decimals(10));
+
+    pay_funding(&mut env).unwrap();
+
+    let premium_fraction = (mark - to_decimals(10)).multiply_ratio(3_600u64, 86_400u64);
+    let funding: FundingResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Funding {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        funding.cumulative_premium_fraction,
+        Integer::new_positive(premium_fraction)
+    );
+
+    // the payments are only settled once the positions are next touched
+    let paid = long.size.multiply_ratio(premium_fraction, to_decimals(1));
+    let received = short.size.multiply_ratio(premium_fraction, to_decimals(1));
+    assert_eq!(env.position(&alice).margin, long.margin);
+    let balance: Uint128 = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::TraderBalance {
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(balance, long.margin - paid);
+
+    for trader in [alice.clone(), bob.clone()] {
+        let msg = Cw20ExecuteMsg::Send {
+            contract: env.engine.addr.to_string(),
+            amount: Uint128::from(1u128),
+            msg: to_binary(&Cw20HookMsg::AddMargin {
+                vamm: env.vamm.addr.to_string(),
+            })
+            .unwrap(),
+        };
+        env.router
+            .execute_contract(trader, env.usdc.addr.clone(), &msg, &[])
+            .unwrap();
+    }
+    assert_eq!(
+        env.position(&alice).margin,
+        long.margin - paid + Uint128::from(1u128)
+    );
+    assert_eq!(
+        env.position(&bob).margin,
+        short.margin + received + Uint128::from(1u128)
+    );
+    assert_eq!(
+        env.position(&alice).last_updated_premium_fraction,
+        funding.cumulative_premium_fraction
+    );
+
+    // the longs are larger so the insurance fund keeps the difference
+    let fund: InsuranceFundResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::InsuranceFund {})
+        .unwrap();
+    assert_eq!(fund.reserve, paid - received);
+
+    // the next round is not due until a funding period has passed
+    let err = pay_funding(&mut env).unwrap_err();
+    assert!(err.contains("funding is not due until"));
+}
+
+#[test]
+fn test_funding_the_fund_cannot_pay_is_bad_debt() {
+    let mut env = setup::setup();
+    setup::setup_price_feed(&mut env);
+
+    // alice's long is alone in the market, so nobody pays in what it receives
+    // once the index is above the mark
+    let alice = env.alice.clone();
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+    let long = env.position(&alice);
+    env.set_oracle_price(Uint128::from(20_000_000u128));
+    env.advance_time(3_600);
+    pay_funding(&mut env).unwrap();
+
+    let funding: FundingResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Funding {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert!(funding.cumulative_premium_fraction.is_negative());
+    let received = long
+        .size
+        .multiply_ratio(funding.cumulative_premium_fraction.value(), to_decimals(1));
+
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: Uint128::from(1u128),
+        msg: to_binary(&Cw20HookMsg::AddMargin {
+            vamm: env.vamm.addr.to_string(),
+        })
+        .unwrap(),
+    };
+    env.router
+        .execute_contract(alice.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the insurance fund is empty so none of it is credited
+    assert_eq!(
+        env.position(&alice).margin,
+        long.margin + Uint128::from(1u128)
+    );
+    let bad_debt: BadDebtResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::BadDebt {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(bad_debt.realized, received);
+    assert_eq!(bad_debt.uncovered, received);
+    assert!(bad_debt.insured.is_zero());
+}
+
+#[test]
+fn test_funding_preview_matches_the_round() {
+    let mut env = setup::setup();
+    setup::setup_price_feed(&mut env);
+
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+    env.open_position(&bob, Side::SELL, 10, 5).unwrap();
+    env.advance_time(3_600);
+
+    let preview = |env: &setup::TestingEnv, trader: &Addr| -> FundingPreviewResponse {
+        env.router
+            .wrap()
+            .query_wasm_smart(
+                &env.engine.addr,
+                &QueryMsg::FundingPreview {
+                    vamm: env.vamm.addr.to_string(),
+                    trader: trader.to_string(),
+                },
+            )
+            .unwrap()
+    };
+    let long = preview(&env, &alice);
+    let short = preview(&env, &bob);
+    assert!(long.premium_fraction.is_positive());
+    assert_eq!(long.index_twap, to_decimals(10));
+    assert!(long.payment.is_positive());
+    assert!(!short.payment.is_positive());
+
+    // settling the round now applies exactly what was previewed
+    let margin = env.position(&alice).margin;
+    pay_funding(&mut env).unwrap();
+    let funding: FundingResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Funding {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(funding.cumulative_premium_fraction, long.premium_fraction);
+
+    let balance: Uint128 = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::TraderBalance {
+                trader: alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(balance, margin - long.payment.value());
+
+    // straight after the round the preview covers no time at all
+    let next = preview(&env, &alice);
+    assert!(next.premium_fraction.is_zero());
+    assert!(next.next_funding_time > long.next_funding_time);
+}
+
+#[test]
+fn test_keeper_is_rewarded_from_fee_pool() {
+    let mut env = setup::setup();
+    setup::setup_price_feed(&mut env);
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::UpdateFeePoolConfig {
+        insurance_share: Uint128::zero(),
+        insurance_floor: None,
+        keeper_reward: Some(to_decimals(2)),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(3),
+        msg: to_binary(&Cw20HookMsg::DepositFees {}).unwrap(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    env.advance_time(3_600);
+    let balance = usdc.balance(&env.router, env.bob.clone()).unwrap();
+    pay_funding(&mut env).unwrap();
+    assert_eq!(
+        usdc.balance(&env.router, env.bob.clone()).unwrap(),
+        balance + to_decimals(2)
+    );
+
+    // the reward is capped by what is left in the pool
+    env.advance_time(3_600);
+    pay_funding(&mut env).unwrap();
+    assert_eq!(
+        usdc.balance(&env.router, env.bob.clone()).unwrap(),
+        balance + to_decimals(3)
+    );
+
+    let pool: FeePoolResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::FeePool {})
+        .unwrap();
+    assert_eq!(pool.balance, Uint128::zero());
+    assert_eq!(pool.keeper_reward, to_decimals(2));
+}
+
+#[test]
+fn test_unrealized_pnl_calc_options() {
+    let mut env = setup::setup();
+    setup::setup_price_feed(&mut env);
+    let alice = env.alice.clone();
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+
+    env.advance_time(1_000);
+
+    let pnl = |env: &setup::TestingEnv, calc_option: PnlCalcOption| {
+        let res: PositionNotionalAndUnrealizedPnlResponse = env
+            .router
+            .wrap()
+            .query_wasm_smart(
+                &env.engine.addr,
+                &QueryMsg::PositionNotionalAndUnrealizedPnl {
+                    vamm: env.vamm.addr.to_string(),
+                    trader: env.alice.to_string(),
+                    calc_option,
+                },
+            )
+            .unwrap();
+        res
+    };
+
+    // closing at the spot price returns the 100 notional that was opened
+    let res = pnl(&env, PnlCalcOption::SpotPrice);
+    assert!(res.position_notional > to_decimals(99));
+    assert!(res.unrealized_pnl.value() < to_decimals(1));
+
+    // the mark has been around 12.1 since alice bought around 9.09 base, but the
+    // twap is of what closing them would return, the same 100 while the reserves
+    // are unchanged
+    let res = pnl(&env, PnlCalcOption::Twap);
+    assert!(res.position_notional > to_decimals(99));
+    assert!(res.unrealized_pnl.value() < to_decimals(1));
+
+    // whereas the index is still 10
+    let res = pnl(&env, PnlCalcOption::Oracle);
+    assert!(res.unrealized_pnl < Integer::new_negative(to_decimals(9)));
+    assert!(res.unrealized_pnl > Integer::new_negative(to_decimals(10)));
+
+    // traders without a position have nothing to value
+    let res: PositionNotionalAndUnrealizedPnlResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::PositionNotionalAndUnrealizedPnl {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.bob.to_string(),
+                calc_option: PnlCalcOption::Oracle,
+            },
+        )
+        .unwrap();
+    assert!(res.position_notional.is_zero());
+}
+
+#[test]
+fn test_funding_rate_history() {
+    let mut env = setup::setup();
+    setup::setup_price_feed(&mut env);
+
+    let next: NextFundingTimeResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::NextFundingTime {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    // the first round is due a funding period after the market opened
+    let opened = env.router.block_info().time;
+    assert_eq!(next.next_funding_time, opened.seconds() + 3_600);
+
+    let alice = env.alice.clone();
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+
+    for _ in 0..2 {
+        env.advance_time(3_600);
+        pay_funding(&mut env).unwrap();
+    }
+    let now = env.router.block_info().time;
+
+    let next: NextFundingTimeResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::NextFundingTime {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(next.next_funding_time, now.seconds() + 3_600);
+
+    let res: FundingRateHistoryResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::FundingRateHistory {
+                vamm: env.vamm.addr.to_string(),
+                start_after: None,
+                limit: Some(1),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.rounds.len(), 1);
+    assert_eq!(res.rounds[0].round, 1);
+    assert_eq!(res.rounds[0].timestamp, now.minus_seconds(3_600));
+    assert_eq!(res.rounds[0].index_twap, to_decimals(10));
+    // the long pushed the mark above the index so longs paid shorts
+    assert!(res.rounds[0].mark_twap > res.rounds[0].index_twap);
+    assert!(res.rounds[0].premium_fraction > Integer::zero());
+    assert_eq!(res.next_key, Some(1));
+
+    let res: FundingRateHistoryResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::FundingRateHistory {
+                vamm: env.vamm.addr.to_string(),
+                start_after: Some(1),
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.rounds.len(), 1);
+    assert_eq!(res.rounds[0].round, 2);
+    assert_eq!(res.rounds[0].timestamp, now);
+    assert_eq!(res.next_key, None);
+
+    // the history adds up to the cumulative premium fraction
+    let funding: FundingResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Funding {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    let res: FundingRateHistoryResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::FundingRateHistory {
+                vamm: env.vamm.addr.to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    let total = res
+        .rounds
+        .iter()
+        .fold(Integer::zero(), |acc, record| acc + record.premium_fraction);
+    assert_eq!(total, funding.cumulative_premium_fraction);
+}
+
+#[test]
+fn test_late_round_shortens_the_next() {
+    let mut env = setup::setup();
+    setup::setup_price_feed(&mut env);
+    let alice = env.alice.clone();
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+
+    // the first round is settled 3000 seconds late, so the next is due after
+    // the 1800 second buffer rather than the remaining 600 seconds
+    env.advance_time(6_600);
+    pay_funding(&mut env).unwrap();
+    let next: NextFundingTimeResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::NextFundingTime {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        next.next_funding_time,
+        env.router.block_info().time.seconds() + 1_800
+    );
+
+    env.advance_time(1_000);
+    let err = pay_funding(&mut env).unwrap_err();
+    assert!(err.contains("funding is not due until"));
+    env.advance_time(800);
+    pay_funding(&mut env).unwrap();
+
+    // and the premium only covers the 1800 seconds since the last round
+    let res: FundingRateHistoryResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::FundingRateHistory {
+                vamm: env.vamm.addr.to_string(),
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    for (record, elapsed) in res.rounds.iter().zip([3_600u64, 1_800u64]) {
+        assert_eq!(
+            record.premium_fraction,
+            Integer::difference(record.mark_twap, record.index_twap)
+                .multiply_ratio(elapsed, 86_400u64)
+        );
+    }
+    assert_eq!(res.rounds.len(), 2);
+}
@@ -0,0 +1,92 @@
+use crate::state::MAX_TRADE_HISTORY;
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{ExecuteMsg, QueryMsg, Side, TradeHistoryResponse, TradeKind};
+
+fn open_and_close(env: &mut setup::TestingEnv) {
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn trade_history(
+    env: &setup::TestingEnv,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> TradeHistoryResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::TradeHistory {
+                trader: env.alice.to_string(),
+                start_after,
+                limit,
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_trades_are_recorded_in_the_trader_history() {
+    let mut env = setup::setup();
+    open_and_close(&mut env);
+
+    let first = trade_history(&env, None, Some(1));
+    assert_eq!(first.trades.len(), 1);
+    let open = &first.trades[0];
+    assert_eq!(open.trade_id, 1);
+    assert_eq!(open.kind, TradeKind::Open);
+    assert_eq!(open.side, Side::BUY);
+    assert_eq!(open.vamm, env.vamm.addr);
+    assert_eq!(first.next_key, Some(1));
+
+    let second = trade_history(&env, first.next_key, Some(1));
+    let close = &second.trades[0];
+    assert_eq!(close.trade_id, 2);
+    assert_eq!(close.kind, TradeKind::Close);
+    assert_eq!(close.side, Side::BUY);
+    assert_eq!(close.size, open.size);
+    // the size is bought and sold back at the same price, but for rounding
+    assert_eq!(open.price, Uint128::from(10_200_000_003u128));
+    assert_eq!(close.price, Uint128::from(10_199_999_999u128));
+    assert_eq!(close.timestamp, env.router.block_info().time);
+
+    let last = trade_history(&env, second.next_key, Some(1));
+    assert!(last.trades.is_empty());
+    assert_eq!(last.next_key, None);
+}
+
+#[test]
+fn test_trade_history_drops_the_oldest_trades_once_full() {
+    let mut env = setup::setup();
+    for _ in 0..=MAX_TRADE_HISTORY / 2 {
+        open_and_close(&mut env);
+    }
+
+    let history = trade_history(&env, None, None);
+    assert_eq!(history.trades[0].trade_id, 3);
+}
@@ -0,0 +1,152 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{Addr, Uint128};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, HoldingCapExemptionsResponse, HoldingCapacityResponse, MarketCapsResponse,
+    QueryMsg, Side,
+};
+
+fn holding_capacity(env: &setup::TestingEnv, trader: &Addr) -> HoldingCapacityResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::HoldingCapacity {
+                vamm: env.vamm.addr.to_string(),
+                trader: trader.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+fn set_exempt(env: &mut setup::TestingEnv, sender: &Addr, exempt: bool) -> Result<(), String> {
+    let trader = env.alice.to_string();
+    let msg = if exempt {
+        ExecuteMsg::AddHoldingCapExemption { trader }
+    } else {
+        ExecuteMsg::RemoveHoldingCapExemption { trader }
+    };
+    env.router
+        .execute_contract(sender.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[test]
+fn test_max_position_notional() {
+    let mut env = setup::setup();
+    let (owner, alice, bob) = (env.owner.clone(), env.alice.clone(), env.bob.clone());
+
+    let msg = ExecuteMsg::UpdateMarketCaps {
+        vamm: env.vamm.addr.to_string(),
+        open_interest_notional_cap: None,
+        max_position_size: None,
+        max_position_notional: Some(to_decimals(100)),
+    };
+    env.router
+        .execute_contract(owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let res: MarketCapsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarketCaps {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.max_position_notional, Some(to_decimals(100)));
+
+    // alice fills her capacity, bob's is untouched
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+    assert_eq!(
+        holding_capacity(&env, &alice),
+        HoldingCapacityResponse {
+            max_position_notional: Some(to_decimals(100)),
+            exempt: false,
+            position_notional: to_decimals(100),
+            remaining: Some(Uint128::zero()),
+        }
+    );
+    assert_eq!(
+        holding_capacity(&env, &bob).remaining,
+        Some(to_decimals(100))
+    );
+
+    assert_eq!(
+        env.open_position(&alice, Side::BUY, 1, 5).unwrap_err(),
+        "Position notional exceeds the market maximum of 100000000000"
+    );
+
+    // the cap applies to increases only, so the position can still be reduced,
+    // freeing around 20 of capacity as the open notional is rounded down
+    env.open_position(&alice, Side::SELL, 4, 5).unwrap();
+    assert_eq!(
+        holding_capacity(&env, &alice).remaining,
+        Some(Uint128::from(20_000_000_002u128))
+    );
+
+    // only the owner may exempt a trader from the cap
+    set_exempt(&mut env, &alice, true).unwrap_err();
+    set_exempt(&mut env, &owner, true).unwrap();
+
+    let res: HoldingCapExemptionsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::HoldingCapExemptions {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.traders, vec![alice.clone()]);
+
+    env.open_position(&alice, Side::BUY, 10, 5).unwrap();
+    let capacity = holding_capacity(&env, &alice);
+    assert!(capacity.exempt);
+    assert_eq!(capacity.remaining, None);
+
+    // and once the exemption is lifted she cannot add to the position
+    set_exempt(&mut env, &owner, false).unwrap();
+    assert_eq!(
+        set_exempt(&mut env, &owner, false).unwrap_err(),
+        "Generic error: trader is not exempt from holding caps"
+    );
+    assert_eq!(
+        env.open_position(&alice, Side::BUY, 1, 5).unwrap_err(),
+        "Position notional exceeds the market maximum of 100000000000"
+    );
+}
+
+#[test]
+fn test_flip_past_max_position_notional() {
+    let mut env = setup::setup();
+    let (owner, alice) = (env.owner.clone(), env.alice.clone());
+
+    let msg = ExecuteMsg::UpdateMarketCaps {
+        vamm: env.vamm.addr.to_string(),
+        open_interest_notional_cap: None,
+        max_position_size: None,
+        max_position_notional: Some(to_decimals(100)),
+    };
+    env.router
+        .execute_contract(owner, env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+
+    // selling 250 closes the long of 100 and would leave a short of around
+    // 150, more than the market allows
+    assert_eq!(
+        env.open_position(&alice, Side::SELL, 50, 5).unwrap_err(),
+        "Position notional exceeds the market maximum of 100000000000"
+    );
+    assert_eq!(env.position(&alice).notional, to_decimals(100));
+
+    // a flip within the cap is still allowed
+    env.open_position(&alice, Side::SELL, 30, 5).unwrap();
+    assert!(env.position(&alice).notional <= to_decimals(100));
+}
@@ -0,0 +1,173 @@
+use crate::state::{InsuranceFund, InsuranceStake};
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{to_binary, Addr, Uint128};
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    Cw20HookMsg, ExecuteMsg, InsuranceFundResponse, InsuranceStakeResponse, QueryMsg,
+};
+
+fn send(env: &mut setup::TestingEnv, sender: &Addr, amount: Uint128, msg: Cw20HookMsg) -> bool {
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount,
+        msg: to_binary(&msg).unwrap(),
+    };
+    env.router
+        .execute_contract(sender.clone(), env.usdc.addr.clone(), &msg, &[])
+        .is_ok()
+}
+
+fn stake_value(env: &setup::TestingEnv, staker: &Addr) -> InsuranceStakeResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::InsuranceStake {
+                staker: staker.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_insurance_fees_are_shared_pro_rata() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    let bob = env.bob.clone();
+
+    // fees cannot be shared before anyone has staked
+    assert!(!send(
+        &mut env,
+        &alice,
+        to_decimals(10),
+        Cw20HookMsg::DepositInsuranceFees {}
+    ));
+
+    assert!(send(
+        &mut env,
+        &alice,
+        to_decimals(100),
+        Cw20HookMsg::StakeInsurance {}
+    ));
+    assert!(send(
+        &mut env,
+        &bob,
+        to_decimals(300),
+        Cw20HookMsg::StakeInsurance {}
+    ));
+    assert!(send(
+        &mut env,
+        &alice,
+        to_decimals(40),
+        Cw20HookMsg::DepositInsuranceFees {}
+    ));
+
+    assert_eq!(stake_value(&env, &alice).value, to_decimals(110));
+    assert_eq!(stake_value(&env, &bob).value, to_decimals(330));
+
+    let res: InsuranceFundResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::InsuranceFund {})
+        .unwrap();
+    assert_eq!(res.balance, to_decimals(440));
+
+    // stakes added after the fees do not share in them
+    assert!(send(
+        &mut env,
+        &alice,
+        to_decimals(100),
+        Cw20HookMsg::StakeInsurance {}
+    ));
+    assert_eq!(stake_value(&env, &alice).value, to_decimals(210));
+}
+
+#[test]
+fn test_unstake_after_cooldown() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::UpdateInsuranceConfig {
+        unstake_cooldown: 3_600u64,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    assert!(send(
+        &mut env,
+        &alice,
+        to_decimals(100),
+        Cw20HookMsg::StakeInsurance {}
+    ));
+    let balance = usdc.balance(&env.router, alice.clone()).unwrap();
+
+    // cannot request more than the stake is worth
+    let msg = ExecuteMsg::RequestUnstake {
+        amount: to_decimals(101),
+    };
+    let res = env
+        .router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    let msg = ExecuteMsg::RequestUnstake {
+        amount: to_decimals(60),
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(
+        stake_value(&env, &alice).unstake_amount,
+        Some(to_decimals(60))
+    );
+
+    let msg = ExecuteMsg::Unstake {};
+    let res = env
+        .router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(3_600);
+    });
+
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res = stake_value(&env, &alice);
+    assert_eq!(res.value, to_decimals(40));
+    assert_eq!(res.unstake_amount, None);
+    assert_eq!(
+        usdc.balance(&env.router, alice).unwrap(),
+        balance + to_decimals(60)
+    );
+}
+
+#[test]
+fn test_insurance_losses_are_first_loss() {
+    let decimals = to_decimals(1);
+    let mut fund = InsuranceFund::new(decimals);
+    fund.balance = to_decimals(200);
+
+    let stake = InsuranceStake {
+        principal: to_decimals(50),
+        index: decimals,
+        epoch: 0u64,
+        unstake: None,
+    };
+
+    // a quarter of the fund is lost
+    let uncovered = fund.absorb_loss(to_decimals(50), decimals);
+    assert_eq!(uncovered, Uint128::zero());
+    assert_eq!(fund.value_of(&stake), Uint128::from(37_500_000_000u128));
+
+    // losses beyond the fund wipe out all stakes
+    let uncovered = fund.absorb_loss(to_decimals(170), decimals);
+    assert_eq!(uncovered, to_decimals(20));
+    assert_eq!(fund.epoch, 1u64);
+    assert_eq!(fund.value_of(&stake), Uint128::zero());
+}
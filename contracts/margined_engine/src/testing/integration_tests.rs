@@ -1,6 +1,6 @@
 use crate::testing::setup::{self, to_decimals};
 use cosmwasm_std::Uint128;
-use cw20::Cw20Contract;
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
 use cw_multi_test::Executor;
 use margined_perp::margined_engine::{
     ConfigResponse, ExecuteMsg, PositionResponse, QueryMsg, Side,
@@ -36,6 +36,10 @@ fn test_open_position_long() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(60u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -85,6 +89,10 @@ fn test_open_position_two_longs() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(60u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -97,6 +105,10 @@ fn test_open_position_two_longs() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(60u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -149,6 +161,10 @@ fn test_open_position_two_shorts() {
         side: Side::SELL,
         quote_asset_amount: to_decimals(40u64),
         leverage: to_decimals(5u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -161,6 +177,10 @@ fn test_open_position_two_shorts() {
         side: Side::SELL,
         quote_asset_amount: to_decimals(40u64),
         leverage: to_decimals(5u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -213,6 +233,10 @@ fn test_open_position_equal_size_opposite_side() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(60u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -225,6 +249,10 @@ fn test_open_position_equal_size_opposite_side() {
         side: Side::SELL,
         quote_asset_amount: to_decimals(300u64),
         leverage: to_decimals(2u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -277,6 +305,10 @@ fn test_open_position_one_long_two_shorts() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(60u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -289,6 +321,10 @@ fn test_open_position_one_long_two_shorts() {
         side: Side::SELL,
         quote_asset_amount: to_decimals(20u64),
         leverage: to_decimals(5u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -316,6 +352,10 @@ fn test_open_position_one_long_two_shorts() {
         side: Side::SELL,
         quote_asset_amount: to_decimals(50u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -368,6 +408,10 @@ fn test_open_position_short_and_two_longs() {
         side: Side::SELL,
         quote_asset_amount: to_decimals(40u64),
         leverage: to_decimals(5u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -394,6 +438,10 @@ fn test_open_position_short_and_two_longs() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(20u64),
         leverage: to_decimals(5u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -413,13 +461,17 @@ fn test_open_position_short_and_two_longs() {
         )
         .unwrap();
     assert_eq!(Uint128::new(11_111_111_112), position.size);
-    assert_eq!(to_decimals(40), position.margin);
+    assert_eq!(Uint128::new(39_999_999_996), position.margin);
 
     let msg = ExecuteMsg::OpenPosition {
         vamm: env.vamm.addr.to_string(),
         side: Side::BUY,
         quote_asset_amount: to_decimals(10u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -440,7 +492,7 @@ fn test_open_position_short_and_two_longs() {
         )
         .unwrap();
     assert_eq!(Uint128::from(1 as u128), position.size);
-    assert_eq!(to_decimals(40u64), position.margin);
+    assert_eq!(Uint128::new(39_999_999_991), position.margin);
 }
 
 #[test]
@@ -459,6 +511,10 @@ fn test_open_position_short_long_short() {
         side: Side::SELL,
         quote_asset_amount: to_decimals(20u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -471,6 +527,10 @@ fn test_open_position_short_long_short() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(150u64),
         leverage: to_decimals(3u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -497,6 +557,10 @@ fn test_open_position_short_long_short() {
         side: Side::SELL,
         quote_asset_amount: to_decimals(25u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -536,6 +600,10 @@ fn test_open_position_long_short_short() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(25u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -548,6 +616,10 @@ fn test_open_position_long_short_short() {
         side: Side::SELL,
         quote_asset_amount: to_decimals(150u64),
         leverage: to_decimals(3u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -574,6 +646,10 @@ fn test_open_position_long_short_short() {
         side: Side::BUY,
         quote_asset_amount: to_decimals(20u64),
         leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
     };
 
     let _res = env
@@ -596,3 +672,275 @@ fn test_open_position_long_short_short() {
     assert_eq!(Uint128::zero(), position.size);
     assert_eq!(Uint128::zero(), position.margin);
 }
+
+#[test]
+fn test_open_position_below_min_notional() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::UpdateConfig {
+        eligible_collateral: None,
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: Some(to_decimals(100u64)),
+        fee_pool: None,
+        open_liquidation: None,
+        pause_level: None,
+        staking_contract: None,
+        liquidation_price_source: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // 9 * 10 = 90 notional
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(9u64),
+        leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert!(err.to_string().contains("minimum of 100000000000"));
+
+    // 10 * 10 = 100 notional
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10u64),
+        leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+#[test]
+fn test_partial_close_position() {
+    let mut env = setup::setup();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // cannot close more than the position
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: Some(to_decimals(40u64)),
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Invalid close size");
+
+    // closing 15 of the 37.5 releases 40% of the margin, the open notional is
+    // reduced by the notional exchanged
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: Some(to_decimals(15u64)),
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let position: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(Uint128::new(22_500_000_000), position.size);
+    assert_eq!(to_decimals(36u64), position.margin);
+    assert_eq!(Uint128::new(290_322_580_646), position.notional);
+
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, to_decimals(36));
+
+    // the rest of the position is closed without a size
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let position: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(Uint128::zero(), position.size);
+    assert_eq!(Uint128::zero(), position.margin);
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::new(4_999_999_999_999));
+
+    // there is nothing left to close
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Trader has no open position");
+}
+
+#[test]
+fn test_close_position_at_a_loss_pays_out_the_remaining_margin() {
+    let mut env = setup::setup();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // bob shorts and pushes the price against alice
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(5u64),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(5u64),
+        leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // alice is paid the margin less the realized loss
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, Uint128::new(4_969_762_845_845));
+}
+
+#[test]
+fn test_reverse_position_opens_the_remainder_on_the_other_side() {
+    let mut env = setup::setup();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60u64),
+        leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // selling 1000 closes the long worth 600 and opens a short of 400
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(100u64),
+        leverage: to_decimals(10u64),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let position: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(Uint128::new(66_666_666_667), position.size);
+    assert_eq!(to_decimals(40u64), position.margin);
+    assert_eq!(to_decimals(400u64), position.notional);
+
+    // the margin of the long was paid out and that of the short taken
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(4960u64));
+    let engine_balance = usdc.balance(&env.router, env.engine.addr.clone()).unwrap();
+    assert_eq!(engine_balance, to_decimals(40u64));
+}
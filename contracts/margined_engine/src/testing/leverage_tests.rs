@@ -0,0 +1,145 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw20::Cw20ExecuteMsg;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, FreeCollateralResponse, PositionResponse, QueryMsg, Side,
+};
+
+fn free_collateral(env: &setup::TestingEnv) -> Uint128 {
+    let res: FreeCollateralResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::FreeCollateral {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    res.free_collateral
+}
+
+#[test]
+fn test_leverage_below_one() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: Uint128::from(500_000_000u128),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Leverage must be at least 1x");
+}
+
+#[test]
+fn test_free_collateral() {
+    let mut env = setup::setup();
+    assert_eq!(free_collateral(&env), Uint128::zero());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // all but the initial margin of the notional of 20 is free, less the
+    // rounding of the position's value
+    let free = free_collateral(&env);
+    assert_eq!(free, Uint128::from(9_999_997_992u128));
+
+    // bob's short takes the price down, alice's loss is no longer free
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(10),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let free = free_collateral(&env);
+    assert!(free < Uint128::from(9_999_997_992u128));
+
+    let msg = ExecuteMsg::WithdrawMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: free + Uint128::from(1u128),
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Position would be left with insufficient margin"
+    );
+
+    let msg = ExecuteMsg::WithdrawMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: free,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(free_collateral(&env), Uint128::zero());
+
+    let position: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(position.margin, to_decimals(10) - free);
+}
+
+#[test]
+fn test_increase_at_a_different_leverage() {
+    let mut env = setup::setup();
+
+    env.open_position(&env.alice.clone(), Side::BUY, 10, 10)
+        .unwrap();
+    assert_eq!(env.position(&env.alice).margin, to_decimals(10));
+
+    // adding at a lower leverage credits only the margin paid for it,
+    // the margin of the first order is neither lost nor recalculated
+    env.open_position(&env.alice.clone(), Side::BUY, 10, 1)
+        .unwrap();
+    let position = env.position(&env.alice);
+    assert_eq!(position.margin, to_decimals(20));
+    assert_eq!(position.notional, to_decimals(110));
+}
@@ -0,0 +1,542 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{to_binary, Uint128};
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    BadDebtResponse, Cw20HookMsg, ExecuteMsg, InsuranceFundResponse, LiquidationPriceResponse,
+    LiquidatorsResponse, MarginRatioResponse, MarginTier, PnlCalcOption, PositionResponse,
+    QueryMsg, Side,
+};
+use margined_perp::margined_vamm::QueryMsg as VammQueryMsg;
+
+// alice goes long 100 notional on 20 margin with a 10% maintenance margin,
+// with the owner whitelisted to liquidate
+fn setup_position() -> setup::TestingEnv {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::UpdateMarginTiers {
+        vamm: env.vamm.addr.to_string(),
+        tiers: vec![MarginTier {
+            notional: Uint128::zero(),
+            initial_margin_ratio: Uint128::from(100_000_000u128),
+            maintenance_margin_ratio: Uint128::from(100_000_000u128),
+        }],
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::AddLiquidator {
+        liquidator: env.owner.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(20),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    env
+}
+
+// bob shorts on 5x leverage, pushing the price down
+fn bob_short(env: &mut setup::TestingEnv, margin: u64) {
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(margin),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(margin),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn liquidate(env: &mut setup::TestingEnv) -> Result<(), String> {
+    let msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+        takeover: false,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn alice_position(env: &setup::TestingEnv) -> PositionResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+fn insurance_fund(env: &setup::TestingEnv) -> InsuranceFundResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::InsuranceFund {})
+        .unwrap()
+}
+
+fn market_bad_debt(env: &setup::TestingEnv) -> BadDebtResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::BadDebt {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_cannot_liquidate_healthy_position() {
+    let mut env = setup_position();
+
+    let err = liquidate(&mut env).unwrap_err();
+    assert_eq!(
+        err,
+        "Generic error: position is above the maintenance margin ratio"
+    );
+
+    let msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.bob.to_string(),
+        takeover: false,
+    };
+    let err = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Trader has no open position");
+}
+
+#[test]
+fn test_liquidate_under_collateralized_position() {
+    let mut env = setup_position();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    // alice's long is worth around 83.3 after bob's short, leaving around
+    // 3.3 of her 20 margin which is below 10%
+    bob_short(&mut env, 20);
+    liquidate(&mut env).unwrap();
+
+    let position = alice_position(&env);
+    assert_eq!(position.size, Uint128::zero());
+    assert_eq!(position.margin, Uint128::zero());
+
+    // the liquidator takes the fee and the rest of the margin is kept by
+    // the insurance fund
+    let fee = usdc.balance(&env.router, env.owner.clone()).unwrap();
+    assert!(!fee.is_zero());
+    let fund = insurance_fund(&env);
+    assert!(fund.reserve > to_decimals(3) && fund.reserve < to_decimals(4));
+    assert_eq!(
+        usdc.balance(&env.router, env.engine.addr.clone()).unwrap(),
+        to_decimals(40) - fee
+    );
+}
+
+#[test]
+fn test_liquidation_bad_debt() {
+    let mut env = setup_position();
+
+    // bob's larger short takes alice's losses past her margin, and with an
+    // empty insurance fund the debt is left uncovered
+    bob_short(&mut env, 40);
+    let msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+        takeover: false,
+    };
+    let res = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let bad_debt = res
+        .events
+        .iter()
+        .flat_map(|event| event.attributes.iter())
+        .find(|attr| attr.key == "bad_debt")
+        .unwrap();
+    assert_ne!(bad_debt.value, "0");
+    assert_eq!(insurance_fund(&env).reserve, Uint128::zero());
+    assert_eq!(alice_position(&env).size, Uint128::zero());
+
+    let res = market_bad_debt(&env);
+    assert_eq!(res.realized.to_string(), bad_debt.value);
+    assert_eq!(res.insured, Uint128::zero());
+    assert_eq!(res.uncovered, res.realized);
+}
+
+#[test]
+fn test_bad_debt_is_drawn_from_insurance_fund() {
+    let mut env = setup_position();
+
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(1),
+        msg: to_binary(&Cw20HookMsg::StakeInsurance {}).unwrap(),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    bob_short(&mut env, 40);
+    let msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+        takeover: false,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the stake covers part of the debt and the rest is left uncovered
+    let res = market_bad_debt(&env);
+    assert_eq!(res.insured, to_decimals(1));
+    assert_eq!(res.uncovered, res.realized - res.insured);
+    assert!(!res.uncovered.is_zero());
+    assert_eq!(insurance_fund(&env).balance, Uint128::zero());
+}
+
+#[test]
+fn test_margin_ratio_tracks_unrealized_pnl() {
+    let mut env = setup_position();
+    let query = QueryMsg::MarginRatio {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+    };
+
+    // 20 margin on a position still worth around 100
+    let res: MarginRatioResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &query)
+        .unwrap();
+    assert!(res.margin_ratio > Uint128::from(199_000_000u128));
+    assert!(res.margin_ratio <= Uint128::from(200_000_000u128));
+    assert_eq!(res.maintenance_margin_ratio, Uint128::from(100_000_000u128));
+
+    // around 3.3 of margin left on a position worth around 83.3
+    bob_short(&mut env, 20);
+    let res: MarginRatioResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &query)
+        .unwrap();
+    assert!(res.margin_ratio > Uint128::from(39_000_000u128));
+    assert!(res.margin_ratio < Uint128::from(41_000_000u128));
+}
+
+fn update_liquidation_price_source(env: &mut setup::TestingEnv, source: PnlCalcOption) {
+    let msg = ExecuteMsg::UpdateConfig {
+        eligible_collateral: None,
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: None,
+        fee_pool: None,
+        open_liquidation: None,
+        pause_level: None,
+        staking_contract: None,
+        liquidation_price_source: Some(source),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+#[test]
+fn test_liquidation_price_source() {
+    let mut env = setup_position();
+    setup::setup_price_feed(&mut env);
+    update_liquidation_price_source(&mut env, PnlCalcOption::Oracle);
+
+    // bob's short moves the spot price but not the index, at which alice's
+    // long is still worth around 95
+    bob_short(&mut env, 20);
+    let res: MarginRatioResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarginRatio {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert!(res.margin_ratio > res.maintenance_margin_ratio);
+    let err = liquidate(&mut env).unwrap_err();
+    assert_eq!(
+        err,
+        "Generic error: position is above the maintenance margin ratio"
+    );
+
+    // at the spot price it is under water
+    update_liquidation_price_source(&mut env, PnlCalcOption::SpotPrice);
+    liquidate(&mut env).unwrap();
+    assert_eq!(alice_position(&env).size, Uint128::zero());
+}
+
+#[test]
+fn test_liquidation_price() {
+    let mut env = setup_position();
+    let query = QueryMsg::LiquidationPrice {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+    };
+
+    // alice's long closes for 80 / 0.9 once the mark falls to around 10.7
+    let res: LiquidationPriceResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &query)
+        .unwrap();
+    let liquidation_price = res.liquidation_price.unwrap();
+    assert!(liquidation_price > Uint128::from(10_690_000_000u128));
+    assert!(liquidation_price < Uint128::from(10_700_000_000u128));
+
+    // still above it after a small short
+    bob_short(&mut env, 5);
+    let spot_price: Uint128 = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.vamm.addr, &VammQueryMsg::SpotPrice {})
+        .unwrap();
+    assert!(spot_price > liquidation_price);
+    liquidate(&mut env).unwrap_err();
+
+    // the price it points to does not move as the market does
+    let res: LiquidationPriceResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &query)
+        .unwrap();
+    let diff = res.liquidation_price.unwrap().u128() as i128 - liquidation_price.u128() as i128;
+    assert!(diff.abs() <= 10);
+
+    // and below it once the short is larger
+    bob_short(&mut env, 15);
+    let spot_price: Uint128 = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.vamm.addr, &VammQueryMsg::SpotPrice {})
+        .unwrap();
+    assert!(spot_price < liquidation_price);
+    liquidate(&mut env).unwrap();
+
+    env.router
+        .wrap()
+        .query_wasm_smart::<LiquidationPriceResponse>(&env.engine.addr, &query)
+        .unwrap_err();
+}
+
+#[test]
+fn test_liquidator_whitelist() {
+    let mut env = setup_position();
+    bob_short(&mut env, 20);
+
+    // bob is not whitelisted
+    let msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+        takeover: false,
+    };
+    let err = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Unauthorized");
+
+    // only the owner manages the whitelist
+    let add = ExecuteMsg::AddLiquidator {
+        liquidator: env.bob.to_string(),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &add, &[])
+        .unwrap_err();
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &add, &[])
+        .unwrap();
+
+    let res: LiquidatorsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Liquidators {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(res.liquidators.len(), 2);
+    assert!(res.liquidators.contains(&env.bob));
+
+    // removing bob and the owner leaves no one able to liquidate
+    for liquidator in [env.bob.clone(), env.owner.clone()] {
+        let msg = ExecuteMsg::RemoveLiquidator {
+            liquidator: liquidator.to_string(),
+        };
+        env.router
+            .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+            .unwrap();
+    }
+    let err = liquidate(&mut env).unwrap_err();
+    assert_eq!(err, "Unauthorized");
+
+    // until liquidation is opened to everyone
+    let msg = ExecuteMsg::UpdateConfig {
+        eligible_collateral: None,
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: None,
+        fee_pool: None,
+        open_liquidation: Some(true),
+        pause_level: None,
+        staking_contract: None,
+        liquidation_price_source: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+        takeover: false,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(alice_position(&env).size, Uint128::zero());
+}
+
+#[test]
+fn test_insurance_fund_takes_over_position() {
+    let mut env = setup_position();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    let long = alice_position(&env);
+    bob_short(&mut env, 20);
+
+    let takeover = |env: &mut setup::TestingEnv| {
+        let msg = ExecuteMsg::Liquidate {
+            vamm: env.vamm.addr.to_string(),
+            trader: env.alice.to_string(),
+            takeover: true,
+        };
+        env.router
+            .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+            .map_err(|err| err.to_string())
+    };
+
+    // the fund must be able to carry the maintenance margin of 10
+    assert_eq!(
+        takeover(&mut env).unwrap_err(),
+        "Generic error: insurance fund cannot carry the position"
+    );
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(10),
+        msg: to_binary(&Cw20HookMsg::StakeInsurance {}).unwrap(),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the position moves to the fund at its bankruptcy notional of 80 and
+    // the price is left where it was
+    let spot_price = |env: &setup::TestingEnv| -> Uint128 {
+        env.router
+            .wrap()
+            .query_wasm_smart(&env.vamm.addr, &VammQueryMsg::SpotPrice {})
+            .unwrap()
+    };
+    let price = spot_price(&env);
+    let balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    takeover(&mut env).unwrap();
+    assert_eq!(spot_price(&env), price);
+    assert_eq!(alice_position(&env).size, Uint128::zero());
+    assert_eq!(
+        usdc.balance(&env.router, env.alice.clone()).unwrap(),
+        balance
+    );
+
+    let fund_position = env.position(&env.engine.addr.clone());
+    assert_eq!(fund_position.size, long.size);
+    assert_eq!(fund_position.notional, to_decimals(80));
+    assert_eq!(fund_position.margin, Uint128::zero());
+
+    // the fund's position cannot be liquidated, only unwound by a liquidator
+    let msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.engine.addr.to_string(),
+        takeover: false,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    let unwind = ExecuteMsg::UnwindInsurancePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: Some(long.size.multiply_ratio(1u128, 2u128)),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &unwind, &[])
+        .unwrap_err();
+
+    // half is unwound in one block, the rest in the next once bob has closed
+    // and the price recovered, the fund gaining on the bankruptcy price
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &unwind, &[])
+        .unwrap();
+    assert_eq!(
+        env.position(&env.engine.addr.clone()).size,
+        long.size - long.size.multiply_ratio(1u128, 2u128)
+    );
+    env.close_position(&env.bob.clone()).unwrap();
+    env.advance_time(15);
+    let msg = ExecuteMsg::UnwindInsurancePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(env.position(&env.engine.addr.clone()).size, Uint128::zero());
+    assert!(!insurance_fund(&env).reserve.is_zero());
+    assert_eq!(market_bad_debt(&env).realized, Uint128::zero());
+}
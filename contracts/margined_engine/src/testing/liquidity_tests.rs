@@ -0,0 +1,230 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{to_binary, Addr, Uint128};
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    Cw20HookMsg, ExecuteMsg, LiquidityPoolResponse, LiquidityStakeResponse, PnlCalcOption,
+    PositionNotionalAndUnrealizedPnlResponse, PositionResponse, QueryMsg, Side,
+};
+use margined_perp::margined_vamm::{
+    ExecuteMsg as VammExecuteMsg, QueryMsg as VammQueryMsg, StateResponse,
+};
+
+// the engine scales the reserves of the vamms it owns
+fn hand_vamm_to_engine(env: &mut setup::TestingEnv) {
+    let msg = VammExecuteMsg::ProposeOwnership {
+        owner: env.engine.addr.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.vamm.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::AcceptVammOwnership {
+        vamm: env.vamm.addr.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn send_to_engine(
+    env: &mut setup::TestingEnv,
+    sender: &Addr,
+    amount: u64,
+    hook: &Cw20HookMsg,
+) -> bool {
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: to_decimals(amount),
+        msg: to_binary(hook).unwrap(),
+    };
+    env.router
+        .execute_contract(sender.clone(), env.usdc.addr.clone(), &msg, &[])
+        .is_ok()
+}
+
+fn vamm_state(env: &setup::TestingEnv) -> StateResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(&env.vamm.addr, &VammQueryMsg::State {})
+        .unwrap()
+}
+
+fn alice_position(env: &setup::TestingEnv) -> PositionResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+fn alice_notional(env: &setup::TestingEnv) -> Uint128 {
+    let res: PositionNotionalAndUnrealizedPnlResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::PositionNotionalAndUnrealizedPnl {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+                calc_option: PnlCalcOption::SpotPrice,
+            },
+        )
+        .unwrap();
+    res.position_notional
+}
+
+#[test]
+fn test_liquidity_scales_reserves_and_earns_fees() {
+    let mut env = setup::setup();
+    hand_vamm_to_engine(&mut env);
+
+    let provide = Cw20HookMsg::ProvideLiquidity {
+        vamm: env.vamm.addr.to_string(),
+    };
+    let alice = env.alice.clone();
+    assert!(send_to_engine(&mut env, &alice, 500, &provide));
+
+    // the market is half as deep again at the same price
+    let state = vamm_state(&env);
+    assert_eq!(state.quote_asset_reserve, to_decimals(1_500));
+    assert_eq!(state.base_asset_reserve, to_decimals(150));
+
+    let res: LiquidityPoolResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::LiquidityPool {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.total, to_decimals(500));
+
+    let fees = Cw20HookMsg::DepositLiquidityFees {
+        vamm: env.vamm.addr.to_string(),
+    };
+    let bob = env.bob.clone();
+    assert!(send_to_engine(&mut env, &bob, 10, &fees));
+
+    let res: LiquidityStakeResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::LiquidityStake {
+                vamm: env.vamm.addr.to_string(),
+                provider: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        res,
+        LiquidityStakeResponse {
+            amount: to_decimals(500),
+            fees: to_decimals(10),
+        }
+    );
+
+    // withdrawing pays out the fees and shrinks the market
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    let balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    let msg = ExecuteMsg::WithdrawLiquidity {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(200),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(
+        usdc.balance(&env.router, env.alice.clone()).unwrap(),
+        balance + to_decimals(210)
+    );
+
+    let state = vamm_state(&env);
+    assert_eq!(state.quote_asset_reserve, to_decimals(1_300));
+    assert_eq!(state.base_asset_reserve, to_decimals(130));
+
+    // only the remaining liquidity can be withdrawn
+    let msg = ExecuteMsg::WithdrawLiquidity {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(301),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+}
+
+#[test]
+fn test_liquidity_needs_the_engine_to_own_the_vamm() {
+    let mut env = setup::setup();
+
+    let provide = Cw20HookMsg::ProvideLiquidity {
+        vamm: env.vamm.addr.to_string(),
+    };
+    let alice = env.alice.clone();
+    assert!(!send_to_engine(&mut env, &alice, 500, &provide));
+
+    // fees cannot be shared out before anyone provides liquidity
+    hand_vamm_to_engine(&mut env);
+    let fees = Cw20HookMsg::DepositLiquidityFees {
+        vamm: env.vamm.addr.to_string(),
+    };
+    let bob = env.bob.clone();
+    assert!(!send_to_engine(&mut env, &bob, 10, &fees));
+}
+
+#[test]
+fn test_positions_are_resized_when_k_changes() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let before = alice_position(&env);
+    let notional = alice_notional(&env);
+
+    let msg = VammExecuteMsg::AdjustK {
+        multiplier: to_decimals(2),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.vamm.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the deeper market slips less, so a smaller position is worth the same
+    // up to rounding
+    let after = alice_position(&env);
+    assert_eq!(after.liquidity_history_index, Uint128::from(1u128));
+    assert!(after.size < before.size);
+    let diff = alice_notional(&env).u128() as i128 - notional.u128() as i128;
+    assert!(diff.abs() <= 10);
+
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(alice_position(&env).size, Uint128::zero());
+}
@@ -0,0 +1,255 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{to_binary, Addr, Uint128};
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    Cw20HookMsg, ExecuteMsg, MarketProposalResponse, MarketStatusResponse, PositionResponse,
+    ProposalStatus, QueryMsg, Side,
+};
+use margined_perp::margined_vamm::{
+    ConfigResponse as VammConfigResponse, InstantiateMsg as VammInstantiateMsg,
+    QueryMsg as VammQueryMsg,
+};
+
+fn market(decimals: u8) -> VammInstantiateMsg {
+    let unit = Uint128::from(10u128.pow(decimals as u32));
+    VammInstantiateMsg {
+        decimals,
+        quote_asset: "BTC".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: unit.checked_mul(Uint128::from(1_000u128)).unwrap(),
+        base_asset_reserve: unit.checked_mul(Uint128::from(100u128)).unwrap(),
+        funding_period: 3_600_u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: None,
+    }
+}
+
+// returns whether the proposal was accepted
+fn propose(env: &mut setup::TestingEnv, bond: Uint128, market: VammInstantiateMsg) -> bool {
+    let msg = Cw20ExecuteMsg::Send {
+        contract: env.engine.addr.to_string(),
+        amount: bond,
+        msg: to_binary(&Cw20HookMsg::ProposeMarket { market }).unwrap(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.usdc.addr.clone(), &msg, &[])
+        .is_ok()
+}
+
+fn enable_listing(env: &mut setup::TestingEnv) {
+    let msg = ExecuteMsg::UpdateListingConfig {
+        vamm_code_id: env.vamm.id,
+        bond: to_decimals(100),
+        challenge_period: 86_400u64,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+#[test]
+fn test_propose_market_requires_listing_config() {
+    let mut env = setup::setup();
+
+    assert!(!propose(&mut env, to_decimals(100), market(9)));
+}
+
+#[test]
+fn test_propose_market_insufficient_bond() {
+    let mut env = setup::setup();
+    enable_listing(&mut env);
+
+    assert!(!propose(&mut env, to_decimals(99), market(9)));
+}
+
+#[test]
+fn test_list_market_after_challenge_period() {
+    let mut env = setup::setup();
+    enable_listing(&mut env);
+
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    assert!(propose(&mut env, to_decimals(100), market(9)));
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(4_900));
+
+    // cannot list during the challenge period
+    let msg = ExecuteMsg::ListMarket { proposal_id: 1 };
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(86_400);
+        block.height += 1;
+    });
+
+    // anyone can list once the period is over
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let proposal: MarketProposalResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarketProposal { proposal_id: 1 },
+        )
+        .unwrap();
+    assert_eq!(proposal.status, ProposalStatus::Listed);
+    assert_eq!(proposal.proposer, env.alice);
+
+    // the new vamm is owned by the engine and the bond is refunded
+    let vamm: Addr = proposal.vamm.unwrap();
+    let config: VammConfigResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&vamm, &VammQueryMsg::Config {})
+        .unwrap();
+    assert_eq!(config.owner, env.engine.addr);
+    assert_eq!(config.quote_asset, "BTC".to_string());
+
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(5_000));
+
+    // cannot be listed twice
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_veto_market_slashes_bond() {
+    let mut env = setup::setup();
+    enable_listing(&mut env);
+
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    assert!(propose(&mut env, to_decimals(100), market(9)));
+
+    // only the owner can veto
+    let msg = ExecuteMsg::VetoMarket { proposal_id: 1 };
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let proposal: MarketProposalResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarketProposal { proposal_id: 1 },
+        )
+        .unwrap();
+    assert_eq!(proposal.status, ProposalStatus::Vetoed);
+
+    let owner_balance = usdc.balance(&env.router, env.owner.clone()).unwrap();
+    assert_eq!(owner_balance, to_decimals(100));
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    assert_eq!(alice_balance, to_decimals(4_900));
+
+    // a vetoed proposal can never be listed
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(86_400);
+        block.height += 1;
+    });
+    let msg = ExecuteMsg::ListMarket { proposal_id: 1 };
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_listed_market_with_fewer_decimals() {
+    let mut env = setup::setup();
+    enable_listing(&mut env);
+
+    assert!(propose(&mut env, to_decimals(100), market(6)));
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(86_400);
+        block.height += 1;
+    });
+    let msg = ExecuteMsg::ListMarket { proposal_id: 1 };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let proposal: MarketProposalResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarketProposal { proposal_id: 1 },
+        )
+        .unwrap();
+    let vamm = proposal.vamm.unwrap();
+
+    // the market is listed closed until the owner opens it
+    let status = |env: &setup::TestingEnv| -> MarketStatusResponse {
+        env.router
+            .wrap()
+            .query_wasm_smart(
+                &env.engine.addr,
+                &QueryMsg::MarketStatus {
+                    vamm: vamm.to_string(),
+                },
+            )
+            .unwrap()
+    };
+    assert!(!status(&env).open);
+
+    let msg = ExecuteMsg::SetMarketOpen {
+        vamm: vamm.to_string(),
+        open: true,
+    };
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert!(status(&env).open);
+
+    // amounts stay in the engine's decimals on either side of the swap
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: vamm.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let position: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: vamm.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(position.notional, to_decimals(20));
+    assert_eq!(position.margin, to_decimals(10));
+    assert_eq!(position.size, Uint128::from(1_960_784_000u128));
+}
@@ -0,0 +1,247 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{to_binary, Addr, Empty, Uint128};
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::{Contract, ContractWrapper, Executor};
+use margined_perp::margined_engine::{
+    ExecuteMsg, MakerVaultResponse, OpenInterestImbalanceResponse, QueryMsg, Side,
+};
+use margined_perp::margined_maker_vault::{
+    Cw20HookMsg as VaultHookMsg, ExecuteMsg as VaultExecuteMsg,
+    InstantiateMsg as VaultInstantiateMsg, QueryMsg as VaultQueryMsg, SharesResponse,
+    StateResponse,
+};
+use margined_perp::margined_vamm::ExecuteMsg as VammExecuteMsg;
+
+fn contract_maker_vault() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new_with_empty(
+        margined_maker_vault::contract::execute,
+        margined_maker_vault::contract::instantiate,
+        margined_maker_vault::contract::query,
+    );
+    Box::new(contract)
+}
+
+// sets up a vault trading at 2x, paid half the toll of its market
+fn setup_maker_vault(env: &mut setup::TestingEnv) -> Addr {
+    let vault_id = env.router.store_code(contract_maker_vault());
+    let vault = env
+        .router
+        .instantiate_contract(
+            vault_id,
+            env.owner.clone(),
+            &VaultInstantiateMsg {
+                engine: env.engine.addr.to_string(),
+                vamm: env.vamm.addr.to_string(),
+                collateral: env.usdc.addr.to_string(),
+                decimals: 9u8,
+                leverage: to_decimals(2),
+            },
+            &[],
+            "maker_vault",
+            None,
+        )
+        .unwrap();
+
+    let msg = ExecuteMsg::UpdateMakerVault {
+        vamm: env.vamm.addr.to_string(),
+        vault: Some(vault.to_string()),
+        fee_share: Uint128::from(500_000_000u128),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    vault
+}
+
+fn vault_state(env: &setup::TestingEnv, vault: &Addr) -> StateResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(vault, &VaultQueryMsg::State {})
+        .unwrap()
+}
+
+fn imbalance(env: &setup::TestingEnv) -> OpenInterestImbalanceResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::OpenInterestImbalance {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_maker_vault_backstops_market() {
+    let mut env = setup::setup();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    // 1% toll
+    let msg = VammExecuteMsg::UpdateConfig {
+        toll_ratio: Some(Uint128::from(10_000_000u128)),
+        spread_ratio: None,
+        price_feed: None,
+        fluctuation_limit_ratio: None,
+        engine: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.vamm.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let vault = setup_maker_vault(&mut env);
+    let res: MakerVaultResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MakerVault {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.vault, Some(vault.clone()));
+
+    // only the owner may set the vault
+    let msg = ExecuteMsg::UpdateMakerVault {
+        vamm: env.vamm.addr.to_string(),
+        vault: None,
+        fee_share: Uint128::zero(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    // bob provides 100 to the vault, the first deposit is minted 1:1 less the
+    // minimum shares held by no one
+    let msg = Cw20ExecuteMsg::Send {
+        contract: vault.to_string(),
+        amount: to_decimals(100),
+        msg: to_binary(&VaultHookMsg::Deposit {}).unwrap(),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // alice goes long 50 notional, paying 0.5 toll of which the vault is paid half
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res: SharesResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &vault,
+            &VaultQueryMsg::Shares {
+                provider: env.bob.to_string(),
+            },
+        )
+        .unwrap();
+    let shares = to_decimals(100) - Uint128::from(1_000u128);
+    assert_eq!(res.shares, shares);
+    assert_eq!(res.value, Uint128::from(100_249_998_997u128));
+    assert_eq!(
+        imbalance(&env),
+        OpenInterestImbalanceResponse {
+            long: to_decimals(50),
+            short: Uint128::zero(),
+        }
+    );
+
+    // anyone may rebalance, the vault shorts the difference at 2x for 25
+    // margin and 0.5 fees, half of which come back to it
+    env.router
+        .execute_contract(
+            env.alice.clone(),
+            vault.clone(),
+            &VaultExecuteMsg::Rebalance {},
+            &[],
+        )
+        .unwrap();
+    let state = vault_state(&env, &vault);
+    assert_eq!(state.idle, to_decimals(75));
+    assert_eq!(state.position_value, Uint128::from(24_999_999_999u128));
+    assert_eq!(imbalance(&env).short, to_decimals(50));
+
+    // once the market is balanced there is nothing left to do
+    env.router
+        .execute_contract(
+            env.alice.clone(),
+            vault.clone(),
+            &VaultExecuteMsg::Rebalance {},
+            &[],
+        )
+        .unwrap_err();
+
+    // bob cannot withdraw more than the vault holds idle
+    env.router
+        .execute_contract(
+            env.bob.clone(),
+            vault.clone(),
+            &VaultExecuteMsg::Withdraw { shares },
+            &[],
+        )
+        .unwrap_err();
+
+    // alice closes, leaving the vault on the heavier side, so it closes too
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    env.router
+        .execute_contract(
+            env.alice.clone(),
+            vault.clone(),
+            &VaultExecuteMsg::Rebalance {},
+            &[],
+        )
+        .unwrap();
+    let state = vault_state(&env, &vault);
+    assert_eq!(state.position_value, Uint128::zero());
+    assert_eq!(
+        imbalance(&env),
+        OpenInterestImbalanceResponse {
+            long: Uint128::zero(),
+            short: Uint128::zero(),
+        }
+    );
+
+    // bob withdraws everything the vault holds but the minimum shares' value
+    let balance = usdc.balance(&env.router, env.bob.clone()).unwrap();
+    env.router
+        .execute_contract(
+            env.bob.clone(),
+            vault.clone(),
+            &VaultExecuteMsg::Withdraw { shares },
+            &[],
+        )
+        .unwrap();
+    let left = vault_state(&env, &vault).total_value;
+    assert_eq!(
+        left,
+        state.idle - state.idle.multiply_ratio(shares, to_decimals(100))
+    );
+    assert_eq!(
+        usdc.balance(&env.router, env.bob.clone()).unwrap(),
+        balance + state.idle - left
+    );
+}
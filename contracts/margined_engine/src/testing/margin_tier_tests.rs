@@ -0,0 +1,111 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, MarginRequirementResponse, MarginTier, MarginTiersResponse, QueryMsg, Side,
+};
+
+// 10% initial margin below 500 notional, 20% from 500
+fn tiers() -> Vec<MarginTier> {
+    vec![
+        MarginTier {
+            notional: Uint128::zero(),
+            initial_margin_ratio: Uint128::from(100_000_000u128),
+            maintenance_margin_ratio: Uint128::from(50_000_000u128),
+        },
+        MarginTier {
+            notional: to_decimals(500),
+            initial_margin_ratio: Uint128::from(200_000_000u128),
+            maintenance_margin_ratio: Uint128::from(100_000_000u128),
+        },
+    ]
+}
+
+fn set_tiers(env: &mut setup::TestingEnv, tiers: Vec<MarginTier>) -> bool {
+    let msg = ExecuteMsg::UpdateMarginTiers {
+        vamm: env.vamm.addr.to_string(),
+        tiers,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .is_ok()
+}
+
+#[test]
+fn test_margin_tiers_query() {
+    let mut env = setup::setup();
+    assert!(set_tiers(&mut env, tiers()));
+
+    let res: MarginTiersResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarginTiers {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.tiers, tiers());
+
+    let res: MarginRequirementResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarginRequirement {
+                vamm: env.vamm.addr.to_string(),
+                notional: to_decimals(750),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        res,
+        MarginRequirementResponse {
+            initial_margin_ratio: Uint128::from(200_000_000u128),
+            maintenance_margin_ratio: Uint128::from(100_000_000u128),
+        }
+    );
+}
+
+#[test]
+fn test_margin_tiers_limit_leverage_by_size() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    assert!(set_tiers(&mut env, tiers()));
+
+    // 600 notional at 10x requires only 10% margin but falls in the 20% tier
+    assert!(env.open_position(&alice, Side::BUY, 60, 10).is_err());
+
+    // 300 notional at 10x is within the first tier
+    assert!(env.open_position(&alice, Side::BUY, 30, 10).is_ok());
+
+    // increasing to 500 notional moves the position into the 20% tier
+    assert!(env.open_position(&alice, Side::BUY, 20, 10).is_err());
+    assert!(env.open_position(&alice, Side::BUY, 40, 5).is_ok());
+}
+
+#[test]
+fn test_margin_tiers_validation() {
+    let mut env = setup::setup();
+
+    // only the owner can set tiers
+    let msg = ExecuteMsg::UpdateMarginTiers {
+        vamm: env.vamm.addr.to_string(),
+        tiers: tiers(),
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    // tiers must be ordered by notional
+    let mut unordered = tiers();
+    unordered.reverse();
+    assert!(!set_tiers(&mut env, unordered));
+
+    // larger positions cannot require less margin
+    let mut decreasing = tiers();
+    decreasing[1].initial_margin_ratio = Uint128::from(50_000_000u128);
+    assert!(!set_tiers(&mut env, decreasing));
+}
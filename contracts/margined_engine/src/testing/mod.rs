@@ -1,3 +1,45 @@
+mod collateral_tests;
+mod commit_reveal_tests;
+mod contract_trader_tests;
+mod crank_tests;
+mod cross_margin_tests;
+mod deposit_tests;
+mod event_tests;
+mod fee_campaign_tests;
+mod fee_pool_tests;
+mod fee_tier_tests;
+mod funding_tests;
+mod history_tests;
+mod holding_cap_tests;
+mod insurance_tests;
 mod integration_tests;
+mod leverage_tests;
+mod liquidation_tests;
+mod liquidity_tests;
+mod listing_tests;
+mod maker_vault_tests;
+mod margin_tier_tests;
+mod open_interest_tests;
+mod oracle_spread_tests;
+mod partial_close_tests;
+mod pause_tests;
+mod position_token_tests;
+mod position_transfer_tests;
+mod positions_tests;
+mod queued_order_tests;
+mod reduce_only_tests;
+mod referral_tests;
+mod risk_params_tests;
+mod settlement_tests;
 mod setup;
+mod signed_order_tests;
+mod simulation_tests;
+mod slippage_tests;
+mod snapshot_tests;
+mod stats_tests;
+mod sweep_tests;
 mod tests;
+mod throttle_tests;
+mod trading_permission_tests;
+mod trigger_order_tests;
+mod vamm_registry_tests;
@@ -0,0 +1,151 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw20::Cw20ExecuteMsg;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, MarketCapsResponse, OpenInterestCapResponse, OpenInterestResponse, QueryMsg, Side,
+};
+
+fn open_interest(env: &setup::TestingEnv) -> Uint128 {
+    let res: OpenInterestResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::OpenInterest {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    res.open_interest
+}
+
+#[test]
+fn test_open_interest_tracks_positions() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    assert!(env.open_position(&alice, Side::BUY, 20, 5).is_ok());
+    assert_eq!(open_interest(&env), to_decimals(100));
+
+    assert!(env.open_position(&alice, Side::BUY, 10, 5).is_ok());
+    assert_eq!(open_interest(&env), to_decimals(150));
+}
+
+#[test]
+fn test_open_interest_cap() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    let bob = env.bob.clone();
+
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(2000),
+        expires: None,
+    };
+    env.router
+        .execute_contract(bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // 60% of the market, but always allow up to 100 notional
+    let msg = ExecuteMsg::UpdateOpenInterestCap {
+        share: Uint128::from(600_000_000u128),
+        floor: to_decimals(100),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res: Option<OpenInterestCapResponse> = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::OpenInterestCap {})
+        .unwrap();
+    assert_eq!(
+        res,
+        Some(OpenInterestCapResponse {
+            share: Uint128::from(600_000_000u128),
+            floor: to_decimals(100),
+        })
+    );
+
+    // the first position is only allowed up to the floor
+    assert!(env.open_position(&alice, Side::BUY, 30, 5).is_err());
+    assert!(env.open_position(&alice, Side::BUY, 20, 5).is_ok());
+    assert!(env.open_position(&bob, Side::BUY, 20, 5).is_ok());
+
+    // 200 of 300 notional is above 60%
+    assert!(env.open_position(&alice, Side::BUY, 20, 5).is_err());
+
+    // 150 of 250 notional is exactly 60%
+    assert!(env.open_position(&alice, Side::BUY, 10, 5).is_ok());
+    assert_eq!(open_interest(&env), to_decimals(250));
+}
+
+#[test]
+fn test_open_interest_cap_unauthorized() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::UpdateOpenInterestCap {
+        share: Uint128::from(600_000_000u128),
+        floor: to_decimals(100),
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_market_caps() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    let msg = ExecuteMsg::UpdateMarketCaps {
+        vamm: env.vamm.addr.to_string(),
+        open_interest_notional_cap: Some(to_decimals(150)),
+        max_position_size: Some(to_decimals(10)),
+        max_position_notional: None,
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let res: MarketCapsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarketCaps {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.open_interest_notional_cap, Some(to_decimals(150)));
+    assert_eq!(res.max_position_size, Some(to_decimals(10)));
+
+    // 100 notional buys around 9.09 of the base asset
+    assert!(env.open_position(&alice, Side::BUY, 20, 5).is_ok());
+
+    // another 25 notional would take the position past 10 of the base asset
+    assert!(env.open_position(&alice, Side::BUY, 5, 5).is_err());
+    assert_eq!(open_interest(&env), to_decimals(100));
+
+    // lifting the size cap leaves the open interest cap of 150 in place
+    let msg = ExecuteMsg::UpdateMarketCaps {
+        vamm: env.vamm.addr.to_string(),
+        open_interest_notional_cap: Some(to_decimals(150)),
+        max_position_size: None,
+        max_position_notional: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    assert!(env.open_position(&alice, Side::BUY, 10, 5).is_ok());
+    assert!(env.open_position(&alice, Side::BUY, 1, 5).is_err());
+    assert_eq!(open_interest(&env), to_decimals(150));
+}
@@ -0,0 +1,81 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{ConfigResponse, ExecuteMsg, QueryMsg, Side};
+
+#[test]
+fn test_max_oracle_spread() {
+    let mut env = setup::setup();
+    let vamm = env.vamm.addr.clone();
+    let alice = env.alice.clone();
+    setup::setup_price_feed(&mut env);
+
+    // only the owner may set the spread, to at most 100%
+    let msg = ExecuteMsg::SetMaxOracleSpread {
+        max_oracle_spread: Some(Uint128::from(50_000_000u128)),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    let err = env
+        .router
+        .execute_contract(
+            env.owner.clone(),
+            env.engine.addr.clone(),
+            &ExecuteMsg::SetMaxOracleSpread {
+                max_oracle_spread: Some(to_decimals(2)),
+            },
+            &[],
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: max oracle spread must be above zero and cannot exceed 100%"
+    );
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let config: ConfigResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::Config {})
+        .unwrap();
+    assert_eq!(
+        config.max_oracle_spread,
+        Some(Uint128::from(50_000_000u128))
+    );
+
+    // the market opens at the index price of 10, the trade pushes the spot
+    // price to ~11.03
+    env.open_position(&alice, Side::BUY, 10, 5).unwrap();
+    assert_eq!(
+        env.open_position(&alice, Side::BUY, 10, 5).unwrap_err(),
+        "Spot price diverges from the index price by more than 50000000"
+    );
+    assert_eq!(
+        env.open_position(&alice, Side::SELL, 10, 1).unwrap_err(),
+        "Spot price diverges from the index price by more than 50000000"
+    );
+
+    // the position can still be wound down
+    env.open_position_with(
+        &vamm,
+        &alice,
+        Side::SELL,
+        to_decimals(10),
+        to_decimals(1),
+        None,
+        true,
+    )
+    .unwrap();
+
+    // and lifting the check reopens the market
+    let msg = ExecuteMsg::SetMaxOracleSpread {
+        max_oracle_spread: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    env.open_position(&alice, Side::BUY, 10, 5).unwrap();
+}
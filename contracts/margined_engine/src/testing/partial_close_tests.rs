@@ -0,0 +1,62 @@
+use crate::testing::setup;
+use cosmwasm_std::Uint128;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{ExecuteMsg, Side};
+use margined_perp::margined_vamm::ExecuteMsg as VammExecuteMsg;
+
+fn close_position(env: &mut setup::TestingEnv, partial: bool) -> Result<(), String> {
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[test]
+fn test_partial_close_within_fluctuation_limit() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    // a long of 300 takes the price from 10 to around 16.9
+    env.open_position(&alice, Side::BUY, 60, 5).unwrap();
+    let size = env.position(&alice).size;
+    env.advance_time(15);
+
+    // the price may then only move 10% a block
+    let msg = VammExecuteMsg::UpdateConfig {
+        toll_ratio: None,
+        spread_ratio: None,
+        price_feed: None,
+        fluctuation_limit_ratio: Some(Uint128::from(100_000_000u128)),
+        engine: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.vamm.addr.clone(), &msg, &[])
+        .unwrap();
+
+    assert!(close_position(&mut env, false)
+        .unwrap_err()
+        .ends_with("Price is over the fluctuation limit"));
+
+    // a partial close takes what fits this block and leaves the rest open
+    close_position(&mut env, true).unwrap();
+    let remaining = env.position(&alice).size;
+    assert!(!remaining.is_zero() && remaining < size);
+
+    // and nothing more can be closed until the next block
+    assert_eq!(
+        close_position(&mut env, true).unwrap_err(),
+        "Invalid close size"
+    );
+
+    env.advance_time(15);
+    close_position(&mut env, true).unwrap();
+    assert!(env.position(&alice).size < remaining);
+}
@@ -0,0 +1,147 @@
+use crate::testing::setup::{self, to_decimals};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, PausableOperation, PauseLevel, PausedOperationsResponse, QueryMsg, Side,
+};
+
+fn paused_operations(env: &setup::TestingEnv) -> Vec<PausableOperation> {
+    let res: PausedOperationsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::PausedOperations {})
+        .unwrap();
+    res.paused
+}
+
+#[test]
+fn test_pause_only_selected_operations() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    assert_eq!(paused_operations(&env), vec![]);
+
+    let msg = ExecuteMsg::UpdatePausedOperations {
+        paused: vec![PausableOperation::Liquidate, PausableOperation::Close],
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // returned in bitmap order
+    assert_eq!(
+        paused_operations(&env),
+        vec![PausableOperation::Close, PausableOperation::Liquidate]
+    );
+
+    // opens are still allowed but closes are not
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_ok());
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.unwrap_err().to_string().contains("paused"));
+}
+
+#[test]
+fn test_pause_and_resume_opens() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    let msg = ExecuteMsg::UpdatePausedOperations {
+        paused: vec![PausableOperation::Open],
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_err());
+
+    let msg = ExecuteMsg::UpdatePausedOperations { paused: vec![] };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_ok());
+}
+
+#[test]
+fn test_set_pause_leaves_closes_running() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_ok());
+
+    let msg = ExecuteMsg::UpdateConfig {
+        eligible_collateral: None,
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: None,
+        fee_pool: None,
+        open_liquidation: None,
+        pause_level: Some(PauseLevel::CloseOnly),
+        staking_contract: None,
+        liquidation_price_source: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::SetPause { paused: true };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(
+        paused_operations(&env),
+        vec![
+            PausableOperation::Open,
+            PausableOperation::AddMargin,
+            PausableOperation::RemoveMargin,
+            PausableOperation::Liquidate,
+            PausableOperation::Funding,
+        ]
+    );
+
+    // nothing but closes gets through
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_err());
+    let msg = ExecuteMsg::WithdrawMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(1),
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "RemoveMargin operations are paused");
+
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::SetPause { paused: false };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(paused_operations(&env), vec![]);
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_ok());
+}
@@ -0,0 +1,280 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{
+    to_binary, Addr, Binary, Deps, DepsMut, Empty, Env, MessageInfo, Response, StdError, StdResult,
+    WasmMsg,
+};
+use cw_multi_test::{Contract, ContractWrapper, Executor};
+use cw_storage_plus::{Item, Map};
+use margined_perp::cw721::{Cw721ExecuteMsg, Cw721ReceiveMsg};
+use margined_perp::margined_engine::{
+    Cw721HookMsg, ExecuteMsg, PositionResponse, PositionTokenResponse, QueryMsg, Side,
+    TriggerOrderType, TriggerOrdersResponse,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// a bare cw721 contract, enough to mint, move and burn position tokens
+
+const MINTER: Item<Addr> = Item::new("minter");
+const OWNERS: Map<&str, Addr> = Map::new("owners");
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+struct NftInstantiateMsg {
+    minter: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum NftQueryMsg {
+    OwnerOf { token_id: String },
+}
+
+fn nft_instantiate(
+    deps: DepsMut,
+    _env: Env,
+    _info: MessageInfo,
+    msg: NftInstantiateMsg,
+) -> StdResult<Response> {
+    MINTER.save(deps.storage, &Addr::unchecked(msg.minter))?;
+    Ok(Response::new())
+}
+
+fn nft_execute(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: Cw721ExecuteMsg,
+) -> StdResult<Response> {
+    if let Cw721ExecuteMsg::Mint(mint) = msg {
+        if info.sender != MINTER.load(deps.storage)? {
+            return Err(StdError::generic_err("unauthorized"));
+        }
+        OWNERS.save(deps.storage, &mint.token_id, &Addr::unchecked(mint.owner))?;
+        return Ok(Response::new());
+    }
+
+    let token_id = match &msg {
+        Cw721ExecuteMsg::TransferNft { token_id, .. }
+        | Cw721ExecuteMsg::SendNft { token_id, .. }
+        | Cw721ExecuteMsg::Burn { token_id } => token_id.clone(),
+        Cw721ExecuteMsg::Mint(_) => unreachable!(),
+    };
+    if info.sender != OWNERS.load(deps.storage, &token_id)? {
+        return Err(StdError::generic_err("unauthorized"));
+    }
+
+    match msg {
+        Cw721ExecuteMsg::TransferNft { recipient, .. } => {
+            OWNERS.save(deps.storage, &token_id, &Addr::unchecked(recipient))?;
+            Ok(Response::new())
+        }
+        Cw721ExecuteMsg::SendNft { contract, msg, .. } => {
+            OWNERS.save(deps.storage, &token_id, &Addr::unchecked(contract.clone()))?;
+            let receive_msg = WasmMsg::Execute {
+                contract_addr: contract,
+                funds: vec![],
+                msg: to_binary(&ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+                    sender: info.sender.to_string(),
+                    token_id,
+                    msg,
+                }))?,
+            };
+            Ok(Response::new().add_message(receive_msg))
+        }
+        _ => {
+            OWNERS.remove(deps.storage, &token_id);
+            Ok(Response::new())
+        }
+    }
+}
+
+fn nft_query(deps: Deps, _env: Env, msg: NftQueryMsg) -> StdResult<Binary> {
+    match msg {
+        NftQueryMsg::OwnerOf { token_id } => to_binary(&OWNERS.load(deps.storage, &token_id)?),
+    }
+}
+
+fn contract_nft() -> Box<dyn Contract<Empty>> {
+    Box::new(ContractWrapper::new_with_empty(
+        nft_execute,
+        nft_instantiate,
+        nft_query,
+    ))
+}
+
+// deploys the nft contract with the engine as minter and enables wrapping
+fn enable_position_tokens(env: &mut setup::TestingEnv) -> Addr {
+    let nft_id = env.router.store_code(contract_nft());
+    let nft = env
+        .router
+        .instantiate_contract(
+            nft_id,
+            env.owner.clone(),
+            &NftInstantiateMsg {
+                minter: env.engine.addr.to_string(),
+            },
+            &[],
+            "positions",
+            None,
+        )
+        .unwrap();
+
+    let msg = ExecuteMsg::UpdatePositionNft {
+        contract: nft.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    nft
+}
+
+fn token_owner(env: &setup::TestingEnv, nft: &Addr) -> StdResult<Addr> {
+    env.router.wrap().query_wasm_smart(
+        nft,
+        &NftQueryMsg::OwnerOf {
+            token_id: "1".to_string(),
+        },
+    )
+}
+
+#[test]
+fn test_wrapped_position_moves_with_its_token() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    let nft = enable_position_tokens(&mut env);
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_ok());
+    let msg = ExecuteMsg::SetTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        trigger_price: to_decimals(9),
+        order_type: TriggerOrderType::StopLoss,
+        size: None,
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::WrapPosition {
+        vamm: env.vamm.addr.to_string(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(token_owner(&env, &nft).unwrap(), env.alice);
+
+    let res: PositionTokenResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::PositionToken {
+                token_id: "1".to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.trader, env.alice);
+
+    // whilst wrapped the position cannot be traded
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_err());
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert!(err.to_string().contains("wrapped"));
+
+    // alice sells the token to bob, who unwraps it
+    let msg = Cw721ExecuteMsg::TransferNft {
+        recipient: env.bob.to_string(),
+        token_id: "1".to_string(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), nft.clone(), &msg, &[])
+        .unwrap();
+    let msg = Cw721ExecuteMsg::SendNft {
+        contract: env.engine.addr.to_string(),
+        token_id: "1".to_string(),
+        msg: to_binary(&Cw721HookMsg::UnwrapPosition {}).unwrap(),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), nft.clone(), &msg, &[])
+        .unwrap();
+
+    // the token is burnt and the position is bob's
+    assert!(token_owner(&env, &nft).is_err());
+    let res: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.bob.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.margin, to_decimals(10));
+    assert_eq!(res.notional, to_decimals(20));
+
+    // alice's stop loss is not left to close whatever she opens next
+    let orders: TriggerOrdersResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::TriggerOrders {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(orders.stop_loss, None);
+
+    // alice is free to open a new position
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_ok());
+}
+
+#[test]
+fn test_only_the_position_nft_can_unwrap() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    // wrapping needs a position nft to be set
+    assert!(env.open_position(&alice, Side::BUY, 10, 2).is_ok());
+    let msg = ExecuteMsg::WrapPosition {
+        vamm: env.vamm.addr.to_string(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    enable_position_tokens(&mut env);
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // a forged receive from another address is rejected
+    let msg = ExecuteMsg::ReceiveNft(Cw721ReceiveMsg {
+        sender: env.bob.to_string(),
+        token_id: "1".to_string(),
+        msg: to_binary(&Cw721HookMsg::UnwrapPosition {}).unwrap(),
+    });
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    // the nft cannot be swapped out whilst positions are wrapped
+    let msg = ExecuteMsg::UpdatePositionNft {
+        contract: "other".to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+}
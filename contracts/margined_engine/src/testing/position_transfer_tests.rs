@@ -0,0 +1,181 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{Addr, Uint128};
+use cw20::Cw20ExecuteMsg;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, MarginTier, PositionResponse, PositionTransferResponse, QueryMsg, Side,
+    TriggerOrderType, TriggerOrdersResponse,
+};
+
+fn offer_to_bob(env: &mut setup::TestingEnv) {
+    let msg = ExecuteMsg::TransferPosition {
+        vamm: env.vamm.addr.to_string(),
+        to: env.bob.to_string(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn accept_from_alice(env: &mut setup::TestingEnv, sender: &Addr) -> bool {
+    let msg = ExecuteMsg::AcceptPosition {
+        vamm: env.vamm.addr.to_string(),
+        from: env.alice.to_string(),
+    };
+    env.router
+        .execute_contract(sender.clone(), env.engine.addr.clone(), &msg, &[])
+        .is_ok()
+}
+
+fn set_initial_margin_ratio(env: &mut setup::TestingEnv, initial_margin_ratio: u128) {
+    let msg = ExecuteMsg::UpdateMarginTiers {
+        vamm: env.vamm.addr.to_string(),
+        tiers: vec![MarginTier {
+            notional: Uint128::zero(),
+            initial_margin_ratio: Uint128::from(initial_margin_ratio),
+            maintenance_margin_ratio: Uint128::from(initial_margin_ratio / 2),
+        }],
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn position_transfer(env: &setup::TestingEnv) -> Option<PositionTransferResponse> {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::PositionTransfer {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_transfer_position_once_accepted() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+    let msg = ExecuteMsg::SetTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        trigger_price: to_decimals(9),
+        order_type: TriggerOrderType::StopLoss,
+        size: None,
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    offer_to_bob(&mut env);
+
+    assert_eq!(
+        position_transfer(&env),
+        Some(PositionTransferResponse {
+            vamm: env.vamm.addr.clone(),
+            from: env.alice.clone(),
+            to: env.bob.clone(),
+        })
+    );
+
+    // only the recipient can accept
+    let owner = env.owner.clone();
+    assert!(!accept_from_alice(&mut env, &owner));
+
+    // the position is at 50% margin, short of a 60% initial margin ratio
+    set_initial_margin_ratio(&mut env, 600_000_000);
+    let bob = env.bob.clone();
+    assert!(!accept_from_alice(&mut env, &bob));
+
+    set_initial_margin_ratio(&mut env, 400_000_000);
+    assert!(accept_from_alice(&mut env, &bob));
+    assert_eq!(position_transfer(&env), None);
+
+    let res: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.bob.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.margin, to_decimals(10));
+    assert_eq!(res.notional, to_decimals(20));
+
+    // the offer is spent
+    assert!(!accept_from_alice(&mut env, &bob));
+
+    // and alice's stop loss went with her position, it cannot close a new one
+    let orders: TriggerOrdersResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::TriggerOrders {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(orders.stop_loss, None);
+}
+
+#[test]
+fn test_position_is_accepted_at_its_margin_after_losses() {
+    let mut env = setup::setup();
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+
+    // alice's long is opened at 50% margin, above a 45% initial margin ratio
+    set_initial_margin_ratio(&mut env, 450_000_000);
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+    offer_to_bob(&mut env);
+
+    // carol's short leaves it around 39% once its losses are counted
+    let carol = Addr::unchecked("carol");
+    let msg = Cw20ExecuteMsg::Transfer {
+        recipient: carol.to_string(),
+        amount: to_decimals(100),
+    };
+    env.router
+        .execute_contract(bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+    env.open_position(&carol, Side::SELL, 50, 2).unwrap();
+    assert!(!accept_from_alice(&mut env, &bob));
+
+    // which is enough once the initial margin ratio is lowered
+    set_initial_margin_ratio(&mut env, 350_000_000);
+    assert!(accept_from_alice(&mut env, &bob));
+}
+
+#[test]
+fn test_recipient_with_a_position_cannot_accept() {
+    let mut env = setup::setup();
+
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(100),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+    env.open_position(&bob, Side::BUY, 10, 2).unwrap();
+    offer_to_bob(&mut env);
+    assert!(!accept_from_alice(&mut env, &bob));
+
+    // alice can withdraw the offer
+    let msg = ExecuteMsg::CancelPositionTransfer {
+        vamm: env.vamm.addr.to_string(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(position_transfer(&env), None);
+}
@@ -0,0 +1,92 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{Addr, Uint128};
+use cw20::Cw20ExecuteMsg;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, PositionsResponse, QueryMsg, Side, TraderPositionsResponse,
+};
+
+fn positions(env: &setup::TestingEnv, start_after: Option<&Addr>) -> PositionsResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Positions {
+                vamm: env.vamm.addr.to_string(),
+                start_after: start_after.map(|addr| addr.to_string()),
+                limit: Some(1),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_positions_are_paged_by_trader() {
+    let mut env = setup::setup();
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(10),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+    env.open_position(&bob, Side::SELL, 10, 2).unwrap();
+
+    let first = positions(&env, None);
+    assert_eq!(first.positions.len(), 1);
+    assert_eq!(first.positions[0].trader, alice);
+    assert_eq!(first.positions[0].side, Side::BUY);
+    assert_eq!(first.positions[0].margin, to_decimals(10));
+    assert_eq!(first.next_key, Some(alice.clone()));
+
+    let second = positions(&env, first.next_key.as_ref());
+    assert_eq!(second.positions[0].trader, bob);
+    assert_eq!(second.positions[0].side, Side::SELL);
+
+    // closed positions are left out
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(alice, env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let first = positions(&env, None);
+    assert_eq!(first.positions[0].trader, bob);
+}
+
+#[test]
+fn test_trader_positions_sum_the_margin_held() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+
+    let portfolio = |env: &setup::TestingEnv, trader: &Addr| -> TraderPositionsResponse {
+        env.router
+            .wrap()
+            .query_wasm_smart(
+                &env.engine.addr,
+                &QueryMsg::TraderPositions {
+                    trader: trader.to_string(),
+                },
+            )
+            .unwrap()
+    };
+
+    let response = portfolio(&env, &alice);
+    assert_eq!(response.positions.len(), 1);
+    assert_eq!(response.positions[0].vamm, env.vamm.addr);
+    assert_eq!(response.total_margin, to_decimals(10));
+
+    let response = portfolio(&env, &env.bob);
+    assert!(response.positions.is_empty());
+    assert_eq!(response.total_margin, Uint128::zero());
+}
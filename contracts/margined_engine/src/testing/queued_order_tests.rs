@@ -0,0 +1,221 @@
+use crate::testing::setup::{self, to_decimals};
+use cw_multi_test::Executor;
+use margined_perp::asset::AssetInfo;
+use margined_perp::margined_engine::{
+    ExecuteMsg, PositionResponse, QueryMsg, QueuedOrderResponse, QueuedTrade, Side,
+};
+
+fn queue_order(env: &mut setup::TestingEnv) {
+    let msg = ExecuteMsg::QueueOrder {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn next_block(env: &mut setup::TestingEnv) {
+    env.router.update_block(|block| {
+        block.height += 1;
+        block.time = block.time.plus_seconds(5);
+    });
+}
+
+// returns whether bob, acting as keeper, filled alice's order
+fn execute_queued_order(env: &mut setup::TestingEnv) -> bool {
+    let msg = ExecuteMsg::ExecuteQueuedOrder {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .is_ok()
+}
+
+#[test]
+fn test_queued_order_fills_in_a_later_block() {
+    let mut env = setup::setup();
+    queue_order(&mut env);
+
+    let order: QueuedOrderResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::QueuedOrder {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(order.queued_at, env.router.block_info().height);
+    assert_eq!(
+        order.trade,
+        QueuedTrade::Open {
+            side: Side::BUY,
+            quote_asset_amount: to_decimals(10),
+            leverage: to_decimals(2),
+            base_asset_limit: None,
+            reduce_only: false,
+        }
+    );
+
+    // not in the block it was queued
+    assert!(!execute_queued_order(&mut env));
+
+    next_block(&mut env);
+    assert!(execute_queued_order(&mut env));
+
+    let position: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(position.notional, to_decimals(20));
+    assert_eq!(position.margin, to_decimals(10));
+
+    // each order is only filled once
+    assert!(!execute_queued_order(&mut env));
+}
+
+#[test]
+fn test_cancel_queued_order() {
+    let mut env = setup::setup();
+    queue_order(&mut env);
+
+    let msg = ExecuteMsg::CancelQueuedOrder {
+        vamm: env.vamm.addr.to_string(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    next_block(&mut env);
+    assert!(!execute_queued_order(&mut env));
+}
+
+#[test]
+fn test_closing_a_position_cancels_its_queued_order() {
+    let mut env = setup::setup();
+
+    let open = |side: Side, quote_asset_amount: u64, leverage: u64| ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side,
+        quote_asset_amount: to_decimals(quote_asset_amount),
+        leverage: to_decimals(leverage),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    let (long, short) = (open(Side::BUY, 60, 10), open(Side::SELL, 300, 2));
+
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &long, &[])
+        .unwrap();
+    queue_order(&mut env);
+
+    // an equal and opposite trade fully closes the position
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &short, &[])
+        .unwrap();
+
+    next_block(&mut env);
+    assert!(!execute_queued_order(&mut env));
+}
+
+#[test]
+fn test_queued_close_fills_in_a_later_block() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    // only an open position can have its close queued
+    let msg = ExecuteMsg::QueueClose {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        deadline: None,
+    };
+    assert!(env
+        .router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .is_err());
+
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    assert!(!execute_queued_order(&mut env));
+    next_block(&mut env);
+    assert!(execute_queued_order(&mut env));
+    assert!(env.position(&alice).size.is_zero());
+}
+
+#[test]
+fn test_queued_order_is_not_filled_past_its_deadline() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::QueueOrder {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: Some(env.router.block_info().time.plus_seconds(5)),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    next_block(&mut env);
+    next_block(&mut env);
+    assert!(!execute_queued_order(&mut env));
+}
+
+#[test]
+fn test_native_collateral_markets_cannot_queue_opens() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::UpdateMarketCollateral {
+        vamm: env.vamm.addr.to_string(),
+        collateral: AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        },
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the keeper filling it would have to send the trader's margin
+    let msg = ExecuteMsg::QueueOrder {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: orders cannot be queued in a native collateral market"
+    );
+}
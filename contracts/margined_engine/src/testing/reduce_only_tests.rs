@@ -0,0 +1,82 @@
+use crate::testing::setup::{self, to_decimals};
+use margined_perp::margined_engine::Side;
+
+#[test]
+fn test_reduce_only_requires_an_open_position() {
+    let mut env = setup::setup();
+    let vamm = env.vamm.addr.clone();
+    let alice = env.alice.clone();
+
+    let err = env
+        .open_position_with(
+            &vamm,
+            &alice,
+            Side::BUY,
+            to_decimals(20),
+            to_decimals(5),
+            None,
+            true,
+        )
+        .unwrap_err();
+    assert_eq!(err, "Reduce only order would increase or flip the position");
+}
+
+#[test]
+fn test_reduce_only_cannot_increase_or_flip() {
+    let mut env = setup::setup();
+    let vamm = env.vamm.addr.clone();
+    let alice = env.alice.clone();
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+
+    // adding to the long is an increase
+    let err = env
+        .open_position_with(
+            &vamm,
+            &alice,
+            Side::BUY,
+            to_decimals(10),
+            to_decimals(5),
+            None,
+            true,
+        )
+        .unwrap_err();
+    assert_eq!(err, "Reduce only order would increase or flip the position");
+
+    // selling more than the position is worth would leave it short
+    let err = env
+        .open_position_with(
+            &vamm,
+            &alice,
+            Side::SELL,
+            to_decimals(40),
+            to_decimals(5),
+            None,
+            true,
+        )
+        .unwrap_err();
+    assert_eq!(err, "Reduce only order would increase or flip the position");
+}
+
+#[test]
+fn test_reduce_only_decreases_position() {
+    let mut env = setup::setup();
+    let vamm = env.vamm.addr.clone();
+    let alice = env.alice.clone();
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+    let before = env.position(&alice);
+
+    env.open_position_with(
+        &vamm,
+        &alice,
+        Side::SELL,
+        to_decimals(10),
+        to_decimals(5),
+        None,
+        true,
+    )
+    .unwrap();
+
+    let after = env.position(&alice);
+    assert!(!after.size.is_zero());
+    assert!(after.size < before.size);
+}
@@ -0,0 +1,123 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw20::Cw20Contract;
+use cw_multi_test::Executor;
+use margined_perp::asset::AssetInfo;
+use margined_perp::margined_engine::{
+    ExecuteMsg, FeePoolResponse, QueryMsg, ReferralReward, ReferralRewardsResponse, Side,
+};
+use margined_perp::margined_vamm::ExecuteMsg as VammExecuteMsg;
+
+fn referral_rewards(env: &setup::TestingEnv) -> ReferralRewardsResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::ReferralRewards {
+                referrer: env.bob.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_referrers_are_paid_a_share_of_the_toll() {
+    let mut env = setup::setup();
+    let msg = VammExecuteMsg::UpdateConfig {
+        toll_ratio: Some(Uint128::from(10_000_000u128)),
+        spread_ratio: None,
+        price_feed: None,
+        fluctuation_limit_ratio: None,
+        engine: None,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.vamm.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // half the toll goes to the referrer
+    let msg = ExecuteMsg::UpdateReferralShare {
+        referral_share: Uint128::from(500_000_000u128),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let register = ExecuteMsg::RegisterReferrer {
+        code: "bob".to_string(),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &register, &[])
+        .unwrap();
+
+    // codes are unique and traders cannot refer themselves
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &register, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Generic error: referral code is taken");
+    let set_code = ExecuteMsg::SetReferralCode {
+        code: "bob".to_string(),
+    };
+    let err = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &set_code, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: traders cannot refer themselves"
+    );
+
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &set_code, &[])
+        .unwrap();
+
+    // a 1% toll on 300 of notional
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let rewards = referral_rewards(&env);
+    assert_eq!(rewards.code, Some("bob".to_string()));
+    assert_eq!(
+        rewards.rewards,
+        vec![ReferralReward {
+            asset: AssetInfo::Token {
+                contract_addr: env.usdc.addr.to_string(),
+            },
+            amount: Uint128::from(1_500_000_000u128),
+        }]
+    );
+    let pool: FeePoolResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &QueryMsg::FeePool {})
+        .unwrap();
+    assert_eq!(pool.balance, Uint128::from(1_500_000_000u128));
+
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+    let balance = usdc.balance(&env.router, env.bob.clone()).unwrap();
+    let msg = ExecuteMsg::ClaimReferralRewards {};
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(
+        usdc.balance(&env.router, env.bob.clone()).unwrap(),
+        balance + Uint128::from(1_500_000_000u128)
+    );
+    assert!(referral_rewards(&env).rewards.is_empty());
+
+    let res = env
+        .router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
@@ -0,0 +1,163 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw20::{Cw20Contract, Cw20ExecuteMsg};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{ExecuteMsg, QueryMsg, RiskParams, RiskParamsResponse, Side};
+
+fn risk_params(env: &setup::TestingEnv) -> RiskParamsResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::RiskParams {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+fn update_risk_params(env: &mut setup::TestingEnv, risk_params: RiskParams) -> Result<(), String> {
+    let msg = ExecuteMsg::UpdateRiskParams {
+        vamm: env.vamm.addr.to_string(),
+        risk_params,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[test]
+fn test_update_risk_params() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    // without overrides the config applies
+    assert_eq!(
+        risk_params(&env),
+        RiskParamsResponse {
+            initial_margin_ratio: Uint128::from(100u128),
+            maintenance_margin_ratio: Uint128::from(100u128),
+            liquidation_fee: Uint128::from(100u128),
+            max_leverage: None,
+        }
+    );
+
+    // only the owner may override them
+    let msg = ExecuteMsg::UpdateRiskParams {
+        vamm: env.vamm.addr.to_string(),
+        risk_params: RiskParams::default(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    // overrides are held to the same bounds as the config
+    assert_eq!(
+        update_risk_params(
+            &mut env,
+            RiskParams {
+                maintenance_margin_ratio: Some(Uint128::from(200u128)),
+                ..RiskParams::default()
+            },
+        )
+        .unwrap_err(),
+        "Generic error: maintenance margin ratio cannot exceed the initial margin ratio"
+    );
+    update_risk_params(
+        &mut env,
+        RiskParams {
+            max_leverage: Some(Uint128::zero()),
+            ..RiskParams::default()
+        },
+    )
+    .unwrap_err();
+
+    // a 10% initial margin allows 10x, but the market is capped at 8x
+    update_risk_params(
+        &mut env,
+        RiskParams {
+            initial_margin_ratio: Some(Uint128::from(100_000_000u128)),
+            maintenance_margin_ratio: None,
+            liquidation_fee: None,
+            max_leverage: Some(to_decimals(8)),
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        risk_params(&env),
+        RiskParamsResponse {
+            initial_margin_ratio: Uint128::from(100_000_000u128),
+            maintenance_margin_ratio: Uint128::from(100u128),
+            liquidation_fee: Uint128::from(100u128),
+            max_leverage: Some(to_decimals(8)),
+        }
+    );
+    assert_eq!(
+        env.open_position(&alice, Side::BUY, 10, 11).unwrap_err(),
+        "Leverage exceeds the maximum of 10000000000 for this position size"
+    );
+    assert_eq!(
+        env.open_position(&alice, Side::BUY, 10, 9).unwrap_err(),
+        "Leverage exceeds the maximum of 8000000000 for this position size"
+    );
+    env.open_position(&alice, Side::BUY, 10, 8).unwrap();
+
+    // clearing the overrides restores the config
+    update_risk_params(&mut env, RiskParams::default()).unwrap();
+    assert_eq!(risk_params(&env).max_leverage, None);
+    env.open_position(&alice, Side::BUY, 10, 20).unwrap();
+}
+
+#[test]
+fn test_market_maintenance_margin_and_liquidation_fee() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    let bob = env.bob.clone();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    update_risk_params(
+        &mut env,
+        RiskParams {
+            initial_margin_ratio: Some(Uint128::from(100_000_000u128)),
+            maintenance_margin_ratio: Some(Uint128::from(100_000_000u128)),
+            liquidation_fee: Some(Uint128::from(10_000_000u128)),
+            max_leverage: None,
+        },
+    )
+    .unwrap();
+
+    let msg = ExecuteMsg::AddLiquidator {
+        liquidator: env.owner.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(20),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // alice's long of 100 notional is worth around 83.3 after bob's short,
+    // leaving around 3.3 of her 20 margin which is below the market's 10%
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+    env.open_position(&bob, Side::SELL, 20, 5).unwrap();
+
+    let msg = ExecuteMsg::Liquidate {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+        takeover: false,
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the liquidator is paid the market's 1% of the 83.3 closed
+    let fee = usdc.balance(&env.router, env.owner.clone()).unwrap();
+    assert!(fee > Uint128::from(833_000_000u128) && fee < Uint128::from(834_000_000u128));
+}
@@ -0,0 +1,112 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw20::Cw20Contract;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, OpenInterestResponse, QueryMsg, SettlementResponse, Side,
+};
+use margined_perp::margined_vamm::ExecuteMsg as VammExecuteMsg;
+
+fn shutdown(env: &mut setup::TestingEnv) {
+    let msg = VammExecuteMsg::Shutdown {};
+    env.router
+        .execute_contract(env.owner.clone(), env.vamm.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn settlement(env: &setup::TestingEnv) -> Option<SettlementResponse> {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Settlement {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_settle_position_after_shutdown() {
+    let mut env = setup::setup();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // positions cannot be settled while the market runs
+    let msg = ExecuteMsg::SettlePosition {
+        vamm: env.vamm.addr.to_string(),
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: market has not been shut down"
+    );
+
+    shutdown(&mut env);
+    assert_eq!(settlement(&env), None);
+
+    // margin stays in the market and it can no longer be traded
+    let withdraw = ExecuteMsg::WithdrawMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(1),
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &withdraw, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Generic error: market is not open");
+
+    // alice's long is worth more at the spot price than the margin held for
+    // the market, so she is paid all of it, less rounding, rather than her
+    // full value
+    let balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(
+        usdc.balance(&env.router, env.alice.clone()).unwrap(),
+        balance + Uint128::from(9_999_999_995u128)
+    );
+
+    let settlement = settlement(&env).unwrap();
+    assert_eq!(
+        settlement.settlement_price,
+        Uint128::from(10_403_999_999u128)
+    );
+    assert!(settlement.payout_ratio < to_decimals(1));
+
+    let res: OpenInterestResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::OpenInterest {
+                vamm: env.vamm.addr.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.open_interest, Uint128::zero());
+
+    // and cannot settle twice
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Trader has no open position");
+}
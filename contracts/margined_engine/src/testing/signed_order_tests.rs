@@ -0,0 +1,140 @@
+use crate::testing::setup::{self, to_decimals};
+use crate::utils::signed_order_bytes;
+use cosmwasm_std::{Binary, Uint128};
+use cw_multi_test::Executor;
+use k256::ecdsa::{signature::Signer, Signature, SigningKey};
+use margined_perp::margined_engine::{
+    ExecuteMsg, PositionResponse, QueryMsg, Side, SignedOrder, SigningKeyResponse,
+};
+
+fn signing_key(seed: u8) -> SigningKey {
+    SigningKey::from_bytes(&[seed; 32]).unwrap()
+}
+
+fn pubkey(key: &SigningKey) -> Binary {
+    Binary::from(key.verifying_key().to_bytes().to_vec())
+}
+
+fn order(env: &setup::TestingEnv, nonce: u64) -> SignedOrder {
+    SignedOrder {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        nonce,
+    }
+}
+
+fn sign(env: &setup::TestingEnv, key: &SigningKey, order: &SignedOrder) -> Binary {
+    let signature: Signature = key.sign(&signed_order_bytes(&env.engine.addr, order));
+    Binary::from(signature.as_ref())
+}
+
+// bob relays an order on alice's behalf
+fn relay(
+    env: &mut setup::TestingEnv,
+    order: SignedOrder,
+    pubkey: Binary,
+    signature: Binary,
+) -> Result<(), String> {
+    let msg = ExecuteMsg::OpenPositionFor {
+        order,
+        pubkey,
+        signature,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn set_signing_key(env: &mut setup::TestingEnv, pubkey: Option<Binary>) -> Result<(), String> {
+    let msg = ExecuteMsg::SetSigningKey { pubkey };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+#[test]
+fn test_open_position_for_signed_order() {
+    let mut env = setup::setup();
+    let key = signing_key(1);
+
+    assert_eq!(
+        set_signing_key(&mut env, Some(Binary::from(vec![2u8; 20]))).unwrap_err(),
+        "Generic error: signing key must be a secp256k1 public key"
+    );
+    set_signing_key(&mut env, Some(pubkey(&key))).unwrap();
+
+    let res: SigningKeyResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::SigningKey {
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(
+        res,
+        SigningKeyResponse {
+            pubkey: Some(pubkey(&key)),
+            nonce: 0,
+        }
+    );
+
+    // the position is opened for alice, drawing her margin
+    let first = order(&env, 0);
+    let signature = sign(&env, &key, &first);
+    relay(&mut env, first.clone(), pubkey(&key), signature.clone()).unwrap();
+
+    let position: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(position.margin, to_decimals(10));
+
+    // and cannot be replayed
+    assert_eq!(
+        relay(&mut env, first, pubkey(&key), signature).unwrap_err(),
+        "Order nonce must be 1"
+    );
+
+    // nor changed once signed, or signed by another key
+    let second = order(&env, 1);
+    let signature = sign(&env, &key, &second);
+    let tampered = SignedOrder {
+        quote_asset_amount: Uint128::from(20u128),
+        ..second.clone()
+    };
+    assert_eq!(
+        relay(&mut env, tampered, pubkey(&key), signature.clone()).unwrap_err(),
+        "Order signature is invalid"
+    );
+    let other = signing_key(2);
+    let forged = sign(&env, &other, &second);
+    assert_eq!(
+        relay(&mut env, second.clone(), pubkey(&other), forged).unwrap_err(),
+        "Order signature is invalid"
+    );
+
+    // orders signed before the key is removed are no longer accepted
+    set_signing_key(&mut env, None).unwrap();
+    assert_eq!(
+        relay(&mut env, second, pubkey(&key), signature).unwrap_err(),
+        "Order signature is invalid"
+    );
+}
@@ -0,0 +1,157 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use margined_perp::integer::Integer;
+use margined_perp::margined_engine::{
+    MarginRatioResponse, PnlCalcOption, PositionNotionalAndUnrealizedPnlResponse, PositionResponse,
+    QueryMsg, Side, SimulationResponse,
+};
+
+fn simulate_open(
+    env: &setup::TestingEnv,
+    side: Side,
+    quote_asset_amount: u64,
+    leverage: u64,
+) -> SimulationResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::SimulateOpenPosition {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+                side,
+                quote_asset_amount: to_decimals(quote_asset_amount),
+                leverage: to_decimals(leverage),
+            },
+        )
+        .unwrap()
+}
+
+fn alice_position(env: &setup::TestingEnv) -> PositionResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_simulate_open_position_matches_execution() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    // 300 of notional buys 100 * 300 / 1300 of base
+    let res = simulate_open(&env, Side::BUY, 60, 5);
+    assert_eq!(
+        res.exchanged_position_size,
+        Uint128::from(23_076_923_076u128)
+    );
+    assert_eq!(res.exchanged_quote_asset_amount, to_decimals(300));
+    assert_eq!(res.entry_price, to_decimals(13));
+    assert_eq!(res.realized_pnl, Integer::zero());
+    assert_eq!(res.margin, to_decimals(60));
+
+    // the order leaves the mark at 1300 / 76.9, around 16.9, and in so
+    // shallow a market the slippage of closing liquidates the long well above
+    // the price it was bought at
+    let liquidation_price = res.liquidation_price.unwrap();
+    assert!(liquidation_price > to_decimals(13) && liquidation_price < to_decimals(14));
+
+    env.open_position(&alice, Side::BUY, 60, 5).unwrap();
+    let position = alice_position(&env);
+    assert_eq!(position.size, res.size);
+    assert_eq!(position.margin, res.margin);
+
+    let margin_ratio: MarginRatioResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::MarginRatio {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(margin_ratio.margin_ratio, res.margin_ratio);
+
+    // an order larger than the position closes it and opens the rest short
+    let res = simulate_open(&env, Side::SELL, 90, 5);
+    assert_eq!(res.exchanged_quote_asset_amount, to_decimals(450));
+    assert!(res.exchanged_position_size > position.size);
+
+    // the short is liquidated above the price it was opened at
+    let short_entry_price = to_decimals(150).multiply_ratio(to_decimals(1), res.size);
+    assert!(res.liquidation_price.unwrap() > short_entry_price);
+
+    env.open_position(&alice, Side::SELL, 90, 5).unwrap();
+    let position = alice_position(&env);
+    let diff = position.size.u128() as i128 - res.size.u128() as i128;
+    assert!(diff.abs() <= 10);
+    assert_eq!(position.margin, res.margin);
+}
+
+#[test]
+fn test_simulate_close_position() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    let msg = QueryMsg::SimulateClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+        size: None,
+    };
+    env.router
+        .wrap()
+        .query_wasm_smart::<SimulationResponse>(&env.engine.addr, &msg)
+        .unwrap_err();
+
+    env.open_position(&alice, Side::BUY, 60, 5).unwrap();
+    let position = alice_position(&env);
+
+    // closing half releases half the margin
+    let res: SimulationResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::SimulateClosePosition {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+                size: Some(position.size.multiply_ratio(1u128, 2u128)),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.size, position.size - res.exchanged_position_size);
+    assert_eq!(res.margin, to_decimals(30));
+    assert!(res.liquidation_price.is_some());
+
+    // closing it all realizes what it would be worth at the spot price
+    let pnl: PositionNotionalAndUnrealizedPnlResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::PositionNotionalAndUnrealizedPnl {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+                calc_option: PnlCalcOption::SpotPrice,
+            },
+        )
+        .unwrap();
+    let res: SimulationResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.engine.addr, &msg)
+        .unwrap();
+    assert_eq!(res.exchanged_position_size, position.size);
+    assert_eq!(res.realized_pnl, pnl.unrealized_pnl);
+    assert_eq!(res.size, Uint128::zero());
+    assert_eq!(res.margin_ratio, Uint128::zero());
+    assert_eq!(res.liquidation_price, None);
+}
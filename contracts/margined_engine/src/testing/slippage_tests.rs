@@ -0,0 +1,204 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{AccountSettingsResponse, ExecuteMsg, QueryMsg, Side};
+
+fn set_max_slippage(env: &mut setup::TestingEnv, max_slippage: Option<Uint128>) {
+    let msg = ExecuteMsg::UpdateAccountSettings { max_slippage };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+#[test]
+fn test_account_settings_default_to_none() {
+    let env = setup::setup();
+
+    let res: AccountSettingsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::AccountSettings {
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.max_slippage, None);
+}
+
+#[test]
+fn test_default_slippage_rejects_large_trades() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    // 1% max slippage
+    set_max_slippage(&mut env, Some(Uint128::from(10_000_000u128)));
+
+    let res: AccountSettingsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::AccountSettings {
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.max_slippage, Some(Uint128::from(10_000_000u128)));
+
+    // 100 quote into a 1000 quote pool moves the price ~9%
+    assert!(env.open_position(&alice, Side::BUY, 100, 1).is_err());
+
+    // 1 quote moves the price ~0.1%
+    assert!(env.open_position(&alice, Side::BUY, 1, 1).is_ok());
+}
+
+#[test]
+fn test_explicit_limit_overrides_default_slippage() {
+    let mut env = setup::setup();
+    let vamm = env.vamm.addr.clone();
+    let alice = env.alice.clone();
+    set_max_slippage(&mut env, Some(Uint128::from(10_000_000u128)));
+
+    assert!(env
+        .open_position_with(
+            &vamm,
+            &alice,
+            Side::BUY,
+            to_decimals(100),
+            to_decimals(1),
+            Some(to_decimals(9)),
+            false,
+        )
+        .is_ok());
+}
+
+#[test]
+fn test_explicit_limit_is_enforced() {
+    let mut env = setup::setup();
+    let vamm = env.vamm.addr.clone();
+    let alice = env.alice.clone();
+
+    // 100 quote buys ~9.09 base
+    assert!(env
+        .open_position_with(
+            &vamm,
+            &alice,
+            Side::BUY,
+            to_decimals(100),
+            to_decimals(1),
+            Some(to_decimals(10)),
+            false,
+        )
+        .is_err());
+    assert!(env
+        .open_position_with(
+            &vamm,
+            &alice,
+            Side::BUY,
+            to_decimals(100),
+            to_decimals(1),
+            Some(to_decimals(9)),
+            false,
+        )
+        .is_ok());
+}
+
+#[test]
+fn test_max_slippage_cannot_exceed_100_percent() {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::UpdateAccountSettings {
+        max_slippage: Some(to_decimals(2)),
+    };
+    let res = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+}
+
+#[test]
+fn test_close_position_quote_asset_limit() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    assert!(env.open_position(&alice, Side::BUY, 60, 1).is_ok());
+
+    // closing the long returns around 60, so demanding 61 reverts
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: Some(to_decimals(61)),
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert!(err.to_string().contains("exceeds slippage limit"));
+
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: Some(to_decimals(59)),
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+#[test]
+fn test_orders_are_rejected_after_their_deadline() {
+    let mut env = setup::setup();
+    let deadline = env.router.block_info().time.plus_seconds(60);
+
+    let open = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(1),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: Some(deadline),
+        trader: None,
+    };
+    let close = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: Some(deadline),
+        trader: None,
+    };
+
+    // up to and including the deadline the order executes
+    env.router
+        .update_block(|block| block.time = block.time.plus_seconds(60));
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &open, &[])
+        .unwrap();
+
+    env.router
+        .update_block(|block| block.time = block.time.plus_seconds(1));
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &open, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("Order expired at its deadline of {}", deadline)
+    );
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &close, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("Order expired at its deadline of {}", deadline)
+    );
+}
@@ -0,0 +1,59 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{ExecuteMsg, PositionSnapshotResponse, QueryMsg, Side};
+
+fn position_snapshot(env: &setup::TestingEnv) -> PositionSnapshotResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::PositionSnapshot {
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_position_snapshot() {
+    let mut env = setup::setup();
+
+    let res = position_snapshot(&env);
+    assert_eq!(res.trader, env.alice);
+    assert!(res.positions.is_empty());
+
+    let opened_at = env.router.block_info().time;
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(60),
+        leverage: to_decimals(10),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(3_600);
+        block.height += 1;
+    });
+
+    let res = position_snapshot(&env);
+    assert_eq!(res.timestamp, env.router.block_info().time);
+    assert_eq!(res.positions.len(), 1);
+
+    // 600 notional buys 37.5 base, an entry price of 16
+    let position = &res.positions[0];
+    assert_eq!(position.vamm, env.vamm.addr);
+    assert_eq!(position.side, Side::BUY);
+    assert_eq!(position.size, to_decimals(75) / Uint128::from(2u128));
+    assert_eq!(position.notional, to_decimals(600));
+    assert_eq!(position.entry_price, to_decimals(16));
+    assert_eq!(position.fees_paid, Uint128::zero());
+    assert_eq!(position.opened_at, opened_at);
+}
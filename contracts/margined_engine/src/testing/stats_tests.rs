@@ -0,0 +1,122 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    AssetTotal, DailyStatsListResponse, ExecuteMsg, QueryMsg, Side, StateResponse,
+};
+
+fn daily_stats(
+    env: &setup::TestingEnv,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> DailyStatsListResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::DailyStats {
+                vamm: env.vamm.addr.to_string(),
+                start_after,
+                end_day: None,
+                limit,
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_daily_stats_are_bucketed_by_day() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    let today = env.router.block_info().time.seconds() / 86_400;
+
+    env.open_position(&alice, Side::BUY, 20, 5).unwrap();
+    env.open_position(&alice, Side::BUY, 10, 5).unwrap();
+
+    env.router.update_block(|block| {
+        block.time = block.time.plus_seconds(86_400);
+    });
+
+    // reduces the position
+    env.open_position(&alice, Side::SELL, 4, 5).unwrap();
+
+    let res = daily_stats(&env, None, None);
+    assert_eq!(res.stats.len(), 2);
+    assert_eq!(res.next_key, None);
+
+    assert_eq!(res.stats[0].day, today);
+    assert_eq!(res.stats[0].volume, to_decimals(150));
+    assert_eq!(res.stats[0].peak_open_interest, to_decimals(150));
+
+    assert_eq!(res.stats[1].day, today + 1);
+    assert_eq!(res.stats[1].volume, to_decimals(20));
+    assert_eq!(
+        res.stats[1].peak_open_interest,
+        Uint128::new(129_999_999_998)
+    );
+
+    // page through one day at a time
+    let res = daily_stats(&env, None, Some(1));
+    assert_eq!(res.stats.len(), 1);
+    assert_eq!(res.stats[0].day, today);
+    assert_eq!(res.next_key, Some(today));
+
+    let res = daily_stats(&env, res.next_key, Some(1));
+    assert_eq!(res.stats.len(), 1);
+    assert_eq!(res.stats[0].day, today + 1);
+
+    let res = daily_stats(&env, res.next_key, Some(1));
+    assert!(res.stats.is_empty());
+    assert_eq!(res.next_key, None);
+}
+
+#[test]
+fn test_state_totals_positions() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+
+    let state = |env: &setup::TestingEnv| -> StateResponse {
+        env.router
+            .wrap()
+            .query_wasm_smart(&env.engine.addr, &QueryMsg::State {})
+            .unwrap()
+    };
+    assert_eq!(
+        state(&env),
+        StateResponse {
+            total_deposits: vec![],
+            total_margin: vec![],
+            insurance_balance: Uint128::zero(),
+            outstanding_notional: Uint128::zero(),
+        }
+    );
+
+    // two opens lock their margin and add their notional
+    env.open_position(&alice, Side::BUY, 10, 5).unwrap();
+    env.open_position(&alice, Side::BUY, 4, 5).unwrap();
+    let totals = state(&env);
+    assert_eq!(
+        totals.total_margin,
+        vec![AssetTotal {
+            asset: env.usdc.addr.to_string(),
+            amount: to_decimals(14),
+        }]
+    );
+    assert_eq!(totals.outstanding_notional, to_decimals(70));
+
+    // closing releases both
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let totals = state(&env);
+    assert_eq!(totals.total_margin[0].amount, Uint128::zero());
+    assert_eq!(totals.outstanding_notional, Uint128::zero());
+}
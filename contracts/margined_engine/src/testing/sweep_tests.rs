@@ -0,0 +1,182 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{coins, Addr, Uint128};
+use cw20::{BalanceResponse, Cw20Coin, Cw20ExecuteMsg, Cw20QueryMsg};
+use cw_multi_test::Executor;
+use margined_perp::asset::AssetInfo;
+use margined_perp::margined_engine::{ExecuteMsg, Side};
+
+fn balance(env: &setup::TestingEnv, token: &Addr, address: &Addr) -> Uint128 {
+    let res: BalanceResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            token,
+            &Cw20QueryMsg::Balance {
+                address: address.to_string(),
+            },
+        )
+        .unwrap();
+    res.balance
+}
+
+#[test]
+fn test_sweep_stray_tokens() {
+    let mut env = setup::setup();
+
+    // a token nothing in the engine uses, sent to it by mistake
+    let stray = env
+        .router
+        .instantiate_contract(
+            env.usdc.id,
+            env.owner.clone(),
+            &cw20_base::msg::InstantiateMsg {
+                name: "Stray".to_string(),
+                symbol: "STRAY".to_string(),
+                decimals: 9,
+                initial_balances: vec![Cw20Coin {
+                    address: env.alice.to_string(),
+                    amount: to_decimals(100),
+                }],
+                mint: None,
+                marketing: None,
+            },
+            &[],
+            "stray",
+            None,
+        )
+        .unwrap();
+    let msg = Cw20ExecuteMsg::Transfer {
+        recipient: env.engine.addr.to_string(),
+        amount: to_decimals(100),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), stray.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::Sweep {
+        asset: AssetInfo::Token {
+            contract_addr: stray.to_string(),
+        },
+        amount: to_decimals(100),
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Unauthorized");
+
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(balance(&env, &stray, &env.owner), to_decimals(100));
+    assert_eq!(balance(&env, &stray, &env.engine.addr), Uint128::zero());
+}
+
+#[test]
+fn test_sweep_only_takes_collateral_above_liabilities() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    env.open_position(&alice, Side::BUY, 10, 2).unwrap();
+    let held = balance(&env, &env.usdc.addr, &env.engine.addr);
+
+    let msg = Cw20ExecuteMsg::Transfer {
+        recipient: env.engine.addr.to_string(),
+        amount: to_decimals(10),
+    };
+    env.router
+        .execute_contract(alice.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // alice's margin is owed back to her, only the stray transfer can go
+    let msg = ExecuteMsg::Sweep {
+        asset: AssetInfo::Token {
+            contract_addr: env.usdc.addr.to_string(),
+        },
+        amount: to_decimals(11),
+    };
+    let err = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: cannot sweep more than the 10000000000 held above liabilities"
+    );
+
+    let msg = ExecuteMsg::Sweep {
+        asset: AssetInfo::Token {
+            contract_addr: env.usdc.addr.to_string(),
+        },
+        amount: to_decimals(10),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(balance(&env, &env.usdc.addr, &env.engine.addr), held);
+}
+
+#[test]
+fn test_sweep_native_denoms() {
+    let mut env = setup::setup();
+    env.router
+        .init_bank_balance(&env.alice, coins(to_decimals(100).u128(), "uusd"))
+        .unwrap();
+
+    let msg = ExecuteMsg::UpdateMarketCollateral {
+        vamm: env.vamm.addr.to_string(),
+        collateral: AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        },
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(2),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(
+            env.alice.clone(),
+            env.engine.addr.clone(),
+            &msg,
+            &coins(to_decimals(10).u128(), "uusd"),
+        )
+        .unwrap();
+    env.router
+        .send_tokens(
+            env.alice.clone(),
+            env.engine.addr.clone(),
+            &coins(to_decimals(5).u128(), "uusd"),
+        )
+        .unwrap();
+
+    let uusd = AssetInfo::NativeToken {
+        denom: "uusd".to_string(),
+    };
+    let msg = ExecuteMsg::Sweep {
+        asset: uusd.clone(),
+        amount: to_decimals(6),
+    };
+    let res = env
+        .router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[]);
+    assert!(res.is_err());
+
+    let msg = ExecuteMsg::Sweep {
+        asset: uusd,
+        amount: to_decimals(5),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let swept = env.router.wrap().query_balance(&env.owner, "uusd").unwrap();
+    assert_eq!(swept.amount, to_decimals(5));
+}
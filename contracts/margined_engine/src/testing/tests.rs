@@ -1,17 +1,77 @@
-use crate::contract::{execute, instantiate, query};
-use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-use cosmwasm_std::{from_binary, Addr, Uint128};
-use margined_perp::margined_engine::{ConfigResponse, ExecuteMsg, InstantiateMsg, QueryMsg};
+use crate::contract::{
+    execute, instantiate, migrate, parse_swap, query, swap_reply_id, SWAP_CLOSE_REPLY_ID,
+};
+use crate::state::{
+    read_config, read_position, read_state, read_tmp_swap, read_totals, remove_tmp_swap,
+    store_tmp_swap, Position, Swap, TOTAL_MARGIN,
+};
+use cosmwasm_std::testing::{mock_env, mock_info, MockApi, MockQuerier, MockStorage};
+use cosmwasm_std::{
+    from_binary, from_slice, to_binary, to_vec, Addr, ContractResult, Empty, Event, OwnedDeps,
+    Querier, QuerierResult, QueryRequest, Storage, SubMsgExecutionResponse, SystemResult, Uint128,
+    WasmQuery,
+};
+use cw2::{get_contract_version, set_contract_version};
+use margined_perp::asset::AssetInfo;
+use margined_perp::integer::Integer;
+use margined_perp::margined_engine::{
+    ConfigResponse, ExecuteMsg, InstantiateMsg, MigrateMsg, PauseLevel, PnlCalcOption, QueryMsg,
+    Side,
+};
+use margined_perp::margined_vamm::{ConfigResponse as VammConfigResponse, SwapResponse};
 
 const TOKEN: &str = "token";
 const OWNER: &str = "owner";
 
+// answers every smart query with the config of a vamm, as the engine looks
+// up the decimals of its vamms on instantiation
+struct VammQuerier {
+    base: MockQuerier,
+}
+
+impl Querier for VammQuerier {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<Empty> = from_slice(bin_request).unwrap();
+        match request {
+            QueryRequest::Wasm(WasmQuery::Smart { .. }) => {
+                let config = VammConfigResponse {
+                    owner: Addr::unchecked(OWNER),
+                    pending_owner: None,
+                    quote_asset: "ETH".to_string(),
+                    base_asset: "USD".to_string(),
+                    toll_ratio: Uint128::zero(),
+                    spread_ratio: Uint128::zero(),
+                    decimals: Uint128::from(10_000_000_000u128),
+                    price_feed: None,
+                    fluctuation_limit_ratio: Uint128::zero(),
+                    paused: false,
+                    engine: None,
+                };
+                SystemResult::Ok(ContractResult::Ok(to_binary(&config).unwrap()))
+            }
+            _ => self.base.raw_query(bin_request),
+        }
+    }
+}
+
+fn mock_dependencies() -> OwnedDeps<MockStorage, MockApi, VammQuerier> {
+    OwnedDeps {
+        storage: MockStorage::default(),
+        api: MockApi::default(),
+        querier: VammQuerier {
+            base: MockQuerier::new(&[]),
+        },
+    }
+}
+
 #[test]
 fn test_instantiation() {
-    let mut deps = mock_dependencies(&[]);
+    let mut deps = mock_dependencies();
     let msg = InstantiateMsg {
         decimals: 10u8,
-        eligible_collateral: TOKEN.to_string(),
+        eligible_collateral: AssetInfo::Token {
+            contract_addr: TOKEN.to_string(),
+        },
         initial_margin_ratio: Uint128::from(100u128),
         maintenance_margin_ratio: Uint128::from(100u128),
         liquidation_fee: Uint128::from(100u128),
@@ -27,17 +87,101 @@ fn test_instantiation() {
         config,
         ConfigResponse {
             owner: info.sender.clone(),
-            eligible_collateral: Addr::unchecked(TOKEN),
+            pending_owner: None,
+            operator: None,
+            eligible_collateral: AssetInfo::Token {
+                contract_addr: TOKEN.to_string(),
+            },
+            decimals: Uint128::from(10_000_000_000u128),
+            initial_margin_ratio: Uint128::from(100u128),
+            maintenance_margin_ratio: Uint128::from(100u128),
+            liquidation_fee: Uint128::from(100u128),
+            min_notional: Uint128::zero(),
+            fee_pool: None,
+            open_liquidation: false,
+            pause_level: PauseLevel::Full,
+            staking_contract: None,
+            max_oracle_spread: None,
+            liquidation_price_source: PnlCalcOption::SpotPrice,
         }
     );
 }
 
+#[test]
+fn test_migrate_moves_legacy_storage() {
+    let mut deps = mock_dependencies();
+    let msg = InstantiateMsg {
+        decimals: 10u8,
+        eligible_collateral: AssetInfo::Token {
+            contract_addr: TOKEN.to_string(),
+        },
+        initial_margin_ratio: Uint128::from(100u128),
+        maintenance_margin_ratio: Uint128::from(100u128),
+        liquidation_fee: Uint128::from(100u128),
+        vamm: vec!["test".to_string()],
+    };
+    instantiate(deps.as_mut(), mock_env(), mock_info(OWNER, &[]), msg).unwrap();
+
+    // rewrite the storage as a deployment from before versioning left it,
+    // the config in a singleton and the position under a hashed key with its
+    // premium fraction under the old name
+    let config = read_config(&deps.storage).unwrap();
+    let position = Position {
+        vamm: Addr::unchecked("vamm"),
+        trader: Addr::unchecked("trader"),
+        size: Uint128::from(100u128),
+        margin: Uint128::from(20u128),
+        notional: Uint128::from(50u128),
+        last_updated_premium_fraction: Integer::new_positive(5u128),
+        ..Position::default()
+    };
+    let legacy = String::from_utf8(to_vec(&position).unwrap())
+        .unwrap()
+        .replace("last_updated_premium_fraction", "premium_fraction");
+    deps.storage.remove(b"config");
+    deps.storage.remove(b"contract_info");
+    deps.storage.remove(b"state");
+    deps.storage
+        .set(b"\x00\x06config", &to_vec(&config).unwrap());
+    deps.storage
+        .set(b"\x00\x08position4c5b1c2a9f0e", legacy.as_bytes());
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+    assert_eq!(read_config(&deps.storage).unwrap(), config);
+    assert_eq!(
+        read_position(&deps.storage, &position.vamm, &position.trader).unwrap(),
+        Some(position)
+    );
+    assert_eq!(deps.storage.get(b"\x00\x06config"), None);
+    assert_eq!(deps.storage.get(b"\x00\x08position4c5b1c2a9f0e"), None);
+
+    // and the totals count the moved position
+    let state = read_state(&deps.storage).unwrap();
+    assert_eq!(
+        read_totals(&deps.storage, &TOTAL_MARGIN).unwrap()[0].amount,
+        Uint128::from(20u128)
+    );
+    assert_eq!(state.outstanding_notional, Uint128::from(50u128));
+
+    // the contract is versioned from then on and only migrates from itself
+    let version = get_contract_version(&deps.storage).unwrap();
+    assert_eq!(version.contract, "crates.io:margined-engine");
+    set_contract_version(&mut deps.storage, "crates.io:other", "0.1.0").unwrap();
+    let err = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: cannot migrate from crates.io:other"
+    );
+}
+
 #[test]
 fn test_update_config() {
-    let mut deps = mock_dependencies(&[]);
+    let mut deps = mock_dependencies();
     let msg = InstantiateMsg {
         decimals: 10u8,
-        eligible_collateral: TOKEN.to_string(),
+        eligible_collateral: AssetInfo::Token {
+            contract_addr: TOKEN.to_string(),
+        },
         initial_margin_ratio: Uint128::from(100u128),
         maintenance_margin_ratio: Uint128::from(100u128),
         liquidation_fee: Uint128::from(100u128),
@@ -48,7 +192,16 @@ fn test_update_config() {
 
     // Update the config
     let msg = ExecuteMsg::UpdateConfig {
-        owner: "addr0001".to_string(),
+        eligible_collateral: None,
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: Some(Uint128::from(1_000u128)),
+        fee_pool: None,
+        open_liquidation: None,
+        pause_level: None,
+        staking_contract: None,
+        liquidation_price_source: None,
     };
 
     let info = mock_info(OWNER, &[]);
@@ -59,17 +212,273 @@ fn test_update_config() {
     assert_eq!(
         config,
         ConfigResponse {
-            owner: Addr::unchecked("addr0001".to_string()),
-            eligible_collateral: Addr::unchecked(TOKEN),
+            owner: Addr::unchecked(OWNER.to_string()),
+            pending_owner: None,
+            operator: None,
+            eligible_collateral: AssetInfo::Token {
+                contract_addr: TOKEN.to_string(),
+            },
+            decimals: Uint128::from(10_000_000_000u128),
+            initial_margin_ratio: Uint128::from(100u128),
+            maintenance_margin_ratio: Uint128::from(100u128),
+            liquidation_fee: Uint128::from(100u128),
+            min_notional: Uint128::from(1_000u128),
+            fee_pool: None,
+            open_liquidation: false,
+            pause_level: PauseLevel::Full,
+            staking_contract: None,
+            max_oracle_spread: None,
+            liquidation_price_source: PnlCalcOption::SpotPrice,
         }
     );
 
     // Update should fail
     let msg = ExecuteMsg::UpdateConfig {
-        owner: OWNER.to_string(),
+        eligible_collateral: None,
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: None,
+        fee_pool: None,
+        open_liquidation: None,
+        pause_level: None,
+        staking_contract: None,
+        liquidation_price_source: None,
     };
 
-    let info = mock_info(OWNER, &[]);
+    let info = mock_info("addr0001", &[]);
     let result = execute(deps.as_mut(), mock_env(), info, msg);
     assert!(result.is_err());
 }
+
+#[test]
+fn test_ownership_transfer() {
+    let mut deps = mock_dependencies();
+    let msg = InstantiateMsg {
+        decimals: 10u8,
+        eligible_collateral: AssetInfo::Token {
+            contract_addr: TOKEN.to_string(),
+        },
+        initial_margin_ratio: Uint128::from(100u128),
+        maintenance_margin_ratio: Uint128::from(100u128),
+        liquidation_fee: Uint128::from(100u128),
+        vamm: vec!["test".to_string()],
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let owner = mock_info(OWNER, &[]);
+    let new_owner = mock_info("addr0001", &[]);
+    let propose = ExecuteMsg::ProposeOwnership {
+        owner: "addr0001".to_string(),
+    };
+    let accept = ExecuteMsg::AcceptOwnership {};
+
+    // only the owner can propose, and only the proposed owner can accept
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        new_owner.clone(),
+        propose.clone(),
+    )
+    .unwrap_err();
+    execute(deps.as_mut(), mock_env(), owner.clone(), propose.clone()).unwrap();
+    execute(deps.as_mut(), mock_env(), owner.clone(), accept.clone()).unwrap_err();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.owner, Addr::unchecked(OWNER));
+    assert_eq!(config.pending_owner, Some(Addr::unchecked("addr0001")));
+
+    // a cancelled proposal cannot be accepted
+    let msg = ExecuteMsg::CancelOwnershipProposal {};
+    execute(deps.as_mut(), mock_env(), owner.clone(), msg).unwrap();
+    execute(deps.as_mut(), mock_env(), new_owner.clone(), accept.clone()).unwrap_err();
+
+    execute(deps.as_mut(), mock_env(), owner, propose).unwrap();
+    execute(deps.as_mut(), mock_env(), new_owner, accept).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.owner, Addr::unchecked("addr0001"));
+    assert_eq!(config.pending_owner, None);
+}
+
+#[test]
+fn test_operator_role() {
+    let mut deps = mock_dependencies();
+    let msg = InstantiateMsg {
+        decimals: 10u8,
+        eligible_collateral: AssetInfo::Token {
+            contract_addr: TOKEN.to_string(),
+        },
+        initial_margin_ratio: Uint128::from(100u128),
+        maintenance_margin_ratio: Uint128::from(100u128),
+        liquidation_fee: Uint128::from(100u128),
+        vamm: vec!["test".to_string()],
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let owner = mock_info(OWNER, &[]);
+    let operator = mock_info("operator", &[]);
+    let pause = ExecuteMsg::SetPause { paused: true };
+
+    // the owner pauses until an operator is appointed
+    execute(deps.as_mut(), mock_env(), operator.clone(), pause.clone()).unwrap_err();
+    execute(deps.as_mut(), mock_env(), owner.clone(), pause.clone()).unwrap();
+
+    // only the owner appoints the operator
+    let msg = ExecuteMsg::SetOperator {
+        operator: Some("operator".to_string()),
+    };
+    execute(deps.as_mut(), mock_env(), operator.clone(), msg.clone()).unwrap_err();
+    execute(deps.as_mut(), mock_env(), owner.clone(), msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.operator, Some(Addr::unchecked("operator")));
+
+    // the operator then takes over pausing and registering vamms
+    execute(deps.as_mut(), mock_env(), owner.clone(), pause.clone()).unwrap_err();
+    execute(deps.as_mut(), mock_env(), operator.clone(), pause).unwrap();
+    let msg = ExecuteMsg::RemoveVamm {
+        vamm: "test".to_string(),
+    };
+    execute(deps.as_mut(), mock_env(), owner.clone(), msg.clone()).unwrap_err();
+    execute(deps.as_mut(), mock_env(), operator.clone(), msg).unwrap();
+
+    // but cannot change the parameters of the engine
+    let msg = ExecuteMsg::UpdateConfig {
+        eligible_collateral: None,
+        initial_margin_ratio: None,
+        maintenance_margin_ratio: None,
+        liquidation_fee: None,
+        min_notional: None,
+        fee_pool: None,
+        open_liquidation: Some(true),
+        pause_level: None,
+        staking_contract: None,
+        liquidation_price_source: None,
+    };
+    execute(deps.as_mut(), mock_env(), operator, msg).unwrap_err();
+}
+
+#[test]
+fn test_update_risk_params() {
+    let mut deps = mock_dependencies();
+    let msg = InstantiateMsg {
+        decimals: 10u8,
+        eligible_collateral: AssetInfo::Token {
+            contract_addr: TOKEN.to_string(),
+        },
+        initial_margin_ratio: Uint128::from(100u128),
+        maintenance_margin_ratio: Uint128::from(100u128),
+        liquidation_fee: Uint128::from(100u128),
+        vamm: vec!["test".to_string()],
+    };
+    let info = mock_info(OWNER, &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let update =
+        |initial_margin_ratio: u128, maintenance_margin_ratio: u128| ExecuteMsg::UpdateConfig {
+            eligible_collateral: Some(AssetInfo::NativeToken {
+                denom: "uusd".to_string(),
+            }),
+            initial_margin_ratio: Some(Uint128::from(initial_margin_ratio)),
+            maintenance_margin_ratio: Some(Uint128::from(maintenance_margin_ratio)),
+            liquidation_fee: Some(Uint128::from(250_000_000u128)),
+            min_notional: None,
+            fee_pool: None,
+            open_liquidation: None,
+            pause_level: None,
+            staking_contract: None,
+            liquidation_price_source: None,
+        };
+
+    // positions cannot be liquidated above the margin they are opened with
+    let info = mock_info(OWNER, &[]);
+    let err = execute(deps.as_mut(), mock_env(), info.clone(), update(500, 1_000)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: maintenance margin ratio cannot exceed the initial margin ratio"
+    );
+
+    execute(deps.as_mut(), mock_env(), info, update(1_000, 500)).unwrap();
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        config.eligible_collateral,
+        AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        }
+    );
+    assert_eq!(config.initial_margin_ratio, Uint128::from(1_000u128));
+    assert_eq!(config.maintenance_margin_ratio, Uint128::from(500u128));
+    assert_eq!(config.liquidation_fee, Uint128::from(250_000_000u128));
+}
+
+#[test]
+fn test_tmp_swaps_are_kept_apart() {
+    let mut storage = MockStorage::new();
+    let swap = |trader: &str| Swap {
+        vamm: Addr::unchecked("vamm"),
+        trader: Addr::unchecked(trader),
+        side: Side::BUY,
+        quote_asset_amount: Uint128::zero(),
+        leverage: Uint128::zero(),
+        open_notional: Uint128::zero(),
+        base_asset_limit: None,
+        quote_asset_limit: None,
+        fee: Uint128::zero(),
+    };
+
+    let alice_id = store_tmp_swap(&mut storage, &swap("alice")).unwrap();
+    let bob_id = store_tmp_swap(&mut storage, &swap("bob")).unwrap();
+    assert_ne!(alice_id, bob_id);
+
+    // each swap's reply finds its own swap and cleans up only that one
+    remove_tmp_swap(&mut storage, alice_id);
+    assert_eq!(read_tmp_swap(&storage, alice_id).unwrap(), None);
+    assert_eq!(read_tmp_swap(&storage, bob_id).unwrap(), Some(swap("bob")));
+
+    // the reply id keeps the kind of reply in its low bits
+    let reply_id = swap_reply_id(SWAP_CLOSE_REPLY_ID, bob_id);
+    assert_eq!(reply_id & 0xff, SWAP_CLOSE_REPLY_ID);
+    assert_eq!(reply_id >> 8, bob_id);
+}
+
+#[test]
+fn test_parse_swap() {
+    // an unrelated wasm event ahead of the vamm's
+    let events = vec![
+        Event::new("wasm").add_attribute("action", "transfer"),
+        Event::new("wasm")
+            .add_attribute("action", "swap_input")
+            .add_attribute("input", "600")
+            .add_attribute("output", "375"),
+    ];
+
+    // the swap data is preferred over the events
+    let response = SubMsgExecutionResponse {
+        events: events.clone(),
+        data: Some(
+            to_binary(&SwapResponse {
+                input: Uint128::from(10u128),
+                output: Uint128::from(1u128),
+            })
+            .unwrap(),
+        ),
+    };
+    assert_eq!(
+        parse_swap(response).unwrap(),
+        (Uint128::from(10u128), Uint128::from(1u128))
+    );
+
+    // vamms without swap data fall back to the event with the amounts
+    let response = SubMsgExecutionResponse { events, data: None };
+    assert_eq!(
+        parse_swap(response).unwrap(),
+        (Uint128::from(600u128), Uint128::from(375u128))
+    );
+}
@@ -0,0 +1,144 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw_multi_test::Executor;
+use margined_perp::asset::AssetInfo;
+use margined_perp::margined_engine::{
+    ExecuteMsg, QueryMsg, Side, ThrottlePeriod, WithdrawalThrottle, WithdrawalThrottleResponse,
+};
+
+fn withdraw_margin(env: &mut setup::TestingEnv, amount: u64) -> Result<(), String> {
+    let msg = ExecuteMsg::WithdrawMargin {
+        vamm: env.vamm.addr.to_string(),
+        amount: to_decimals(amount),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn update_throttle(env: &mut setup::TestingEnv, throttle: Option<WithdrawalThrottle>) {
+    let msg = ExecuteMsg::UpdateWithdrawalThrottle { throttle };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
+
+fn next_block(env: &mut setup::TestingEnv) {
+    env.router.update_block(|block| {
+        block.height += 1;
+        block.time = block.time.plus_seconds(5);
+    });
+}
+
+#[test]
+fn test_withdrawal_throttle() {
+    let mut env = setup::setup();
+
+    // alice holds 100 margin at 1x, the only collateral in the engine
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(100),
+        leverage: to_decimals(1),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // only the owner may throttle withdrawals, to at most 100%
+    let msg = ExecuteMsg::UpdateWithdrawalThrottle {
+        throttle: Some(WithdrawalThrottle {
+            period: ThrottlePeriod::Block,
+            limit_ratio: Uint128::from(100_000_000u128),
+        }),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    let msg = ExecuteMsg::UpdateWithdrawalThrottle {
+        throttle: Some(WithdrawalThrottle {
+            period: ThrottlePeriod::Block,
+            limit_ratio: Uint128::from(2_000_000_000u128),
+        }),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    // 10% of the 100 held can leave in each block
+    update_throttle(
+        &mut env,
+        Some(WithdrawalThrottle {
+            period: ThrottlePeriod::Block,
+            limit_ratio: Uint128::from(100_000_000u128),
+        }),
+    );
+    assert_eq!(
+        withdraw_margin(&mut env, 20).unwrap_err(),
+        "Payout exceeds what can leave the engine this period, 10000000000"
+    );
+    withdraw_margin(&mut env, 6).unwrap();
+    assert_eq!(
+        withdraw_margin(&mut env, 6).unwrap_err(),
+        "Payout exceeds what can leave the engine this period, 4000000000"
+    );
+
+    let res: WithdrawalThrottleResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::WithdrawalThrottle { asset: None },
+        )
+        .unwrap();
+    assert_eq!(res.remaining, Some(to_decimals(4)));
+
+    // the usdc held does not let any other asset leave
+    let res: WithdrawalThrottleResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::WithdrawalThrottle {
+                asset: Some(AssetInfo::NativeToken {
+                    denom: "uusd".to_string(),
+                }),
+            },
+        )
+        .unwrap();
+    assert_eq!(res.remaining, Some(Uint128::zero()));
+
+    // the next block opens a new period on what is then held
+    next_block(&mut env);
+    withdraw_margin(&mut env, 6).unwrap();
+
+    // closing pays out far more than the limit
+    next_block(&mut env);
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    let err = env
+        .router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Payout exceeds what can leave the engine this period, 8799999999"
+    );
+
+    // until the throttle is lifted
+    update_throttle(&mut env, None);
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+}
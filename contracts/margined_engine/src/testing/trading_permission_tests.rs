@@ -0,0 +1,127 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{Addr, Uint128};
+use cw20::Cw20Contract;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, PositionResponse, QueryMsg, Side, TradingPermissionResponse,
+};
+
+// bob opens a long of 10 margin at 5x for alice
+fn open_for_alice(env: &mut setup::TestingEnv) -> Result<(), String> {
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(10),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: Some(env.alice.to_string()),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn size(env: &setup::TestingEnv, trader: &Addr) -> Uint128 {
+    env.router
+        .wrap()
+        .query_wasm_smart::<PositionResponse>(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: trader.to_string(),
+            },
+        )
+        .map_or(Uint128::zero(), |position| position.size)
+}
+
+fn notional_cap(env: &setup::TestingEnv) -> Uint128 {
+    let res: TradingPermissionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::TradingPermission {
+                trader: env.alice.to_string(),
+                delegate: env.bob.to_string(),
+            },
+        )
+        .unwrap();
+    res.notional_cap
+}
+
+#[test]
+fn test_delegate_trades_within_its_cap() {
+    let mut env = setup::setup();
+    let usdc = Cw20Contract(env.usdc.addr.clone());
+
+    // bob cannot trade for alice until she grants him permission
+    let err = open_for_alice(&mut env).unwrap_err();
+    assert_eq!(err, "Unauthorized");
+
+    let msg = ExecuteMsg::GrantTradingPermission {
+        delegate: env.bob.to_string(),
+        notional_cap: to_decimals(80),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the margin comes from alice and the position is hers
+    let alice_balance = usdc.balance(&env.router, env.alice.clone()).unwrap();
+    let bob_balance = usdc.balance(&env.router, env.bob.clone()).unwrap();
+    open_for_alice(&mut env).unwrap();
+    assert_eq!(
+        usdc.balance(&env.router, env.alice.clone()).unwrap(),
+        alice_balance - to_decimals(10)
+    );
+    assert_eq!(
+        usdc.balance(&env.router, env.bob.clone()).unwrap(),
+        bob_balance
+    );
+    assert!(!size(&env, &env.alice).is_zero());
+    assert!(size(&env, &env.bob).is_zero());
+    assert_eq!(notional_cap(&env), to_decimals(30));
+
+    // a second 50 notional is beyond what is left
+    let err = open_for_alice(&mut env).unwrap_err();
+    assert_eq!(
+        err,
+        format!(
+            "Notional exceeds what is left of the trading permission, {}",
+            to_decimals(30)
+        )
+    );
+
+    // closing spends nothing and pays out to alice
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: None,
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: Some(env.alice.to_string()),
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert!(size(&env, &env.alice).is_zero());
+    assert_eq!(
+        usdc.balance(&env.router, env.bob.clone()).unwrap(),
+        bob_balance
+    );
+    assert_eq!(notional_cap(&env), to_decimals(30));
+
+    // once revoked bob can no longer trade for alice
+    let msg = ExecuteMsg::RevokeTradingPermission {
+        delegate: env.bob.to_string(),
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(notional_cap(&env), Uint128::zero());
+    let err = open_for_alice(&mut env).unwrap_err();
+    assert_eq!(err, "Unauthorized");
+}
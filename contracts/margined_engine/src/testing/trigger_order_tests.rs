@@ -0,0 +1,322 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::Uint128;
+use cw20::Cw20ExecuteMsg;
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    ExecuteMsg, PositionResponse, QueryMsg, Side, TriggerOrderType, TriggerOrdersResponse,
+};
+use margined_perp::margined_vamm::QueryMsg as VammQueryMsg;
+
+// alice goes long 100 notional, pushing the price to around 12.1
+fn setup_position() -> setup::TestingEnv {
+    let mut env = setup::setup();
+
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::BUY,
+        quote_asset_amount: to_decimals(20),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    env
+}
+
+fn set_trigger_order(
+    env: &mut setup::TestingEnv,
+    trigger_price: u64,
+    order_type: TriggerOrderType,
+) -> Result<(), String> {
+    let msg = ExecuteMsg::SetTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        trigger_price: to_decimals(trigger_price),
+        order_type,
+        size: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn execute_trigger_order(env: &mut setup::TestingEnv) -> Result<(), String> {
+    let msg = ExecuteMsg::ExecuteTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        trader: env.alice.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+fn trigger_orders(env: &setup::TestingEnv) -> TriggerOrdersResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::TriggerOrders {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_trigger_orders_must_not_execute_immediately() {
+    let mut env = setup_position();
+
+    let err = set_trigger_order(&mut env, 13, TriggerOrderType::StopLoss).unwrap_err();
+    assert_eq!(
+        err,
+        "Generic error: trigger price would execute immediately"
+    );
+    let err = set_trigger_order(&mut env, 11, TriggerOrderType::TakeProfit).unwrap_err();
+    assert_eq!(
+        err,
+        "Generic error: trigger price would execute immediately"
+    );
+
+    set_trigger_order(&mut env, 11, TriggerOrderType::StopLoss).unwrap();
+    set_trigger_order(&mut env, 15, TriggerOrderType::TakeProfit).unwrap();
+    assert_eq!(
+        trigger_orders(&env),
+        TriggerOrdersResponse {
+            stop_loss: Some(to_decimals(11)),
+            take_profit: Some(to_decimals(15)),
+            stop_loss_size: None,
+            take_profit_size: None,
+            linked: false,
+        }
+    );
+
+    // the orders can be cancelled one at a time
+    let msg = ExecuteMsg::CancelTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        order_type: TriggerOrderType::TakeProfit,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert_eq!(trigger_orders(&env).take_profit, None);
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+}
+
+#[test]
+fn test_stop_loss_closes_position_once_reached() {
+    let mut env = setup_position();
+    set_trigger_order(&mut env, 11, TriggerOrderType::StopLoss).unwrap();
+
+    let err = execute_trigger_order(&mut env).unwrap_err();
+    assert_eq!(err, "Generic error: no trigger order has been reached");
+
+    // bob's short takes the price back down to 10
+    let msg = Cw20ExecuteMsg::IncreaseAllowance {
+        spender: env.engine.addr.to_string(),
+        amount: to_decimals(20),
+        expires: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.usdc.addr.clone(), &msg, &[])
+        .unwrap();
+    let msg = ExecuteMsg::OpenPosition {
+        vamm: env.vamm.addr.to_string(),
+        side: Side::SELL,
+        quote_asset_amount: to_decimals(20),
+        leverage: to_decimals(5),
+        base_asset_limit: None,
+        reduce_only: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(env.bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    execute_trigger_order(&mut env).unwrap();
+
+    let position: PositionResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::Position {
+                vamm: env.vamm.addr.to_string(),
+                trader: env.alice.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(position.size, Uint128::zero());
+    assert_eq!(trigger_orders(&env).stop_loss, None);
+}
+
+#[test]
+fn test_paired_orders_cancel_each_other() {
+    let mut env = setup_position();
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+    let size = env.position(&alice).size;
+
+    // the pair closes half the position
+    let msg = ExecuteMsg::SetTriggerOrderPair {
+        vamm: env.vamm.addr.to_string(),
+        stop_loss: to_decimals(11),
+        take_profit: to_decimals(15),
+        size: Some(size.multiply_ratio(1u128, 2u128)),
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    assert!(trigger_orders(&env).linked);
+
+    // bob's short takes the price back down to 10 and the stop loss executes,
+    // cancelling the take profit with it
+    env.open_position(&bob, Side::SELL, 20, 5).unwrap();
+    execute_trigger_order(&mut env).unwrap();
+    assert_eq!(
+        env.position(&alice).size,
+        size - size.multiply_ratio(1u128, 2u128)
+    );
+    assert_eq!(
+        trigger_orders(&env),
+        TriggerOrdersResponse {
+            stop_loss: None,
+            take_profit: None,
+            stop_loss_size: None,
+            take_profit_size: None,
+            linked: false,
+        }
+    );
+    assert_eq!(
+        execute_trigger_order(&mut env).unwrap_err(),
+        "Generic error: no trigger order has been reached"
+    );
+}
+
+#[test]
+fn test_unpaired_orders_are_left_standing() {
+    let mut env = setup_position();
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+    let size = env.position(&alice).size;
+
+    let msg = ExecuteMsg::SetTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        trigger_price: to_decimals(11),
+        order_type: TriggerOrderType::StopLoss,
+        size: Some(size.multiply_ratio(1u128, 2u128)),
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    set_trigger_order(&mut env, 15, TriggerOrderType::TakeProfit).unwrap();
+
+    // a size beyond the position cannot be closed
+    let msg = ExecuteMsg::SetTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        trigger_price: to_decimals(11),
+        order_type: TriggerOrderType::StopLoss,
+        size: Some(size + Uint128::from(1u128)),
+    };
+    assert_eq!(
+        env.router
+            .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+            .unwrap_err()
+            .to_string(),
+        "Invalid close size"
+    );
+
+    env.open_position(&bob, Side::SELL, 20, 5).unwrap();
+    execute_trigger_order(&mut env).unwrap();
+    let orders = trigger_orders(&env);
+    assert_eq!(orders.stop_loss, None);
+    assert_eq!(orders.take_profit, Some(to_decimals(15)));
+    assert!(!env.position(&alice).size.is_zero());
+}
+
+#[test]
+fn test_opposing_orders_are_matched_at_the_spot_price() {
+    let mut env = setup_position();
+    let (alice, bob) = (env.alice.clone(), env.bob.clone());
+    env.open_position(&bob, Side::SELL, 12, 5).unwrap();
+    let bob_size = env.position(&bob).size;
+
+    // bob's take profit and alice's stop loss for the same size are both
+    // reached once alice's partial close takes the price below 10.5
+    let msg = ExecuteMsg::SetTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        trigger_price: Uint128::from(10_500_000_000u128),
+        order_type: TriggerOrderType::TakeProfit,
+        size: None,
+    };
+    env.router
+        .execute_contract(bob.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let msg = ExecuteMsg::SetTriggerOrder {
+        vamm: env.vamm.addr.to_string(),
+        trigger_price: Uint128::from(10_500_000_000u128),
+        order_type: TriggerOrderType::StopLoss,
+        size: Some(bob_size),
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let msg = ExecuteMsg::ClosePosition {
+        vamm: env.vamm.addr.to_string(),
+        size: Some(to_decimals(2)),
+        quote_asset_limit: None,
+        partial: false,
+        deadline: None,
+        trader: None,
+    };
+    env.router
+        .execute_contract(alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let alice_size = env.position(&alice).size;
+
+    // the orders must close opposite sides
+    let msg = ExecuteMsg::MatchTriggerOrders {
+        vamm: env.vamm.addr.to_string(),
+        long: bob.to_string(),
+        short: alice.to_string(),
+    };
+    assert_eq!(
+        env.router
+            .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+            .unwrap_err()
+            .to_string(),
+        "Generic error: orders must close a long and a short"
+    );
+
+    let spot_price: Uint128 = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.vamm.addr, &VammQueryMsg::SpotPrice {})
+        .unwrap();
+    let msg = ExecuteMsg::MatchTriggerOrders {
+        vamm: env.vamm.addr.to_string(),
+        long: alice.to_string(),
+        short: bob.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    // the matched size never reached the vamm, so its price is unmoved
+    let spot_price_after: Uint128 = env
+        .router
+        .wrap()
+        .query_wasm_smart(&env.vamm.addr, &VammQueryMsg::SpotPrice {})
+        .unwrap();
+    assert_eq!(spot_price_after, spot_price);
+    assert_eq!(env.position(&bob).size, Uint128::zero());
+    assert_eq!(env.position(&alice).size, alice_size - bob_size);
+}
@@ -0,0 +1,187 @@
+use crate::testing::setup::{self, to_decimals};
+use cosmwasm_std::{Addr, Uint128};
+use cw_multi_test::Executor;
+use margined_perp::margined_engine::{
+    AllVammsResponse, ExecuteMsg, QueryMsg, RiskParams, RiskParamsResponse, Side,
+};
+use margined_perp::margined_vamm::InstantiateMsg as VammInstantiateMsg;
+
+fn instantiate_vamm(env: &mut setup::TestingEnv) -> Addr {
+    env.router
+        .instantiate_contract(
+            env.vamm.id,
+            env.owner.clone(),
+            &VammInstantiateMsg {
+                decimals: 9u8,
+                quote_asset: "BTC".to_string(),
+                base_asset: "USD".to_string(),
+                quote_asset_reserve: to_decimals(1_000),
+                base_asset_reserve: to_decimals(100),
+                funding_period: 3_600 as u64,
+                funding_buffer_period: 1_800_u64,
+                toll_ratio: Uint128::zero(),
+                spread_ratio: Uint128::zero(),
+                engine: Some(env.engine.addr.to_string()),
+            },
+            &[],
+            "vamm",
+            None,
+        )
+        .unwrap()
+}
+
+fn all_vamms(env: &setup::TestingEnv, start_after: Option<String>) -> AllVammsResponse {
+    env.router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::AllVamms {
+                start_after,
+                limit: Some(1),
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_add_and_remove_vamm() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    let vamm = instantiate_vamm(&mut env);
+
+    assert_eq!(
+        env.open_position_with(
+            &vamm,
+            &alice,
+            Side::BUY,
+            to_decimals(10),
+            to_decimals(5),
+            None,
+            false
+        )
+        .unwrap_err(),
+        "vAMM is not registered"
+    );
+
+    let msg = ExecuteMsg::AddVamm {
+        vamm: vamm.to_string(),
+        risk_params: None,
+    };
+    env.router
+        .execute_contract(env.alice.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    // both markets are listed a page at a time
+    let page = all_vamms(&env, None);
+    assert_eq!(page.vamms.len(), 1);
+    let next = all_vamms(&env, page.next_key.map(|key| key.to_string()));
+    let mut vamms = vec![page.vamms[0].clone(), next.vamms[0].clone()];
+    vamms.sort();
+    let mut expected = vec![env.vamm.addr.clone(), vamm.clone()];
+    expected.sort();
+    assert_eq!(vamms, expected);
+
+    env.open_position_with(
+        &vamm,
+        &alice,
+        Side::BUY,
+        to_decimals(10),
+        to_decimals(5),
+        None,
+        false,
+    )
+    .unwrap();
+
+    // a market with open positions cannot be removed
+    let msg = ExecuteMsg::RemoveVamm {
+        vamm: vamm.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap_err();
+
+    let msg = ExecuteMsg::RemoveVamm {
+        vamm: env.vamm.addr.to_string(),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+    let removed = env.vamm.addr.clone();
+    assert_eq!(
+        env.open_position_with(
+            &removed,
+            &alice,
+            Side::BUY,
+            to_decimals(10),
+            to_decimals(5),
+            None,
+            false
+        )
+        .unwrap_err(),
+        "vAMM is not registered"
+    );
+}
+
+#[test]
+fn test_add_vamm_with_risk_params() {
+    let mut env = setup::setup();
+    let alice = env.alice.clone();
+    let vamm = instantiate_vamm(&mut env);
+
+    // the market is registered capped at 4x
+    let msg = ExecuteMsg::AddVamm {
+        vamm: vamm.to_string(),
+        risk_params: Some(RiskParams {
+            max_leverage: Some(to_decimals(4)),
+            ..RiskParams::default()
+        }),
+    };
+    env.router
+        .execute_contract(env.owner.clone(), env.engine.addr.clone(), &msg, &[])
+        .unwrap();
+
+    let params: RiskParamsResponse = env
+        .router
+        .wrap()
+        .query_wasm_smart(
+            &env.engine.addr,
+            &QueryMsg::RiskParams {
+                vamm: vamm.to_string(),
+            },
+        )
+        .unwrap();
+    assert_eq!(params.max_leverage, Some(to_decimals(4)));
+
+    assert_eq!(
+        env.open_position_with(
+            &vamm,
+            &alice,
+            Side::BUY,
+            to_decimals(10),
+            to_decimals(5),
+            None,
+            false
+        )
+        .unwrap_err(),
+        "Leverage exceeds the maximum of 4000000000 for this position size"
+    );
+
+    // the rest of the markets keep the config's
+    let other = env.vamm.addr.clone();
+    env.open_position_with(
+        &other,
+        &alice,
+        Side::BUY,
+        to_decimals(10),
+        to_decimals(5),
+        None,
+        false,
+    )
+    .unwrap();
+}
@@ -0,0 +1,128 @@
+// Limits the collateral paid out of the engine in each block or hour, a last
+// line of defense against a pricing bug draining it at once. Each asset is
+// limited on its own, by a share of what is held of it
+use cosmwasm_std::{Env, Order, StdResult, Storage, Uint128};
+use cw_storage_plus::{Item, Map};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ContractError,
+    state::{read_config, read_total_deposits, read_total_margin},
+};
+use margined_perp::asset::AssetInfo;
+use margined_perp::margined_engine::{ThrottlePeriod, WithdrawalThrottle};
+
+pub const WITHDRAWAL_THROTTLE: Item<WithdrawalThrottle> = Item::new("withdrawal_throttle");
+pub const OUTFLOWS: Map<&str, Outflow> = Map::new("outflows");
+
+const SECONDS_PER_HOUR: u64 = 3_600;
+
+// what has been paid out of an asset in a period, the period is a block height or an
+// hour since the epoch
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Outflow {
+    pub period: u64,
+    pub limit: Uint128,
+    pub paid: Uint128,
+}
+
+pub fn store_withdrawal_throttle(
+    storage: &mut dyn Storage,
+    throttle: Option<&WithdrawalThrottle>,
+) -> StdResult<()> {
+    // the next payout of each asset opens a period under the new limit
+    let assets = OUTFLOWS
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<Vec<_>>();
+    for asset in assets {
+        OUTFLOWS.remove(storage, &String::from_utf8_lossy(&asset));
+    }
+
+    match throttle {
+        Some(throttle) => WITHDRAWAL_THROTTLE.save(storage, throttle),
+        None => {
+            WITHDRAWAL_THROTTLE.remove(storage);
+            Ok(())
+        }
+    }
+}
+
+pub fn read_withdrawal_throttle(storage: &dyn Storage) -> StdResult<Option<WithdrawalThrottle>> {
+    WITHDRAWAL_THROTTLE.may_load(storage)
+}
+
+fn current_period(env: &Env, throttle: &WithdrawalThrottle) -> u64 {
+    match throttle.period {
+        ThrottlePeriod::Block => env.block.height,
+        ThrottlePeriod::Hour => env.block.time.seconds() / SECONDS_PER_HOUR,
+    }
+}
+
+// returns the asset's outflow of the current period, a new period is limited
+// to the share of the asset's margin and deposits held as it opens, including
+// any payout that has been taken off them but not yet sent
+fn current_outflow(
+    storage: &dyn Storage,
+    env: &Env,
+    throttle: &WithdrawalThrottle,
+    asset: &AssetInfo,
+    pending: Uint128,
+) -> StdResult<Outflow> {
+    let period = current_period(env, throttle);
+    match OUTFLOWS.may_load(storage, &asset.to_string())? {
+        Some(outflow) if outflow.period == period => Ok(outflow),
+        _ => {
+            let decimals = read_config(storage)?.decimals;
+            let held = read_total_margin(storage, asset)?
+                .checked_add(read_total_deposits(storage, asset)?)?
+                .checked_add(pending)?;
+            Ok(Outflow {
+                period,
+                limit: held.multiply_ratio(throttle.limit_ratio, decimals),
+                paid: Uint128::zero(),
+            })
+        }
+    }
+}
+
+// returns what can still be paid out of the asset in the current period, None
+// if there is no throttle
+pub fn remaining_outflow(
+    storage: &dyn Storage,
+    env: &Env,
+    asset: &AssetInfo,
+) -> StdResult<Option<Uint128>> {
+    let throttle = match read_withdrawal_throttle(storage)? {
+        Some(throttle) => throttle,
+        None => return Ok(None),
+    };
+    let outflow = current_outflow(storage, env, &throttle, asset, Uint128::zero())?;
+
+    Ok(Some(outflow.limit.saturating_sub(outflow.paid)))
+}
+
+// records a payout of the asset to a trader once it has been taken off their
+// margin or deposits, rejecting it if it would take the period over its limit
+pub fn record_outflow(
+    storage: &mut dyn Storage,
+    env: &Env,
+    asset: &AssetInfo,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let throttle = match read_withdrawal_throttle(storage)? {
+        Some(throttle) => throttle,
+        None => return Ok(()),
+    };
+
+    let mut outflow = current_outflow(storage, env, &throttle, asset, amount)?;
+    let paid = outflow.paid.checked_add(amount)?;
+    if paid > outflow.limit {
+        return Err(ContractError::WithdrawalThrottled {
+            remaining: outflow.limit.saturating_sub(outflow.paid),
+        });
+    }
+    outflow.paid = paid;
+
+    Ok(OUTFLOWS.save(storage, &asset.to_string(), &outflow)?)
+}
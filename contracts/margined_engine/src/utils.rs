@@ -1,14 +1,1286 @@
-use cosmwasm_std::{Addr, Response, StdError, StdResult, Storage};
+use cosmwasm_std::{
+    Addr, Api, Binary, Coin, Deps, Env, MessageInfo, Response, StdError, StdResult, Storage,
+    Timestamp, Uint128,
+};
+use sha2::{Digest as _, Sha256};
+use sha3::{Digest, Sha3_256};
 
-use crate::state::{read_vamm, VammList};
-use margined_perp::margined_engine::Side;
-use margined_perp::margined_vamm::Direction;
+use crate::{
+    error::ContractError,
+    querier::{
+        query_pricefeed_config, query_pricefeed_price, query_pricefeed_twap_price, query_staked,
+        query_vamm_calc_fee, query_vamm_config, query_vamm_liquidity_history,
+        query_vamm_output_price, query_vamm_output_twap, query_vamm_settlement_price,
+        query_vamm_spot_price, query_vamm_state, query_vamm_twap_price,
+    },
+    state::{
+        has_market_collateral, is_vamm, read_account_settings, read_all_vamms,
+        read_collateral_asset, read_collateral_balances, read_commit_reveal, read_config,
+        read_fee_campaigns, read_fee_pool, read_fee_tiers, read_funding, read_insurance_fund,
+        read_margin_tiers, read_market_fees, read_open_interest, read_order_nonce,
+        read_paused_operations, read_position, read_risk_params, read_signing_key,
+        read_trading_permission, read_vamm_decimals, read_vamm_positions, read_wrapped_position,
+        remove_queued_order, remove_trigger_orders, store_order_nonce, store_trading_permission,
+        Config, Position, Settlement, SECONDS_PER_DAY,
+    },
+};
+use margined_perp::asset::AssetInfo;
+use margined_perp::decimals::{div_d, mul_d, sqrt};
+use margined_perp::events::PositionChange;
+use margined_perp::integer::Integer;
+use margined_perp::margined_engine::{
+    CollateralAsset, InsuranceFloor, MarginRequirementResponse, PausableOperation, PauseLevel,
+    PnlCalcOption, RiskParams, RiskParamsResponse, Side, SignedOrder, TradeKind, TradeRecord,
+    TriggerOrderType,
+};
+use margined_perp::margined_vamm::{CalcFeeResponse, Direction};
 
-pub fn require_vamm(storage: &dyn Storage, vamm: &Addr) -> StdResult<Response> {
+pub fn require_vamm(storage: &dyn Storage, vamm: &Addr) -> Result<Response, ContractError> {
     // check that it is a registered vamm
-    let vamm_list: VammList = read_vamm(storage)?;
-    if !vamm_list.is_vamm(&vamm.to_string()) {
-        return Err(StdError::generic_err("vAMM is not registered"));
+    if !is_vamm(storage, vamm) {
+        return Err(ContractError::VammNotRegistered {});
+    }
+
+    Ok(Response::new())
+}
+
+// the window over which the vamm's exit price is averaged when valuing positions
+const PNL_TWAP_INTERVAL: u64 = 15 * 60;
+
+// every pausable operation, in bitmap order
+const PAUSABLE_OPERATIONS: [PausableOperation; 6] = [
+    PausableOperation::Open,
+    PausableOperation::Close,
+    PausableOperation::AddMargin,
+    PausableOperation::RemoveMargin,
+    PausableOperation::Liquidate,
+    PausableOperation::Funding,
+];
+
+// returns the bit representing the operation in the paused bitmap
+pub fn pause_mask(operation: &PausableOperation) -> u8 {
+    let index = PAUSABLE_OPERATIONS
+        .iter()
+        .position(|op| op == operation)
+        .unwrap();
+    1 << index
+}
+
+// returns the paused bitmap of every operation the pause level halts
+pub fn pause_level_mask(level: &PauseLevel) -> u8 {
+    let allowed = match level {
+        PauseLevel::Full => vec![],
+        PauseLevel::CloseOnly => vec![PausableOperation::Close],
+        PauseLevel::WithdrawOnly => {
+            vec![PausableOperation::Close, PausableOperation::RemoveMargin]
+        }
+    };
+
+    PAUSABLE_OPERATIONS
+        .iter()
+        .filter(|op| !allowed.contains(op))
+        .fold(0u8, |bitmap, op| bitmap | pause_mask(op))
+}
+
+// expands the paused bitmap into the list of paused operations
+pub fn paused_operations(paused: u8) -> Vec<PausableOperation> {
+    PAUSABLE_OPERATIONS
+        .iter()
+        .filter(|op| paused & pause_mask(op) != 0)
+        .cloned()
+        .collect()
+}
+
+pub fn require_not_paused(
+    storage: &dyn Storage,
+    operation: PausableOperation,
+) -> Result<Response, ContractError> {
+    if read_paused_operations(storage)? & pause_mask(&operation) != 0 {
+        return Err(ContractError::Paused { operation });
+    }
+
+    Ok(Response::new())
+}
+
+// the margin ratios and liquidation fee are ratios, i.e. 100% is equal to the
+// decimals, and positions must be opened with more margin than they are
+// liquidated below
+pub fn validate_risk_params(config: &Config) -> StdResult<()> {
+    if config.initial_margin_ratio > config.decimals || config.liquidation_fee > config.decimals {
+        return Err(StdError::generic_err(
+            "margin ratios and liquidation fee cannot exceed 100%",
+        ));
+    }
+
+    if config.maintenance_margin_ratio > config.initial_margin_ratio {
+        return Err(StdError::generic_err(
+            "maintenance margin ratio cannot exceed the initial margin ratio",
+        ));
+    }
+
+    Ok(())
+}
+
+// a market's overrides are held to the same bounds as the config once they
+// are applied over it
+pub fn validate_market_risk_params(config: &Config, params: &RiskParams) -> StdResult<()> {
+    let mut config = config.clone();
+    config.initial_margin_ratio = params
+        .initial_margin_ratio
+        .unwrap_or(config.initial_margin_ratio);
+    config.maintenance_margin_ratio = params
+        .maintenance_margin_ratio
+        .unwrap_or(config.maintenance_margin_ratio);
+    config.liquidation_fee = params.liquidation_fee.unwrap_or(config.liquidation_fee);
+    validate_risk_params(&config)?;
+
+    if params.max_leverage == Some(Uint128::zero()) {
+        return Err(StdError::generic_err(
+            "max leverage must be greater than zero",
+        ));
+    }
+
+    Ok(())
+}
+
+// returns the risk parameters in force in a market, i.e. its overrides with
+// the config filling in the rest
+pub fn risk_params(storage: &dyn Storage, vamm: &Addr) -> StdResult<RiskParamsResponse> {
+    let config = read_config(storage)?;
+    let params = read_risk_params(storage, vamm)?;
+
+    Ok(RiskParamsResponse {
+        initial_margin_ratio: params
+            .initial_margin_ratio
+            .unwrap_or(config.initial_margin_ratio),
+        maintenance_margin_ratio: params
+            .maintenance_margin_ratio
+            .unwrap_or(config.maintenance_margin_ratio),
+        liquidation_fee: params.liquidation_fee.unwrap_or(config.liquidation_fee),
+        max_leverage: params.max_leverage,
+    })
+}
+
+// the eligible collateral can only change while nothing is held in it, i.e.
+// the fee pool and insurance fund are empty and markets without their own
+// collateral have no open positions
+pub fn require_eligible_collateral_unused(storage: &dyn Storage) -> StdResult<()> {
+    let err = StdError::generic_err("cannot change the eligible collateral while it is in use");
+    if !read_fee_pool(storage)?.balance.is_zero()
+        || !read_insurance_fund(storage)?.total()?.is_zero()
+    {
+        return Err(err);
+    }
+
+    for vamm in read_all_vamms(storage)? {
+        if !has_market_collateral(storage, &vamm) && !read_open_interest(storage, &vamm)?.is_zero()
+        {
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+// the operator pauses the engine and adds or removes vamms, the owner does so
+// until an operator is appointed
+pub fn require_operator(storage: &dyn Storage, sender: &Addr) -> Result<(), ContractError> {
+    let config = read_config(storage)?;
+    if sender != config.operator.as_ref().unwrap_or(&config.owner) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    Ok(())
+}
+
+// returns who an order trades for, the sender unless it names a trader that
+// granted it permission to trade for them. The notional a delegate opens is
+// spent from its cap
+pub fn resolve_trader(
+    api: &dyn Api,
+    storage: &mut dyn Storage,
+    sender: &Addr,
+    trader: Option<String>,
+    notional: Uint128,
+) -> Result<Addr, ContractError> {
+    let trader = match trader {
+        Some(trader) => api.addr_validate(&trader)?,
+        None => return Ok(sender.clone()),
+    };
+    if trader == *sender {
+        return Ok(trader);
+    }
+
+    let notional_cap =
+        read_trading_permission(storage, &trader, sender)?.ok_or(ContractError::Unauthorized {})?;
+    let remaining = notional_cap
+        .checked_sub(notional)
+        .map_err(|_| ContractError::TradingPermissionCap { notional_cap })?;
+    store_trading_permission(storage, &trader, sender, remaining)?;
+
+    Ok(trader)
+}
+
+// encodes an order for its trader to sign, along with the engine so that it
+// cannot be replayed against another deployment. Optional fields are prefixed
+// with whether they are set so that no two orders encode the same
+pub fn signed_order_bytes(engine: &Addr, order: &SignedOrder) -> Vec<u8> {
+    let mut bytes = vec![];
+
+    for field in [engine.as_str(), &order.vamm, &order.trader] {
+        bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(field.as_bytes());
+    }
+    bytes.push(match order.side {
+        Side::BUY => 0,
+        Side::SELL => 1,
+    });
+    bytes.extend_from_slice(&order.quote_asset_amount.u128().to_be_bytes());
+    bytes.extend_from_slice(&order.leverage.u128().to_be_bytes());
+    match order.base_asset_limit {
+        Some(limit) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&limit.u128().to_be_bytes());
+        }
+        None => bytes.push(0),
+    }
+    bytes.push(order.reduce_only as u8);
+    match order.deadline {
+        Some(deadline) => {
+            bytes.push(1);
+            bytes.extend_from_slice(&deadline.nanos().to_be_bytes());
+        }
+        None => bytes.push(0),
+    }
+    bytes.extend_from_slice(&order.nonce.to_be_bytes());
+
+    bytes
+}
+
+// checks an order was signed by its trader's signing key and carries their
+// next nonce, which it spends, returning the trader it opens for
+pub fn verify_signed_order(
+    api: &dyn Api,
+    storage: &mut dyn Storage,
+    env: &Env,
+    order: &SignedOrder,
+    pubkey: &Binary,
+    signature: &Binary,
+) -> Result<Addr, ContractError> {
+    let trader = api.addr_validate(&order.trader)?;
+    if read_signing_key(storage, &trader)?.as_ref() != Some(pubkey) {
+        return Err(ContractError::InvalidSignature {});
+    }
+
+    // the trader signs the sha256 hash of the encoded order
+    let hash = Sha256::digest(&signed_order_bytes(&env.contract.address, order));
+    if !api
+        .secp256k1_verify(&hash, signature, pubkey)
+        .unwrap_or(false)
+    {
+        return Err(ContractError::InvalidSignature {});
+    }
+
+    let nonce = read_order_nonce(storage, &trader)?;
+    if order.nonce != nonce {
+        return Err(ContractError::InvalidNonce { nonce });
+    }
+    store_order_nonce(storage, &trader, nonce + 1)?;
+
+    Ok(trader)
+}
+
+// orders that lingered until after their deadline are not executed, they
+// would trade at a price the trader never saw
+pub fn require_deadline(env: &Env, deadline: Option<Timestamp>) -> Result<(), ContractError> {
+    match deadline {
+        Some(deadline) if env.block.time > deadline => {
+            Err(ContractError::DeadlineExpired { deadline })
+        }
+        _ => Ok(()),
+    }
+}
+
+// orders on markets with commit-reveal enabled must be committed first
+pub fn require_direct_orders(storage: &dyn Storage, vamm: &Addr) -> StdResult<Response> {
+    if read_commit_reveal(storage, vamm)?.is_some() {
+        return Err(StdError::generic_err(
+            "orders on this market must be committed and revealed",
+        ));
+    }
+
+    Ok(Response::new())
+}
+
+// hashes the order with the trader and a secret salt, so that the commitment
+// reveals nothing about the order until it is revealed. It is encoded as signed
+// orders are, so that no two orders hash the same
+#[allow(clippy::too_many_arguments)]
+pub fn order_commitment_hash(
+    vamm: &Addr,
+    trader: &Addr,
+    side: &Side,
+    quote_asset_amount: Uint128,
+    leverage: Uint128,
+    base_asset_limit: Option<Uint128>,
+    salt: &str,
+) -> Binary {
+    let mut hasher = Sha3_256::new();
+
+    for field in [vamm.as_str(), trader.as_str()] {
+        hasher.update((field.len() as u32).to_be_bytes());
+        hasher.update(field.as_bytes());
+    }
+    hasher.update([match side {
+        Side::BUY => 0,
+        Side::SELL => 1,
+    }]);
+    hasher.update(quote_asset_amount.u128().to_be_bytes());
+    hasher.update(leverage.u128().to_be_bytes());
+    match base_asset_limit {
+        Some(limit) => {
+            hasher.update([1]);
+            hasher.update(limit.u128().to_be_bytes());
+        }
+        None => hasher.update([0]),
+    }
+    hasher.update((salt.len() as u32).to_be_bytes());
+    hasher.update(salt.as_bytes());
+
+    Binary::from(hasher.finalize().to_vec())
+}
+
+// positions wrapped as a token can only be traded once unwrapped by the holder
+pub fn require_unwrapped(storage: &dyn Storage, vamm: &Addr, trader: &Addr) -> StdResult<()> {
+    if read_wrapped_position(storage, vamm, trader)?.is_some() {
+        return Err(StdError::generic_err(
+            "position is wrapped as a token, unwrap it to trade",
+        ));
+    }
+
+    Ok(())
+}
+
+// native collateral must be sent with the message in exactly the amount due,
+// cw20 collateral is drawn from an allowance so nothing may be sent
+pub fn require_funds(
+    info: &MessageInfo,
+    collateral: &AssetInfo,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    let expected = match collateral {
+        AssetInfo::NativeToken { denom } if !amount.is_zero() => vec![Coin {
+            denom: denom.to_string(),
+            amount,
+        }],
+        _ => vec![],
+    };
+
+    if info.funds != expected {
+        return Err(ContractError::InvalidFunds {
+            expected: match expected.first() {
+                Some(coin) => format!("{}{}", coin.amount, coin.denom),
+                None => "none".to_string(),
+            },
+        });
+    }
+
+    Ok(())
+}
+
+// describes the change a swap, liquidation or funding payment made to a
+// position, with nothing yet realized, owed or charged
+pub fn position_changed(before: &Position, after: &Position) -> PositionChange {
+    PositionChange {
+        vamm: after.vamm.clone(),
+        trader: after.trader.clone(),
+        side: direction_to_side(after.direction.clone()),
+        size: after.size,
+        size_delta: Integer::difference(after.size, before.size),
+        margin: after.margin,
+        margin_delta: Integer::difference(after.margin, before.margin),
+        notional: after.notional,
+        realized_pnl: Integer::zero(),
+        bad_debt: Uint128::zero(),
+        fee: Uint128::zero(),
+        funding_payment: Integer::zero(),
+    }
+}
+
+// describes a trade of the position at the average price the size was
+// exchanged for, with nothing yet realized or charged. The trade is numbered
+// when it is recorded
+pub fn traded(
+    kind: TradeKind,
+    position: &Position,
+    size: Uint128,
+    notional: Uint128,
+    decimals: Uint128,
+    timestamp: Timestamp,
+) -> TradeRecord {
+    let price = if size.is_zero() {
+        Uint128::zero()
+    } else {
+        notional.multiply_ratio(decimals, size)
+    };
+
+    TradeRecord {
+        trade_id: 0,
+        trader: position.trader.clone(),
+        vamm: position.vamm.clone(),
+        kind,
+        side: direction_to_side(position.direction.clone()),
+        size,
+        price,
+        fee: Uint128::zero(),
+        realized_pnl: Integer::zero(),
+        timestamp,
+    }
+}
+
+// cancels the orders resting against a position once it is fully closed, so
+// they cannot reopen exposure the trader has already exited
+pub fn cancel_resting_orders(storage: &mut dyn Storage, vamm: &Addr, trader: &Addr) {
+    remove_queued_order(storage, vamm, trader);
+    remove_trigger_orders(storage, vamm, trader);
+}
+
+// whether the price has reached the trigger of an order against a position
+pub fn is_triggered(
+    direction: &Direction,
+    order_type: &TriggerOrderType,
+    trigger_price: Uint128,
+    price: Uint128,
+) -> bool {
+    match (direction, order_type) {
+        (Direction::AddToAmm, TriggerOrderType::StopLoss)
+        | (Direction::RemoveFromAmm, TriggerOrderType::TakeProfit) => price <= trigger_price,
+        (Direction::AddToAmm, TriggerOrderType::TakeProfit)
+        | (Direction::RemoveFromAmm, TriggerOrderType::StopLoss) => price >= trigger_price,
+    }
+}
+
+// resizes a position opened before changes to k in its vamm, so that closing
+// it against the new reserves returns what closing it against the reserves
+// before each change would have. A closed position has nothing to resize and
+// moves straight to the latest change
+pub fn migrate_liquidity(deps: Deps, mut position: Position) -> StdResult<Position> {
+    let start_after = position.liquidity_history_index.u128() as u64;
+    let history = query_vamm_liquidity_history(deps, position.vamm.to_string(), Some(start_after))?;
+
+    if position.size.is_zero() {
+        position.liquidity_history_index = Uint128::from(history.latest_index);
+        return Ok(position);
+    }
+
+    let mut size = to_vamm_decimals(deps.storage, &position.vamm, position.size)?;
+    for snapshot in history.snapshots {
+        // closing a long adds its base to the amm, closing a short removes it
+        size = match position.direction {
+            Direction::AddToAmm => {
+                let notional = snapshot
+                    .quote_asset_reserve_before
+                    .multiply_ratio(size, snapshot.base_asset_reserve_before.checked_add(size)?);
+                snapshot.base_asset_reserve.multiply_ratio(
+                    notional,
+                    snapshot.quote_asset_reserve.checked_sub(notional)?,
+                )
+            }
+            Direction::RemoveFromAmm => {
+                let notional = snapshot
+                    .quote_asset_reserve_before
+                    .multiply_ratio(size, snapshot.base_asset_reserve_before.checked_sub(size)?);
+                snapshot.base_asset_reserve.multiply_ratio(
+                    notional,
+                    snapshot.quote_asset_reserve.checked_add(notional)?,
+                )
+            }
+        };
+        position.liquidity_history_index = Uint128::from(snapshot.index);
+    }
+    position.size = from_vamm_decimals(deps.storage, &position.vamm, size)?;
+
+    Ok(position)
+}
+
+// Splits the PnL of a position between the size closed for the exchanged
+// notional and the remainder. The position is valued at the exchanged notional
+// plus the remaining value, of which the closed share is realized. Returns the
+// realized PnL and the open notional of the remainder, which keeps the rest of
+// the PnL unrealized
+pub fn split_pnl(
+    position: &Position,
+    size: Uint128,
+    exchanged: Uint128,
+    remaining_value: Uint128,
+) -> StdResult<(Integer, Uint128)> {
+    let pnl = unrealized_pnl(position, exchanged.checked_add(remaining_value)?);
+    let realized_pnl = pnl.multiply_ratio(size, position.size);
+    let remaining_pnl = pnl - realized_pnl;
+
+    let notional = match position.direction {
+        Direction::AddToAmm => Integer::from(remaining_value) - remaining_pnl,
+        Direction::RemoveFromAmm => Integer::from(remaining_value) + remaining_pnl,
+    };
+    if notional.is_negative() {
+        return Ok((realized_pnl, Uint128::zero()));
+    }
+
+    Ok((realized_pnl, notional.value()))
+}
+
+// the reserves of a vamm as a simulated order leaves them, in the engine's
+// decimals
+pub struct Reserves {
+    pub quote: Uint128,
+    pub base: Uint128,
+}
+
+impl Reserves {
+    pub fn query(deps: Deps, vamm: &Addr) -> StdResult<Self> {
+        let state = query_vamm_state(deps, vamm.to_string())?;
+
+        Ok(Reserves {
+            quote: from_vamm_decimals(deps.storage, vamm, state.quote_asset_reserve)?,
+            base: from_vamm_decimals(deps.storage, vamm, state.base_asset_reserve)?,
+        })
+    }
+
+    // opening a long adds its notional to the amm and takes base out, a short
+    // the reverse
+    pub fn open(
+        &mut self,
+        direction: &Direction,
+        notional: Uint128,
+        size: Uint128,
+    ) -> StdResult<()> {
+        match direction {
+            Direction::AddToAmm => {
+                self.quote = self.quote.checked_add(notional)?;
+                self.base = self.base.checked_sub(size)?;
+            }
+            Direction::RemoveFromAmm => {
+                self.quote = self.quote.checked_sub(notional)?;
+                self.base = self.base.checked_add(size)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // closing a position undoes opening it
+    pub fn close(
+        &mut self,
+        direction: &Direction,
+        notional: Uint128,
+        size: Uint128,
+    ) -> StdResult<()> {
+        match direction {
+            Direction::AddToAmm => self.open(&Direction::RemoveFromAmm, notional, size),
+            Direction::RemoveFromAmm => self.open(&Direction::AddToAmm, notional, size),
+        }
+    }
+
+    // the size opening the notional in the direction would buy
+    pub fn size_for(&self, direction: &Direction, notional: Uint128) -> StdResult<Uint128> {
+        let quote = match direction {
+            Direction::AddToAmm => self.quote.checked_add(notional)?,
+            Direction::RemoveFromAmm => self.quote.checked_sub(notional)?,
+        };
+
+        Ok(self.base.multiply_ratio(notional, quote))
+    }
+
+    // the notional closing the size in the direction would return
+    pub fn notional_for(&self, direction: &Direction, size: Uint128) -> StdResult<Uint128> {
+        let base = match direction {
+            Direction::AddToAmm => self.base.checked_add(size)?,
+            Direction::RemoveFromAmm => self.base.checked_sub(size)?,
+        };
+
+        Ok(self.quote.multiply_ratio(size, base))
+    }
+}
+
+// returns the mark price at which the position would fall to its maintenance
+// margin ratio, were the price moved there by other trades against the same
+// k. The margin is all that backs the position, including that shared with
+// the other notional of a cross margined account, which is held at its
+// current value. A long whose margin covers its notional is never liquidated
+// so has none, a short that is already liquidatable has zero
+pub fn liquidation_price(
+    storage: &dyn Storage,
+    position: &Position,
+    margin: Uint128,
+    other_notional: Uint128,
+    reserves: &Reserves,
+) -> StdResult<Option<Uint128>> {
+    let config = read_config(storage)?;
+    if position.size.is_zero() {
+        return Ok(None);
+    }
+
+    // the value the position closes for at its maintenance margin ratio
+    let maintenance_margin_ratio =
+        margin_requirement(storage, &position.vamm, position.notional)?.maintenance_margin_ratio;
+    let other_margin = other_notional.multiply_ratio(maintenance_margin_ratio, config.decimals);
+    let value = match position.direction {
+        Direction::AddToAmm => {
+            let value = position.notional.checked_add(other_margin)?;
+            if value <= margin {
+                return Ok(None);
+            }
+
+            value.checked_sub(margin)?.multiply_ratio(
+                config.decimals,
+                config.decimals.checked_sub(maintenance_margin_ratio)?,
+            )
+        }
+        Direction::RemoveFromAmm => position
+            .notional
+            .checked_add(margin)?
+            .saturating_sub(other_margin)
+            .multiply_ratio(
+                config.decimals,
+                config.decimals.checked_add(maintenance_margin_ratio)?,
+            ),
+    };
+
+    // at a mark price p the reserves are sqrt(k * p) and sqrt(k / p), so the
+    // value of closing the size s is a quadratic in x = sqrt(p), i.e.
+    // sqrt(k) * x^2 -/+ value * x - value * sqrt(k) / s = 0 for a long or short
+    let k = mul_d(reserves.quote, reserves.base, config.decimals)?;
+    let root_k = sqrt(k, config.decimals)?;
+    let discriminant = mul_d(value, value, config.decimals)?.checked_add(mul_d(
+        k.checked_mul(Uint128::from(4u128))?,
+        div_d(value, position.size, config.decimals)?,
+        config.decimals,
+    )?)?;
+    let root = sqrt(discriminant, config.decimals)?;
+    let x = match position.direction {
+        Direction::AddToAmm => root.checked_add(value)?,
+        Direction::RemoveFromAmm => root.saturating_sub(value),
+    };
+    let x = div_d(
+        x,
+        root_k.checked_mul(Uint128::from(2u128))?,
+        config.decimals,
+    )?;
+
+    Ok(Some(mul_d(x, x, config.decimals)?))
+}
+
+// returns the premium fraction the market's next funding round would settle
+// now, with the mark and index twaps it is taken from. Longs pay shorts whilst
+// the mark is above the index and vice versa. A round the buffer lets follow a
+// late one within a period only covers the time since
+pub fn next_premium_fraction(
+    deps: Deps,
+    env: &Env,
+    vamm: &Addr,
+) -> StdResult<(Integer, Uint128, Uint128)> {
+    let config = read_config(deps.storage)?;
+    let vamm_config = query_vamm_config(deps, vamm.to_string())?;
+    let funding_period = query_vamm_state(deps, vamm.to_string())?.funding_period;
+    let price_feed = vamm_config
+        .price_feed
+        .ok_or_else(|| StdError::generic_err("market has no price feed"))?;
+
+    let mark_twap = query_vamm_twap_price(deps, vamm.to_string(), funding_period)?;
+    let mark_twap = from_vamm_decimals(deps.storage, vamm, mark_twap)?;
+    let index_decimals = query_pricefeed_config(deps, price_feed.to_string())?.decimals;
+    let index_twap = query_pricefeed_twap_price(
+        deps,
+        price_feed.to_string(),
+        vamm_config.quote_asset,
+        funding_period,
+    )?
+    .multiply_ratio(config.decimals, index_decimals);
+
+    let now = env.block.time.seconds();
+    let last_funding_time = read_funding(deps.storage, vamm)?.last_funding_time;
+    let elapsed = if last_funding_time == 0 {
+        funding_period
+    } else {
+        now.saturating_sub(last_funding_time).min(funding_period)
+    };
+    let premium_fraction =
+        Integer::difference(mark_twap, index_twap).multiply_ratio(elapsed, SECONDS_PER_DAY);
+
+    Ok((premium_fraction, mark_twap, index_twap))
+}
+
+// returns the funding a position owes since it last settled, negative if it
+// is owed funding
+pub fn pending_funding(storage: &dyn Storage, position: &Position) -> StdResult<Integer> {
+    let config = read_config(storage)?;
+    let funding = read_funding(storage, &position.vamm)?;
+    let premium_fraction = funding
+        .cumulative_premium_fraction
+        .checked_sub(position.last_updated_premium_fraction)?;
+
+    Ok(position
+        .signed_size()
+        .mul_ratio(premium_fraction, config.decimals))
+}
+
+// returns the position with its pending funding settled into its margin and
+// the payment, negative if it was owed funding. A payment beyond the margin is
+// left for liquidation to settle
+pub fn apply_funding(
+    storage: &dyn Storage,
+    mut position: Position,
+) -> StdResult<(Position, Integer)> {
+    let payment = pending_funding(storage, &position)?;
+    position.last_updated_premium_fraction =
+        read_funding(storage, &position.vamm)?.cumulative_premium_fraction;
+
+    if payment.is_positive() {
+        let payment = payment.value().min(position.margin);
+        position.margin -= payment;
+        Ok((position, Integer::new_positive(payment)))
+    } else {
+        position.margin = position.margin.checked_add(payment.value())?;
+        Ok((position, payment))
+    }
+}
+
+// returns the sum of the trader's position notionals across every market
+pub fn total_notional(storage: &dyn Storage, trader: &Addr) -> StdResult<Uint128> {
+    let mut total = Uint128::zero();
+    for vamm in read_all_vamms(storage)?.iter() {
+        if let Some(position) = read_position(storage, vamm, trader)? {
+            total = total.checked_add(position.notional)?;
+        }
+    }
+
+    Ok(total)
+}
+
+// returns the trader's open positions across every market
+pub fn open_positions(storage: &dyn Storage, trader: &Addr) -> StdResult<Vec<Position>> {
+    let mut positions = vec![];
+    for vamm in read_all_vamms(storage)? {
+        match read_position(storage, &vamm, trader)? {
+            Some(position) if !position.size.is_zero() => positions.push(position),
+            _ => {}
+        }
+    }
+
+    Ok(positions)
+}
+
+// converts an amount in the engine's decimals to the vamm's decimals
+pub fn to_vamm_decimals(storage: &dyn Storage, vamm: &Addr, amount: Uint128) -> StdResult<Uint128> {
+    let config = read_config(storage)?;
+    let vamm_decimals = read_vamm_decimals(storage, vamm)?;
+
+    Ok(amount.multiply_ratio(vamm_decimals, config.decimals))
+}
+
+// converts an amount in the vamm's decimals to the engine's decimals
+pub fn from_vamm_decimals(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    amount: Uint128,
+) -> StdResult<Uint128> {
+    let config = read_config(storage)?;
+    let vamm_decimals = read_vamm_decimals(storage, vamm)?;
+
+    Ok(amount.multiply_ratio(config.decimals, vamm_decimals))
+}
+
+// returns the trader's stake and the discount of the largest fee tier it
+// reaches, nothing is queried while there are no tiers
+pub fn staking_discount(deps: Deps, trader: &Addr) -> StdResult<(Uint128, Uint128)> {
+    let config = read_config(deps.storage)?;
+    let tiers = read_fee_tiers(deps.storage)?;
+    let staking_contract = match config.staking_contract {
+        Some(staking_contract) if !tiers.is_empty() => staking_contract,
+        _ => return Ok((Uint128::zero(), Uint128::zero())),
+    };
+
+    let stake = query_staked(deps, staking_contract.to_string(), trader.to_string())?;
+    let discount = tiers
+        .iter()
+        .rev()
+        .find(|tier| stake >= tier.min_stake)
+        .map(|tier| tier.discount)
+        .unwrap_or_default();
+
+    Ok((stake, discount))
+}
+
+// returns the fees charged by the vamm for the amount, reduced by the
+// largest discount of any fee campaign active on that vamm and the toll
+// further reduced by the trader's fee tier
+pub fn calc_fee(
+    deps: Deps,
+    env: &Env,
+    vamm: &Addr,
+    trader: Option<&Addr>,
+    quote_asset_amount: Uint128,
+) -> StdResult<CalcFeeResponse> {
+    let config = read_config(deps.storage)?;
+    let fees = query_vamm_calc_fee(
+        deps,
+        vamm.to_string(),
+        to_vamm_decimals(deps.storage, vamm, quote_asset_amount)?,
+    )?;
+
+    // markets may override the fee ratios of their vamm
+    let overrides = read_market_fees(deps.storage, vamm)?;
+    let fees = CalcFeeResponse {
+        toll_fee: match overrides.toll_ratio {
+            Some(ratio) => quote_asset_amount.multiply_ratio(ratio, config.decimals),
+            None => from_vamm_decimals(deps.storage, vamm, fees.toll_fee)?,
+        },
+        spread_fee: match overrides.spread_ratio {
+            Some(ratio) => quote_asset_amount.multiply_ratio(ratio, config.decimals),
+            None => from_vamm_decimals(deps.storage, vamm, fees.spread_fee)?,
+        },
+    };
+
+    let discount = read_fee_campaigns(deps.storage)?
+        .iter()
+        .filter(|campaign| campaign.applies(vamm, env.block.time.seconds()))
+        .map(|campaign| campaign.discount)
+        .max()
+        .unwrap_or_default();
+
+    let multiplier = config.decimals.checked_sub(discount)?;
+    let mut fees = CalcFeeResponse {
+        toll_fee: mul_d(fees.toll_fee, multiplier, config.decimals)?,
+        spread_fee: mul_d(fees.spread_fee, multiplier, config.decimals)?,
+    };
+
+    if let Some(trader) = trader {
+        let (_, discount) = staking_discount(deps, trader)?;
+        let multiplier = config.decimals.checked_sub(discount)?;
+        fees.toll_fee = mul_d(fees.toll_fee, multiplier, config.decimals)?;
+    }
+
+    Ok(fees)
+}
+
+// returns the initial and maintenance margin ratios for a position of the
+// given notional, i.e. those of the largest tier it reaches or the market's
+// risk parameters if it reaches none
+pub fn margin_requirement(
+    storage: &dyn Storage,
+    vamm: &Addr,
+    notional: Uint128,
+) -> StdResult<MarginRequirementResponse> {
+    let params = risk_params(storage, vamm)?;
+    let tier = read_margin_tiers(storage, vamm)?
+        .into_iter()
+        .take_while(|tier| tier.notional <= notional)
+        .last();
+
+    Ok(match tier {
+        Some(tier) => MarginRequirementResponse {
+            initial_margin_ratio: tier.initial_margin_ratio,
+            maintenance_margin_ratio: tier.maintenance_margin_ratio,
+        },
+        None => MarginRequirementResponse {
+            initial_margin_ratio: params.initial_margin_ratio,
+            maintenance_margin_ratio: params.maintenance_margin_ratio,
+        },
+    })
+}
+
+// returns the insurance fund balance below which fees are used to top it up
+pub fn insurance_floor(
+    storage: &dyn Storage,
+    floor: &Option<InsuranceFloor>,
+) -> StdResult<Uint128> {
+    match floor {
+        None => Ok(Uint128::zero()),
+        Some(InsuranceFloor::Absolute { amount }) => Ok(*amount),
+        Some(InsuranceFloor::OpenInterestRatio { ratio }) => {
+            let config = read_config(storage)?;
+            let mut open_interest = Uint128::zero();
+            for vamm in read_all_vamms(storage)?.iter() {
+                open_interest = open_interest.checked_add(read_open_interest(storage, vamm)?)?;
+            }
+
+            Ok(open_interest.multiply_ratio(*ratio, config.decimals))
+        }
+    }
+}
+
+// returns the ratio of the margin left in a position, once its pending funding
+// is settled, to the notional it would close for valued at the price given
+pub fn margin_ratio(
+    deps: Deps,
+    vamm: &Addr,
+    position: &Position,
+    calc_option: &PnlCalcOption,
+) -> StdResult<Uint128> {
+    // cross margined positions share the margin of the whole account
+    if read_account_settings(deps.storage, &position.trader)?.cross_margin {
+        return account_margin_ratio(deps, &position.trader, calc_option);
+    }
+    let (position, _) = apply_funding(deps.storage, position.clone())?;
+
+    let config = read_config(deps.storage)?;
+    let notional = margin_notional(deps, vamm, &position, calc_option)?;
+    if notional.is_zero() {
+        return Ok(Uint128::zero());
+    }
+
+    // the account's collateral backs each of its positions
+    let (margin, bad_debt) = remaining_margin(&position, notional);
+    let margin = margin
+        .checked_add(collateral_value(deps, &position.trader)?)?
+        .saturating_sub(bad_debt);
+
+    Ok(margin.multiply_ratio(config.decimals, notional))
+}
+
+// returns the margin ratio of a cross margined account, the margin and PnL of
+// all of its positions pooled with its collateral over their total notional
+pub fn account_margin_ratio(
+    deps: Deps,
+    trader: &Addr,
+    calc_option: &PnlCalcOption,
+) -> StdResult<Uint128> {
+    let config = read_config(deps.storage)?;
+    let mut margin = collateral_value(deps, trader)?;
+    let mut bad_debt = Uint128::zero();
+    let mut notional = Uint128::zero();
+    for position in open_positions(deps.storage, trader)? {
+        let (position, _) = apply_funding(deps.storage, position)?;
+        let position_notional = margin_notional(deps, &position.vamm, &position, calc_option)?;
+        let (remaining, debt) = remaining_margin(&position, position_notional);
+        margin = margin.checked_add(remaining)?;
+        bad_debt = bad_debt.checked_add(debt)?;
+        notional = notional.checked_add(position_notional)?;
+    }
+
+    if notional.is_zero() {
+        return Ok(Uint128::zero());
+    }
+
+    Ok(margin
+        .saturating_sub(bad_debt)
+        .multiply_ratio(config.decimals, notional))
+}
+
+// values a position for its margin ratio, markets without a price feed fall
+// back to the spot price rather than having no margin ratio at all
+fn margin_notional(
+    deps: Deps,
+    vamm: &Addr,
+    position: &Position,
+    calc_option: &PnlCalcOption,
+) -> StdResult<Uint128> {
+    if *calc_option == PnlCalcOption::Oracle
+        && query_vamm_config(deps, vamm.to_string())?
+            .price_feed
+            .is_none()
+    {
+        return position_notional(deps, vamm, position, &PnlCalcOption::SpotPrice);
+    }
+
+    position_notional(deps, vamm, position, calc_option)
+}
+
+// returns the value of a position at the vamm's output price, its twap or the
+// index price of its price feed
+pub fn position_notional(
+    deps: Deps,
+    vamm: &Addr,
+    position: &Position,
+    calc_option: &PnlCalcOption,
+) -> StdResult<Uint128> {
+    let config = read_config(deps.storage)?;
+    let price = match calc_option {
+        PnlCalcOption::SpotPrice => {
+            let amount = to_vamm_decimals(deps.storage, vamm, position.size)?;
+            let notional = query_vamm_output_price(
+                deps,
+                vamm.to_string(),
+                position.direction.clone(),
+                amount,
+            )?;
+            return from_vamm_decimals(deps.storage, vamm, notional);
+        }
+        PnlCalcOption::Twap => {
+            let amount = to_vamm_decimals(deps.storage, vamm, position.size)?;
+            let notional = query_vamm_output_twap(
+                deps,
+                vamm.to_string(),
+                position.direction.clone(),
+                amount,
+                PNL_TWAP_INTERVAL,
+            )?;
+            return from_vamm_decimals(deps.storage, vamm, notional);
+        }
+        PnlCalcOption::Oracle => index_price(deps, vamm)?
+            .ok_or_else(|| StdError::generic_err("market has no price feed"))?,
+    };
+
+    Ok(position.size.multiply_ratio(price, config.decimals))
+}
+
+// returns the index price of the market's price feed in the engine's decimals,
+// or none if it has no price feed
+pub fn index_price(deps: Deps, vamm: &Addr) -> StdResult<Option<Uint128>> {
+    let config = read_config(deps.storage)?;
+    let vamm_config = query_vamm_config(deps, vamm.to_string())?;
+    let price_feed = match vamm_config.price_feed {
+        Some(price_feed) => price_feed,
+        None => return Ok(None),
+    };
+
+    let index_decimals = query_pricefeed_config(deps, price_feed.to_string())?.decimals;
+    let price = query_pricefeed_price(deps, price_feed.to_string(), vamm_config.quote_asset)?;
+
+    Ok(Some(price.multiply_ratio(config.decimals, index_decimals)))
+}
+
+// opens are rejected while the spot price is further from the index price than
+// the config allows, trades would fill at a price the oracle does not back
+pub fn require_oracle_spread(deps: Deps, vamm: &Addr) -> Result<(), ContractError> {
+    let config = read_config(deps.storage)?;
+    let max_oracle_spread = match config.max_oracle_spread {
+        Some(max_oracle_spread) => max_oracle_spread,
+        None => return Ok(()),
+    };
+
+    // markets without a price feed have nothing to be checked against
+    let index = match index_price(deps, vamm)? {
+        Some(index) if !index.is_zero() => index,
+        _ => return Ok(()),
+    };
+    let spot = query_vamm_spot_price(deps, vamm.to_string())?;
+    let spot = from_vamm_decimals(deps.storage, vamm, spot)?;
+
+    let spread = div_d(
+        Integer::difference(spot, index).value(),
+        index,
+        config.decimals,
+    )?;
+    if spread > max_oracle_spread {
+        return Err(ContractError::OracleSpread { max_oracle_spread });
+    }
+
+    Ok(())
+}
+
+// returns the profit, or loss if negative, of a position valued at the notional
+pub fn unrealized_pnl(position: &Position, notional: Uint128) -> Integer {
+    match position.direction {
+        Direction::AddToAmm => Integer::difference(notional, position.notional),
+        Direction::RemoveFromAmm => Integer::difference(position.notional, notional),
+    }
+}
+
+// returns the margin left in a position were it closed for the given notional,
+// along with the bad debt if its losses exceed the margin
+pub fn remaining_margin(position: &Position, notional: Uint128) -> (Uint128, Uint128) {
+    add_pnl(position.margin, unrealized_pnl(position, notional))
+}
+
+// adds PnL to a margin, returning the margin left and the bad debt if the
+// losses exceed it
+pub fn add_pnl(margin: Uint128, pnl: Integer) -> (Uint128, Uint128) {
+    let margin = Integer::from(margin) + pnl;
+    if margin.is_negative() {
+        (Uint128::zero(), margin.value())
+    } else {
+        (margin.value(), Uint128::zero())
+    }
+}
+
+// returns the margin that can be withdrawn from a position without leaving it
+// under the initial margin ratio, unrealized losses count against the margin
+// but unrealized profits cannot be withdrawn until they are realized
+pub fn free_collateral(deps: Deps, vamm: &Addr, position: &Position) -> StdResult<Uint128> {
+    // a cross margined position can release what the whole account can spare
+    if read_account_settings(deps.storage, &position.trader)?.cross_margin {
+        let (margin, required_margin) = account_margin_balance(deps, &position.trader)?;
+        return Ok(margin
+            .checked_add(collateral_value(deps, &position.trader)?)?
+            .saturating_sub(required_margin)
+            .min(position.margin));
+    }
+
+    let (margin, loss, required_margin) = initial_margin_balance(deps, vamm, position)?;
+
+    Ok(margin.saturating_sub(loss).saturating_sub(required_margin))
+}
+
+// returns the margin of a position once its pending funding is settled, its
+// unrealized loss and the margin the initial margin ratio of its tier requires
+fn initial_margin_balance(
+    deps: Deps,
+    vamm: &Addr,
+    position: &Position,
+) -> StdResult<(Uint128, Uint128, Uint128)> {
+    let config = read_config(deps.storage)?;
+    let position = &apply_funding(deps.storage, position.clone())?.0;
+    let notional = position_notional(deps, vamm, position, &PnlCalcOption::SpotPrice)?;
+    let pnl = unrealized_pnl(position, notional);
+    let loss = if pnl.is_negative() {
+        pnl.value()
+    } else {
+        Uint128::zero()
+    };
+
+    let requirement = margin_requirement(deps.storage, vamm, position.notional)?;
+    let required_margin = position
+        .notional
+        .multiply_ratio(requirement.initial_margin_ratio, config.decimals);
+
+    Ok((position.margin, loss, required_margin))
+}
+
+// returns the margin of all of a trader's positions net of their unrealized
+// losses, and the margin they require together
+fn account_margin_balance(deps: Deps, trader: &Addr) -> StdResult<(Uint128, Uint128)> {
+    let mut margin = Uint128::zero();
+    let mut required = Uint128::zero();
+    for position in open_positions(deps.storage, trader)? {
+        let (position_margin, loss, required_margin) =
+            initial_margin_balance(deps, &position.vamm, &position)?;
+        margin = margin.checked_add(position_margin)?;
+        required = required.checked_add(required_margin)?.checked_add(loss)?;
+    }
+
+    Ok((margin, required))
+}
+
+// returns the price of a collateral asset in the quote, normalised to the
+// engine's decimals. Assets without a price feed are valued at par
+pub fn collateral_price(deps: Deps, asset: &CollateralAsset) -> StdResult<Uint128> {
+    let config = read_config(deps.storage)?;
+    match &asset.price {
+        Some(price) => {
+            let decimals = query_pricefeed_config(deps, price.pricefeed.clone())?.decimals;
+            Ok(
+                query_pricefeed_price(deps, price.pricefeed.clone(), price.key.clone())?
+                    .multiply_ratio(config.decimals, decimals),
+            )
+        }
+        None => Ok(config.decimals),
+    }
+}
+
+// returns the value of a trader's collateral after the haircut of each asset,
+// assets no longer accepted as collateral are not counted
+pub fn collateral_value(deps: Deps, trader: &Addr) -> StdResult<Uint128> {
+    let config = read_config(deps.storage)?;
+    let mut value = Uint128::zero();
+    for balance in read_collateral_balances(deps.storage, trader)? {
+        let asset = match read_collateral_asset(deps.storage, &balance.asset)? {
+            Some(asset) => asset,
+            None => continue,
+        };
+
+        let price = mul_d(
+            collateral_price(deps, &asset)?,
+            asset.weight,
+            config.decimals,
+        )?;
+        value = value.checked_add(mul_d(balance.amount, price, config.decimals)?)?;
+    }
+
+    Ok(value)
+}
+
+// returns how far the trader's positions fall short of the initial margin they
+// require, which their collateral must make up. The positions of a cross
+// margined account make up each other's shortfall
+pub fn collateral_shortfall(deps: Deps, trader: &Addr) -> StdResult<Uint128> {
+    if read_account_settings(deps.storage, trader)?.cross_margin {
+        let (margin, required_margin) = account_margin_balance(deps, trader)?;
+        return Ok(required_margin.saturating_sub(margin));
+    }
+
+    let mut shortfall = Uint128::zero();
+    for position in open_positions(deps.storage, trader)? {
+        let (margin, loss, required_margin) =
+            initial_margin_balance(deps, &position.vamm, &position)?;
+        shortfall =
+            shortfall.checked_add(required_margin.checked_add(loss)?.saturating_sub(margin))?;
+    }
+
+    Ok(shortfall)
+}
+
+// fixes the settlement of a shut down market, the payout ratio is the margin
+// held for the market over what its positions are owed at the settlement price
+pub fn calc_settlement(deps: Deps, vamm: &Addr) -> StdResult<Settlement> {
+    let config = read_config(deps.storage)?;
+    let settlement_price = query_vamm_settlement_price(deps, vamm.to_string())?
+        .ok_or_else(|| StdError::generic_err("market has not been shut down"))?;
+    let settlement_price = from_vamm_decimals(deps.storage, vamm, settlement_price)?;
+
+    let mut balance = Uint128::zero();
+    let mut owed = Uint128::zero();
+    for position in read_vamm_positions(deps.storage, vamm)? {
+        if position.size.is_zero() {
+            continue;
+        }
+        let notional = position
+            .size
+            .multiply_ratio(settlement_price, config.decimals);
+        balance = balance.checked_add(position.margin)?;
+        owed = owed.checked_add(remaining_margin(&position, notional).0)?;
+    }
+
+    let payout_ratio = if owed <= balance {
+        config.decimals
+    } else {
+        balance.multiply_ratio(config.decimals, owed)
+    };
+
+    Ok(Settlement {
+        settlement_price,
+        payout_ratio,
+    })
+}
+
+// checks the base asset output of a swap against the limit, buys must receive
+// at least the limit and sells cannot exceed it
+pub fn require_base_asset_limit(
+    side: &Side,
+    output: Uint128,
+    base_asset_limit: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    if let Some(limit) = base_asset_limit {
+        let exceeded = match side {
+            Side::BUY => output < limit,
+            Side::SELL => output > limit,
+        };
+        if exceeded {
+            return Err(ContractError::BaseAssetLimit {
+                amount: output,
+                limit,
+            });
+        }
+    }
+
+    Ok(Response::new())
+}
+
+// checks the quote asset amount of a close against the limit, closing a long
+// must receive at least the limit and closing a short cannot cost more
+pub fn require_quote_asset_limit(
+    side: &Side,
+    amount: Uint128,
+    quote_asset_limit: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    if let Some(limit) = quote_asset_limit {
+        let exceeded = match side {
+            Side::BUY => amount < limit,
+            Side::SELL => amount > limit,
+        };
+        if exceeded {
+            return Err(ContractError::QuoteAssetLimit { amount, limit });
+        }
     }
 
     Ok(Response::new())
@@ -32,7 +1304,7 @@ pub fn direction_to_side(direction: Direction) -> Side {
 
 // takes the side (buy|sell) and returns opposite (short|long)
 // this is useful when closing/reversing a position
-pub fn switch_direction(dir: Direction) -> Direction {
+pub fn _switch_direction(dir: Direction) -> Direction {
     match dir {
         Direction::RemoveFromAmm => Direction::AddToAmm,
         Direction::AddToAmm => Direction::RemoveFromAmm,
@@ -0,0 +1,100 @@
+use crate::error::ContractError;
+use crate::{
+    handle::{deposit, liquidate, rebalance, update_config, withdraw},
+    query::{query_config, query_shares, query_state},
+    state::{read_config, store_config, store_total_shares, Config},
+};
+#[cfg(not(feature = "library"))]
+use cosmwasm_std::entry_point;
+use cosmwasm_std::{
+    from_binary, to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult,
+    Uint128,
+};
+use cw2::{get_contract_version, set_contract_version};
+use cw20::Cw20ReceiveMsg;
+use margined_perp::margined_maker_vault::{
+    Cw20HookMsg, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg,
+};
+
+const CONTRACT_NAME: &str = "crates.io:margined-maker-vault";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn instantiate(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    msg: InstantiateMsg,
+) -> Result<Response, ContractError> {
+    let config = Config {
+        owner: info.sender,
+        engine: deps.api.addr_validate(&msg.engine)?,
+        vamm: deps.api.addr_validate(&msg.vamm)?,
+        collateral: deps.api.addr_validate(&msg.collateral)?,
+        decimals: Uint128::from(10u128.pow(msg.decimals as u32)),
+        leverage: msg.leverage,
+    };
+
+    store_config(deps.storage, &config)?;
+    store_total_shares(deps.storage, Uint128::zero())?;
+
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::default())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let version = get_contract_version(deps.storage)?;
+    if version.contract != CONTRACT_NAME {
+        return Err(
+            StdError::generic_err(format!("cannot migrate from {}", version.contract)).into(),
+        );
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "migrate"), ("version", CONTRACT_VERSION)]))
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn execute(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    msg: ExecuteMsg,
+) -> Result<Response, ContractError> {
+    match msg {
+        ExecuteMsg::Receive(msg) => receive_cw20(deps, env, info, msg),
+        ExecuteMsg::UpdateConfig { leverage } => update_config(deps, info, leverage),
+        ExecuteMsg::Withdraw { shares } => withdraw(deps, env, info, shares),
+        ExecuteMsg::Rebalance {} => rebalance(deps, env),
+        ExecuteMsg::Liquidate { trader } => liquidate(deps, trader),
+    }
+}
+
+pub fn receive_cw20(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    cw20_msg: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+
+    // only the vault's collateral can be deposited
+    if info.sender != config.collateral {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    match from_binary(&cw20_msg.msg)? {
+        Cw20HookMsg::Deposit {} => deposit(deps, env, cw20_msg.sender, cw20_msg.amount),
+    }
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps)?),
+        QueryMsg::State {} => to_binary(&query_state(deps, env)?),
+        QueryMsg::Shares { provider } => to_binary(&query_shares(deps, env, provider)?),
+    }
+}
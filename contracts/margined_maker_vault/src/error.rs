@@ -0,0 +1,29 @@
+use cosmwasm_std::{OverflowError, StdError, Uint128};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ContractError {
+    #[error("{0}")]
+    Std(#[from] StdError),
+
+    #[error("{0}")]
+    Overflow(#[from] OverflowError),
+
+    #[error("Unauthorized")]
+    Unauthorized {},
+
+    #[error("Amount must be greater than zero")]
+    ZeroAmount {},
+
+    #[error("Not enough idle collateral to withdraw, {idle}")]
+    InsufficientIdle { idle: Uint128 },
+
+    #[error("Market is balanced, nothing to rebalance")]
+    NothingToRebalance {},
+
+    #[error("The first deposit must be more than the minimum shares")]
+    BelowMinimumShares {},
+
+    #[error("The vault is worth nothing, its shares cannot be added to")]
+    WorthlessShares {},
+}
@@ -0,0 +1,250 @@
+use cosmwasm_std::{
+    to_binary, Addr, CosmosMsg, DepsMut, Env, MessageInfo, Response, Uint128, WasmMsg,
+};
+use cw20::Cw20ExecuteMsg;
+
+use margined_perp::decimals::{div_d, mul_d};
+use margined_perp::margined_engine::{
+    Cw20HookMsg as EngineHookMsg, ExecuteMsg as EngineExecuteMsg, Side,
+};
+
+use crate::{
+    error::ContractError,
+    querier::{query_fee, query_idle, query_imbalance, query_total_value, query_vault_position},
+    state::{
+        read_config, read_shares, read_total_shares, store_config, store_shares,
+        store_total_shares, Config, MINIMUM_SHARES,
+    },
+};
+
+pub fn update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    leverage: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let mut config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // change the leverage the vault trades at
+    if let Some(leverage) = leverage {
+        config.leverage = leverage;
+    }
+
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::default())
+}
+
+// Mints shares for collateral transferred via the cw20 hook, in proportion to
+// what the vault was worth before the deposit
+pub fn deposit(
+    deps: DepsMut,
+    env: Env,
+    provider: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    let provider = deps.api.addr_validate(&provider)?;
+
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount {});
+    }
+
+    // the vault's balance already includes the deposit
+    let total_shares = read_total_shares(deps.storage)?;
+    let value_before =
+        query_total_value(deps.as_ref(), &config, &env.contract.address)?.checked_sub(amount)?;
+
+    // the first deposit is minted 1:1 less the minimum shares, which are held
+    // by no one so that the share price cannot be inflated from a dust deposit
+    let (minted, shares) = if total_shares.is_zero() {
+        if amount <= MINIMUM_SHARES {
+            return Err(ContractError::BelowMinimumShares {});
+        }
+        (amount, amount - MINIMUM_SHARES)
+    } else if value_before.is_zero() {
+        // the vault has lost everything, new collateral would only be shared
+        // with the worthless shares
+        return Err(ContractError::WorthlessShares {});
+    } else {
+        let shares = amount.multiply_ratio(total_shares, value_before);
+        (shares, shares)
+    };
+    if shares.is_zero() {
+        return Err(ContractError::ZeroAmount {});
+    }
+
+    let provider_shares = read_shares(deps.storage, &provider)?.checked_add(shares)?;
+    store_shares(deps.storage, &provider, provider_shares)?;
+    store_total_shares(deps.storage, total_shares.checked_add(minted)?)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "deposit"),
+        ("provider", provider.as_str()),
+        ("amount", &amount.to_string()),
+        ("shares", &shares.to_string()),
+    ]))
+}
+
+// Burns the sender's shares and pays out their value from the collateral the
+// vault holds outside of its position
+pub fn withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    shares: Uint128,
+) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+
+    if shares.is_zero() {
+        return Err(ContractError::ZeroAmount {});
+    }
+
+    let provider_shares = read_shares(deps.storage, &info.sender)?.checked_sub(shares)?;
+    let total_shares = read_total_shares(deps.storage)?;
+
+    let amount = shares.multiply_ratio(
+        query_total_value(deps.as_ref(), &config, &env.contract.address)?,
+        total_shares,
+    );
+    let idle = query_idle(deps.as_ref(), &config, &env.contract.address)?;
+    if amount > idle {
+        return Err(ContractError::InsufficientIdle { idle });
+    }
+
+    store_shares(deps.storage, &info.sender, provider_shares)?;
+    store_total_shares(deps.storage, total_shares.checked_sub(shares)?)?;
+
+    let msg = WasmMsg::Execute {
+        contract_addr: config.collateral.to_string(),
+        funds: vec![],
+        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+            recipient: info.sender.to_string(),
+            amount,
+        })?,
+    };
+
+    Ok(Response::new().add_message(msg).add_attributes(vec![
+        ("action", "withdraw"),
+        ("provider", info.sender.as_str()),
+        ("shares", &shares.to_string()),
+        ("amount", &amount.to_string()),
+    ]))
+}
+
+// Takes the lighter side of the market's open interest with as much of the
+// idle collateral as it takes to even it out, or closes the vault's position
+// once it is on the heavier side
+pub fn rebalance(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    let vault = env.contract.address;
+
+    let imbalance = query_imbalance(deps.as_ref(), &config)?;
+    let (side, difference) = if imbalance.long > imbalance.short {
+        (Side::SELL, imbalance.long - imbalance.short)
+    } else {
+        (Side::BUY, imbalance.short - imbalance.long)
+    };
+
+    if difference.is_zero() {
+        return Err(ContractError::NothingToRebalance {});
+    }
+
+    if let Some(position) = query_vault_position(deps.as_ref(), &config, &vault)? {
+        if position.side != side {
+            let msg = engine_msg(
+                &config.engine,
+                &EngineExecuteMsg::ClosePosition {
+                    vamm: config.vamm.to_string(),
+                    size: None,
+                    quote_asset_limit: None,
+                    partial: false,
+                    deadline: None,
+                    trader: None,
+                },
+            )?;
+
+            return Ok(Response::new()
+                .add_message(msg)
+                .add_attributes(vec![("action", "rebalance"), ("close", "true")]));
+        }
+    }
+
+    // the fees are paid on top of the margin, so the margin is cut back to
+    // leave room for them
+    let idle = query_idle(deps.as_ref(), &config, &vault)?;
+    let mut margin = div_d(difference, config.leverage, config.decimals)?.min(idle);
+    let mut fee = query_fee(
+        deps.as_ref(),
+        &config,
+        mul_d(margin, config.leverage, config.decimals)?,
+    )?;
+    if margin.checked_add(fee)? > idle {
+        margin = idle.saturating_sub(fee);
+        fee = query_fee(
+            deps.as_ref(),
+            &config,
+            mul_d(margin, config.leverage, config.decimals)?,
+        )?;
+    }
+
+    if margin.is_zero() {
+        return Err(ContractError::NothingToRebalance {});
+    }
+
+    let msg = WasmMsg::Execute {
+        contract_addr: config.collateral.to_string(),
+        funds: vec![],
+        msg: to_binary(&Cw20ExecuteMsg::Send {
+            contract: config.engine.to_string(),
+            amount: margin.checked_add(fee)?,
+            msg: to_binary(&EngineHookMsg::OpenPosition {
+                vamm: config.vamm.to_string(),
+                side,
+                quote_asset_amount: margin,
+                leverage: config.leverage,
+                base_asset_limit: None,
+                reduce_only: false,
+                deadline: None,
+            })?,
+        })?,
+    };
+
+    Ok(Response::new().add_message(msg).add_attributes(vec![
+        ("action", "rebalance"),
+        ("margin", &margin.to_string()),
+        ("fee", &fee.to_string()),
+    ]))
+}
+
+// Liquidates a position in the vault's market, the engine pays the
+// liquidation fee to the vault
+pub fn liquidate(deps: DepsMut, trader: String) -> Result<Response, ContractError> {
+    let config = read_config(deps.storage)?;
+    deps.api.addr_validate(&trader)?;
+
+    let msg = engine_msg(
+        &config.engine,
+        &EngineExecuteMsg::Liquidate {
+            vamm: config.vamm.to_string(),
+            trader: trader.clone(),
+            takeover: false,
+        },
+    )?;
+
+    Ok(Response::new()
+        .add_message(msg)
+        .add_attributes(vec![("action", "liquidate"), ("trader", &trader)]))
+}
+
+fn engine_msg(engine: &Addr, msg: &EngineExecuteMsg) -> Result<CosmosMsg, ContractError> {
+    Ok(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: engine.to_string(),
+        funds: vec![],
+        msg: to_binary(msg)?,
+    }))
+}
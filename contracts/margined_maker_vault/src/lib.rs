@@ -0,0 +1,9 @@
+pub mod contract;
+mod error;
+mod handle;
+mod querier;
+mod query;
+mod state;
+
+#[cfg(test)]
+mod testing;
@@ -0,0 +1,101 @@
+// Contains queries for external contracts
+use cosmwasm_std::{to_binary, Addr, Deps, QueryRequest, StdResult, Uint128, WasmQuery};
+use cw20::{BalanceResponse, Cw20QueryMsg};
+
+use margined_perp::margined_engine::{
+    OpenInterestImbalanceResponse, PnlCalcOption, PositionInfo,
+    PositionNotionalAndUnrealizedPnlResponse, QueryMsg, TraderPositionsResponse,
+};
+use margined_perp::margined_vamm::CalcFeeResponse;
+
+use crate::state::Config;
+
+// returns the collateral the vault holds outside of its position
+pub fn query_idle(deps: Deps, config: &Config, vault: &Addr) -> StdResult<Uint128> {
+    let res: BalanceResponse = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: config.collateral.to_string(),
+        msg: to_binary(&Cw20QueryMsg::Balance {
+            address: vault.to_string(),
+        })?,
+    }))?;
+
+    Ok(res.balance)
+}
+
+// returns the vault's position in its market, if it has one
+pub fn query_vault_position(
+    deps: Deps,
+    config: &Config,
+    vault: &Addr,
+) -> StdResult<Option<PositionInfo>> {
+    let res: TraderPositionsResponse =
+        deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: config.engine.to_string(),
+            msg: to_binary(&QueryMsg::TraderPositions {
+                trader: vault.to_string(),
+            })?,
+        }))?;
+
+    Ok(res
+        .positions
+        .into_iter()
+        .find(|position| position.vamm == config.vamm && !position.size.is_zero()))
+}
+
+// returns what the vault's position would close for at the spot price,
+// a position underwater is worth nothing to the vault
+pub fn query_position_value(deps: Deps, config: &Config, vault: &Addr) -> StdResult<Uint128> {
+    let position = match query_vault_position(deps, config, vault)? {
+        Some(position) => position,
+        None => return Ok(Uint128::zero()),
+    };
+
+    let res: PositionNotionalAndUnrealizedPnlResponse =
+        deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: config.engine.to_string(),
+            msg: to_binary(&QueryMsg::PositionNotionalAndUnrealizedPnl {
+                vamm: config.vamm.to_string(),
+                trader: vault.to_string(),
+                calc_option: PnlCalcOption::SpotPrice,
+            })?,
+        }))?;
+
+    if res.unrealized_pnl.is_negative() {
+        Ok(position.margin.saturating_sub(res.unrealized_pnl.value()))
+    } else {
+        position
+            .margin
+            .checked_add(res.unrealized_pnl.value())
+            .map_err(Into::into)
+    }
+}
+
+// the vault is worth its idle collateral and what its position would close for
+pub fn query_total_value(deps: Deps, config: &Config, vault: &Addr) -> StdResult<Uint128> {
+    query_idle(deps, config, vault)?
+        .checked_add(query_position_value(deps, config, vault)?)
+        .map_err(Into::into)
+}
+
+// returns the open notional of the longs and shorts of the vault's market
+pub fn query_imbalance(deps: Deps, config: &Config) -> StdResult<OpenInterestImbalanceResponse> {
+    deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: config.engine.to_string(),
+        msg: to_binary(&QueryMsg::OpenInterestImbalance {
+            vamm: config.vamm.to_string(),
+        })?,
+    }))
+}
+
+// returns the fees the engine charges to open the given notional
+pub fn query_fee(deps: Deps, config: &Config, notional: Uint128) -> StdResult<Uint128> {
+    let res: CalcFeeResponse = deps.querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: config.engine.to_string(),
+        msg: to_binary(&QueryMsg::CalcFee {
+            vamm: config.vamm.to_string(),
+            quote_asset_amount: notional,
+        })?,
+    }))?;
+
+    res.toll_fee.checked_add(res.spread_fee).map_err(Into::into)
+}
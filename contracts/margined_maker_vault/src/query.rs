@@ -0,0 +1,53 @@
+use cosmwasm_std::{Deps, Env, StdResult};
+
+use margined_perp::margined_maker_vault::{ConfigResponse, SharesResponse, StateResponse};
+
+use crate::{
+    querier::{query_idle, query_position_value},
+    state::{read_config, read_shares, read_total_shares},
+};
+
+/// Queries contract config
+pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = read_config(deps.storage)?;
+
+    Ok(ConfigResponse {
+        owner: config.owner,
+        engine: config.engine,
+        vamm: config.vamm,
+        collateral: config.collateral,
+        decimals: config.decimals,
+        leverage: config.leverage,
+    })
+}
+
+/// Queries the shares issued and what backs them
+pub fn query_state(deps: Deps, env: Env) -> StdResult<StateResponse> {
+    let config = read_config(deps.storage)?;
+
+    let idle = query_idle(deps, &config, &env.contract.address)?;
+    let position_value = query_position_value(deps, &config, &env.contract.address)?;
+
+    Ok(StateResponse {
+        total_shares: read_total_shares(deps.storage)?,
+        idle,
+        position_value,
+        total_value: idle.checked_add(position_value)?,
+    })
+}
+
+/// Queries a provider's shares and what they are worth
+pub fn query_shares(deps: Deps, env: Env, provider: String) -> StdResult<SharesResponse> {
+    let provider = deps.api.addr_validate(&provider)?;
+    let shares = read_shares(deps.storage, &provider)?;
+
+    let total_shares = read_total_shares(deps.storage)?;
+    let value = if total_shares.is_zero() {
+        total_shares
+    } else {
+        let state = query_state(deps, env)?;
+        shares.multiply_ratio(state.total_value, total_shares)
+    };
+
+    Ok(SharesResponse { shares, value })
+}
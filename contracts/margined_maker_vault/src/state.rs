@@ -0,0 +1,50 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, StdResult, Storage, Uint128};
+use cw_storage_plus::{Item, Map};
+
+pub const CONFIG: Item<Config> = Item::new("config");
+pub const TOTAL_SHARES: Item<Uint128> = Item::new("total_shares");
+pub const SHARES: Map<&Addr, Uint128> = Map::new("shares");
+
+// minted on the first deposit to no one, they are never withdrawn
+pub const MINIMUM_SHARES: Uint128 = Uint128::new(1_000);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Config {
+    pub owner: Addr,
+    pub engine: Addr,
+    pub vamm: Addr,
+    pub collateral: Addr,
+    pub decimals: Uint128,
+    pub leverage: Uint128,
+}
+
+pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
+    CONFIG.save(storage, config)
+}
+
+pub fn read_config(storage: &dyn Storage) -> StdResult<Config> {
+    CONFIG.load(storage)
+}
+
+pub fn store_total_shares(storage: &mut dyn Storage, shares: Uint128) -> StdResult<()> {
+    TOTAL_SHARES.save(storage, &shares)
+}
+
+pub fn read_total_shares(storage: &dyn Storage) -> StdResult<Uint128> {
+    Ok(TOTAL_SHARES.may_load(storage)?.unwrap_or_default())
+}
+
+pub fn store_shares(storage: &mut dyn Storage, provider: &Addr, shares: Uint128) -> StdResult<()> {
+    if shares.is_zero() {
+        SHARES.remove(storage, provider);
+        return Ok(());
+    }
+    SHARES.save(storage, provider, &shares)
+}
+
+pub fn read_shares(storage: &dyn Storage, provider: &Addr) -> StdResult<Uint128> {
+    Ok(SHARES.may_load(storage, provider)?.unwrap_or_default())
+}
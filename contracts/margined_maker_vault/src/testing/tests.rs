@@ -0,0 +1,162 @@
+use crate::contract::{execute, instantiate, query};
+use crate::error::ContractError;
+use cosmwasm_std::testing::{
+    mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+};
+use cosmwasm_std::{
+    from_binary, from_slice, to_binary, Addr, ContractResult, Empty, OwnedDeps, Querier,
+    QuerierResult, QueryRequest, SystemResult, Uint128, WasmQuery,
+};
+use cw20::{BalanceResponse, Cw20QueryMsg, Cw20ReceiveMsg};
+use margined_perp::margined_engine::TraderPositionsResponse;
+use margined_perp::margined_maker_vault::{
+    ConfigResponse, Cw20HookMsg, ExecuteMsg, InstantiateMsg, QueryMsg, SharesResponse,
+};
+
+#[test]
+fn test_instantiation() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        engine: "engine0000".to_string(),
+        vamm: "vamm0000".to_string(),
+        collateral: "token0000".to_string(),
+        decimals: 9u8,
+        leverage: Uint128::from(2_000_000_000u128),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        config,
+        ConfigResponse {
+            owner: Addr::unchecked("addr0000"),
+            engine: Addr::unchecked("engine0000"),
+            vamm: Addr::unchecked("vamm0000"),
+            collateral: Addr::unchecked("token0000"),
+            decimals: Uint128::from(1_000_000_000u128),
+            leverage: Uint128::from(2_000_000_000u128),
+        }
+    );
+}
+
+#[test]
+fn test_update_config() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        engine: "engine0000".to_string(),
+        vamm: "vamm0000".to_string(),
+        collateral: "token0000".to_string(),
+        decimals: 9u8,
+        leverage: Uint128::from(2_000_000_000u128),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // only the owner may change the leverage
+    let msg = ExecuteMsg::UpdateConfig {
+        leverage: Some(Uint128::from(5_000_000_000u128)),
+    };
+    let info = mock_info("addr0001", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.leverage, Uint128::from(5_000_000_000u128));
+}
+
+// answers the vault's balance queries with the balance set and its position
+// queries with no position, so the vault is worth its balance
+struct BalanceQuerier {
+    base: MockQuerier,
+    balance: Uint128,
+}
+
+impl Querier for BalanceQuerier {
+    fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
+        let request: QueryRequest<Empty> = from_slice(bin_request).unwrap();
+        match request {
+            QueryRequest::Wasm(WasmQuery::Smart { msg, .. }) => {
+                let res = match from_binary(&msg) {
+                    Ok(Cw20QueryMsg::Balance { .. }) => to_binary(&BalanceResponse {
+                        balance: self.balance,
+                    }),
+                    _ => to_binary(&TraderPositionsResponse {
+                        trader: Addr::unchecked("vault"),
+                        positions: vec![],
+                        total_margin: Uint128::zero(),
+                    }),
+                };
+                SystemResult::Ok(ContractResult::Ok(res.unwrap()))
+            }
+            _ => self.base.raw_query(bin_request),
+        }
+    }
+}
+
+// transfers the amount to the vault and deposits it, the transfer is undone
+// if the deposit fails
+fn deposit(
+    deps: &mut OwnedDeps<MockStorage, MockApi, BalanceQuerier>,
+    amount: u128,
+) -> Result<(), ContractError> {
+    deps.querier.balance += Uint128::from(amount);
+    let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+        sender: "addr0001".to_string(),
+        amount: Uint128::from(amount),
+        msg: to_binary(&Cw20HookMsg::Deposit {}).unwrap(),
+    });
+    let res = execute(deps.as_mut(), mock_env(), mock_info("token0000", &[]), msg);
+    if res.is_err() {
+        deps.querier.balance -= Uint128::from(amount);
+    }
+    res.map(|_| ())
+}
+
+#[test]
+fn test_deposit_shares() {
+    let mut deps = OwnedDeps {
+        storage: MockStorage::default(),
+        api: MockApi::default(),
+        querier: BalanceQuerier {
+            base: MockQuerier::new(&[]),
+            balance: Uint128::zero(),
+        },
+    };
+    let msg = InstantiateMsg {
+        engine: "engine0000".to_string(),
+        vamm: "vamm0000".to_string(),
+        collateral: "token0000".to_string(),
+        decimals: 9u8,
+        leverage: Uint128::from(2_000_000_000u128),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // a dust first deposit could inflate the share price
+    let err = deposit(&mut deps, 1_000).unwrap_err();
+    assert!(matches!(err, ContractError::BelowMinimumShares {}));
+
+    // the first deposit is minted 1:1 less the minimum shares
+    deposit(&mut deps, 100_000).unwrap();
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::Shares {
+            provider: "addr0001".to_string(),
+        },
+    )
+    .unwrap();
+    let res: SharesResponse = from_binary(&res).unwrap();
+    assert_eq!(res.shares, Uint128::from(99_000u128));
+    assert_eq!(res.value, Uint128::from(99_000u128));
+
+    // once the vault has lost everything its shares cannot be added to
+    deps.querier.balance = Uint128::zero();
+    let err = deposit(&mut deps, 100_000).unwrap_err();
+    assert!(matches!(err, ContractError::WorthlessShares {}));
+}
@@ -2,14 +2,18 @@ use crate::error::ContractError;
 use crate::{
     handle::{append_multiple_price, append_price, update_config},
     query::{query_config, query_get_previous_price, query_get_price, query_get_twap_price},
-    state::{store_config, Config},
+    state::{migrate_legacy_storage, store_config, Config},
 };
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Uint128,
+    to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Uint128,
 };
-use margined_perp::margined_pricefeed::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use cw2::{get_contract_version, set_contract_version};
+use margined_perp::margined_pricefeed::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
+
+const CONTRACT_NAME: &str = "crates.io:margined-pricefeed";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
@@ -21,13 +25,33 @@ pub fn instantiate(
     let config = Config {
         owner: info.sender,
         decimals: Uint128::from(10u128.pow(msg.decimals as u32)),
+        feeder: None,
     };
 
     store_config(deps.storage, &config)?;
 
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     Ok(Response::default())
 }
 
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    // deployments that predate versioning are still in the legacy layout
+    match get_contract_version(deps.storage) {
+        Ok(version) if version.contract != CONTRACT_NAME => {
+            return Err(
+                StdError::generic_err(format!("cannot migrate from {}", version.contract)).into(),
+            );
+        }
+        Ok(_) => {}
+        Err(_) => migrate_legacy_storage(deps.storage)?,
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "migrate"), ("version", CONTRACT_VERSION)]))
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
@@ -46,7 +70,7 @@ pub fn execute(
             prices,
             timestamps,
         } => append_multiple_price(deps, info, key, prices, timestamps),
-        ExecuteMsg::UpdateConfig { owner } => update_config(deps, info, owner),
+        ExecuteMsg::UpdateConfig { owner, feeder } => update_config(deps, info, owner, feeder),
     }
 }
 
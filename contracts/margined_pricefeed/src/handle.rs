@@ -9,6 +9,7 @@ pub fn update_config(
     deps: DepsMut,
     info: MessageInfo,
     owner: Option<String>,
+    feeder: Option<String>,
 ) -> Result<Response, ContractError> {
     let mut config: Config = read_config(deps.storage)?;
 
@@ -22,13 +23,18 @@ pub fn update_config(
         config.owner = deps.api.addr_validate(owner.as_str())?;
     }
 
+    // change the account authorized to feed prices
+    if let Some(feeder) = feeder {
+        config.feeder = Some(deps.api.addr_validate(feeder.as_str())?);
+    }
+
     store_config(deps.storage, &config)?;
 
     Ok(Response::default())
 }
 
 /// this is a mock function that enables storage of data
-/// by the contract owner will be replaced by integration
+/// by the contract owner or feeder will be replaced by integration
 /// with on-chain price oracles in the future.
 pub fn append_price(
     deps: DepsMut,
@@ -40,7 +46,7 @@ pub fn append_price(
     let config: Config = read_config(deps.storage)?;
 
     // check permission
-    if info.sender != config.owner {
+    if !config.can_feed(&info.sender) {
         return Err(ContractError::Unauthorized {});
     }
 
@@ -50,7 +56,7 @@ pub fn append_price(
 }
 
 /// this is a mock function that enables storage of data
-/// by the contract owner will be replaced by integration
+/// by the contract owner or feeder will be replaced by integration
 /// with on-chain price oracles in the future.
 pub fn append_multiple_price(
     deps: DepsMut,
@@ -62,7 +68,7 @@ pub fn append_multiple_price(
     let config: Config = read_config(deps.storage)?;
 
     // check permission
-    if info.sender != config.owner {
+    if !config.can_feed(&info.sender) {
         return Err(ContractError::Unauthorized {});
     }
 
@@ -10,6 +10,7 @@ pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
     Ok(ConfigResponse {
         owner: config.owner,
         decimals: config.decimals,
+        feeder: config.feeder,
     })
 }
 
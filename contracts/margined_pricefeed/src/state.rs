@@ -2,10 +2,9 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use cosmwasm_std::{Addr, StdResult, Storage, Timestamp, Uint128};
-use cosmwasm_storage::{singleton, singleton_read};
-use cw_storage_plus::Map;
+use cw_storage_plus::{Item, Map};
 
-pub static KEY_CONFIG: &[u8] = b"config";
+pub const CONFIG: Item<Config> = Item::new("config");
 
 pub const PRICES: Map<String, Vec<PriceData>> = Map::new("prices");
 
@@ -13,14 +12,37 @@ pub const PRICES: Map<String, Vec<PriceData>> = Map::new("prices");
 pub struct Config {
     pub owner: Addr,
     pub decimals: Uint128,
+    // besides the owner, only the feeder may append prices
+    pub feeder: Option<Addr>,
+}
+
+impl Config {
+    pub fn can_feed(&self, sender: &Addr) -> bool {
+        *sender == self.owner || self.feeder.as_ref() == Some(sender)
+    }
 }
 
 pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
-    singleton(storage, KEY_CONFIG).save(config)
+    CONFIG.save(storage, config)
 }
 
 pub fn read_config(storage: &dyn Storage) -> StdResult<Config> {
-    singleton_read(storage, KEY_CONFIG).load()
+    CONFIG.load(storage)
+}
+
+// Deployments from before the contract was versioned kept the config in a
+// cosmwasm-storage singleton, which prefixes its namespace the same way a map
+// does, so it is read as a map with an empty key
+const LEGACY_CONFIG: Map<&[u8], Config> = Map::new("config");
+
+// moves the config of a legacy deployment into the current layout
+pub fn migrate_legacy_storage(storage: &mut dyn Storage) -> StdResult<()> {
+    if let Some(config) = LEGACY_CONFIG.may_load(storage, b"")? {
+        CONFIG.save(storage, &config)?;
+        LEGACY_CONFIG.remove(storage, b"");
+    }
+
+    Ok(())
 }
 
 #[derive(Serialize, Default, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
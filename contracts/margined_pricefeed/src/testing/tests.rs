@@ -27,6 +27,7 @@ fn test_instantiation() {
         ConfigResponse {
             owner: info.sender.clone(),
             decimals: Uint128::from(1_000_000_000 as u128),
+            feeder: None,
         }
     );
 }
@@ -44,6 +45,7 @@ fn test_update_config() {
     // Update the config
     let msg = ExecuteMsg::UpdateConfig {
         owner: Some("addr0001".to_string()),
+        feeder: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -56,6 +58,7 @@ fn test_update_config() {
         ConfigResponse {
             owner: Addr::unchecked("addr0001".to_string()),
             decimals: Uint128::from(1_000_000_000 as u128),
+            feeder: None,
         }
     );
 }
@@ -78,6 +81,7 @@ fn test_set_and_get_price() {
         ConfigResponse {
             owner: info.sender.clone(),
             decimals: Uint128::from(1_000_000_000 as u128),
+            feeder: None,
         }
     );
 
@@ -156,6 +160,7 @@ fn test_set_multiple_price() {
         ConfigResponse {
             owner: info.sender.clone(),
             decimals: Uint128::from(1_000_000_000 as u128),
+            feeder: None,
         }
     );
 
@@ -214,6 +219,7 @@ fn test_get_previous_price() {
         ConfigResponse {
             owner: info.sender.clone(),
             decimals: Uint128::from(1_000_000_000 as u128),
+            feeder: None,
         }
     );
 
@@ -295,6 +301,7 @@ fn test_get_twap_price() {
         ConfigResponse {
             owner: info.sender.clone(),
             decimals: Uint128::from(1_000_000_000 as u128),
+            feeder: None,
         }
     );
 
@@ -381,6 +388,7 @@ fn test_get_twap_variant_price_period() {
         ConfigResponse {
             owner: info.sender.clone(),
             decimals: Uint128::from(1_000_000_000 as u128),
+            feeder: None,
         }
     );
 
@@ -443,6 +451,7 @@ fn test_get_twap_error_zero_interval() {
         ConfigResponse {
             owner: info.sender.clone(),
             decimals: Uint128::from(1_000_000_000 as u128),
+            feeder: None,
         }
     );
 
@@ -480,3 +489,47 @@ fn test_get_twap_error_zero_interval() {
     );
     assert!(res.is_err());
 }
+
+#[test]
+fn test_feeder_can_append_prices() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        oracle_hub_contract: "oracle_hub0000".to_string(),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let msg = ExecuteMsg::AppendPrice {
+        key: "ETHUSD".to_string(),
+        price: Uint128::from(500_000_000u128),
+        timestamp: 1_000_000_000,
+    };
+
+    // no one else may feed prices until authorized by the owner
+    let info = mock_info("feeder0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+
+    let update = ExecuteMsg::UpdateConfig {
+        owner: None,
+        feeder: Some("feeder0000".to_string()),
+    };
+    let info = mock_info("feeder0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, update.clone()).unwrap_err();
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, update).unwrap();
+
+    let info = mock_info("feeder0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(
+        deps.as_ref(),
+        mock_env(),
+        QueryMsg::GetPrice {
+            key: "ETHUSD".to_string(),
+        },
+    )
+    .unwrap();
+    let price: PriceData = from_binary(&res).unwrap();
+    assert_eq!(price.price, Uint128::from(500_000_000u128));
+}
@@ -1,19 +1,32 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Uint128,
+    to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Uint128,
 };
-use margined_perp::margined_vamm::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use cw2::{get_contract_version, set_contract_version};
+use margined_perp::events::{namespace_attributes, VAMM_NAMESPACE};
+use margined_perp::margined_vamm::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 
 use crate::error::ContractError;
-use crate::query::{query_calc_fee, query_output_price, query_spot_price, query_twap_price};
-use crate::state::{store_reserve_snapshot, ReserveSnapshot};
+use crate::query::{
+    query_calc_fee, query_input_price, query_input_twap, query_liquidity_history,
+    query_max_swap_output, query_output_price, query_output_twap, query_spot_price,
+    query_twap_price,
+};
+use crate::state::{migrate_legacy_storage, store_reserve_snapshot, ReserveSnapshot};
 use crate::{
-    handle::{swap_input, swap_output, update_config},
-    query::{query_config, query_state},
+    handle::{
+        accept_ownership, adjust_k, cancel_ownership_proposal, propose_ownership, scale_reserves,
+        set_funding_period, set_open, set_pause, settle_funding, shutdown, swap_input, swap_output,
+        sweep, update_config, validate_funding_period,
+    },
+    query::{query_config, query_settlement_price, query_state},
     state::{store_config, store_state, Config, State},
 };
 
+const CONTRACT_NAME: &str = "crates.io:margined-vamm";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
@@ -21,13 +34,23 @@ pub fn instantiate(
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
+    validate_funding_period(msg.funding_period, msg.funding_buffer_period)?;
+
     let config = Config {
         owner: info.sender,
+        pending_owner: None,
         quote_asset: msg.quote_asset,
         base_asset: msg.base_asset,
         toll_ratio: msg.toll_ratio,
         spread_ratio: msg.spread_ratio,
         decimals: Uint128::from(10u128.pow(msg.decimals as u32)),
+        price_feed: None,
+        fluctuation_limit_ratio: Uint128::zero(),
+        paused: false,
+        engine: msg
+            .engine
+            .map(|engine| deps.api.addr_validate(&engine))
+            .transpose()?,
     };
 
     store_config(deps.storage, &config)?;
@@ -37,6 +60,10 @@ pub fn instantiate(
         quote_asset_reserve: msg.quote_asset_reserve,
         funding_rate: Uint128::zero(), // Initialise the funding rate as 0
         funding_period: msg.funding_period, // Funding period in seconds
+        funding_buffer_period: msg.funding_buffer_period,
+        next_funding_time: env.block.time.seconds() + msg.funding_period,
+        open: true,
+        settlement_price: None,
     };
 
     store_state(deps.storage, &state)?;
@@ -50,9 +77,31 @@ pub fn instantiate(
 
     store_reserve_snapshot(deps.storage, &reserve)?;
 
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     Ok(Response::default())
 }
 
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    // deployments that predate versioning are still in the legacy layout
+    match get_contract_version(deps.storage) {
+        Ok(version) if version.contract != CONTRACT_NAME => {
+            return Err(
+                StdError::generic_err(format!("cannot migrate from {}", version.contract)).into(),
+            );
+        }
+        Ok(_) => {}
+        Err(_) => migrate_legacy_storage(deps.storage)?,
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    let response =
+        Response::new().add_attributes(vec![("action", "migrate"), ("version", CONTRACT_VERSION)]);
+
+    Ok(namespace_attributes(VAMM_NAMESPACE, response))
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
@@ -60,12 +109,22 @@ pub fn execute(
     info: MessageInfo,
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
-    match msg {
+    let response = match msg {
         ExecuteMsg::UpdateConfig {
-            owner,
             toll_ratio,
             spread_ratio,
-        } => update_config(deps, info, owner, toll_ratio, spread_ratio),
+            price_feed,
+            fluctuation_limit_ratio,
+            engine,
+        } => update_config(
+            deps,
+            info,
+            toll_ratio,
+            spread_ratio,
+            price_feed,
+            fluctuation_limit_ratio,
+            engine,
+        ),
         ExecuteMsg::SwapInput {
             direction,
             quote_asset_amount,
@@ -74,7 +133,25 @@ pub fn execute(
             direction,
             base_asset_amount,
         } => swap_output(deps, env, info, direction, base_asset_amount),
-    }
+        ExecuteMsg::ProposeOwnership { owner } => propose_ownership(deps, info, owner),
+        ExecuteMsg::AcceptOwnership {} => accept_ownership(deps, info),
+        ExecuteMsg::CancelOwnershipProposal {} => cancel_ownership_proposal(deps, info),
+        ExecuteMsg::ScaleReserves {
+            quote_asset_reserve,
+        } => scale_reserves(deps, env, info, quote_asset_reserve),
+        ExecuteMsg::AdjustK { multiplier } => adjust_k(deps, env, info, multiplier),
+        ExecuteMsg::SetOpen { open } => set_open(deps, info, open),
+        ExecuteMsg::Shutdown {} => shutdown(deps, info),
+        ExecuteMsg::SetPause { paused } => set_pause(deps, info, paused),
+        ExecuteMsg::Sweep { token, amount } => sweep(deps, info, token, amount),
+        ExecuteMsg::SetFundingPeriod {
+            funding_period,
+            funding_buffer_period,
+        } => set_funding_period(deps, info, funding_period, funding_buffer_period),
+        ExecuteMsg::SettleFunding {} => settle_funding(deps, env, info),
+    }?;
+
+    Ok(namespace_attributes(VAMM_NAMESPACE, response))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -82,13 +159,33 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::Config {} => to_binary(&query_config(deps)?),
         QueryMsg::State {} => to_binary(&query_state(deps)?),
+        QueryMsg::InputPrice { direction, amount } => {
+            to_binary(&query_input_price(deps, direction, amount)?)
+        }
         QueryMsg::OutputPrice { direction, amount } => {
             to_binary(&query_output_price(deps, direction, amount)?)
         }
+        QueryMsg::InputTwap {
+            direction,
+            amount,
+            interval,
+        } => to_binary(&query_input_twap(deps, env, direction, amount, interval)?),
+        QueryMsg::OutputTwap {
+            direction,
+            amount,
+            interval,
+        } => to_binary(&query_output_twap(deps, env, direction, amount, interval)?),
+        QueryMsg::MaxSwapOutput { direction } => {
+            to_binary(&query_max_swap_output(deps, env, direction)?)
+        }
         QueryMsg::CalcFee { quote_asset_amount } => {
             to_binary(&query_calc_fee(deps, quote_asset_amount)?)
         }
         QueryMsg::SpotPrice {} => to_binary(&query_spot_price(deps)?),
+        QueryMsg::SettlementPrice {} => to_binary(&query_settlement_price(deps)?),
         QueryMsg::TwapPrice { interval } => to_binary(&query_twap_price(deps, env, interval)?),
+        QueryMsg::LiquidityHistory { start_after, limit } => {
+            to_binary(&query_liquidity_history(deps, start_after, limit)?)
+        }
     }
 }
@@ -8,4 +8,19 @@ pub enum ContractError {
 
     #[error("Unauthorized")]
     Unauthorized {},
+
+    #[error("Price is over the fluctuation limit")]
+    OverFluctuationLimit {},
+
+    #[error("Swaps are paused")]
+    Paused {},
+
+    #[error("Market is not open")]
+    MarketClosed {},
+
+    #[error("Market has been shut down")]
+    MarketShutdown {},
+
+    #[error("Funding is not due until {next_funding_time}")]
+    FundingNotDue { next_funding_time: u64 },
 }
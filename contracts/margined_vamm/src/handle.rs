@@ -1,21 +1,30 @@
-use cosmwasm_std::{Deps, DepsMut, Env, MessageInfo, Response, StdResult, Storage, Uint128};
+use cosmwasm_std::{
+    to_binary, Deps, DepsMut, Env, MessageInfo, Response, StdError, StdResult, Storage, Uint128,
+    WasmMsg,
+};
+use cw20::Cw20ExecuteMsg;
 
 use crate::{
-    decimals::modulo,
     error::ContractError,
+    invariants::debug_assert_swap,
     state::{
-        read_config, read_state, store_config, store_reserve_snapshot, store_state, Config,
-        ReserveSnapshot, State,
+        read_config, read_reserve_snapshot, read_reserve_snapshot_counter, read_state,
+        store_config, store_liquidity_snapshot, store_reserve_snapshot, store_state, Config,
+        LiquiditySnapshot, ReserveSnapshot, State,
     },
 };
-use margined_perp::margined_vamm::Direction;
+use margined_perp::decimals::{div_d, modulo, mul_d};
+use margined_perp::events::Swap;
+use margined_perp::margined_vamm::{Direction, SwapResponse};
 
 pub fn update_config(
     deps: DepsMut,
     info: MessageInfo,
-    owner: Option<String>,
     toll_ratio: Option<Uint128>,
     spread_ratio: Option<Uint128>,
+    price_feed: Option<String>,
+    fluctuation_limit_ratio: Option<Uint128>,
+    engine: Option<String>,
 ) -> Result<Response, ContractError> {
     let mut config: Config = read_config(deps.storage)?;
 
@@ -24,11 +33,6 @@ pub fn update_config(
         return Err(ContractError::Unauthorized {});
     }
 
-    // change owner of amm
-    if let Some(owner) = owner {
-        config.owner = deps.api.addr_validate(owner.as_str())?;
-    }
-
     // change toll ratio
     if let Some(toll_ratio) = toll_ratio {
         config.toll_ratio = toll_ratio;
@@ -39,50 +43,144 @@ pub fn update_config(
         config.spread_ratio = spread_ratio;
     }
 
+    // change price feed
+    if let Some(price_feed) = price_feed {
+        config.price_feed = Some(deps.api.addr_validate(price_feed.as_str())?);
+    }
+
+    // change fluctuation limit
+    if let Some(fluctuation_limit_ratio) = fluctuation_limit_ratio {
+        config.fluctuation_limit_ratio = fluctuation_limit_ratio;
+    }
+
+    // change the margin engine
+    if let Some(engine) = engine {
+        config.engine = Some(deps.api.addr_validate(engine.as_str())?);
+    }
+
     store_config(deps.storage, &config)?;
 
     Ok(Response::default())
 }
 
-// Function should only be called by the margin engine
+// Proposes the next owner, who takes over once they accept. A new proposal
+// replaces the last
+pub fn propose_ownership(
+    deps: DepsMut,
+    info: MessageInfo,
+    owner: String,
+) -> Result<Response, ContractError> {
+    let mut config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let owner = deps.api.addr_validate(&owner)?;
+    config.pending_owner = Some(owner.clone());
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "propose_ownership"),
+        ("owner", owner.as_str()),
+    ]))
+}
+
+// Hands the vamm to the proposed owner, only they can accept
+pub fn accept_ownership(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let mut config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if config.pending_owner.as_ref() != Some(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    config.owner = info.sender;
+    config.pending_owner = None;
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "accept_ownership"),
+        ("owner", config.owner.as_str()),
+    ]))
+}
+
+// Withdraws the proposal of the next owner
+pub fn cancel_ownership_proposal(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let mut config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    config.pending_owner = None;
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "cancel_ownership_proposal")]))
+}
+
+// Function can only be called by the margin engine
 pub fn swap_input(
     deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     direction: Direction,
     quote_asset_amount: Uint128,
 ) -> Result<Response, ContractError> {
+    require_engine(deps.storage, &info)?;
+    require_tradable(deps.storage)?;
+
     let base_asset_amount =
         get_input_price_with_reserves(deps.as_ref(), &direction, quote_asset_amount)?;
 
     update_reserve(
         deps.storage,
-        env,
-        direction,
+        env.clone(),
+        direction.clone(),
         quote_asset_amount,
         base_asset_amount,
     )?;
-
-    Ok(Response::new().add_attributes(vec![
-        ("action", "swap_input"),
-        ("input", &quote_asset_amount.to_string()),
-        ("output", &base_asset_amount.to_string()),
-    ]))
+    check_fluctuation_limit(deps.storage, &env)?;
+
+    let state = read_state(deps.storage)?;
+    Ok(Response::new()
+        .set_data(to_binary(&SwapResponse {
+            input: quote_asset_amount,
+            output: base_asset_amount,
+        })?)
+        .add_attributes(
+            Swap {
+                direction,
+                input: quote_asset_amount,
+                output: base_asset_amount,
+                quote_asset_reserve: state.quote_asset_reserve,
+                base_asset_reserve: state.base_asset_reserve,
+            }
+            .attributes("swap_input"),
+        ))
 }
 
-// Function should only be called by the margin engine
+// Function can only be called by the margin engine
 pub fn swap_output(
     deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
+    info: MessageInfo,
     direction: Direction,
     base_asset_amount: Uint128,
 ) -> Result<Response, ContractError> {
+    require_engine(deps.storage, &info)?;
+    require_tradable(deps.storage)?;
+
     let quote_asset_amount =
         get_output_price_with_reserves(deps.as_ref(), &direction, base_asset_amount)?;
 
     // flip direction when updating reserve
-    let mut update_direction = direction;
+    let mut update_direction = direction.clone();
     if update_direction == Direction::AddToAmm {
         update_direction = Direction::RemoveFromAmm;
     } else {
@@ -91,16 +189,296 @@ pub fn swap_output(
 
     update_reserve(
         deps.storage,
-        env,
+        env.clone(),
         update_direction,
         quote_asset_amount,
         base_asset_amount,
     )?;
+    check_fluctuation_limit(deps.storage, &env)?;
+
+    let state = read_state(deps.storage)?;
+    Ok(Response::new()
+        .set_data(to_binary(&SwapResponse {
+            input: base_asset_amount,
+            output: quote_asset_amount,
+        })?)
+        .add_attributes(
+            Swap {
+                direction,
+                input: base_asset_amount,
+                output: quote_asset_amount,
+                quote_asset_reserve: state.quote_asset_reserve,
+                base_asset_reserve: state.base_asset_reserve,
+            }
+            .attributes("swap_output"),
+        ))
+}
+
+pub fn set_open(deps: DepsMut, info: MessageInfo, open: bool) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check permission, markets listed through the engine are owned by it
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    // a shut down market stays closed
+    let mut state: State = read_state(deps.storage)?;
+    if state.settlement_price.is_some() {
+        return Err(ContractError::MarketShutdown {});
+    }
+    state.open = open;
+    store_state(deps.storage, &state)?;
+
+    Ok(Response::new().add_attributes(vec![("action", "set_open"), ("open", &open.to_string())]))
+}
+
+// Closes the market permanently, positions are settled in the engine at the
+// spot price recorded here
+pub fn shutdown(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut state: State = read_state(deps.storage)?;
+    if state.settlement_price.is_some() {
+        return Err(ContractError::MarketShutdown {});
+    }
+
+    let settlement_price = div_d(
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+        config.decimals,
+    )?;
+    state.open = false;
+    state.settlement_price = Some(settlement_price);
+    store_state(deps.storage, &state)?;
 
     Ok(Response::new().add_attributes(vec![
-        ("action", "swap_output"),
-        ("input", &base_asset_amount.to_string()),
-        ("output", &quote_asset_amount.to_string()),
+        ("action", "shutdown"),
+        ("settlement_price", &settlement_price.to_string()),
+    ]))
+}
+
+pub fn set_pause(
+    deps: DepsMut,
+    info: MessageInfo,
+    paused: bool,
+) -> Result<Response, ContractError> {
+    let mut config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    config.paused = paused;
+    store_config(deps.storage, &config)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_pause"),
+        ("paused", &paused.to_string()),
+    ]))
+}
+
+pub fn set_funding_period(
+    deps: DepsMut,
+    info: MessageInfo,
+    funding_period: u64,
+    funding_buffer_period: u64,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    validate_funding_period(funding_period, funding_buffer_period)?;
+
+    let mut state: State = read_state(deps.storage)?;
+    state.funding_period = funding_period;
+    state.funding_buffer_period = funding_buffer_period;
+    store_state(deps.storage, &state)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "set_funding_period"),
+        ("funding_period", &funding_period.to_string()),
+        ("funding_buffer_period", &funding_buffer_period.to_string()),
+    ]))
+}
+
+// Moves the funding schedule on once a round is due. The next round follows a
+// funding period after this one was due, but no sooner than the buffer period
+// from now, so a round settled late is not followed straight away by another
+pub fn settle_funding(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    require_engine(deps.storage, &info)?;
+
+    let mut state: State = read_state(deps.storage)?;
+    let now = env.block.time.seconds();
+    if now < state.next_funding_time {
+        return Err(ContractError::FundingNotDue {
+            next_funding_time: state.next_funding_time,
+        });
+    }
+
+    state.next_funding_time =
+        (state.next_funding_time + state.funding_period).max(now + state.funding_buffer_period);
+    store_state(deps.storage, &state)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "settle_funding"),
+        ("next_funding_time", &state.next_funding_time.to_string()),
+    ]))
+}
+
+// funding is settled at least once a period, with rounds settled late pushing
+// the next back by no more than the period
+pub fn validate_funding_period(funding_period: u64, funding_buffer_period: u64) -> StdResult<()> {
+    if funding_period == 0 || funding_buffer_period > funding_period {
+        return Err(StdError::generic_err(
+            "funding period must be above zero and cannot be shorter than the buffer period",
+        ));
+    }
+
+    Ok(())
+}
+
+// Scales the reserves so the quote reserve is the amount given, the base
+// reserve moves in proportion so the price is unchanged
+pub fn scale_reserves(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    quote_asset_reserve: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    if quote_asset_reserve.is_zero() {
+        return Err(StdError::generic_err("quote asset reserve cannot be zero").into());
+    }
+
+    let state = rescale(deps.storage, env, quote_asset_reserve)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "scale_reserves"),
+        (
+            "quote_asset_reserve",
+            &state.quote_asset_reserve.to_string(),
+        ),
+        ("base_asset_reserve", &state.base_asset_reserve.to_string()),
+    ]))
+}
+
+// Scales the reserves by the multiplier, a ratio where 100% is equal to the
+// decimals, so the liquidity can follow the open interest
+pub fn adjust_k(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    multiplier: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let quote_asset_reserve = read_state(deps.storage)?
+        .quote_asset_reserve
+        .multiply_ratio(multiplier, config.decimals);
+    if quote_asset_reserve.is_zero() {
+        return Err(StdError::generic_err("multiplier leaves the reserves empty").into());
+    }
+
+    let state = rescale(deps.storage, env, quote_asset_reserve)?;
+
+    Ok(Response::new().add_attributes(vec![
+        ("action", "adjust_k"),
+        ("multiplier", &multiplier.to_string()),
+        (
+            "quote_asset_reserve",
+            &state.quote_asset_reserve.to_string(),
+        ),
+        ("base_asset_reserve", &state.base_asset_reserve.to_string()),
+    ]))
+}
+
+// sets the quote reserve and moves the base reserve in proportion, then
+// records the change to k and the new reserves as snapshots
+fn rescale(storage: &mut dyn Storage, env: Env, quote_asset_reserve: Uint128) -> StdResult<State> {
+    let mut state: State = read_state(storage)?;
+    let before = state.clone();
+    state.base_asset_reserve = state
+        .base_asset_reserve
+        .multiply_ratio(quote_asset_reserve, state.quote_asset_reserve);
+    state.quote_asset_reserve = quote_asset_reserve;
+
+    store_state(storage, &state)?;
+
+    store_liquidity_snapshot(
+        storage,
+        &LiquiditySnapshot {
+            quote_asset_reserve_before: before.quote_asset_reserve,
+            base_asset_reserve_before: before.base_asset_reserve,
+            quote_asset_reserve: state.quote_asset_reserve,
+            base_asset_reserve: state.base_asset_reserve,
+            timestamp: env.block.time,
+            block_height: env.block.height,
+        },
+    )?;
+
+    add_reserve_snapshot(
+        storage,
+        env,
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+    )?;
+
+    Ok(state)
+}
+
+// Transfers tokens sent to the vamm by mistake to the owner
+pub fn sweep(
+    deps: DepsMut,
+    info: MessageInfo,
+    token: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    let config: Config = read_config(deps.storage)?;
+
+    // check permission
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let token = deps.api.addr_validate(&token)?;
+    let msg = WasmMsg::Execute {
+        contract_addr: token.to_string(),
+        funds: vec![],
+        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+            recipient: config.owner.to_string(),
+            amount,
+        })?,
+    };
+
+    Ok(Response::new().add_message(msg).add_attributes(vec![
+        ("action", "sweep"),
+        ("token", token.as_str()),
+        ("amount", &amount.to_string()),
     ]))
 }
 
@@ -112,39 +490,51 @@ pub fn get_input_price_with_reserves(
     let state: State = read_state(deps.storage)?;
     let config: Config = read_config(deps.storage)?;
 
+    calc_input_price(
+        direction,
+        quote_asset_amount,
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+        config.decimals,
+    )
+}
+
+// returns the base asset the quote asset amount swaps for against the reserves
+pub fn calc_input_price(
+    direction: &Direction,
+    quote_asset_amount: Uint128,
+    quote_asset_reserve: Uint128,
+    base_asset_reserve: Uint128,
+    decimals: Uint128,
+) -> StdResult<Uint128> {
     if quote_asset_amount == Uint128::zero() {
         Uint128::zero();
     }
 
     // k = x * y (divided by decimal places)
-    let invariant_k = state
-        .quote_asset_reserve
-        .checked_mul(state.base_asset_reserve)?
-        .checked_div(config.decimals)?;
+    let invariant_k = mul_d(quote_asset_reserve, base_asset_reserve, decimals)?;
 
     let quote_asset_after: Uint128;
     let base_asset_after: Uint128;
 
     match direction {
         Direction::AddToAmm => {
-            quote_asset_after = state.quote_asset_reserve.checked_add(quote_asset_amount)?;
+            quote_asset_after = quote_asset_reserve.checked_add(quote_asset_amount)?;
         }
         Direction::RemoveFromAmm => {
-            quote_asset_after = state.quote_asset_reserve.checked_sub(quote_asset_amount)?;
+            quote_asset_after = quote_asset_reserve.checked_sub(quote_asset_amount)?;
         }
     }
 
-    base_asset_after = invariant_k
-        .checked_mul(config.decimals)?
-        .checked_div(quote_asset_after)?;
+    base_asset_after = div_d(invariant_k, quote_asset_after, decimals)?;
 
-    let mut base_asset_bought = if base_asset_after > state.base_asset_reserve {
-        base_asset_after - state.base_asset_reserve
+    let mut base_asset_bought = if base_asset_after > base_asset_reserve {
+        base_asset_after - base_asset_reserve
     } else {
-        state.base_asset_reserve - base_asset_after
+        base_asset_reserve - base_asset_after
     };
 
-    let remainder = modulo(invariant_k, quote_asset_after);
+    let remainder = modulo(invariant_k, quote_asset_after, decimals)?;
     if remainder != Uint128::zero() {
         if *direction == Direction::AddToAmm {
             base_asset_bought = base_asset_bought.checked_sub(Uint128::new(1u128))?;
@@ -164,36 +554,48 @@ pub fn get_output_price_with_reserves(
     let state: State = read_state(deps.storage)?;
     let config: Config = read_config(deps.storage)?;
 
+    calc_output_price(
+        direction,
+        base_asset_amount,
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+        config.decimals,
+    )
+}
+
+// returns the quote asset the base asset amount swaps for against the reserves
+pub fn calc_output_price(
+    direction: &Direction,
+    base_asset_amount: Uint128,
+    quote_asset_reserve: Uint128,
+    base_asset_reserve: Uint128,
+    decimals: Uint128,
+) -> StdResult<Uint128> {
     if base_asset_amount == Uint128::zero() {
         Uint128::zero();
     }
-    let invariant_k = state
-        .quote_asset_reserve
-        .checked_mul(state.base_asset_reserve)?
-        .checked_div(config.decimals)?;
+    let invariant_k = mul_d(quote_asset_reserve, base_asset_reserve, decimals)?;
 
     let quote_asset_after: Uint128;
     let base_asset_after: Uint128;
 
     match direction {
         Direction::AddToAmm => {
-            base_asset_after = state.base_asset_reserve.checked_add(base_asset_amount)?;
+            base_asset_after = base_asset_reserve.checked_add(base_asset_amount)?;
         }
         Direction::RemoveFromAmm => {
-            base_asset_after = state.base_asset_reserve.checked_sub(base_asset_amount)?;
+            base_asset_after = base_asset_reserve.checked_sub(base_asset_amount)?;
         }
     }
-    quote_asset_after = invariant_k
-        .checked_mul(config.decimals)?
-        .checked_div(base_asset_after)?;
+    quote_asset_after = div_d(invariant_k, base_asset_after, decimals)?;
 
-    let mut quote_asset_sold = if quote_asset_after > state.quote_asset_reserve {
-        quote_asset_after - state.quote_asset_reserve
+    let mut quote_asset_sold = if quote_asset_after > quote_asset_reserve {
+        quote_asset_after - quote_asset_reserve
     } else {
-        state.quote_asset_reserve - quote_asset_after
+        quote_asset_reserve - quote_asset_after
     };
 
-    let remainder = modulo(invariant_k, base_asset_after);
+    let remainder = modulo(invariant_k, base_asset_after, decimals)?;
     if remainder != Uint128::zero() {
         if *direction == Direction::AddToAmm {
             quote_asset_sold = quote_asset_sold.checked_sub(Uint128::from(1u128))?;
@@ -230,6 +632,7 @@ fn update_reserve(
                 state.quote_asset_reserve.checked_sub(quote_asset_amount)?;
         }
     }
+    debug_assert_swap(storage, &state, &update_state);
 
     store_state(storage, &update_state)?;
 
@@ -259,3 +662,82 @@ fn add_reserve_snapshot(
 
     Ok(Response::default())
 }
+
+// Returns the lowest and highest prices allowed by the fluctuation limit this
+// block, around the price at the start of the block, i.e. the last snapshot of
+// an earlier block, or the first snapshot if there is none
+pub fn fluctuation_bounds(
+    storage: &dyn Storage,
+    env: &Env,
+) -> StdResult<Option<(Uint128, Uint128)>> {
+    let config: Config = read_config(storage)?;
+    if config.fluctuation_limit_ratio.is_zero() {
+        return Ok(None);
+    }
+
+    let mut counter = read_reserve_snapshot_counter(storage)?;
+    let mut snapshot = read_reserve_snapshot(storage, counter)?;
+    while snapshot.block_height >= env.block.height && counter > 1 {
+        counter -= 1;
+        snapshot = read_reserve_snapshot(storage, counter)?;
+    }
+
+    let reference_price = div_d(
+        snapshot.quote_asset_reserve,
+        snapshot.base_asset_reserve,
+        config.decimals,
+    )?;
+    let limit = mul_d(
+        reference_price,
+        config.fluctuation_limit_ratio,
+        config.decimals,
+    )?;
+
+    Ok(Some((
+        reference_price.saturating_sub(limit),
+        reference_price.checked_add(limit)?,
+    )))
+}
+
+// Errors if the spot price has moved further than the fluctuation limit
+fn check_fluctuation_limit(storage: &dyn Storage, env: &Env) -> Result<(), ContractError> {
+    let (lower, upper) = match fluctuation_bounds(storage, env)? {
+        Some(bounds) => bounds,
+        None => return Ok(()),
+    };
+
+    let config: Config = read_config(storage)?;
+    let state: State = read_state(storage)?;
+    let price = div_d(
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+        config.decimals,
+    )?;
+    if price > upper || price < lower {
+        return Err(ContractError::OverFluctuationLimit {});
+    }
+
+    Ok(())
+}
+
+// swaps can only come from the margin engine
+fn require_engine(storage: &dyn Storage, info: &MessageInfo) -> Result<(), ContractError> {
+    if read_config(storage)?.engine.as_ref() != Some(&info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    Ok(())
+}
+
+// swaps need the market to be open and not paused
+fn require_tradable(storage: &dyn Storage) -> Result<(), ContractError> {
+    if !read_state(storage)?.open {
+        return Err(ContractError::MarketClosed {});
+    }
+
+    if read_config(storage)?.paused {
+        return Err(ContractError::Paused {});
+    }
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use cosmwasm_std::{StdResult, Storage, Uint128};
+use margined_perp::decimals::mul_d;
+
+use crate::state::{read_config, State};
+
+// invariants of the reserves, checked after every swap in debug builds where
+// the tests run and skipped on chain
+
+// returns k, the product of the reserves in the vamm's decimals, as the swaps
+// price against it
+pub fn invariant_k(state: &State, decimals: Uint128) -> StdResult<Uint128> {
+    mul_d(
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+        decimals,
+    )
+}
+
+// swaps round the reserve they solve for towards the pool, so that no swap
+// leaves k lower than it found it or drains a reserve
+pub fn debug_assert_swap(storage: &dyn Storage, before: &State, after: &State) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+
+    let decimals = read_config(storage).unwrap().decimals;
+    let (k_before, k_after) = match (invariant_k(before, decimals), invariant_k(after, decimals)) {
+        (Ok(k_before), Ok(k_after)) => (k_before, k_after),
+        // the swap itself would already have overflowed
+        _ => return,
+    };
+
+    debug_assert!(
+        k_after >= k_before,
+        "swap decreased k from {} to {}",
+        k_before,
+        k_after
+    );
+    debug_assert!(
+        !after.quote_asset_reserve.is_zero() && !after.base_asset_reserve.is_zero(),
+        "swap drained the reserves"
+    );
+}
@@ -1,7 +1,7 @@
 pub mod contract;
-mod decimals;
 mod error;
 mod handle;
+mod invariants;
 mod query;
 mod state;
 
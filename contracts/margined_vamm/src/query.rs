@@ -1,10 +1,19 @@
 use cosmwasm_std::{Deps, Env, StdResult, Uint128};
-use margined_perp::margined_vamm::{CalcFeeResponse, ConfigResponse, Direction, StateResponse};
+use margined_perp::decimals::{div_d, mul_d, sqrt};
+use margined_perp::margined_vamm::{
+    CalcFeeResponse, ConfigResponse, Direction, LiquidityHistoryResponse,
+    LiquiditySnapshotResponse, SettlementPriceResponse, StateResponse,
+};
+use margined_perp::pagination::{next_key, page_limit, DEFAULT_LIMIT, MAX_LIMIT};
 
 use crate::{
-    handle::get_output_price_with_reserves,
+    handle::{
+        calc_input_price, calc_output_price, fluctuation_bounds, get_input_price_with_reserves,
+        get_output_price_with_reserves,
+    },
     state::{
-        read_config, read_reserve_snapshot, read_reserve_snapshot_counter, read_state, Config,
+        read_config, read_liquidity_snapshot_counter, read_liquidity_snapshots,
+        read_reserve_snapshot, read_reserve_snapshot_counter, read_state, Config, ReserveSnapshot,
         State,
     },
 };
@@ -15,11 +24,16 @@ pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
 
     Ok(ConfigResponse {
         owner: config.owner,
+        pending_owner: config.pending_owner,
         quote_asset: config.quote_asset,
         base_asset: config.base_asset,
         toll_ratio: config.toll_ratio,
         spread_ratio: config.spread_ratio,
         decimals: config.decimals,
+        price_feed: config.price_feed,
+        fluctuation_limit_ratio: config.fluctuation_limit_ratio,
+        paused: config.paused,
+        engine: config.engine,
     })
 }
 
@@ -32,9 +46,28 @@ pub fn query_state(deps: Deps) -> StdResult<StateResponse> {
         base_asset_reserve: state.base_asset_reserve,
         funding_rate: state.funding_rate,
         funding_period: state.funding_period,
+        funding_buffer_period: state.funding_buffer_period,
+        next_funding_time: state.next_funding_time,
+        open: state.open,
+    })
+}
+
+/// Queries the price positions settle at, if the market has been shut down
+pub fn query_settlement_price(deps: Deps) -> StdResult<SettlementPriceResponse> {
+    let state: State = read_state(deps.storage)?;
+
+    Ok(SettlementPriceResponse {
+        settlement_price: state.settlement_price,
     })
 }
 
+/// Queries input price, i.e. the base asset a quote asset amount swaps for
+pub fn query_input_price(deps: Deps, direction: Direction, amount: Uint128) -> StdResult<Uint128> {
+    let res = get_input_price_with_reserves(deps, &direction, amount)?;
+
+    Ok(res)
+}
+
 /// Queries output price
 pub fn query_output_price(deps: Deps, direction: Direction, amount: Uint128) -> StdResult<Uint128> {
     let res = get_output_price_with_reserves(deps, &direction, amount)?;
@@ -42,15 +75,99 @@ pub fn query_output_price(deps: Deps, direction: Direction, amount: Uint128) ->
     Ok(res)
 }
 
+/// Queries the twap of the input price over the interval, i.e. the base asset
+/// a quote asset amount would have swapped for against each reserve snapshot
+pub fn query_input_twap(
+    deps: Deps,
+    env: Env,
+    direction: Direction,
+    amount: Uint128,
+    interval: u64,
+) -> StdResult<Uint128> {
+    let config: Config = read_config(deps.storage)?;
+
+    calc_twap(deps, env, interval, |snapshot| {
+        calc_input_price(
+            &direction,
+            amount,
+            snapshot.quote_asset_reserve,
+            snapshot.base_asset_reserve,
+            config.decimals,
+        )
+    })
+}
+
+/// Queries the twap of the output price over the interval
+pub fn query_output_twap(
+    deps: Deps,
+    env: Env,
+    direction: Direction,
+    amount: Uint128,
+    interval: u64,
+) -> StdResult<Uint128> {
+    let config: Config = read_config(deps.storage)?;
+
+    calc_twap(deps, env, interval, |snapshot| {
+        calc_output_price(
+            &direction,
+            amount,
+            snapshot.quote_asset_reserve,
+            snapshot.base_asset_reserve,
+            config.decimals,
+        )
+    })
+}
+
+/// Queries the most base that can be swapped out in the direction this block
+/// before the price leaves the fluctuation limit, none if it is unlimited
+pub fn query_max_swap_output(
+    deps: Deps,
+    env: Env,
+    direction: Direction,
+) -> StdResult<Option<Uint128>> {
+    let (lower, upper) = match fluctuation_bounds(deps.storage, &env)? {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+
+    let config: Config = read_config(deps.storage)?;
+    let state: State = read_state(deps.storage)?;
+
+    // with k = quote * base the price at a base reserve b is k / b^2, so the
+    // bounds on the price are bounds of sqrt(k / price) on the base reserve
+    let k = mul_d(
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+        config.decimals,
+    )?;
+    let max = match direction {
+        Direction::AddToAmm => {
+            if lower.is_zero() {
+                return Ok(None);
+            }
+            sqrt(div_d(k, lower, config.decimals)?, config.decimals)?
+                .saturating_sub(state.base_asset_reserve)
+        }
+        // the reserve is rounded up so the price stays below the bound
+        Direction::RemoveFromAmm => state.base_asset_reserve.saturating_sub(
+            sqrt(div_d(k, upper, config.decimals)?, config.decimals)?
+                .checked_add(Uint128::from(1u128))?,
+        ),
+    };
+
+    Ok(Some(max))
+}
+
 /// Queries spot price of the vAMM
 pub fn query_spot_price(deps: Deps) -> StdResult<Uint128> {
     let config: Config = read_config(deps.storage)?;
     let state: State = read_state(deps.storage)?;
 
-    let res = state
-        .quote_asset_reserve
-        .checked_mul(config.decimals)?
-        .checked_div(state.base_asset_reserve)?;
+    let res = div_d(
+        state.quote_asset_reserve,
+        state.base_asset_reserve,
+        config.decimals,
+    )?;
 
     Ok(res)
 }
@@ -70,12 +187,8 @@ pub fn query_calc_fee(deps: Deps, quote_asset_amount: Uint128) -> StdResult<Calc
     if quote_asset_amount != Uint128::zero() {
         let config: Config = read_config(deps.storage)?;
 
-        res.toll_fee = quote_asset_amount
-            .checked_mul(config.toll_ratio)?
-            .checked_div(config.decimals)?;
-        res.spread_fee = quote_asset_amount
-            .checked_mul(config.spread_ratio)?
-            .checked_div(config.decimals)?;
+        res.toll_fee = mul_d(quote_asset_amount, config.toll_ratio, config.decimals)?;
+        res.spread_fee = mul_d(quote_asset_amount, config.spread_ratio, config.decimals)?;
     }
 
     Ok(res)
@@ -84,14 +197,26 @@ pub fn query_calc_fee(deps: Deps, quote_asset_amount: Uint128) -> StdResult<Calc
 /// Calculates the TWAP of the AMM reserves
 fn calc_reserve_twap(deps: Deps, env: Env, interval: u64) -> StdResult<Uint128> {
     let config: Config = read_config(deps.storage)?;
+
+    calc_twap(deps, env, interval, |snapshot| {
+        div_d(
+            snapshot.quote_asset_reserve,
+            snapshot.base_asset_reserve,
+            config.decimals,
+        )
+    })
+}
+
+/// Calculates the TWAP of a price taken from each reserve snapshot
+fn calc_twap<F>(deps: Deps, env: Env, interval: u64, price: F) -> StdResult<Uint128>
+where
+    F: Fn(&ReserveSnapshot) -> StdResult<Uint128>,
+{
     let mut counter = read_reserve_snapshot_counter(deps.storage).unwrap();
     let current_snapshot = read_reserve_snapshot(deps.storage, counter);
     let mut current_snapshot = current_snapshot.unwrap();
 
-    let mut current_price = current_snapshot
-        .quote_asset_reserve
-        .checked_mul(config.decimals)?
-        .checked_div(current_snapshot.base_asset_reserve)?;
+    let mut current_price = price(&current_snapshot)?;
     if interval == 0 {
         return Ok(current_price);
     }
@@ -119,10 +244,7 @@ fn calc_reserve_twap(deps: Deps, env: Env, interval: u64) -> StdResult<Uint128>
             return Ok(weighted_price.checked_div(period)?);
         }
         current_snapshot = read_reserve_snapshot(deps.storage, counter).unwrap();
-        current_price = current_snapshot
-            .quote_asset_reserve
-            .checked_mul(config.decimals)?
-            .checked_div(current_snapshot.base_asset_reserve)?;
+        current_price = price(&current_snapshot)?;
 
         if current_snapshot.timestamp.seconds() <= base_timestamp {
             let delta_timestamp =
@@ -150,3 +272,31 @@ fn calc_reserve_twap(deps: Deps, env: Env, interval: u64) -> StdResult<Uint128>
 
     Ok(weighted_price.checked_div(Uint128::from(interval))?)
 }
+
+/// Queries the changes to k after the given index, oldest first
+pub fn query_liquidity_history(
+    deps: Deps,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<LiquidityHistoryResponse> {
+    let limit = page_limit(limit, DEFAULT_LIMIT, MAX_LIMIT);
+
+    let snapshots = read_liquidity_snapshots(deps.storage, start_after, limit)?
+        .into_iter()
+        .map(|(index, snapshot)| LiquiditySnapshotResponse {
+            index,
+            quote_asset_reserve_before: snapshot.quote_asset_reserve_before,
+            base_asset_reserve_before: snapshot.base_asset_reserve_before,
+            quote_asset_reserve: snapshot.quote_asset_reserve,
+            base_asset_reserve: snapshot.base_asset_reserve,
+            timestamp: snapshot.timestamp,
+        })
+        .collect::<Vec<LiquiditySnapshotResponse>>();
+    let next_key = next_key(&snapshots, limit, |snapshot| snapshot.index);
+
+    Ok(LiquidityHistoryResponse {
+        latest_index: read_liquidity_snapshot_counter(deps.storage)?,
+        snapshots,
+        next_key,
+    })
+}
@@ -1,30 +1,43 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, StdResult, Storage, Timestamp, Uint128};
-use cosmwasm_storage::{bucket, bucket_read, singleton, singleton_read};
+use cosmwasm_std::{Addr, Order, StdResult, Storage, Timestamp, Uint128};
+use cw_storage_plus::{Bound, Item, Map, U64Key};
 
-pub static KEY_CONFIG: &[u8] = b"config";
-pub static KEY_STATE: &[u8] = b"state";
-pub static KEY_RESERVE_SNAPSHOT: &[u8] = b"reserve_snapshot";
-pub static KEY_RESERVE_SNAPSHOT_COUNTER: &[u8] = b"reserve_snapshot_counter";
+pub const CONFIG: Item<Config> = Item::new("config");
+pub const STATE: Item<State> = Item::new("state");
+pub const RESERVE_SNAPSHOTS: Map<U64Key, ReserveSnapshot> = Map::new("reserve_snapshot");
+pub const RESERVE_SNAPSHOT_COUNTER: Item<u64> = Item::new("reserve_snapshot_counter");
+pub const LIQUIDITY_SNAPSHOTS: Map<U64Key, LiquiditySnapshot> = Map::new("liquidity_snapshots");
+pub const LIQUIDITY_SNAPSHOT_COUNTER: Item<u64> = Item::new("liquidity_snapshot_counter");
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub owner: Addr,
+    // proposed as the next owner, who must accept before taking over
+    pub pending_owner: Option<Addr>,
     pub quote_asset: String,
     pub base_asset: String,
     pub decimals: Uint128,
     pub toll_ratio: Uint128,
     pub spread_ratio: Uint128,
+    // the index price of the quote asset is read from this price feed
+    pub price_feed: Option<Addr>,
+    // the most a swap may move the price from where it started the block,
+    // zero disables the limit
+    pub fluctuation_limit_ratio: Uint128,
+    // swaps are halted while paused
+    pub paused: bool,
+    // only the margin engine may swap
+    pub engine: Option<Addr>,
 }
 
 pub fn store_config(storage: &mut dyn Storage, config: &Config) -> StdResult<()> {
-    singleton(storage, KEY_CONFIG).save(config)
+    CONFIG.save(storage, config)
 }
 
 pub fn read_config(storage: &dyn Storage) -> StdResult<Config> {
-    singleton_read(storage, KEY_CONFIG).load()
+    CONFIG.load(storage)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -33,14 +46,25 @@ pub struct State {
     pub base_asset_reserve: Uint128,
     pub funding_rate: Uint128,
     pub funding_period: u64,
+    // a round settled late pushes the next one back to at least this long
+    // after it
+    #[serde(default)]
+    pub funding_buffer_period: u64,
+    // funding cannot be settled before this time
+    #[serde(default)]
+    pub next_funding_time: u64,
+    // swaps are only allowed while the market is open
+    pub open: bool,
+    // the spot price when the market was shut down, set only once it is
+    pub settlement_price: Option<Uint128>,
 }
 
 pub fn store_state(storage: &mut dyn Storage, state: &State) -> StdResult<()> {
-    singleton(storage, KEY_STATE).save(state)
+    STATE.save(storage, state)
 }
 
 pub fn read_state(storage: &dyn Storage) -> StdResult<State> {
-    singleton_read(storage, KEY_STATE).load()
+    STATE.load(storage)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
@@ -52,7 +76,7 @@ pub struct ReserveSnapshot {
 }
 
 pub fn read_reserve_snapshot(storage: &dyn Storage, height: u64) -> StdResult<ReserveSnapshot> {
-    bucket_read(storage, KEY_RESERVE_SNAPSHOT).load(&height.to_be_bytes())
+    RESERVE_SNAPSHOTS.load(storage, U64Key::new(height))
 }
 
 pub fn store_reserve_snapshot(
@@ -63,19 +87,97 @@ pub fn store_reserve_snapshot(
 
     let height = read_reserve_snapshot_counter(storage)?;
 
-    bucket(storage, KEY_RESERVE_SNAPSHOT).save(&height.to_be_bytes(), reserve_snapshot)?;
+    RESERVE_SNAPSHOTS.save(storage, U64Key::new(height), reserve_snapshot)?;
 
     Ok(())
 }
 
 pub fn read_reserve_snapshot_counter(storage: &dyn Storage) -> StdResult<u64> {
-    Ok(singleton_read(storage, KEY_RESERVE_SNAPSHOT_COUNTER)
-        .may_load()?
+    Ok(RESERVE_SNAPSHOT_COUNTER
+        .may_load(storage)?
         .unwrap_or_default())
 }
 
 pub fn increment_reserve_snapshot_counter(storage: &mut dyn Storage) -> StdResult<()> {
     let val = read_reserve_snapshot_counter(storage)? + 1;
 
-    singleton(storage, KEY_RESERVE_SNAPSHOT_COUNTER).save(&val)
+    RESERVE_SNAPSHOT_COUNTER.save(storage, &val)
+}
+
+// the reserves either side of a change to k
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquiditySnapshot {
+    pub quote_asset_reserve_before: Uint128,
+    pub base_asset_reserve_before: Uint128,
+    pub quote_asset_reserve: Uint128,
+    pub base_asset_reserve: Uint128,
+    pub timestamp: Timestamp,
+    pub block_height: u64,
+}
+
+// stores the snapshot under the next index, counting from one, and returns
+// that index
+pub fn store_liquidity_snapshot(
+    storage: &mut dyn Storage,
+    snapshot: &LiquiditySnapshot,
+) -> StdResult<u64> {
+    let index = read_liquidity_snapshot_counter(storage)? + 1;
+    LIQUIDITY_SNAPSHOTS.save(storage, U64Key::new(index), snapshot)?;
+    LIQUIDITY_SNAPSHOT_COUNTER.save(storage, &index)?;
+
+    Ok(index)
+}
+
+pub fn read_liquidity_snapshot_counter(storage: &dyn Storage) -> StdResult<u64> {
+    Ok(LIQUIDITY_SNAPSHOT_COUNTER
+        .may_load(storage)?
+        .unwrap_or_default())
+}
+
+pub fn read_liquidity_snapshots(
+    storage: &dyn Storage,
+    start_after: Option<u64>,
+    limit: usize,
+) -> StdResult<Vec<(u64, LiquiditySnapshot)>> {
+    LIQUIDITY_SNAPSHOTS
+        .range(
+            storage,
+            start_after.map(Bound::exclusive_int),
+            None,
+            Order::Ascending,
+        )
+        .take(limit)
+        .map(|item| {
+            let (key, snapshot) = item?;
+            let mut index = [0u8; 8];
+            index.copy_from_slice(&key);
+            Ok((u64::from_be_bytes(index), snapshot))
+        })
+        .collect()
+}
+
+// Deployments from before the contract was versioned kept their config, state
+// and snapshot counter in cosmwasm-storage singletons. A singleton prefixes its
+// namespace the same way a map does, so each is read as a map with an empty
+// key. The snapshots themselves are already laid out as the map expects
+const LEGACY_CONFIG: Map<&[u8], Config> = Map::new("config");
+const LEGACY_STATE: Map<&[u8], State> = Map::new("state");
+const LEGACY_RESERVE_SNAPSHOT_COUNTER: Map<&[u8], u64> = Map::new("reserve_snapshot_counter");
+
+// moves the singletons of a legacy deployment into the current layout
+pub fn migrate_legacy_storage(storage: &mut dyn Storage) -> StdResult<()> {
+    if let Some(config) = LEGACY_CONFIG.may_load(storage, b"")? {
+        CONFIG.save(storage, &config)?;
+        LEGACY_CONFIG.remove(storage, b"");
+    }
+    if let Some(state) = LEGACY_STATE.may_load(storage, b"")? {
+        STATE.save(storage, &state)?;
+        LEGACY_STATE.remove(storage, b"");
+    }
+    if let Some(counter) = LEGACY_RESERVE_SNAPSHOT_COUNTER.may_load(storage, b"")? {
+        RESERVE_SNAPSHOT_COUNTER.save(storage, &counter)?;
+        LEGACY_RESERVE_SNAPSHOT_COUNTER.remove(storage, b"");
+    }
+
+    Ok(())
 }
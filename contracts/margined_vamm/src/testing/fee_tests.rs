@@ -1,9 +1,9 @@
 use crate::contract::{execute, instantiate, query};
 use crate::error::ContractError;
-use crate::testing::setup::to_decimals;
 use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
 use cosmwasm_std::{from_binary, Uint128};
 use margined_perp::margined_vamm::{CalcFeeResponse, ExecuteMsg, InstantiateMsg, QueryMsg};
+use margined_perp::mock_app::to_decimals;
 
 #[test]
 fn test_calc_fee() {
@@ -15,8 +15,10 @@ fn test_calc_fee() {
         quote_asset_reserve: to_decimals(100),
         base_asset_reserve: to_decimals(10_000),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::from(10_000_000u128),   // 0.01
         spread_ratio: Uint128::from(10_000_000u128), // 0.01
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -51,17 +53,21 @@ fn test_set_diff_fee_ratio() {
         quote_asset_reserve: to_decimals(100),
         base_asset_reserve: to_decimals(10_000),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::from(10_000_000u128),   // 0.01
         spread_ratio: Uint128::from(10_000_000u128), // 0.01
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
 
     // Update the config
     let msg = ExecuteMsg::UpdateConfig {
-        owner: None,
         toll_ratio: Some(Uint128::from(100_000_000u128)), // 0.1
         spread_ratio: Some(Uint128::from(50_000_000u128)), // 0.01
+        price_feed: None,
+        fluctuation_limit_ratio: None,
+        engine: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -97,8 +103,10 @@ fn test_set_fee_ratio_zero() {
         quote_asset_reserve: to_decimals(100),
         base_asset_reserve: to_decimals(10_000),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::from(50_000_000u128), // 0.05
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -133,8 +141,10 @@ fn test_calc_fee_input_zero() {
         quote_asset_reserve: to_decimals(100),
         base_asset_reserve: to_decimals(10_000),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::from(50_000_000u128), // 0.05,
         spread_ratio: Uint128::from(50_000_000u128), // 0.05
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -169,17 +179,21 @@ fn test_update_not_owner() {
         quote_asset_reserve: to_decimals(100),
         base_asset_reserve: to_decimals(10_000),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::from(50_000_000u128), // 0.05,
         spread_ratio: Uint128::from(50_000_000u128), // 0.05
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
 
     // Update the config
     let msg = ExecuteMsg::UpdateConfig {
-        owner: None,
         toll_ratio: Some(Uint128::from(100_000_000u128)), // 0.1
         spread_ratio: Some(Uint128::from(50_000_000u128)), // 0.01
+        price_feed: None,
+        fluctuation_limit_ratio: None,
+        engine: None,
     };
 
     let info = mock_info("addr0001", &[]);
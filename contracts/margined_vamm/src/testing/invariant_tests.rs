@@ -0,0 +1,173 @@
+use crate::contract::{execute, instantiate};
+use crate::handle::{get_input_price_with_reserves, get_output_price_with_reserves};
+use crate::invariants::invariant_k;
+use crate::state::{read_config, read_state};
+use cosmwasm_std::testing::{
+    mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+};
+use cosmwasm_std::{from_binary, OwnedDeps, Uint128};
+use margined_perp::margined_vamm::{Direction, ExecuteMsg, InstantiateMsg, SwapResponse};
+use proptest::prelude::*;
+
+type Deps = OwnedDeps<MockStorage, MockApi, MockQuerier>;
+
+// reserves from 1 to a billion whole units, to any precision
+const MIN_RESERVE: u128 = 1_000_000_000;
+const MAX_RESERVE: u128 = 1_000_000_000_000_000_000;
+
+fn setup(quote_asset_reserve: u128, base_asset_reserve: u128) -> Deps {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: Uint128::from(quote_asset_reserve),
+        base_asset_reserve: Uint128::from(base_asset_reserve),
+        funding_period: 3_600_u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("engine".to_string()),
+    };
+    instantiate(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+    deps
+}
+
+fn direction(add_to_amm: bool) -> Direction {
+    if add_to_amm {
+        Direction::AddToAmm
+    } else {
+        Direction::RemoveFromAmm
+    }
+}
+
+// swaps too large for the reserves fail, leaving them as they were
+fn swap(deps: &mut Deps, msg: ExecuteMsg) -> Option<SwapResponse> {
+    execute(deps.as_mut(), mock_env(), mock_info("engine", &[]), msg)
+        .ok()
+        .map(|res| from_binary(&res.data.unwrap()).unwrap())
+}
+
+proptest! {
+    #[test]
+    fn test_swaps_never_decrease_k(
+        quote_asset_reserve in MIN_RESERVE..MAX_RESERVE,
+        base_asset_reserve in MIN_RESERVE..MAX_RESERVE,
+        swaps in prop::collection::vec((any::<bool>(), any::<bool>(), 1u128..1_000), 1..20),
+    ) {
+        let mut deps = setup(quote_asset_reserve, base_asset_reserve);
+        let decimals = read_config(&deps.storage).unwrap().decimals;
+
+        // each swap trades up to 99.9% of the reserve it is priced in
+        for (input, add_to_amm, share) in swaps {
+            let before = read_state(&deps.storage).unwrap();
+            let msg = if input {
+                ExecuteMsg::SwapInput {
+                    direction: direction(add_to_amm),
+                    quote_asset_amount: before.quote_asset_reserve.multiply_ratio(share, 1_000u128),
+                }
+            } else {
+                ExecuteMsg::SwapOutput {
+                    direction: direction(add_to_amm),
+                    base_asset_amount: before.base_asset_reserve.multiply_ratio(share, 1_000u128),
+                }
+            };
+            if swap(&mut deps, msg).is_none() {
+                continue;
+            }
+
+            let after = read_state(&deps.storage).unwrap();
+            prop_assert!(
+                invariant_k(&after, decimals).unwrap() >= invariant_k(&before, decimals).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_trips_create_no_value(
+        quote_asset_reserve in MIN_RESERVE..MAX_RESERVE,
+        base_asset_reserve in MIN_RESERVE..MAX_RESERVE,
+        share in 1u128..1_000,
+        long in any::<bool>(),
+    ) {
+        let mut deps = setup(quote_asset_reserve, base_asset_reserve);
+        let amount = Uint128::from(quote_asset_reserve).multiply_ratio(share, 1_000u128);
+
+        // a long buys base for the quote and sells it straight back, a short
+        // borrows the base to sell and buys it straight back
+        let opened = swap(
+            &mut deps,
+            ExecuteMsg::SwapInput {
+                direction: direction(long),
+                quote_asset_amount: amount,
+            },
+        )
+        .unwrap();
+        let closed = swap(
+            &mut deps,
+            ExecuteMsg::SwapOutput {
+                direction: direction(long),
+                base_asset_amount: opened.output,
+            },
+        )
+        .unwrap();
+
+        if long {
+            prop_assert!(closed.output <= amount);
+        } else {
+            prop_assert!(closed.output >= amount);
+        }
+    }
+
+    #[test]
+    fn test_rounding_favours_the_pool(
+        quote_asset_reserve in MIN_RESERVE..MAX_RESERVE,
+        base_asset_reserve in MIN_RESERVE..MAX_RESERVE,
+        share in 1u128..1_000,
+        input in any::<bool>(),
+        add_to_amm in any::<bool>(),
+    ) {
+        let deps = setup(quote_asset_reserve, base_asset_reserve);
+        let state = read_state(&deps.storage).unwrap();
+        let decimals = read_config(&deps.storage).unwrap().decimals;
+        let k = invariant_k(&state, decimals).unwrap().u128() * decimals.u128();
+
+        // the amount solved for, the reserve it comes out of or goes into,
+        // and that reserve once the other has been traded against
+        let (amount, reserve, other_after) = if input {
+            let amount = state.quote_asset_reserve.multiply_ratio(share, 1_000u128);
+            let result =
+                get_input_price_with_reserves(deps.as_ref(), &direction(add_to_amm), amount)
+                    .unwrap();
+            let quote_after = if add_to_amm {
+                state.quote_asset_reserve + amount
+            } else {
+                state.quote_asset_reserve - amount
+            };
+            (result.u128(), state.base_asset_reserve.u128(), quote_after.u128())
+        } else {
+            let amount = state.base_asset_reserve.multiply_ratio(share, 1_000u128);
+            let result =
+                get_output_price_with_reserves(deps.as_ref(), &direction(add_to_amm), amount)
+                    .unwrap();
+            let base_after = if add_to_amm {
+                state.base_asset_reserve + amount
+            } else {
+                state.base_asset_reserve - amount
+            };
+            (result.u128(), state.quote_asset_reserve.u128(), base_after.u128())
+        };
+
+        // adding to the amm takes the exact reserve - k / other_after from the
+        // pool, removing from it pays the exact k / other_after - reserve, the
+        // result must be no better for the trader and at most 1 worse
+        if add_to_amm {
+            prop_assert!(amount * other_after <= reserve * other_after - k);
+            prop_assert!((amount + 1) * other_after > reserve * other_after - k);
+        } else {
+            prop_assert!(amount * other_after >= k - reserve * other_after);
+            prop_assert!(amount * other_after < k - reserve * other_after + other_after);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 mod fee_tests;
-mod setup;
+mod invariant_tests;
 mod swap_tests;
 mod tests;
 mod twap_tests;
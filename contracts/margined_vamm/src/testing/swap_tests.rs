@@ -1,11 +1,9 @@
 use crate::contract::instantiate;
-use crate::{
-    handle::{get_input_price_with_reserves, get_output_price_with_reserves},
-    testing::setup::to_decimals,
-};
+use crate::handle::{get_input_price_with_reserves, get_output_price_with_reserves};
 use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
 use cosmwasm_std::Uint128;
 use margined_perp::margined_vamm::{Direction, InstantiateMsg};
+use margined_perp::mock_app::to_decimals;
 
 /// Unit tests
 #[test]
@@ -18,8 +16,10 @@ fn test_get_input_and_output_price() {
         quote_asset_reserve: to_decimals(1_000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -1,10 +1,17 @@
-use crate::contract::{execute, instantiate, query};
-use crate::testing::setup::{to_decimals, DECIMAL_MULTIPLIER};
-use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
-use cosmwasm_std::{from_binary, Addr, Uint128};
+use crate::contract::{execute, instantiate, migrate, query};
+use crate::state::{read_config, read_reserve_snapshot, read_reserve_snapshot_counter, read_state};
+use cosmwasm_std::testing::{
+    mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+};
+use cosmwasm_std::{
+    from_binary, to_binary, to_vec, Addr, CosmosMsg, Deps, OwnedDeps, Storage, Uint128, WasmMsg,
+};
+use cw20::Cw20ExecuteMsg;
 use margined_perp::margined_vamm::{
-    ConfigResponse, Direction, ExecuteMsg, InstantiateMsg, QueryMsg, StateResponse,
+    ConfigResponse, Direction, ExecuteMsg, InstantiateMsg, LiquidityHistoryResponse, MigrateMsg,
+    QueryMsg, SettlementPriceResponse, StateResponse, SwapResponse,
 };
+use margined_perp::mock_app::{to_decimals, DECIMAL_MULTIPLIER};
 
 #[test]
 fn test_instantiation() {
@@ -16,8 +23,10 @@ fn test_instantiation() {
         quote_asset_reserve: Uint128::from(100u128),
         base_asset_reserve: Uint128::from(10_000u128),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -29,11 +38,16 @@ fn test_instantiation() {
         config,
         ConfigResponse {
             owner: info.sender.clone(),
+            pending_owner: None,
             quote_asset: "ETH".to_string(),
             base_asset: "USD".to_string(),
             toll_ratio: Uint128::zero(),
             spread_ratio: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
+            price_feed: None,
+            fluctuation_limit_ratio: Uint128::zero(),
+            paused: false,
+            engine: Some(Addr::unchecked("addr0000")),
         }
     );
 
@@ -46,10 +60,62 @@ fn test_instantiation() {
             base_asset_reserve: Uint128::from(10_000u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
 
+#[test]
+fn test_migrate_moves_legacy_storage() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: Uint128::from(100u128),
+        base_asset_reserve: Uint128::from(10_000u128),
+        funding_period: 3_600u64,
+        funding_buffer_period: 1_800u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    instantiate(deps.as_mut(), mock_env(), mock_info("addr0000", &[]), msg).unwrap();
+
+    // rewrite the storage as a deployment from before versioning left it,
+    // with its singletons and the snapshot bucket
+    let config = read_config(&deps.storage).unwrap();
+    let state = read_state(&deps.storage).unwrap();
+    let snapshot = read_reserve_snapshot(&deps.storage, 1).unwrap();
+    for key in [
+        b"config".as_ref(),
+        b"state",
+        b"reserve_snapshot_counter",
+        b"\x00\x10reserve_snapshot\x00\x00\x00\x00\x00\x00\x00\x01",
+        b"contract_info",
+    ] {
+        deps.storage.remove(key);
+    }
+    deps.storage
+        .set(b"\x00\x06config", &to_vec(&config).unwrap());
+    deps.storage.set(b"\x00\x05state", &to_vec(&state).unwrap());
+    deps.storage
+        .set(b"\x00\x18reserve_snapshot_counter", &to_vec(&1u64).unwrap());
+    deps.storage.set(
+        b"\x00\x10reserve_snapshot\x00\x00\x00\x00\x00\x00\x00\x01",
+        &to_vec(&snapshot).unwrap(),
+    );
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+    assert_eq!(read_config(&deps.storage).unwrap(), config);
+    assert_eq!(read_state(&deps.storage).unwrap(), state);
+    assert_eq!(read_reserve_snapshot_counter(&deps.storage).unwrap(), 1);
+    assert_eq!(read_reserve_snapshot(&deps.storage, 1).unwrap(), snapshot);
+    assert_eq!(deps.storage.get(b"\x00\x05state"), None);
+}
+
 #[test]
 fn test_update_config() {
     let mut deps = mock_dependencies(&[]);
@@ -60,17 +126,21 @@ fn test_update_config() {
         quote_asset_reserve: Uint128::from(100u128),
         base_asset_reserve: Uint128::from(10_000u128),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
 
     // Update the config
     let msg = ExecuteMsg::UpdateConfig {
-        owner: Some("addr0001".to_string()),
-        toll_ratio: None,
+        toll_ratio: Some(Uint128::from(1_000_000u128)),
         spread_ratio: None,
+        price_feed: None,
+        fluctuation_limit_ratio: None,
+        engine: None,
     };
 
     let info = mock_info("addr0000", &[]);
@@ -81,16 +151,71 @@ fn test_update_config() {
     assert_eq!(
         config,
         ConfigResponse {
-            owner: Addr::unchecked("addr0001".to_string()),
+            owner: Addr::unchecked("addr0000".to_string()),
+            pending_owner: None,
             quote_asset: "ETH".to_string(),
             base_asset: "USD".to_string(),
-            toll_ratio: Uint128::zero(),
+            toll_ratio: Uint128::from(1_000_000u128),
             spread_ratio: Uint128::zero(),
             decimals: DECIMAL_MULTIPLIER,
+            price_feed: None,
+            fluctuation_limit_ratio: Uint128::zero(),
+            paused: false,
+            engine: Some(Addr::unchecked("addr0000")),
         }
     );
 }
 
+#[test]
+fn test_ownership_transfer() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: Uint128::from(100u128),
+        base_asset_reserve: Uint128::from(10_000u128),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let owner = mock_info("addr0000", &[]);
+    let propose = ExecuteMsg::ProposeOwnership {
+        owner: "addr0001".to_string(),
+    };
+    let accept = ExecuteMsg::AcceptOwnership {};
+
+    // only the owner can propose, and only the proposed owner can accept
+    execute(
+        deps.as_mut(),
+        mock_env(),
+        mock_info("addr0001", &[]),
+        propose.clone(),
+    )
+    .unwrap_err();
+    execute(deps.as_mut(), mock_env(), owner.clone(), propose.clone()).unwrap();
+    execute(deps.as_mut(), mock_env(), owner.clone(), accept.clone()).unwrap_err();
+
+    // a cancelled proposal cannot be accepted
+    let msg = ExecuteMsg::CancelOwnershipProposal {};
+    execute(deps.as_mut(), mock_env(), owner.clone(), msg).unwrap();
+    let new_owner = mock_info("addr0001", &[]);
+    execute(deps.as_mut(), mock_env(), new_owner.clone(), accept.clone()).unwrap_err();
+
+    execute(deps.as_mut(), mock_env(), owner, propose).unwrap();
+    execute(deps.as_mut(), mock_env(), new_owner, accept).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.owner, Addr::unchecked("addr0001"));
+    assert_eq!(config.pending_owner, None);
+}
+
 #[test]
 fn test_swap_input_long() {
     let mut deps = mock_dependencies(&[]);
@@ -101,8 +226,10 @@ fn test_swap_input_long() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -114,7 +241,15 @@ fn test_swap_input_long() {
     };
 
     let info = mock_info("addr0000", &[]);
-    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+    let res = execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+    let swap: SwapResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        swap,
+        SwapResponse {
+            input: to_decimals(600),
+            output: Uint128::from(37_500_000_000u128),
+        }
+    );
     let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
     let state: StateResponse = from_binary(&res).unwrap();
     assert_eq!(
@@ -124,6 +259,9 @@ fn test_swap_input_long() {
             base_asset_reserve: Uint128::from(62_500_000_000u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -138,8 +276,10 @@ fn test_swap_input_short() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -161,6 +301,9 @@ fn test_swap_input_short() {
             base_asset_reserve: to_decimals(250),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -175,8 +318,10 @@ fn test_swap_output_short() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -198,6 +343,9 @@ fn test_swap_output_short() {
             base_asset_reserve: to_decimals(250),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -212,8 +360,10 @@ fn test_swap_output_long() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -225,7 +375,15 @@ fn test_swap_output_long() {
     };
 
     let info = mock_info("addr0000", &[]);
-    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+    let res = execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+    let swap: SwapResponse = from_binary(&res.data.unwrap()).unwrap();
+    assert_eq!(
+        swap,
+        SwapResponse {
+            input: to_decimals(50),
+            output: to_decimals(1_000),
+        }
+    );
     let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
     let state: StateResponse = from_binary(&res).unwrap();
     assert_eq!(
@@ -235,6 +393,9 @@ fn test_swap_output_long() {
             base_asset_reserve: to_decimals(50),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -249,8 +410,10 @@ fn test_swap_input_short_long() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -273,6 +436,9 @@ fn test_swap_input_short_long() {
             base_asset_reserve: Uint128::from(192_307_692_308u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 
@@ -293,6 +459,9 @@ fn test_swap_input_short_long() {
             base_asset_reserve: Uint128::from(67_567_567_568u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -307,8 +476,10 @@ fn test_swap_input_short_long_long() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -330,6 +501,9 @@ fn test_swap_input_short_long_long() {
             base_asset_reserve: to_decimals(125),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 
@@ -350,6 +524,9 @@ fn test_swap_input_short_long_long() {
             base_asset_reserve: Uint128::from(111_111_111_112u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 
@@ -370,6 +547,9 @@ fn test_swap_input_short_long_long() {
             base_asset_reserve: Uint128::from(90_909_090_910u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -384,8 +564,10 @@ fn test_swap_input_short_long_short() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -407,6 +589,9 @@ fn test_swap_input_short_long_short() {
             base_asset_reserve: to_decimals(125),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 
@@ -427,6 +612,9 @@ fn test_swap_input_short_long_short() {
             base_asset_reserve: to_decimals(80),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 
@@ -447,6 +635,9 @@ fn test_swap_input_short_long_short() {
             base_asset_reserve: to_decimals(100),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -461,8 +652,10 @@ fn test_swap_input_long_integration_example() {
         quote_asset_reserve: to_decimals(1_000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -484,6 +677,9 @@ fn test_swap_input_long_integration_example() {
             base_asset_reserve: Uint128::from(62_500_000_000u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -498,8 +694,10 @@ fn test_swap_input_long_short_integration_example() {
         quote_asset_reserve: to_decimals(1_000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -521,6 +719,9 @@ fn test_swap_input_long_short_integration_example() {
             base_asset_reserve: Uint128::from(62_500_000_000u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 
@@ -541,6 +742,9 @@ fn test_swap_input_long_short_integration_example() {
             base_asset_reserve: Uint128::from(100_000_000_000u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -555,8 +759,10 @@ fn test_swap_input_twice_short_long() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -587,6 +793,9 @@ fn test_swap_input_twice_short_long() {
             base_asset_reserve: Uint128::from(100_000_000_001u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -601,8 +810,10 @@ fn test_swap_input_twice_long_short() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -633,6 +844,9 @@ fn test_swap_input_twice_long_short() {
             base_asset_reserve: Uint128::from(100_000_000_001u128),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -647,8 +861,10 @@ fn test_swap_output_twice_short_long() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -679,6 +895,9 @@ fn test_swap_output_twice_short_long() {
             base_asset_reserve: to_decimals(100),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
@@ -693,8 +912,10 @@ fn test_swap_output_twice_long_short() {
         quote_asset_reserve: to_decimals(1000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::zero(),
         spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
     };
     let info = mock_info("addr0000", &[]);
     instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
@@ -725,6 +946,655 @@ fn test_swap_output_twice_long_short() {
             base_asset_reserve: to_decimals(100),
             funding_rate: Uint128::zero(),
             funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
         }
     );
 }
+
+#[test]
+fn test_scale_reserves() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // only the owner can scale the reserves
+    let msg = ExecuteMsg::ScaleReserves {
+        quote_asset_reserve: to_decimals(1500),
+    };
+    let info = mock_info("addr0001", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(
+        state,
+        StateResponse {
+            quote_asset_reserve: to_decimals(1_500),
+            base_asset_reserve: to_decimals(150),
+            funding_rate: Uint128::zero(),
+            funding_period: 3_600 as u64,
+            funding_buffer_period: 1_800_u64,
+            next_funding_time: 1_571_801_019,
+            open: true,
+        }
+    );
+
+    // the price is unchanged
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SpotPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(10));
+}
+
+#[test]
+fn test_adjust_k() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // only the owner can adjust k
+    let msg = ExecuteMsg::AdjustK {
+        multiplier: to_decimals(2),
+    };
+    let info = mock_info("addr0001", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert_eq!(state.quote_asset_reserve, to_decimals(2_000));
+    assert_eq!(state.base_asset_reserve, to_decimals(200));
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SpotPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(10));
+
+    // the change is recorded for positions opened before it
+    let msg = QueryMsg::LiquidityHistory {
+        start_after: None,
+        limit: None,
+    };
+    let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+    let history: LiquidityHistoryResponse = from_binary(&res).unwrap();
+    assert_eq!(history.latest_index, 1);
+    assert_eq!(
+        history.snapshots[0].base_asset_reserve_before,
+        to_decimals(100)
+    );
+    assert_eq!(history.snapshots[0].base_asset_reserve, to_decimals(200));
+
+    // the reserves cannot be emptied
+    let msg = ExecuteMsg::AdjustK {
+        multiplier: Uint128::zero(),
+    };
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+}
+
+#[test]
+fn test_sweep() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // only the owner can sweep tokens
+    let msg = ExecuteMsg::Sweep {
+        token: "token0000".to_string(),
+        amount: to_decimals(10),
+    };
+    let info = mock_info("addr0001", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+
+    let info = mock_info("addr0000", &[]);
+    let res = execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    assert_eq!(
+        res.messages[0].msg,
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: "token0000".to_string(),
+            funds: vec![],
+            msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                recipient: "addr0000".to_string(),
+                amount: to_decimals(10),
+            })
+            .unwrap(),
+        })
+    );
+}
+
+#[test]
+fn test_input_and_output_price() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // 250 quote buys 20 base
+    let msg = QueryMsg::InputPrice {
+        direction: Direction::AddToAmm,
+        amount: to_decimals(250),
+    };
+    let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+    let base: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(base, to_decimals(20));
+
+    // and taking 20 base out costs the same 250 quote
+    let msg = QueryMsg::OutputPrice {
+        direction: Direction::RemoveFromAmm,
+        amount: to_decimals(20),
+    };
+    let res = query(deps.as_ref(), mock_env(), msg).unwrap();
+    let quote: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(quote, to_decimals(250));
+
+    // quoting does not move the market
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SpotPrice {}).unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(price, to_decimals(10));
+}
+
+#[test]
+fn test_fluctuation_limit() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // 10% either side of the price the block started at
+    let msg = ExecuteMsg::UpdateConfig {
+        toll_ratio: None,
+        spread_ratio: None,
+        price_feed: None,
+        fluctuation_limit_ratio: Some(Uint128::from(100_000_000u128)),
+        engine: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // moves the price from 10 to around 10.4
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(20),
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+
+    // would take the price to around 12.5 within the same block
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(100),
+    };
+    let info = mock_info("addr0000", &[]);
+    let err = execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap_err();
+    assert_eq!(err.to_string(), "Price is over the fluctuation limit");
+
+    // in the next block the limit is measured from around 10.4 instead
+    let mut env = mock_env();
+    env.block.height += 1;
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(20),
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env.clone(), info, swap_msg).unwrap();
+
+    let swap_msg = ExecuteMsg::SwapOutput {
+        direction: Direction::RemoveFromAmm,
+        base_asset_amount: to_decimals(20),
+    };
+    let info = mock_info("addr0000", &[]);
+    let err = execute(deps.as_mut(), env, info, swap_msg).unwrap_err();
+    assert_eq!(err.to_string(), "Price is over the fluctuation limit");
+}
+
+// instantiates a vamm at a price of 10, limited to 10% either side
+fn fluctuation_limited_vamm() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let msg = ExecuteMsg::UpdateConfig {
+        toll_ratio: None,
+        spread_ratio: None,
+        price_feed: None,
+        fluctuation_limit_ratio: Some(Uint128::from(100_000_000u128)),
+        engine: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    deps
+}
+
+#[test]
+fn test_max_swap_output() {
+    let max = |deps: Deps, direction| -> Option<Uint128> {
+        from_binary(&query(deps, mock_env(), QueryMsg::MaxSwapOutput { direction }).unwrap())
+            .unwrap()
+    };
+
+    // without a limit any amount may be swapped
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+    assert_eq!(max(deps.as_ref(), Direction::AddToAmm), None);
+
+    // the base reserve may move to sqrt(k / 9) or sqrt(k / 11)
+    let deps = fluctuation_limited_vamm();
+    assert_eq!(
+        max(deps.as_ref(), Direction::AddToAmm),
+        Some(Uint128::from(5_409_255_338u128))
+    );
+    assert_eq!(
+        max(deps.as_ref(), Direction::RemoveFromAmm),
+        Some(Uint128::from(4_653_741_075u128))
+    );
+
+    // the whole of either fits within the limit but no more
+    for direction in [Direction::AddToAmm, Direction::RemoveFromAmm] {
+        let mut deps = fluctuation_limited_vamm();
+        let amount = max(deps.as_ref(), direction.clone()).unwrap();
+        let swap_msg = ExecuteMsg::SwapOutput {
+            direction: direction.clone(),
+            base_asset_amount: amount,
+        };
+        let info = mock_info("addr0000", &[]);
+        execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+
+        let mut deps = fluctuation_limited_vamm();
+        let swap_msg = ExecuteMsg::SwapOutput {
+            direction,
+            base_asset_amount: amount + Uint128::from(1_000u128),
+        };
+        let info = mock_info("addr0000", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap_err();
+        assert_eq!(err.to_string(), "Price is over the fluctuation limit");
+    }
+}
+
+#[test]
+fn test_set_pause() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // only the owner can pause
+    let msg = ExecuteMsg::SetPause { paused: true };
+    let info = mock_info("addr0001", &[]);
+    let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+    assert_eq!(err.to_string(), "Unauthorized");
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(20),
+    };
+    let info = mock_info("addr0000", &[]);
+    let err = execute(deps.as_mut(), mock_env(), info, swap_msg.clone()).unwrap_err();
+    assert_eq!(err.to_string(), "Swaps are paused");
+
+    let msg = ExecuteMsg::SwapOutput {
+        direction: Direction::AddToAmm,
+        base_asset_amount: to_decimals(1),
+    };
+    let info = mock_info("addr0000", &[]);
+    let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+    assert_eq!(err.to_string(), "Swaps are paused");
+
+    let msg = ExecuteMsg::SetPause { paused: false };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+}
+
+#[test]
+fn test_set_open() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let msg = ExecuteMsg::SetOpen { open: false };
+    let info = mock_info("addr0001", &[]);
+    let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+    assert_eq!(err.to_string(), "Unauthorized");
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+    let state: StateResponse = from_binary(&res).unwrap();
+    assert!(!state.open);
+
+    let swap_msg = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(20),
+    };
+    let info = mock_info("addr0000", &[]);
+    let err = execute(deps.as_mut(), mock_env(), info, swap_msg.clone()).unwrap_err();
+    assert_eq!(err.to_string(), "Market is not open");
+
+    let msg = ExecuteMsg::SetOpen { open: true };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, swap_msg).unwrap();
+}
+
+#[test]
+fn test_shutdown() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("addr0000".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Shutdown {}).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::SettlementPrice {}).unwrap();
+    let res: SettlementPriceResponse = from_binary(&res).unwrap();
+    assert_eq!(res.settlement_price, Some(to_decimals(10)));
+
+    // the market cannot be reopened
+    let msg = ExecuteMsg::SetOpen { open: true };
+    let info = mock_info("addr0000", &[]);
+    let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+    assert_eq!(err.to_string(), "Market has been shut down");
+
+    let info = mock_info("addr0000", &[]);
+    let err = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Shutdown {}).unwrap_err();
+    assert_eq!(err.to_string(), "Market has been shut down");
+}
+
+#[test]
+fn test_swaps_are_restricted_to_the_engine() {
+    let mut deps = mock_dependencies(&[]);
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: None,
+    };
+    let info = mock_info("addr0000", &[]);
+    instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    // without an engine nobody can swap, not even the owner
+    let swap_input = ExecuteMsg::SwapInput {
+        direction: Direction::AddToAmm,
+        quote_asset_amount: to_decimals(20),
+    };
+    let swap_output = ExecuteMsg::SwapOutput {
+        direction: Direction::AddToAmm,
+        base_asset_amount: to_decimals(1),
+    };
+    let info = mock_info("addr0000", &[]);
+    let err = execute(deps.as_mut(), mock_env(), info, swap_input.clone()).unwrap_err();
+    assert_eq!(err.to_string(), "Unauthorized");
+
+    let msg = ExecuteMsg::UpdateConfig {
+        toll_ratio: None,
+        spread_ratio: None,
+        price_feed: None,
+        fluctuation_limit_ratio: None,
+        engine: Some("engine".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+    let res = query(deps.as_ref(), mock_env(), QueryMsg::Config {}).unwrap();
+    let config: ConfigResponse = from_binary(&res).unwrap();
+    assert_eq!(config.engine, Some(Addr::unchecked("engine")));
+
+    for msg in [swap_input, swap_output] {
+        let info = mock_info("addr0001", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg.clone()).unwrap_err();
+        assert_eq!(err.to_string(), "Unauthorized");
+
+        let info = mock_info("engine", &[]);
+        execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+    }
+}
+
+#[test]
+fn test_funding_schedule() {
+    let mut deps = mock_dependencies(&[]);
+    let mut env = mock_env();
+    let msg = InstantiateMsg {
+        decimals: 9u8,
+        quote_asset: "ETH/USD".to_string(),
+        base_asset: "USD".to_string(),
+        quote_asset_reserve: to_decimals(1000),
+        base_asset_reserve: to_decimals(100),
+        funding_period: 3_600_u64,
+        funding_buffer_period: 7_200_u64,
+        toll_ratio: Uint128::zero(),
+        spread_ratio: Uint128::zero(),
+        engine: Some("engine".to_string()),
+    };
+    let info = mock_info("addr0000", &[]);
+    let err = instantiate(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: funding period must be above zero and cannot be shorter than the buffer period"
+    );
+    let msg = InstantiateMsg {
+        funding_buffer_period: 1_800_u64,
+        ..msg
+    };
+    instantiate(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    let opened = env.block.time.seconds();
+    let state: StateResponse =
+        from_binary(&query(deps.as_ref(), env.clone(), QueryMsg::State {}).unwrap()).unwrap();
+    assert_eq!(state.next_funding_time, opened + 3_600);
+
+    // only the engine settles funding, once it is due
+    let info = mock_info("addr0000", &[]);
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        info,
+        ExecuteMsg::SettleFunding {},
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "Unauthorized");
+    let info = mock_info("engine", &[]);
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        info,
+        ExecuteMsg::SettleFunding {},
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("Funding is not due until {}", opened + 3_600)
+    );
+
+    // a round settled on time is followed a period later
+    env.block.time = env.block.time.plus_seconds(3_600);
+    let info = mock_info("engine", &[]);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        info,
+        ExecuteMsg::SettleFunding {},
+    )
+    .unwrap();
+    let state = read_state(&deps.storage).unwrap();
+    assert_eq!(state.next_funding_time, opened + 7_200);
+
+    // one settled late leaves at least the buffer before the next
+    env.block.time = env.block.time.plus_seconds(6_600);
+    let info = mock_info("engine", &[]);
+    execute(
+        deps.as_mut(),
+        env.clone(),
+        info,
+        ExecuteMsg::SettleFunding {},
+    )
+    .unwrap();
+    let state = read_state(&deps.storage).unwrap();
+    assert_eq!(state.next_funding_time, opened + 10_200 + 1_800);
+
+    // only the owner sets the periods
+    let msg = ExecuteMsg::SetFundingPeriod {
+        funding_period: 28_800,
+        funding_buffer_period: 14_400,
+    };
+    let info = mock_info("addr0001", &[]);
+    let err = execute(deps.as_mut(), env.clone(), info, msg.clone()).unwrap_err();
+    assert_eq!(err.to_string(), "Unauthorized");
+    let info = mock_info("addr0000", &[]);
+    let err = execute(
+        deps.as_mut(),
+        env.clone(),
+        info,
+        ExecuteMsg::SetFundingPeriod {
+            funding_period: 0,
+            funding_buffer_period: 0,
+        },
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Generic error: funding period must be above zero and cannot be shorter than the buffer period"
+    );
+    let info = mock_info("addr0000", &[]);
+    execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+
+    // which apply from the round already scheduled
+    let state: StateResponse =
+        from_binary(&query(deps.as_ref(), env, QueryMsg::State {}).unwrap()).unwrap();
+    assert_eq!(state.funding_period, 28_800);
+    assert_eq!(state.funding_buffer_period, 14_400);
+    assert_eq!(state.next_funding_time, opened + 12_000);
+}
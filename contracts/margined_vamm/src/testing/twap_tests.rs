@@ -1,10 +1,10 @@
 use crate::contract::{execute, instantiate, query};
-use crate::testing::setup::to_decimals;
 use cosmwasm_std::testing::{
     mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
 };
 use cosmwasm_std::{from_binary, Env, OwnedDeps, Uint128};
 use margined_perp::margined_vamm::{Direction, ExecuteMsg, InstantiateMsg, QueryMsg};
+use margined_perp::mock_app::to_decimals;
 
 pub struct TestingEnv {
     pub deps: OwnedDeps<MockStorage, MockApi, MockQuerier>,
@@ -22,8 +22,10 @@ fn setup() -> TestingEnv {
         quote_asset_reserve: to_decimals(1_000),
         base_asset_reserve: to_decimals(100),
         funding_period: 3_600 as u64,
+        funding_buffer_period: 1_800_u64,
         toll_ratio: Uint128::from(10_000_000u128),   // 0.01
         spread_ratio: Uint128::from(10_000_000u128), // 0.01
+        engine: Some("addr0000".to_string()),
     };
 
     let info = mock_info("addr0000", &[]);
@@ -146,3 +148,117 @@ fn test_zero_interval() {
     let spot: Uint128 = from_binary(&res).unwrap();
     assert_eq!(twap, spot);
 }
+
+#[test]
+fn test_get_input_twap() {
+    let app = setup();
+
+    let res = query(
+        app.deps.as_ref(),
+        app.env.clone(),
+        QueryMsg::InputTwap {
+            direction: Direction::AddToAmm,
+            amount: to_decimals(10),
+            interval: 210,
+        },
+    )
+    .unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, Uint128::from(1_102_530_486u128));
+
+    let res = query(
+        app.deps.as_ref(),
+        app.env,
+        QueryMsg::InputTwap {
+            direction: Direction::RemoveFromAmm,
+            amount: to_decimals(10),
+            interval: 210,
+        },
+    )
+    .unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, Uint128::from(1_126_120_430u128));
+}
+
+#[test]
+fn test_get_output_twap() {
+    let app = setup();
+
+    let res = query(
+        app.deps.as_ref(),
+        app.env.clone(),
+        QueryMsg::OutputTwap {
+            direction: Direction::AddToAmm,
+            amount: to_decimals(1),
+            interval: 210,
+        },
+    )
+    .unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, Uint128::from(8_956_269_894u128));
+
+    let res = query(
+        app.deps.as_ref(),
+        app.env,
+        QueryMsg::OutputTwap {
+            direction: Direction::RemoveFromAmm,
+            amount: to_decimals(1),
+            interval: 210,
+        },
+    )
+    .unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, Uint128::from(9_128_710_658u128));
+}
+
+#[test]
+fn test_zero_interval_input_and_output_twap() {
+    let app = setup();
+
+    // with no interval the twaps are the current input and output prices
+    let res = query(
+        app.deps.as_ref(),
+        app.env.clone(),
+        QueryMsg::InputTwap {
+            direction: Direction::AddToAmm,
+            amount: to_decimals(10),
+            interval: 0,
+        },
+    )
+    .unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    let res = query(
+        app.deps.as_ref(),
+        app.env.clone(),
+        QueryMsg::InputPrice {
+            direction: Direction::AddToAmm,
+            amount: to_decimals(10),
+        },
+    )
+    .unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, price);
+
+    let res = query(
+        app.deps.as_ref(),
+        app.env.clone(),
+        QueryMsg::OutputTwap {
+            direction: Direction::AddToAmm,
+            amount: to_decimals(1),
+            interval: 0,
+        },
+    )
+    .unwrap();
+    let twap: Uint128 = from_binary(&res).unwrap();
+    let res = query(
+        app.deps.as_ref(),
+        app.env,
+        QueryMsg::OutputPrice {
+            direction: Direction::AddToAmm,
+            amount: to_decimals(1),
+        },
+    )
+    .unwrap();
+    let price: Uint128 = from_binary(&res).unwrap();
+    assert_eq!(twap, price);
+}
@@ -0,0 +1,47 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use cosmwasm_std::{Addr, Api, StdResult};
+
+// Collateral is either a cw20 token, drawn from the trader's allowance, or a
+// native bank denom, which is attached to the message as funds
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AssetInfo {
+    Token { contract_addr: String },
+    NativeToken { denom: String },
+}
+
+impl AssetInfo {
+    pub fn is_native_token(&self) -> bool {
+        matches!(self, AssetInfo::NativeToken { .. })
+    }
+
+    /// whether the asset is the cw20 token at the given address
+    pub fn is_token(&self, addr: &Addr) -> bool {
+        match self {
+            AssetInfo::Token { contract_addr } => contract_addr == addr.as_str(),
+            AssetInfo::NativeToken { .. } => false,
+        }
+    }
+
+    /// validates the token address, native denoms are checked by the bank
+    /// when funds are sent
+    pub fn check(&self, api: &dyn Api) -> StdResult<()> {
+        if let AssetInfo::Token { contract_addr } = self {
+            api.addr_validate(contract_addr)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for AssetInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetInfo::Token { contract_addr } => write!(f, "{}", contract_addr),
+            AssetInfo::NativeToken { denom } => write!(f, "{}", denom),
+        }
+    }
+}
@@ -0,0 +1,56 @@
+// Fixed point math on Uint128 amounts that carry the given decimals, e.g. a
+// decimals of 10^9 means 1_000_000_000 represents one. Follows the design of
+// the perpetual protocol decimals
+// https://github.com/perpetual-protocol/perpetual-protocol/blob/release/v2.1.x/src/utils/Decimal.sol
+use cosmwasm_std::{StdResult, Uint128};
+
+/// returns a * b
+pub fn mul_d(a: Uint128, b: Uint128, decimals: Uint128) -> StdResult<Uint128> {
+    Ok(a.checked_mul(b)?.checked_div(decimals)?)
+}
+
+/// returns a / b
+pub fn div_d(a: Uint128, b: Uint128, decimals: Uint128) -> StdResult<Uint128> {
+    Ok(a.checked_mul(decimals)?.checked_div(b)?)
+}
+
+/// returns 1 / a
+pub fn reciprocal(a: Uint128, decimals: Uint128) -> StdResult<Uint128> {
+    div_d(decimals, a, decimals)
+}
+
+/// returns a to the power of n
+pub fn pow(a: Uint128, n: u32, decimals: Uint128) -> StdResult<Uint128> {
+    let mut res = decimals;
+    for _ in 0..n {
+        res = mul_d(res, a, decimals)?;
+    }
+
+    Ok(res)
+}
+
+/// returns the square root of a, rounded down
+pub fn sqrt(a: Uint128, decimals: Uint128) -> StdResult<Uint128> {
+    let n = a.checked_mul(decimals)?.u128();
+    if n == 0 {
+        return Ok(Uint128::zero());
+    }
+
+    // newton's method converges from above on the integer square root
+    let mut x = n;
+    let mut y = (x + n / x) / 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+
+    Ok(Uint128::from(x))
+}
+
+/// returns the remainder of a / b, i.e. what div_d rounds away
+pub fn modulo(a: Uint128, b: Uint128, decimals: Uint128) -> StdResult<Uint128> {
+    let a = a.checked_mul(decimals)?;
+    let integral = a.checked_div(b)?;
+
+    Ok(a.checked_sub(b.checked_mul(integral)?)?)
+}
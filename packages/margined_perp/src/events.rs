@@ -0,0 +1,181 @@
+use cosmwasm_std::{attr, Addr, Attribute, Event, Response, Uint128};
+
+use crate::integer::Integer;
+use crate::margined_engine::Side;
+use crate::margined_vamm::Direction;
+
+// Events emitted by the margined contracts are named `<namespace>/<name>`,
+// e.g. `margined-engine/open_position`, and carry the version of the schema
+// their attributes follow. The version is bumped whenever an attribute is
+// renamed or removed, adding attributes does not change it
+
+pub const SCHEMA_VERSION: &str = "1";
+pub const ENGINE_NAMESPACE: &str = "margined-engine";
+pub const VAMM_NAMESPACE: &str = "margined-vamm";
+
+/// returns an empty event with the namespaced name and the schema version
+pub fn namespaced_event(namespace: &str, name: &str) -> Event {
+    Event::new(format!("{}/{}", namespace, name)).add_attribute("schema_version", SCHEMA_VERSION)
+}
+
+/// moves the attributes of a response into events named after the action
+/// each follows, namespaced and tagged with the schema version. Only the
+/// actions are left at the top level
+pub fn namespace_attributes(namespace: &str, mut response: Response) -> Response {
+    let mut actions: Vec<Attribute> = vec![];
+    let mut groups: Vec<Vec<Attribute>> = vec![];
+    let mut leading: Vec<Attribute> = vec![];
+    for attribute in std::mem::take(&mut response.attributes) {
+        if attribute.key == "action" {
+            // anything ahead of the first action belongs to it
+            groups.push(std::mem::take(&mut leading));
+            actions.push(attribute);
+        } else if let Some(group) = groups.last_mut() {
+            group.push(attribute);
+        } else {
+            leading.push(attribute);
+        }
+    }
+
+    if actions.is_empty() {
+        response.attributes = leading;
+        return response;
+    }
+
+    let events = actions
+        .iter()
+        .zip(groups)
+        .map(|(action, attributes)| {
+            namespaced_event(namespace, &action.value).add_attributes(attributes)
+        })
+        .collect::<Vec<Event>>();
+    response.attributes = actions;
+
+    response.add_events(events)
+}
+
+fn side_name(side: &Side) -> &'static str {
+    match side {
+        Side::BUY => "long",
+        Side::SELL => "short",
+    }
+}
+
+/// a change to a trader's position in a market, by a trade, a liquidation or
+/// a funding payment. The size, margin and notional are as they stand after
+/// the change, the deltas are the change itself
+#[derive(Clone, Debug, PartialEq)]
+pub struct PositionChange {
+    pub vamm: Addr,
+    pub trader: Addr,
+    pub side: Side,
+    pub size: Uint128,
+    pub size_delta: Integer,
+    pub margin: Uint128,
+    pub margin_delta: Integer,
+    pub notional: Uint128,
+    pub realized_pnl: Integer,
+    pub bad_debt: Uint128,
+    pub fee: Uint128,
+    pub funding_payment: Integer,
+}
+
+impl PositionChange {
+    pub fn attributes(&self) -> Vec<Attribute> {
+        vec![
+            attr("action", "position_changed"),
+            attr("vamm", self.vamm.as_str()),
+            attr("trader", self.trader.as_str()),
+            attr("side", side_name(&self.side)),
+            attr("size", self.size.to_string()),
+            attr("size_delta", self.size_delta.to_string()),
+            attr("margin", self.margin.to_string()),
+            attr("margin_delta", self.margin_delta.to_string()),
+            attr("notional", self.notional.to_string()),
+            attr("realized_pnl", self.realized_pnl.to_string()),
+            attr("bad_debt", self.bad_debt.to_string()),
+            attr("fee", self.fee.to_string()),
+            attr("funding_payment", self.funding_payment.to_string()),
+        ]
+    }
+}
+
+/// a position closed by a liquidator, the position change itself is reported
+/// separately
+#[derive(Clone, Debug, PartialEq)]
+pub struct Liquidation {
+    pub vamm: Addr,
+    pub trader: Addr,
+    pub liquidator: Addr,
+    pub liquidation_fee: Uint128,
+    pub bad_debt: Uint128,
+}
+
+impl Liquidation {
+    pub fn attributes(&self) -> Vec<Attribute> {
+        vec![
+            attr("action", "liquidation"),
+            attr("vamm", self.vamm.as_str()),
+            attr("trader", self.trader.as_str()),
+            attr("liquidator", self.liquidator.as_str()),
+            attr("liquidation_fee", self.liquidation_fee.to_string()),
+            attr("bad_debt", self.bad_debt.to_string()),
+        ]
+    }
+}
+
+/// a funding round paid in a market, the payment of each position is reported
+/// as a change to it when it is next settled
+#[derive(Clone, Debug, PartialEq)]
+pub struct FundingPayment {
+    pub vamm: Addr,
+    pub mark_twap: Uint128,
+    pub index_twap: Uint128,
+    pub premium_fraction: Integer,
+    pub payer: Side,
+}
+
+impl FundingPayment {
+    pub fn attributes(&self) -> Vec<Attribute> {
+        let payer = match self.payer {
+            Side::BUY => "longs",
+            Side::SELL => "shorts",
+        };
+        vec![
+            attr("action", "pay_funding"),
+            attr("vamm", self.vamm.as_str()),
+            attr("mark_twap", self.mark_twap.to_string()),
+            attr("index_twap", self.index_twap.to_string()),
+            attr("premium_fraction", self.premium_fraction.to_string()),
+            attr("payer", payer),
+        ]
+    }
+}
+
+/// a swap against a vamm's reserves, the input is the amount fixed by the
+/// swap and the reserves are as they stand after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Swap {
+    pub direction: Direction,
+    pub input: Uint128,
+    pub output: Uint128,
+    pub quote_asset_reserve: Uint128,
+    pub base_asset_reserve: Uint128,
+}
+
+impl Swap {
+    pub fn attributes(&self, action: &str) -> Vec<Attribute> {
+        let direction = match self.direction {
+            Direction::AddToAmm => "add_to_amm",
+            Direction::RemoveFromAmm => "remove_from_amm",
+        };
+        vec![
+            attr("action", action),
+            attr("direction", direction),
+            attr("input", self.input.to_string()),
+            attr("output", self.output.to_string()),
+            attr("quote_asset_reserve", self.quote_asset_reserve.to_string()),
+            attr("base_asset_reserve", self.base_asset_reserve.to_string()),
+        ]
+    }
+}
@@ -0,0 +1,162 @@
+// A signed integer made up of a Uint128 magnitude and a sign, for amounts such
+// as pnl, premium fractions and position sizes that are negative for shorts.
+// Zero is always positive so that equal values compare equal.
+use cosmwasm_std::{StdResult, Uint128};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Neg, Sub};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub struct Integer {
+    value: Uint128,
+    negative: bool,
+}
+
+impl Integer {
+    pub fn zero() -> Self {
+        Integer::default()
+    }
+
+    pub fn new_positive(value: impl Into<Uint128>) -> Self {
+        Integer {
+            value: value.into(),
+            negative: false,
+        }
+    }
+
+    pub fn new_negative(value: impl Into<Uint128>) -> Self {
+        let value = value.into();
+        Integer {
+            value,
+            negative: !value.is_zero(),
+        }
+    }
+
+    // returns the difference a - b of two unsigned amounts
+    pub fn difference(a: Uint128, b: Uint128) -> Self {
+        if a >= b {
+            Integer::new_positive(a - b)
+        } else {
+            Integer::new_negative(b - a)
+        }
+    }
+
+    pub fn value(&self) -> Uint128 {
+        self.value
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.value.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_positive(&self) -> bool {
+        !self.negative && !self.value.is_zero()
+    }
+
+    pub fn checked_add(self, other: Self) -> StdResult<Self> {
+        if self.negative == other.negative {
+            return Ok(Integer {
+                value: self.value.checked_add(other.value)?,
+                negative: self.negative,
+            });
+        }
+
+        // the signs differ so the result takes the sign of the larger value
+        if self.value >= other.value {
+            Ok(Integer::signed(self.value - other.value, self.negative))
+        } else {
+            Ok(Integer::signed(other.value - self.value, other.negative))
+        }
+    }
+
+    pub fn checked_sub(self, other: Self) -> StdResult<Self> {
+        self.checked_add(-other)
+    }
+
+    // multiplies by numerator / denominator, keeping the sign
+    pub fn multiply_ratio(self, numerator: impl Into<u128>, denominator: impl Into<u128>) -> Self {
+        Integer::signed(
+            self.value.multiply_ratio(numerator, denominator),
+            self.negative,
+        )
+    }
+
+    // multiplies by other / decimals, e.g. a size by a price
+    pub fn mul_ratio(self, other: Self, decimals: Uint128) -> Self {
+        Integer::signed(
+            self.value.multiply_ratio(other.value, decimals),
+            self.negative != other.negative,
+        )
+    }
+
+    fn signed(value: Uint128, negative: bool) -> Self {
+        if negative {
+            Integer::new_negative(value)
+        } else {
+            Integer::new_positive(value)
+        }
+    }
+}
+
+impl From<Uint128> for Integer {
+    fn from(value: Uint128) -> Self {
+        Integer::new_positive(value)
+    }
+}
+
+impl Add for Integer {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        self.checked_add(other).unwrap()
+    }
+}
+
+impl Sub for Integer {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        self.checked_sub(other).unwrap()
+    }
+}
+
+impl Neg for Integer {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Integer::signed(self.value, !self.negative)
+    }
+}
+
+impl Ord for Integer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, false) => self.value.cmp(&other.value),
+            (true, true) => other.value.cmp(&self.value),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        }
+    }
+}
+
+impl PartialOrd for Integer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Integer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negative {
+            write!(f, "-{}", self.value)
+        } else {
+            write!(f, "{}", self.value)
+        }
+    }
+}
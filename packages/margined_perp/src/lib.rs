@@ -1,3 +1,13 @@
+pub mod asset;
+pub mod cw721;
+pub mod decimals;
+pub mod events;
+pub mod integer;
 pub mod margined_engine;
+pub mod margined_maker_vault;
 pub mod margined_pricefeed;
 pub mod margined_vamm;
+#[cfg(feature = "testing")]
+pub mod mock_app;
+pub mod pagination;
+pub mod staking;
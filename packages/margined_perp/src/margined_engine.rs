@@ -1,9 +1,14 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Timestamp, Uint128};
+use cosmwasm_std::{Addr, Binary, Timestamp, Uint128};
 use cw20::Cw20ReceiveMsg;
 
+use crate::asset::AssetInfo;
+use crate::cw721::Cw721ReceiveMsg;
+use crate::integer::Integer;
+use crate::margined_vamm::InstantiateMsg as VammInstantiateMsg;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Side {
@@ -11,6 +16,25 @@ pub enum Side {
     SELL,
 }
 
+// stop losses close a position once the price moves against it past the
+// trigger price, take profits once it moves in its favour
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerOrderType {
+    StopLoss,
+    TakeProfit,
+}
+
+// the price a position is valued at when calculating its unrealized pnl
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PnlCalcOption {
+    #[default]
+    SpotPrice,
+    Twap,
+    Oracle,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum PNLCalc {
@@ -19,82 +43,1362 @@ pub enum PNLCalc {
     ORACLE,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProposalStatus {
+    Pending,
+    Vetoed,
+    Listed,
+}
+
+// operations that can be paused independently during an incident
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PausableOperation {
+    Open,
+    Close,
+    AddMargin,
+    RemoveMargin,
+    Liquidate,
+    Funding,
+}
+
+// the operations left running while the engine is paused
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseLevel {
+    Full,
+    CloseOnly,
+    WithdrawOnly,
+}
+
+// the insurance fund balance below which fees top it up
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum InsuranceFloor {
+    Absolute { amount: Uint128 },
+    OpenInterestRatio { ratio: Uint128 },
+}
+
+// an asset that can be deposited against an account, its value counts toward
+// the margin of the account's positions after the weight is applied as a
+// haircut. Assets without a price feed are valued at par with the quote
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CollateralAsset {
+    pub asset: AssetInfo,
+    pub weight: Uint128,
+    pub price: Option<CollateralPrice>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CollateralPrice {
+    pub pricefeed: String,
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CollateralBalance {
+    pub asset: AssetInfo,
+    pub amount: Uint128,
+}
+
+// margin ratios applying to positions with at least the given notional
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarginTier {
+    pub notional: Uint128,
+    pub initial_margin_ratio: Uint128,
+    pub maintenance_margin_ratio: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottlePeriod {
+    Block,
+    Hour,
+}
+
+// caps the collateral paid out to traders in each period at a share of the
+// margin and deposits the engine holds when the period starts
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawalThrottle {
+    pub period: ThrottlePeriod,
+    pub limit_ratio: Uint128,
+}
+
+// overrides of the config's risk parameters for a single market, None falls
+// back to the config
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct RiskParams {
+    pub initial_margin_ratio: Option<Uint128>,
+    pub maintenance_margin_ratio: Option<Uint128>,
+    pub liquidation_fee: Option<Uint128>,
+    pub max_leverage: Option<Uint128>,
+}
+
+// an order the trader signs off-chain for a relayer to submit, the nonce must
+// be the trader's next so that it can only be executed once
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SignedOrder {
+    pub vamm: String,
+    pub trader: String,
+    pub side: Side,
+    pub quote_asset_amount: Uint128,
+    pub leverage: Uint128,
+    pub base_asset_limit: Option<Uint128>,
+    pub reduce_only: bool,
+    pub deadline: Option<Timestamp>,
+    pub nonce: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct InstantiateMsg {
     pub decimals: u8,
-    pub eligible_collateral: String,
+    pub eligible_collateral: AssetInfo,
     pub initial_margin_ratio: Uint128,
     pub maintenance_margin_ratio: Uint128,
     pub liquidation_fee: Uint128,
     pub vamm: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
     Receive(Cw20ReceiveMsg),
+    ReceiveNft(Cw721ReceiveMsg),
     UpdateConfig {
+        eligible_collateral: Option<AssetInfo>,
+        initial_margin_ratio: Option<Uint128>,
+        maintenance_margin_ratio: Option<Uint128>,
+        liquidation_fee: Option<Uint128>,
+        min_notional: Option<Uint128>,
+        fee_pool: Option<String>,
+        open_liquidation: Option<bool>,
+        pause_level: Option<PauseLevel>,
+        staking_contract: Option<String>,
+        liquidation_price_source: Option<PnlCalcOption>,
+    },
+    // ownership moves in two steps, the proposed owner must accept it
+    ProposeOwnership {
         owner: String,
     },
+    AcceptOwnership {},
+    CancelOwnershipProposal {},
+    // appoints the operator who pauses the engine and adds or removes vamms,
+    // without one the owner does so
+    SetOperator {
+        operator: Option<String>,
+    },
+    // rejects opens while a market's spot price is further than this ratio
+    // from the index price of its price feed, none lifts the check
+    SetMaxOracleSpread {
+        max_oracle_spread: Option<Uint128>,
+    },
+    // accepts the ownership of a vamm proposed to the engine
+    AcceptVammOwnership {
+        vamm: String,
+    },
+    // registers a vAMM, optionally with stricter risk parameters than the
+    // config's
+    AddVamm {
+        vamm: String,
+        risk_params: Option<RiskParams>,
+    },
+    RemoveVamm {
+        vamm: String,
+    },
+    // whitelisted liquidators may liquidate while liquidation is not open to
+    // everyone
+    AddLiquidator {
+        liquidator: String,
+    },
+    RemoveLiquidator {
+        liquidator: String,
+    },
+    // exempt traders, such as market makers, may hold more than the market's
+    // max position notional
+    AddHoldingCapExemption {
+        trader: String,
+    },
+    RemoveHoldingCapExemption {
+        trader: String,
+    },
+    // a reduce only order reverts unless it shrinks the existing position
+    // without flipping its direction, any order reverts once the block time
+    // is past its deadline. A delegate trades for the trader that granted it
+    // permission, the sender trades for itself without one
     OpenPosition {
         vamm: String,
         side: Side,
         quote_asset_amount: Uint128,
         leverage: Uint128,
+        base_asset_limit: Option<Uint128>,
+        reduce_only: bool,
+        deadline: Option<Timestamp>,
+        trader: Option<String>,
     },
+    // closes the given base asset size of the position, all of it if None,
+    // reverting if the quote asset amount is worse than the limit or the
+    // block time is past the deadline
+    // a partial close only closes as much of the size as the vamm's
+    // fluctuation limit allows this block, leaving the rest open
     ClosePosition {
         vamm: String,
+        size: Option<Uint128>,
+        quote_asset_limit: Option<Uint128>,
+        #[serde(default)]
+        partial: bool,
+        deadline: Option<Timestamp>,
+        trader: Option<String>,
+    },
+    // lets the delegate open and close the sender's positions, spending the
+    // notional it opens from the cap. A new grant replaces the last
+    GrantTradingPermission {
+        delegate: String,
+        notional_cap: Uint128,
+    },
+    RevokeTradingPermission {
+        delegate: String,
+    },
+    // registers the secp256k1 public key that signs the sender's orders for
+    // relayers to submit, removing it if None
+    SetSigningKey {
+        pubkey: Option<Binary>,
+    },
+    // opens a position for the trader of an order they signed with their
+    // signing key, the sender only relays it
+    OpenPositionFor {
+        order: SignedOrder,
+        pubkey: Binary,
+        signature: Binary,
+    },
+    // pays the maker vault of a market its share of the toll, removing the
+    // vault if None
+    UpdateMakerVault {
+        vamm: String,
+        vault: Option<String>,
+        fee_share: Uint128,
+    },
+    UpdateListingConfig {
+        vamm_code_id: u64,
+        bond: Uint128,
+        challenge_period: u64,
+    },
+    VetoMarket {
+        proposal_id: u64,
+    },
+    ListMarket {
+        proposal_id: u64,
+    },
+    AddFeeCampaign {
+        start: u64,
+        end: u64,
+        vamms: Option<Vec<String>>,
+        discount: Uint128,
+    },
+    RemoveFeeCampaign {
+        campaign_id: u64,
+    },
+    UpdateAccountSettings {
+        max_slippage: Option<Uint128>,
+    },
+    SetCrossMargin {
+        enabled: bool,
+    },
+    UpdateMarginTiers {
+        vamm: String,
+        tiers: Vec<MarginTier>,
+    },
+    UpdateOpenInterestCap {
+        share: Uint128,
+        floor: Uint128,
+    },
+    Crank {
+        vamm: String,
+        max_ops: u32,
+    },
+    UpdateInsuranceConfig {
+        unstake_cooldown: u64,
+    },
+    RequestUnstake {
+        amount: Uint128,
+    },
+    Unstake {},
+    UpdateFeePoolConfig {
+        insurance_share: Uint128,
+        insurance_floor: Option<InsuranceFloor>,
+        keeper_reward: Option<Uint128>,
+    },
+    DistributeFees {},
+    UpdatePausedOperations {
+        paused: Vec<PausableOperation>,
+    },
+    // circuit breaker, pausing halts every operation not allowed by the
+    // configured pause level and unpausing resumes them all
+    SetPause {
+        paused: bool,
+    },
+    DepositFor {
+        trader: String,
+        vamm: String,
+        amount: Uint128,
+    },
+    UpdateMarketCollateral {
+        vamm: String,
+        collateral: AssetInfo,
+    },
+    UpdateCollateralAsset {
+        asset: AssetInfo,
+        weight: Uint128,
+        price: Option<CollateralPrice>,
+    },
+    RemoveCollateralAsset {
+        asset: AssetInfo,
+    },
+    DepositCollateral {
+        asset: AssetInfo,
+        amount: Uint128,
+    },
+    WithdrawCollateral {
+        asset: AssetInfo,
+        amount: Uint128,
+    },
+    UpdateMarketFees {
+        vamm: String,
+        toll_ratio: Option<Uint128>,
+        spread_ratio: Option<Uint128>,
+    },
+    // caps the market's total open notional, the base asset size of any one
+    // position and the notional any one trader may hold, None leaves that cap
+    // off
+    UpdateMarketCaps {
+        vamm: String,
+        open_interest_notional_cap: Option<Uint128>,
+        max_position_size: Option<Uint128>,
+        max_position_notional: Option<Uint128>,
+    },
+    // overrides the config's risk parameters for the market, those left None
+    // fall back to the config
+    UpdateRiskParams {
+        vamm: String,
+        risk_params: RiskParams,
+    },
+    // limits how much collateral withdrawals and closes can take out of the
+    // engine in each period, None lifts the limit
+    UpdateWithdrawalThrottle {
+        throttle: Option<WithdrawalThrottle>,
+    },
+    SetMarketOpen {
+        vamm: String,
+        open: bool,
+    },
+    ShutdownMarket {
+        vamm: String,
+    },
+    // pays out the sender's position in a shut down market
+    SettlePosition {
+        vamm: String,
+    },
+    UpdatePositionNft {
+        contract: String,
+    },
+    WrapPosition {
+        vamm: String,
+    },
+    TransferPosition {
+        vamm: String,
+        to: String,
+    },
+    WithdrawLiquidity {
+        vamm: String,
+        amount: Uint128,
+    },
+    CancelPositionTransfer {
+        vamm: String,
+    },
+    AcceptPosition {
+        vamm: String,
+        from: String,
+    },
+    UpdateCommitReveal {
+        vamm: String,
+        expiry_blocks: Option<u64>,
+    },
+    CommitOrder {
+        vamm: String,
+        commitment: Binary,
+    },
+    RevealOrder {
+        vamm: String,
+        side: Side,
+        quote_asset_amount: Uint128,
+        leverage: Uint128,
+        base_asset_limit: Option<Uint128>,
+        salt: String,
+    },
+    RegisterContractTrader {
+        contract: String,
+        notional_cap: Option<Uint128>,
+    },
+    DeregisterContractTrader {
+        contract: String,
+    },
+    // queued orders are filled by a keeper at a later block's price, an order
+    // is not filled past its deadline
+    QueueOrder {
+        vamm: String,
+        side: Side,
+        quote_asset_amount: Uint128,
+        leverage: Uint128,
+        base_asset_limit: Option<Uint128>,
+        #[serde(default)]
+        reduce_only: bool,
+        deadline: Option<Timestamp>,
+    },
+    QueueClose {
+        vamm: String,
+        size: Option<Uint128>,
+        quote_asset_limit: Option<Uint128>,
+        deadline: Option<Timestamp>,
+    },
+    CancelQueuedOrder {
+        vamm: String,
+    },
+    ExecuteQueuedOrder {
+        vamm: String,
+        trader: String,
+    },
+    SetTriggerOrder {
+        vamm: String,
+        trigger_price: Uint128,
+        order_type: TriggerOrderType,
+        size: Option<Uint128>,
+    },
+    // sets a stop loss and take profit as a pair, once either executes the
+    // other is cancelled
+    SetTriggerOrderPair {
+        vamm: String,
+        stop_loss: Uint128,
+        take_profit: Uint128,
+        size: Option<Uint128>,
+    },
+    CancelTriggerOrder {
+        vamm: String,
+        order_type: TriggerOrderType,
+    },
+    ExecuteTriggerOrder {
+        vamm: String,
+        trader: String,
+    },
+    MatchTriggerOrders {
+        vamm: String,
+        long: String,
+        short: String,
+    },
+    // sends the owner what the engine holds of the asset above what it owes
+    Sweep {
+        asset: AssetInfo,
+        amount: Uint128,
+    },
+    DepositMargin {
+        vamm: String,
+        amount: Uint128,
+    },
+    WithdrawMargin {
+        vamm: String,
+        amount: Uint128,
+    },
+    // a takeover moves the position to the insurance fund at its bankruptcy
+    // price instead of closing it on the vamm, for the fund to unwind later
+    Liquidate {
+        vamm: String,
+        trader: String,
+        #[serde(default)]
+        takeover: bool,
+    },
+    // closes the size of the position the insurance fund took over, the whole
+    // of it if none, by the owner or a liquidator
+    UnwindInsurancePosition {
+        vamm: String,
+        size: Option<Uint128>,
+    },
+    PayFunding {
+        vamm: String,
+    },
+    // registers the sender as a referrer under a unique code
+    RegisterReferrer {
+        code: String,
+    },
+    // refers the sender to the referrer registered under the code, who is
+    // then paid a share of the toll on the sender's trades
+    SetReferralCode {
+        code: String,
+    },
+    // the share of the toll paid to referrers, 100% is equal to the decimals
+    UpdateReferralShare {
+        referral_share: Uint128,
+    },
+    ClaimReferralRewards {},
+    // discounts the toll of traders with at least the given stake, replacing
+    // the tier with the same minimum stake if there is one
+    SetFeeTier {
+        min_stake: Uint128,
+        discount: Uint128,
+    },
+    RemoveFeeTier {
+        min_stake: Uint128,
     },
-    // Liquidate {},
-    // PayFunding {},
-    // DepositMargin {},
-    // WithdrawMargin {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Cw20HookMsg {
-    // allows you to open a position and directly transfer funds
+    // allows you to open a position and directly transfer funds, which must
+    // be exactly the margin and fees due
     OpenPosition {
         vamm: String,
         side: Side,
+        quote_asset_amount: Uint128,
         leverage: Uint128,
+        base_asset_limit: Option<Uint128>,
+        reduce_only: bool,
+        deadline: Option<Timestamp>,
+    },
+    // bonds the transferred funds against a proposal to list a new market
+    ProposeMarket {
+        market: VammInstantiateMsg,
+    },
+    // stakes the transferred funds into the insurance fund
+    StakeInsurance {},
+    // distributes the transferred fees to insurance stakers
+    DepositInsuranceFees {},
+    // adds the transferred fees to the fee pool
+    DepositFees {},
+    // adds the transferred funds to the margin of the sender's position
+    AddMargin {
+        vamm: String,
+    },
+    // adds the transferred funds to the margin of another trader's position
+    DepositFor {
+        trader: String,
+        vamm: String,
+    },
+    // deposits the transferred funds as liquidity, deepening the market
+    ProvideLiquidity {
+        vamm: String,
+    },
+    // shares the transferred fees between the market's liquidity providers
+    DepositLiquidityFees {
+        vamm: String,
     },
+    // deposits the transferred funds against the sender's account
+    DepositCollateral {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub enum QueryMsg {
-    Config {},
-    Position { vamm: String, trader: String },
-    TraderBalance { trader: String },
-    // MarginRatio {},
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct ConfigResponse {
-    pub owner: Addr,
-    pub eligible_collateral: Addr,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct PositionResponse {
-    pub size: Uint128,
-    pub margin: Uint128,
-    pub notional: Uint128,
-    pub premium_fraction: Uint128,
-    pub liquidity_history_index: Uint128,
-    pub timestamp: Timestamp,
+pub enum Cw721HookMsg {
+    // burns the sent position token, handing the position to its sender
+    UnwrapPosition {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-pub struct SwapResponse {
-    pub vamm: String,
-    pub trader: String,
-    pub side: String,
-    pub quote_asset_amount: Uint128,
-    pub leverage: Uint128,
-    pub open_notional: Uint128,
-    pub input: Uint128,
-    pub output: Uint128,
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    Config {},
+    // totals across every market and account, for monitoring solvency
+    State {},
+    WithdrawalThrottle {
+        #[serde(default)]
+        asset: Option<AssetInfo>,
+    },
+    AllVamms {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    Liquidators {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    HoldingCapExemptions {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    Position {
+        vamm: String,
+        trader: String,
+    },
+    Positions {
+        vamm: String,
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    TraderBalance {
+        trader: String,
+    },
+    TraderPositions {
+        trader: String,
+    },
+    TradeHistory {
+        trader: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    ListingConfig {},
+    MarketProposal {
+        proposal_id: u64,
+    },
+    FeeCampaigns {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    CalcFee {
+        vamm: String,
+        quote_asset_amount: Uint128,
+    },
+    AccountSettings {
+        trader: String,
+    },
+    MarginTiers {
+        vamm: String,
+    },
+    MarginRequirement {
+        vamm: String,
+        notional: Uint128,
+    },
+    MarginRatio {
+        vamm: String,
+        trader: String,
+    },
+    // the mark price at which the position falls to its maintenance margin
+    // ratio, after any funding it has yet to settle
+    LiquidationPrice {
+        vamm: String,
+        trader: String,
+    },
+    FreeCollateral {
+        vamm: String,
+        trader: String,
+    },
+    PositionNotionalAndUnrealizedPnl {
+        vamm: String,
+        trader: String,
+        calc_option: PnlCalcOption,
+    },
+    OpenInterest {
+        vamm: String,
+    },
+    // the open notional of the longs and shorts of a market
+    OpenInterestImbalance {
+        vamm: String,
+    },
+    MakerVault {
+        vamm: String,
+    },
+    Funding {
+        vamm: String,
+    },
+    // the funding rounds of a market, oldest first
+    FundingRateHistory {
+        vamm: String,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+    NextFundingTime {
+        vamm: String,
+    },
+    // the funding round that would be settled now and what the trader's
+    // position would pay in it
+    FundingPreview {
+        vamm: String,
+        trader: String,
+    },
+    OpenInterestCap {},
+    InsuranceFund {},
+    InsuranceStake {
+        staker: String,
+    },
+    FeePool {},
+    DailyStats {
+        vamm: String,
+        start_after: Option<u64>,
+        end_day: Option<u64>,
+        limit: Option<u32>,
+    },
+    PausedOperations {},
+    PositionSnapshot {
+        trader: String,
+    },
+    ReferralRewards {
+        referrer: String,
+    },
+    TraderFeeRate {
+        trader: String,
+    },
+    BadDebt {
+        vamm: String,
+    },
+    // the expected outcome of an order priced against the vamm as it stands,
+    // nothing is executed
+    SimulateOpenPosition {
+        vamm: String,
+        trader: String,
+        side: Side,
+        quote_asset_amount: Uint128,
+        leverage: Uint128,
+    },
+    SimulateClosePosition {
+        vamm: String,
+        trader: String,
+        size: Option<Uint128>,
+    },
+    MarketCollateral {
+        vamm: String,
+    },
+    CollateralAssets {},
+    AccountCollateral {
+        trader: String,
+    },
+    MarketFees {
+        vamm: String,
+    },
+    MarketCaps {
+        vamm: String,
+    },
+    // the notional the trader may still open in the market under its max
+    // position notional
+    HoldingCapacity {
+        vamm: String,
+        trader: String,
+    },
+    // the risk parameters in force in a market, after its overrides
+    RiskParams {
+        vamm: String,
+    },
+    MarketStatus {
+        vamm: String,
+    },
+    Settlement {
+        vamm: String,
+    },
+    PositionNft {},
+    PositionToken {
+        token_id: String,
+    },
+    PositionTransfer {
+        vamm: String,
+        trader: String,
+    },
+    LiquidityPool {
+        vamm: String,
+    },
+    LiquidityStake {
+        vamm: String,
+        provider: String,
+    },
+    CommitReveal {
+        vamm: String,
+    },
+    OrderCommitment {
+        vamm: String,
+        trader: String,
+    },
+    // the commitment to submit for an order, clients should compute this
+    // against a node they trust
+    CommitmentHash {
+        vamm: String,
+        trader: String,
+        side: Side,
+        quote_asset_amount: Uint128,
+        leverage: Uint128,
+        base_asset_limit: Option<Uint128>,
+        salt: String,
+    },
+    QueuedOrder {
+        vamm: String,
+        trader: String,
+    },
+    TriggerOrders {
+        vamm: String,
+        trader: String,
+    },
+    ContractTraders {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    TradingPermission {
+        trader: String,
+        delegate: String,
+    },
+    SigningKey {
+        trader: String,
+    },
+    // MarginRatio {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub owner: Addr,
+    pub pending_owner: Option<Addr>,
+    pub operator: Option<Addr>,
+    pub eligible_collateral: AssetInfo,
+    pub decimals: Uint128,
+    pub initial_margin_ratio: Uint128,
+    pub maintenance_margin_ratio: Uint128,
+    pub liquidation_fee: Uint128,
+    pub min_notional: Uint128,
+    pub fee_pool: Option<Addr>,
+    pub open_liquidation: bool,
+    pub pause_level: PauseLevel,
+    pub staking_contract: Option<Addr>,
+    pub max_oracle_spread: Option<Uint128>,
+    pub liquidation_price_source: PnlCalcOption,
+}
+
+// the collateral deposited against accounts and the margin locked in
+// positions, each keyed by asset, and the insurance fund backing them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StateResponse {
+    pub total_deposits: Vec<AssetTotal>,
+    pub total_margin: Vec<AssetTotal>,
+    pub insurance_balance: Uint128,
+    pub outstanding_notional: Uint128,
+}
+
+// a running total held in units of the asset
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AssetTotal {
+    pub asset: String,
+    pub amount: Uint128,
+}
+
+// the throttle in force and what can still be paid out in the current period
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawalThrottleResponse {
+    pub throttle: Option<WithdrawalThrottle>,
+    pub remaining: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionResponse {
+    pub size: Uint128,
+    pub margin: Uint128,
+    pub notional: Uint128,
+    pub last_updated_premium_fraction: Integer,
+    pub liquidity_history_index: Uint128,
+    pub timestamp: Timestamp,
+}
+
+// an open position along with the market and trader that hold it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionInfo {
+    pub vamm: Addr,
+    pub trader: Addr,
+    pub side: Side,
+    pub size: Uint128,
+    pub margin: Uint128,
+    pub notional: Uint128,
+    pub last_updated_premium_fraction: Integer,
+    pub timestamp: Timestamp,
+}
+
+// a page of the open positions in a market, ordered by trader
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionsResponse {
+    pub positions: Vec<PositionInfo>,
+    pub next_key: Option<Addr>,
+}
+
+// a trader's open positions across every market and the margin they hold
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TraderPositionsResponse {
+    pub trader: Addr,
+    pub positions: Vec<PositionInfo>,
+    pub total_margin: Uint128,
+}
+
+// the code of a referrer and the rewards they have yet to claim, in each
+// collateral asset the fees were paid in
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferralRewardsResponse {
+    pub code: Option<String>,
+    pub referral_share: Uint128,
+    pub rewards: Vec<ReferralReward>,
+}
+
+// the toll discount of traders who have staked at least the minimum stake,
+// 100% is equal to the decimals
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeTier {
+    pub min_stake: Uint128,
+    pub discount: Uint128,
+}
+
+// the trader's stake in the staking contract and the toll discount it earns
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TraderFeeRateResponse {
+    pub stake: Uint128,
+    pub discount: Uint128,
+}
+
+// the losses of a market's positions beyond their margin, the insured part was
+// drawn from the insurance fund and the uncovered part could not be paid
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct BadDebtResponse {
+    pub vamm: Addr,
+    pub realized: Uint128,
+    pub insured: Uint128,
+    pub uncovered: Uint128,
+}
+
+// the expected outcome of an order, the margin ratio and liquidation price are
+// those of the position left once it is filled and are zero and None if none
+// is left
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SimulationResponse {
+    pub exchanged_position_size: Uint128,
+    pub exchanged_quote_asset_amount: Uint128,
+    pub entry_price: Uint128,
+    pub toll_fee: Uint128,
+    pub spread_fee: Uint128,
+    pub realized_pnl: Integer,
+    pub size: Uint128,
+    pub margin: Uint128,
+    pub margin_ratio: Uint128,
+    pub liquidation_price: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ReferralReward {
+    pub asset: AssetInfo,
+    pub amount: Uint128,
+}
+
+// a trader's open positions across every market at the given time
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionSnapshotResponse {
+    pub trader: Addr,
+    pub timestamp: Timestamp,
+    pub positions: Vec<PositionSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionSnapshot {
+    pub vamm: Addr,
+    pub side: Side,
+    pub size: Uint128,
+    pub margin: Uint128,
+    pub notional: Uint128,
+    pub entry_price: Uint128,
+    pub accrued_funding: Uint128,
+    pub fees_paid: Uint128,
+    pub opened_at: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapResponse {
+    pub vamm: String,
+    pub trader: String,
+    pub side: String,
+    pub quote_asset_amount: Uint128,
+    pub leverage: Uint128,
+    pub open_notional: Uint128,
+    pub input: Uint128,
+    pub output: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ListingConfigResponse {
+    pub vamm_code_id: u64,
+    pub bond: Uint128,
+    pub challenge_period: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketProposalResponse {
+    pub proposal_id: u64,
+    pub proposer: Addr,
+    pub bond: Uint128,
+    pub market: VammInstantiateMsg,
+    pub created_at: Timestamp,
+    pub status: ProposalStatus,
+    pub vamm: Option<Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeCampaignResponse {
+    pub campaign_id: u64,
+    pub start: u64,
+    pub end: u64,
+    pub vamms: Option<Vec<Addr>>,
+    pub discount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeeCampaignsResponse {
+    pub campaigns: Vec<FeeCampaignResponse>,
+    pub next_key: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AccountSettingsResponse {
+    pub max_slippage: Option<Uint128>,
+    pub cross_margin: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarginTiersResponse {
+    pub tiers: Vec<MarginTier>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarginRequirementResponse {
+    pub initial_margin_ratio: Uint128,
+    pub maintenance_margin_ratio: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarginRatioResponse {
+    pub margin_ratio: Uint128,
+    pub maintenance_margin_ratio: Uint128,
+}
+
+// None if the position can never be liquidated
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidationPriceResponse {
+    pub liquidation_price: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FreeCollateralResponse {
+    pub free_collateral: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionNotionalAndUnrealizedPnlResponse {
+    pub position_notional: Uint128,
+    pub unrealized_pnl: Integer,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OpenInterestResponse {
+    pub open_interest: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OpenInterestImbalanceResponse {
+    pub long: Uint128,
+    pub short: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MakerVaultResponse {
+    pub vault: Option<Addr>,
+    pub fee_share: Uint128,
+}
+
+// the premium longs have paid shorts over all funding rounds
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FundingResponse {
+    pub cumulative_premium_fraction: Integer,
+    pub last_funding_time: u64,
+}
+
+// a funding round of a market, the premium fraction is what longs paid shorts
+// per unit of size, negative when shorts paid longs
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FundingRecord {
+    pub round: u64,
+    pub timestamp: Timestamp,
+    pub premium_fraction: Integer,
+    pub mark_twap: Uint128,
+    pub index_twap: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FundingRateHistoryResponse {
+    pub rounds: Vec<FundingRecord>,
+    pub next_key: Option<u64>,
+}
+
+// funding can be paid from this time on, a market that has never paid funding
+// can pay it straight away
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct NextFundingTimeResponse {
+    pub next_funding_time: u64,
+}
+
+// the premium fraction the next funding round would settle at now, and the
+// payment of the position, negative when it would be paid funding
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FundingPreviewResponse {
+    pub premium_fraction: Integer,
+    pub mark_twap: Uint128,
+    pub index_twap: Uint128,
+    pub next_funding_time: u64,
+    pub payment: Integer,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CommitRevealResponse {
+    pub expiry_blocks: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OrderCommitmentResponse {
+    pub commitment: Binary,
+    pub committed_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ContractTraderResponse {
+    pub contract: Addr,
+    pub notional_cap: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AllVammsResponse {
+    pub vamms: Vec<Addr>,
+    pub next_key: Option<Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidatorsResponse {
+    pub liquidators: Vec<Addr>,
+    pub next_key: Option<Addr>,
+}
+
+// what is left of the notional a delegate may open for a trader
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TradingPermissionResponse {
+    pub notional_cap: Uint128,
+}
+
+// the key that signs a trader's orders and the nonce their next must carry
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SigningKeyResponse {
+    pub pubkey: Option<Binary>,
+    pub nonce: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ContractTradersResponse {
+    pub traders: Vec<ContractTraderResponse>,
+    pub next_key: Option<Addr>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QueuedOrderResponse {
+    pub trade: QueuedTrade,
+    pub queued_at: u64,
+    pub deadline: Option<Timestamp>,
+}
+
+// what a queued order does once it is filled
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueuedTrade {
+    Open {
+        side: Side,
+        quote_asset_amount: Uint128,
+        leverage: Uint128,
+        base_asset_limit: Option<Uint128>,
+        reduce_only: bool,
+    },
+    Close {
+        size: Option<Uint128>,
+        quote_asset_limit: Option<Uint128>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TriggerOrdersResponse {
+    pub stop_loss: Option<Uint128>,
+    pub take_profit: Option<Uint128>,
+    pub stop_loss_size: Option<Uint128>,
+    pub take_profit_size: Option<Uint128>,
+    pub linked: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketCollateralResponse {
+    pub vamm: Addr,
+    pub collateral: AssetInfo,
+    // held by the market itself when it is not backed by the eligible collateral
+    pub reserve: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CollateralAssetsResponse {
+    pub assets: Vec<CollateralAsset>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AccountCollateralResponse {
+    pub balances: Vec<CollateralBalance>,
+    pub weighted_value: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketFeesResponse {
+    pub vamm: Addr,
+    pub toll_ratio: Option<Uint128>,
+    pub spread_ratio: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketCapsResponse {
+    pub vamm: Addr,
+    pub open_interest_notional_cap: Option<Uint128>,
+    pub max_position_size: Option<Uint128>,
+    pub max_position_notional: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HoldingCapExemptionsResponse {
+    pub traders: Vec<Addr>,
+    pub next_key: Option<Addr>,
+}
+
+// the remaining capacity is None where the trader is not capped, because the
+// market has no max position notional or the trader is exempt
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct HoldingCapacityResponse {
+    pub max_position_notional: Option<Uint128>,
+    pub exempt: bool,
+    pub position_notional: Uint128,
+    pub remaining: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RiskParamsResponse {
+    pub initial_margin_ratio: Uint128,
+    pub maintenance_margin_ratio: Uint128,
+    pub liquidation_fee: Uint128,
+    pub max_leverage: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SettlementResponse {
+    pub vamm: Addr,
+    pub settlement_price: Uint128,
+    pub payout_ratio: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketStatusResponse {
+    pub vamm: Addr,
+    pub open: bool,
+    pub paused: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionTokenResponse {
+    pub token_id: String,
+    pub vamm: Addr,
+    pub trader: Addr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PositionTransferResponse {
+    pub vamm: Addr,
+    pub from: Addr,
+    pub to: Addr,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidityPoolResponse {
+    pub vamm: Addr,
+    pub total: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidityStakeResponse {
+    pub amount: Uint128,
+    pub fees: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PausedOperationsResponse {
+    pub paused: Vec<PausableOperation>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OpenInterestCapResponse {
+    pub share: Uint128,
+    pub floor: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InsuranceFundResponse {
+    pub balance: Uint128,
+    pub reserve: Uint128,
+    pub index: Uint128,
+    pub epoch: u64,
+    pub unstake_cooldown: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InsuranceStakeResponse {
+    pub value: Uint128,
+    pub unstake_amount: Option<Uint128>,
+    pub unstake_available_at: Option<Timestamp>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeePoolResponse {
+    pub balance: Uint128,
+    pub insurance_share: Uint128,
+    pub insurance_floor: Option<InsuranceFloor>,
+    pub keeper_reward: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DailyStatsResponse {
+    pub day: u64,
+    pub volume: Uint128,
+    pub peak_open_interest: Uint128,
+    pub fees: Uint128,
+}
+
+// the kind of trade recorded in a trader's history, decreasing a position is
+// recorded as a close of the size exchanged
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeKind {
+    Open,
+    Close,
+    Liquidation,
+}
+
+// a trade in a trader's history, the side is that of the position traded and
+// the price is the average the size was exchanged at
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TradeRecord {
+    pub trade_id: u64,
+    pub trader: Addr,
+    pub vamm: Addr,
+    pub kind: TradeKind,
+    pub side: Side,
+    pub size: Uint128,
+    pub price: Uint128,
+    pub fee: Uint128,
+    pub realized_pnl: Integer,
+    pub timestamp: Timestamp,
+}
+
+// a page of a trader's most recent trades, oldest first
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct TradeHistoryResponse {
+    pub trades: Vec<TradeRecord>,
+    pub next_key: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct DailyStatsListResponse {
+    pub stats: Vec<DailyStatsResponse>,
+    pub next_key: Option<u64>,
 }
@@ -0,0 +1,75 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use cosmwasm_std::{Addr, Uint128};
+use cw20::Cw20ReceiveMsg;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct InstantiateMsg {
+    pub engine: String,
+    pub vamm: String,
+    // the cw20 token that backs the market
+    pub collateral: String,
+    pub decimals: u8,
+    // the leverage the vault trades at
+    pub leverage: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecuteMsg {
+    Receive(Cw20ReceiveMsg),
+    UpdateConfig { leverage: Option<Uint128> },
+    // pays out the value of the shares from the vault's idle collateral
+    Withdraw { shares: Uint128 },
+    // takes the side of the market's open interest that is short of the
+    // other, or closes the vault's position once it is on the heavier side.
+    // Anyone may call it
+    Rebalance {},
+    // liquidates a position through the engine, the liquidation fee is paid
+    // to the vault
+    Liquidate { trader: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Cw20HookMsg {
+    // deposits the transferred collateral for shares of the vault
+    Deposit {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryMsg {
+    Config {},
+    State {},
+    Shares { provider: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct ConfigResponse {
+    pub owner: Addr,
+    pub engine: Addr,
+    pub vamm: Addr,
+    pub collateral: Addr,
+    pub decimals: Uint128,
+    pub leverage: Uint128,
+}
+
+// the vault is worth its idle collateral and what its position would close for
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct StateResponse {
+    pub total_shares: Uint128,
+    pub idle: Uint128,
+    pub position_value: Uint128,
+    pub total_value: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SharesResponse {
+    pub shares: Uint128,
+    pub value: Uint128,
+}
@@ -16,6 +16,9 @@ pub struct InstantiateMsg {
     pub oracle_hub_contract: String, // address of the oracle hub we are using
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
@@ -31,6 +34,7 @@ pub enum ExecuteMsg {
     },
     UpdateConfig {
         owner: Option<String>,
+        feeder: Option<String>,
     },
 }
 
@@ -55,4 +59,5 @@ pub enum QueryMsg {
 pub struct ConfigResponse {
     pub owner: Addr,
     pub decimals: Uint128,
+    pub feeder: Option<Addr>,
 }
@@ -1,7 +1,7 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, Timestamp, Uint128};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
@@ -18,20 +18,28 @@ pub struct InstantiateMsg {
     pub quote_asset_reserve: Uint128,
     pub base_asset_reserve: Uint128,
     pub funding_period: u64,
+    // the least time between funding rounds when one is settled late
+    pub funding_buffer_period: u64,
     pub toll_ratio: Uint128,
     pub spread_ratio: Uint128,
+    // the margin engine, the only address allowed to swap
+    pub engine: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
     UpdateConfig {
-        owner: Option<String>,
         // open: Option<bool>,
         // spot_price_twap_interval: Option<Uint128>,
         toll_ratio: Option<Uint128>,
         spread_ratio: Option<Uint128>,
-        // price_feed: Option<String>,
+        price_feed: Option<String>,
+        fluctuation_limit_ratio: Option<Uint128>,
+        engine: Option<String>,
     },
     SwapInput {
         direction: Direction,
@@ -41,7 +49,45 @@ pub enum ExecuteMsg {
         direction: Direction,
         base_asset_amount: Uint128,
     },
-    // SettleFunding {},
+    // ownership moves in two steps, the proposed owner must accept it
+    ProposeOwnership {
+        owner: String,
+    },
+    AcceptOwnership {},
+    CancelOwnershipProposal {},
+    // scales both reserves to the given quote reserve, keeping the price
+    ScaleReserves {
+        quote_asset_reserve: Uint128,
+    },
+    // scales both reserves by a ratio of the decimals, keeping the price, so
+    // k grows or shrinks by its square
+    AdjustK {
+        multiplier: Uint128,
+    },
+    // a closed market cannot be traded, either because it has not launched
+    // yet or because it has been shut down
+    SetOpen {
+        open: bool,
+    },
+    // closes the market for good, fixing the price positions settle at
+    Shutdown {},
+    // halts swaps during an incident
+    SetPause {
+        paused: bool,
+    },
+    // recovers cw20 tokens sent to the vamm, which never holds collateral
+    Sweep {
+        token: String,
+        amount: Uint128,
+    },
+    // sets how often funding is settled, the next round stays as scheduled
+    SetFundingPeriod {
+        funding_period: u64,
+        funding_buffer_period: u64,
+    },
+    // moves the funding schedule on to the next round, called by the engine
+    // as it settles funding
+    SettleFunding {},
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -49,18 +95,31 @@ pub enum ExecuteMsg {
 pub enum QueryMsg {
     Config {},
     State {},
+    InputPrice {
+        direction: Direction,
+        amount: Uint128,
+    },
     OutputPrice {
         direction: Direction,
         amount: Uint128,
     },
-    // InputTwap {
-    //     direction: Direction,
-    //     amount: Uint128,
-    // },
-    // OutputTwap {
-    //     direction: Direction,
-    //     amount: Uint128,
-    // },
+    // the twap over the interval of the input and output prices of the amount,
+    // priced against each reserve snapshot
+    InputTwap {
+        direction: Direction,
+        amount: Uint128,
+        interval: u64,
+    },
+    OutputTwap {
+        direction: Direction,
+        amount: Uint128,
+        interval: u64,
+    },
+    // the most base that can be swapped out in the direction this block within
+    // the fluctuation limit, null when the limit is off
+    MaxSwapOutput {
+        direction: Direction,
+    },
     // UnderlyingPrice {},
     // UnderlyingTwapPrice {},
     SpotPrice {},
@@ -70,16 +129,27 @@ pub enum QueryMsg {
     CalcFee {
         quote_asset_amount: Uint128,
     },
+    SettlementPrice {},
+    // the changes to k after the given index, oldest first
+    LiquidityHistory {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct ConfigResponse {
     pub owner: Addr,
+    pub pending_owner: Option<Addr>,
     pub quote_asset: String,
     pub base_asset: String,
     pub toll_ratio: Uint128,
     pub spread_ratio: Uint128,
     pub decimals: Uint128,
+    pub price_feed: Option<Addr>,
+    pub fluctuation_limit_ratio: Uint128,
+    pub paused: bool,
+    pub engine: Option<Addr>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -88,6 +158,14 @@ pub struct StateResponse {
     pub base_asset_reserve: Uint128,
     pub funding_rate: Uint128,
     pub funding_period: u64,
+    pub funding_buffer_period: u64,
+    pub next_funding_time: u64,
+    pub open: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SettlementPriceResponse {
+    pub settlement_price: Option<Uint128>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -95,3 +173,30 @@ pub struct CalcFeeResponse {
     pub toll_fee: Uint128,
     pub spread_fee: Uint128,
 }
+
+// the reserves either side of a change to k, positions opened before it are
+// resized in proportion to the base reserve
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquiditySnapshotResponse {
+    pub index: u64,
+    pub quote_asset_reserve_before: Uint128,
+    pub base_asset_reserve_before: Uint128,
+    pub quote_asset_reserve: Uint128,
+    pub base_asset_reserve: Uint128,
+    pub timestamp: Timestamp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct LiquidityHistoryResponse {
+    // the index of the latest change, zero while k has never changed
+    pub latest_index: u64,
+    pub snapshots: Vec<LiquiditySnapshotResponse>,
+    pub next_key: Option<u64>,
+}
+
+/// Returned as the data of a swap so callers need not read events
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapResponse {
+    pub input: Uint128,
+    pub output: Uint128,
+}
@@ -0,0 +1,353 @@
+// cw-multi-test helpers that wire a vAMM and the engine to a cw20 collateral,
+// so that tests share one setup. The contracts under test depend on this
+// package, so their code is handed in rather than linked here
+use cosmwasm_std::{Addr, Empty, Uint128};
+use cw20::{Cw20Coin, Cw20ExecuteMsg};
+use cw_multi_test::{App, AppBuilder, AppResponse, Contract, ContractWrapper, Executor};
+
+use crate::asset::AssetInfo;
+use crate::margined_engine::{
+    ExecuteMsg as EngineExecuteMsg, InstantiateMsg as EngineInstantiateMsg, PositionResponse,
+    QueryMsg as EngineQueryMsg, Side,
+};
+use crate::margined_pricefeed::{
+    ExecuteMsg as PricefeedExecuteMsg, InstantiateMsg as PricefeedInstantiateMsg,
+};
+use crate::margined_vamm::{ExecuteMsg as VammExecuteMsg, InstantiateMsg as VammInstantiateMsg};
+
+pub const DECIMAL_MULTIPLIER: Uint128 = Uint128::new(1_000_000_000);
+// the seconds between blocks when time is advanced
+pub const BLOCK_TIME: u64 = 5;
+
+pub struct ContractInfo {
+    pub addr: Addr,
+    pub id: u64,
+}
+
+// the code of the contracts under test
+pub struct MockContracts {
+    pub engine: Box<dyn Contract<Empty>>,
+    pub vamm: Box<dyn Contract<Empty>>,
+    pub pricefeed: Box<dyn Contract<Empty>>,
+}
+
+pub struct TestingEnv {
+    pub router: App,
+    pub owner: Addr,
+    pub alice: Addr,
+    pub bob: Addr,
+    pub usdc: ContractInfo,
+    pub vamm: ContractInfo,
+    pub engine: ContractInfo,
+    pub pricefeed: Option<ContractInfo>,
+    pricefeed_id: u64,
+}
+
+fn contract_cw20() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new_with_empty(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    );
+    Box::new(contract)
+}
+
+impl TestingEnv {
+    // alice and bob hold 5000 USDC each, with alice allowing the engine to
+    // draw 2000 of it. The vamm quotes ETH at 10 with no fees and the engine
+    // has next to no margin requirements
+    pub fn new(contracts: MockContracts) -> Self {
+        let mut router = AppBuilder::new().build();
+
+        let owner = Addr::unchecked("owner");
+        let alice = Addr::unchecked("alice");
+        let bob = Addr::unchecked("bob");
+
+        let usdc_id = router.store_code(contract_cw20());
+        let engine_id = router.store_code(contracts.engine);
+        let vamm_id = router.store_code(contracts.vamm);
+        let pricefeed_id = router.store_code(contracts.pricefeed);
+
+        let usdc_addr = router
+            .instantiate_contract(
+                usdc_id,
+                owner.clone(),
+                &cw20_base::msg::InstantiateMsg {
+                    name: "USDC".to_string(),
+                    symbol: "USDC".to_string(),
+                    decimals: 9,
+                    initial_balances: vec![
+                        Cw20Coin {
+                            address: alice.to_string(),
+                            amount: to_decimals(5000),
+                        },
+                        Cw20Coin {
+                            address: bob.to_string(),
+                            amount: to_decimals(5000),
+                        },
+                    ],
+                    mint: None,
+                    marketing: None,
+                },
+                &[],
+                "cw20",
+                None,
+            )
+            .unwrap();
+
+        let vamm_addr = router
+            .instantiate_contract(
+                vamm_id,
+                owner.clone(),
+                &VammInstantiateMsg {
+                    decimals: 9u8,
+                    quote_asset: "ETH".to_string(),
+                    base_asset: "USD".to_string(),
+                    quote_asset_reserve: to_decimals(1_000),
+                    base_asset_reserve: to_decimals(100),
+                    funding_period: 3_600_u64,
+                    funding_buffer_period: 1_800_u64,
+                    toll_ratio: Uint128::zero(),
+                    spread_ratio: Uint128::zero(),
+                    engine: None,
+                },
+                &[],
+                "vamm",
+                None,
+            )
+            .unwrap();
+
+        let engine_addr = router
+            .instantiate_contract(
+                engine_id,
+                owner.clone(),
+                &EngineInstantiateMsg {
+                    decimals: 9u8,
+                    eligible_collateral: AssetInfo::Token {
+                        contract_addr: usdc_addr.to_string(),
+                    },
+                    initial_margin_ratio: Uint128::from(100u128),
+                    maintenance_margin_ratio: Uint128::from(100u128),
+                    liquidation_fee: Uint128::from(100u128),
+                    vamm: vec![vamm_addr.to_string()],
+                },
+                &[],
+                "engine",
+                None,
+            )
+            .unwrap();
+
+        // only the engine may swap on the vamm
+        router
+            .execute_contract(
+                owner.clone(),
+                vamm_addr.clone(),
+                &VammExecuteMsg::UpdateConfig {
+                    toll_ratio: None,
+                    spread_ratio: None,
+                    price_feed: None,
+                    fluctuation_limit_ratio: None,
+                    engine: Some(engine_addr.to_string()),
+                },
+                &[],
+            )
+            .unwrap();
+
+        router
+            .execute_contract(
+                alice.clone(),
+                usdc_addr.clone(),
+                &Cw20ExecuteMsg::IncreaseAllowance {
+                    spender: engine_addr.to_string(),
+                    amount: to_decimals(2000),
+                    expires: None,
+                },
+                &[],
+            )
+            .unwrap();
+
+        TestingEnv {
+            router,
+            owner,
+            alice,
+            bob,
+            usdc: ContractInfo {
+                addr: usdc_addr,
+                id: usdc_id,
+            },
+            vamm: ContractInfo {
+                addr: vamm_addr,
+                id: vamm_id,
+            },
+            engine: ContractInfo {
+                addr: engine_addr,
+                id: engine_id,
+            },
+            pricefeed: None,
+            pricefeed_id,
+        }
+    }
+
+    // sets up a price feed with 6 decimals quoting the index at 10 and points
+    // the vamm at it
+    pub fn setup_price_feed(&mut self) -> Addr {
+        let pricefeed = self
+            .router
+            .instantiate_contract(
+                self.pricefeed_id,
+                self.owner.clone(),
+                &PricefeedInstantiateMsg {
+                    decimals: 6u8,
+                    oracle_hub_contract: "oracle_hub".to_string(),
+                },
+                &[],
+                "pricefeed",
+                None,
+            )
+            .unwrap();
+        self.pricefeed = Some(ContractInfo {
+            addr: pricefeed.clone(),
+            id: self.pricefeed_id,
+        });
+        self.set_oracle_price(Uint128::from(10_000_000u128));
+
+        let msg = VammExecuteMsg::UpdateConfig {
+            toll_ratio: None,
+            spread_ratio: None,
+            price_feed: Some(pricefeed.to_string()),
+            fluctuation_limit_ratio: None,
+            engine: None,
+        };
+        self.router
+            .execute_contract(self.owner.clone(), self.vamm.addr.clone(), &msg, &[])
+            .unwrap();
+
+        pricefeed
+    }
+
+    // quotes the index at the price, in the 6 decimals of the price feed, as of
+    // the current block
+    pub fn set_oracle_price(&mut self, price: Uint128) {
+        let pricefeed = self
+            .pricefeed
+            .as_ref()
+            .expect("price feed is not set up")
+            .addr
+            .clone();
+        let msg = PricefeedExecuteMsg::AppendPrice {
+            key: "ETH".to_string(),
+            price,
+            timestamp: self.router.block_info().time.seconds(),
+        };
+        self.router
+            .execute_contract(self.owner.clone(), pricefeed, &msg, &[])
+            .unwrap();
+    }
+
+    // moves the chain on by the seconds, a block every BLOCK_TIME
+    pub fn advance_time(&mut self, seconds: u64) {
+        self.router.update_block(|block| {
+            block.height += seconds / BLOCK_TIME;
+            block.time = block.time.plus_seconds(seconds);
+        });
+    }
+
+    // allows the engine to draw more of the trader's USDC
+    pub fn approve(&mut self, trader: &Addr, amount: u64) {
+        self.increase_allowance(trader, to_decimals(amount));
+    }
+
+    fn increase_allowance(&mut self, trader: &Addr, amount: Uint128) {
+        let msg = Cw20ExecuteMsg::IncreaseAllowance {
+            spender: self.engine.addr.to_string(),
+            amount,
+            expires: None,
+        };
+        self.router
+            .execute_contract(trader.clone(), self.usdc.addr.clone(), &msg, &[])
+            .unwrap();
+    }
+
+    // opens a position on the margin at the leverage, both in whole units,
+    // approving the margin first
+    pub fn open_position(
+        &mut self,
+        trader: &Addr,
+        side: Side,
+        margin: u64,
+        leverage: u64,
+    ) -> Result<AppResponse, String> {
+        let vamm = self.vamm.addr.clone();
+        self.open_position_with(
+            &vamm,
+            trader,
+            side,
+            to_decimals(margin),
+            to_decimals(leverage),
+            None,
+            false,
+        )
+    }
+
+    // opens a position as open_position does, in any vamm, with the margin and
+    // leverage in the engine's decimals and the order's limits given
+    #[allow(clippy::too_many_arguments)]
+    pub fn open_position_with(
+        &mut self,
+        vamm: &Addr,
+        trader: &Addr,
+        side: Side,
+        quote_asset_amount: Uint128,
+        leverage: Uint128,
+        base_asset_limit: Option<Uint128>,
+        reduce_only: bool,
+    ) -> Result<AppResponse, String> {
+        self.increase_allowance(trader, quote_asset_amount);
+
+        let msg = EngineExecuteMsg::OpenPosition {
+            vamm: vamm.to_string(),
+            side,
+            quote_asset_amount,
+            leverage,
+            base_asset_limit,
+            reduce_only,
+            deadline: None,
+            trader: None,
+        };
+        self.router
+            .execute_contract(trader.clone(), self.engine.addr.clone(), &msg, &[])
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn close_position(&mut self, trader: &Addr) -> Result<AppResponse, String> {
+        let msg = EngineExecuteMsg::ClosePosition {
+            vamm: self.vamm.addr.to_string(),
+            size: None,
+            quote_asset_limit: None,
+            partial: false,
+            deadline: None,
+            trader: None,
+        };
+        self.router
+            .execute_contract(trader.clone(), self.engine.addr.clone(), &msg, &[])
+            .map_err(|err| err.to_string())
+    }
+
+    pub fn position(&self, trader: &Addr) -> PositionResponse {
+        self.router
+            .wrap()
+            .query_wasm_smart(
+                &self.engine.addr,
+                &EngineQueryMsg::Position {
+                    vamm: self.vamm.addr.to_string(),
+                    trader: trader.to_string(),
+                },
+            )
+            .unwrap()
+    }
+}
+
+// takes in a Uint128 and multiplies by the decimals just to make tests more legible
+pub fn to_decimals(input: u64) -> Uint128 {
+    Uint128::from(input) * DECIMAL_MULTIPLIER
+}
@@ -0,0 +1,22 @@
+// Shared pagination for list queries. Every list query takes an optional
+// `start_after` key and `limit`, and returns the `next_key` to pass as
+// `start_after` to fetch the following page.
+
+pub const DEFAULT_LIMIT: u32 = 10;
+pub const MAX_LIMIT: u32 = 30;
+
+/// returns the number of items to read, the default if no limit is given
+/// and never more than the max
+pub fn page_limit(limit: Option<u32>, default: u32, max: u32) -> usize {
+    limit.unwrap_or(default).min(max) as usize
+}
+
+/// returns the key of the last item if the page is full, otherwise there
+/// are no more items and so no next key
+pub fn next_key<T, K>(items: &[T], limit: usize, key: impl Fn(&T) -> K) -> Option<K> {
+    if items.len() < limit {
+        return None;
+    }
+
+    items.last().map(key)
+}